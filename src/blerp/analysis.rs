@@ -0,0 +1,455 @@
+use std::{f64::consts::PI, fmt};
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+use super::audiofile::AudioBuffer;
+
+const PITCH_CLASSES: [&str; 12] = [
+    "C", "C#", "D", "D#", "E", "F", "F#", "G", "G#", "A", "A#", "B",
+];
+// Krumhansl-Kessler key profiles, starting from the tonic
+const MAJOR_PROFILE: [f64; 12] = [
+    6.35, 2.23, 3.48, 2.33, 4.38, 4.09, 2.52, 5.19, 2.39, 3.66, 2.29, 2.88,
+];
+const MINOR_PROFILE: [f64; 12] = [
+    6.33, 2.68, 3.52, 5.38, 2.60, 3.53, 2.54, 4.75, 3.98, 2.69, 3.34, 3.17,
+];
+// Tempos outside of this get halved or doubled into it
+const MIN_BPM: f64 = 70.;
+const MAX_BPM: f64 = 180.;
+// Only the start of long files gets looked at for the tempo and key
+const MAX_ANALYSED_SECS: f64 = 60.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Key {
+    // 0 is C
+    pub tonic: u8,
+    pub minor: bool,
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} {}",
+            PITCH_CLASSES[usize::from(self.tonic % 12)],
+            if self.minor { "min" } else { "maj" }
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SampleType {
+    OneShot,
+    Loop,
+}
+
+impl fmt::Display for SampleType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::OneShot => write!(f, "1-shot"),
+            Self::Loop => write!(f, "loop"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Analysis {
+    pub bpm: Option<f64>,
+    pub key: Option<Key>,
+    // dBFS
+    pub peak: f64,
+    pub rms: f64,
+    // Integrated loudness as in ITU-R BS.1770, `None` for files that are silent after gating
+    pub lufs: Option<f64>,
+    pub sample_type: SampleType,
+}
+
+fn to_db(amplitude: f64) -> f64 {
+    20. * amplitude.max(1e-10).log10()
+}
+
+fn mono(buffer: &AudioBuffer) -> Vec<f32> {
+    let channels = usize::from(buffer.channels.max(1));
+    #[allow(clippy::cast_precision_loss)]
+    let scale = 1. / channels as f32;
+    buffer
+        .samples
+        .chunks_exact(channels)
+        .map(|frame| frame.iter().sum::<f32>() * scale)
+        .collect()
+}
+
+fn hann(size: usize) -> Vec<f32> {
+    #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+    (0..size)
+        .map(|i| (0.5 - 0.5 * (2. * PI * i as f64 / size as f64).cos()) as f32)
+        .collect()
+}
+
+// Magnitude spectra of overlapping windows
+fn spectrogram(samples: &[f32], size: usize, hop: usize) -> Vec<Vec<f32>> {
+    let fft = FftPlanner::new().plan_fft_forward(size);
+    let window = hann(size);
+    let mut buffer = vec![Complex::default(); size];
+    (0..samples.len().saturating_sub(size) / hop + 1)
+        .filter(|frame| frame * hop + size <= samples.len())
+        .map(|frame| {
+            let start = frame * hop;
+            for ((bin, sample), window) in buffer.iter_mut().zip(&samples[start..start + size]).zip(&window) {
+                *bin = Complex::new(sample * window, 0.);
+            }
+            fft.process(&mut buffer);
+            buffer[..size / 2].iter().map(|bin| bin.norm()).collect()
+        })
+        .collect()
+}
+
+// Spectral flux onsets, then the autocorrelation of those picks the beat period
+fn tempo(samples: &[f32], sample_rate: u32) -> Option<f64> {
+    const SIZE: usize = 1024;
+    const HOP: usize = 512;
+    let spectra = spectrogram(samples, SIZE, HOP);
+    if spectra.len() < 16 {
+        return None;
+    }
+    let compressed: Vec<Vec<f32>> = spectra
+        .iter()
+        .map(|spectrum| spectrum.iter().map(|bin| (1. + 100. * bin).ln()).collect())
+        .collect();
+    let flux: Vec<f64> = compressed
+        .windows(2)
+        .map(|pair| {
+            pair[1]
+                .iter()
+                .zip(&pair[0])
+                .map(|(current, previous)| f64::from((current - previous).max(0.)))
+                .sum()
+        })
+        .collect();
+    // Take out the slowly moving part so only the onsets are left
+    let envelope: Vec<f64> = (0..flux.len())
+        .map(|i| {
+            let around = &flux[i.saturating_sub(8)..(i + 8).min(flux.len())];
+            #[allow(clippy::cast_precision_loss)]
+            let mean = around.iter().sum::<f64>() / around.len() as f64;
+            (flux[i] - mean).max(0.)
+        })
+        .collect();
+
+    let frame_rate = f64::from(sample_rate) / HOP as f64;
+    let autocorrelation = |lag: usize| {
+        if lag >= envelope.len() {
+            return 0.;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let count = (envelope.len() - lag) as f64;
+        envelope
+            .iter()
+            .zip(&envelope[lag..])
+            .map(|(a, b)| a * b)
+            .sum::<f64>()
+            / count
+    };
+    let energy = autocorrelation(0);
+    // Onsets have to stand out from the spectrum itself, or there's nothing to find a beat in
+    #[allow(clippy::cast_precision_loss)]
+    let level = compressed
+        .iter()
+        .map(|spectrum| spectrum.iter().map(|bin| f64::from(*bin)).sum::<f64>())
+        .sum::<f64>()
+        / compressed.len() as f64;
+    if energy.sqrt() < level * 0.1 {
+        return None;
+    }
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let lags = (frame_rate * 60. / 240.) as usize..=(frame_rate * 60. / 50.) as usize;
+    // The double period counts too, so a beat wins over its off-beats
+    let score = |lag: usize| autocorrelation(lag) + 0.5 * autocorrelation(lag * 2);
+    let best = lags.clone().max_by(|a, b| score(*a).total_cmp(&score(*b)))?;
+    if autocorrelation(best) < energy * 0.1 {
+        return None;
+    }
+    // Parabolic interpolation between neighbouring lags
+    #[allow(clippy::cast_precision_loss)]
+    let lag = if best > *lags.start() && best < *lags.end() {
+        let (before, at, after) = (score(best - 1), score(best), score(best + 1));
+        let denominator = before - 2. * at + after;
+        if denominator.abs() > f64::EPSILON {
+            best as f64 + 0.5 * (before - after) / denominator
+        } else {
+            best as f64
+        }
+    } else {
+        best as f64
+    };
+    let mut bpm = 60. * frame_rate / lag;
+    while bpm < MIN_BPM {
+        bpm *= 2.;
+    }
+    while bpm > MAX_BPM {
+        bpm /= 2.;
+    }
+    Some(bpm)
+}
+
+fn pearson(a: &[f64; 12], b: &[f64; 12]) -> f64 {
+    let mean = |values: &[f64; 12]| values.iter().sum::<f64>() / 12.;
+    let (mean_a, mean_b) = (mean(a), mean(b));
+    let (mut covariance, mut variance_a, mut variance_b) = (0., 0., 0.);
+    for (a, b) in a.iter().zip(b) {
+        covariance += (a - mean_a) * (b - mean_b);
+        variance_a += (a - mean_a).powi(2);
+        variance_b += (b - mean_b).powi(2);
+    }
+    covariance / (variance_a * variance_b).sqrt().max(f64::EPSILON)
+}
+
+// Chroma of the whole file matched against the major and minor profiles in every key
+fn key(samples: &[f32], sample_rate: u32) -> Option<Key> {
+    const SIZE: usize = 4096;
+    let spectra = spectrogram(samples, SIZE, SIZE / 2);
+    let mut chroma = [0.; 12];
+    for spectrum in &spectra {
+        for (bin, magnitude) in spectrum.iter().enumerate().skip(1) {
+            #[allow(clippy::cast_precision_loss)]
+            let frequency = bin as f64 * f64::from(sample_rate) / SIZE as f64;
+            if !(55. ..=2000.).contains(&frequency) {
+                continue;
+            }
+            let pitch = 12. * (frequency / 440.).log2() + 69.;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let class = (pitch.round() as usize) % 12;
+            chroma[class] += f64::from(*magnitude).powi(2);
+        }
+    }
+    if chroma.iter().sum::<f64>() <= f64::EPSILON {
+        return None;
+    }
+    let mut best = None;
+    for tonic in 0..12 {
+        for (minor, profile) in [(false, MAJOR_PROFILE), (true, MINOR_PROFILE)] {
+            let rotated: [f64; 12] = std::array::from_fn(|class| profile[(class + 12 - tonic) % 12]);
+            let correlation = pearson(&chroma, &rotated);
+            if best.is_none_or(|(best, _)| correlation > best) {
+                #[allow(clippy::cast_possible_truncation)]
+                let key = Key {
+                    tonic: tonic as u8,
+                    minor,
+                };
+                best = Some((correlation, key));
+            }
+        }
+    }
+    // Drums and noise correlate with everything a little, that's not a key
+    best.filter(|(correlation, _)| *correlation > 0.5)
+        .map(|(_, key)| key)
+}
+
+// Biquad in direct form 1, used for the K-weighting
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn process(&mut self, input: f64) -> f64 {
+        let output = self.b[0] * input + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+        self.x = [input, self.x[0]];
+        self.y = [output, self.y[0]];
+        output
+    }
+}
+
+// The two K-weighting stages from BS.1770, derived for any sample rate
+fn k_weighting(sample_rate: u32) -> [Biquad; 2] {
+    let rate = f64::from(sample_rate);
+
+    let k = (PI * 1_681.974_450_955_533 / rate).tan();
+    let q = 0.707_175_236_955_419_6;
+    let vh = 10_f64.powf(3.999_843_853_973_347 / 20.);
+    let vb = vh.powf(0.499_666_774_154_541_6);
+    let a0 = 1. + k / q + k * k;
+    let shelf = Biquad {
+        b: [
+            (vh + vb * k / q + k * k) / a0,
+            2. * (k * k - vh) / a0,
+            (vh - vb * k / q + k * k) / a0,
+        ],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        x: [0.; 2],
+        y: [0.; 2],
+    };
+
+    let k = (PI * 38.135_470_876_024_44 / rate).tan();
+    let q = 0.500_327_037_323_877_3;
+    let a0 = 1. + k / q + k * k;
+    let high_pass = Biquad {
+        b: [1., -2., 1.],
+        a: [2. * (k * k - 1.) / a0, (1. - k / q + k * k) / a0],
+        x: [0.; 2],
+        y: [0.; 2],
+    };
+    [shelf, high_pass]
+}
+
+// Gated integrated loudness over 400 ms blocks that overlap by 75%
+fn integrated_loudness(buffer: &AudioBuffer) -> Option<f64> {
+    let channels = usize::from(buffer.channels.max(1));
+    let frames = buffer.frames();
+    if frames == 0 {
+        return None;
+    }
+    let mut weighted = vec![0.; frames];
+    for channel in 0..channels.min(2) {
+        let mut filters = k_weighting(buffer.sample_rate);
+        for (frame, power) in weighted.iter_mut().enumerate() {
+            let sample = filters
+                .iter_mut()
+                .fold(f64::from(buffer.samples[frame * channels + channel]), |sample, filter| {
+                    filter.process(sample)
+                });
+            *power += sample * sample;
+        }
+    }
+    // Mono plays back on both speakers
+    if channels == 1 {
+        for power in &mut weighted {
+            *power *= 2.;
+        }
+    }
+
+    // Files shorter than a 400 ms block get measured as a single block
+    let block = (buffer.sample_rate as usize * 2 / 5).clamp(1, frames);
+    let step = (block / 4).max(1);
+    #[allow(clippy::cast_precision_loss)]
+    let powers: Vec<f64> = (0..=frames.saturating_sub(block) / step)
+        .map(|index| weighted[index * step..index * step + block].iter().sum::<f64>() / block as f64)
+        .collect();
+    let loudness = |power: f64| -0.691 + 10. * power.max(1e-20).log10();
+    let gated_mean = |threshold: f64| {
+        let gated: Vec<f64> = powers
+            .iter()
+            .copied()
+            .filter(|power| loudness(*power) > threshold)
+            .collect();
+        #[allow(clippy::cast_precision_loss)]
+        (!gated.is_empty()).then(|| gated.iter().sum::<f64>() / gated.len() as f64)
+    };
+    let absolute = gated_mean(-70.)?;
+    gated_mean(loudness(absolute) - 10.).map(loudness)
+}
+
+fn rms(samples: &[f32]) -> f64 {
+    if samples.is_empty() {
+        return 0.;
+    }
+    #[allow(clippy::cast_precision_loss)]
+    let count = samples.len() as f64;
+    (samples.iter().map(|sample| f64::from(*sample).powi(2)).sum::<f64>() / count).sqrt()
+}
+
+pub fn analyse(buffer: &AudioBuffer) -> Analysis {
+    let mono = mono(buffer);
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let analysed = &mono[..mono
+        .len()
+        .min((MAX_ANALYSED_SECS * f64::from(buffer.sample_rate)) as usize)];
+    let duration = buffer.duration_secs();
+
+    let peak = to_db(
+        buffer
+            .samples
+            .iter()
+            .fold(0_f64, |peak, sample| peak.max(f64::from(sample.abs()))),
+    );
+    let overall = rms(&buffer.samples);
+
+    // Too short for a beat to repeat
+    let mut bpm = (duration >= 2.)
+        .then(|| tempo(analysed, buffer.sample_rate))
+        .flatten();
+
+    // Loops get cut to whole bars and still have signal right up to the end,
+    // one-shots decay into silence
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let tail = &mono[mono.len() - (mono.len() / 20).max(buffer.sample_rate as usize / 20).min(mono.len())..];
+    let sustained = to_db(rms(tail)) > to_db(rms(&mono)) - 12.;
+    let whole_beats = bpm.map(|bpm| duration * bpm / 60.).filter(|beats| {
+        let bars = beats / 4.;
+        bars.round() >= 1. && (bars - bars.round()).abs() < 0.05
+    });
+    let sample_type = if duration >= 1. && (whole_beats.is_some() || (sustained && bpm.is_some())) {
+        SampleType::Loop
+    } else {
+        SampleType::OneShot
+    };
+    // A loop's length gives its tempo away more precisely than the onsets do, and a one-shot
+    // doesn't have one at all
+    if let Some(beats) = whole_beats {
+        bpm = Some(beats.round() * 60. / duration);
+    }
+    if sample_type == SampleType::OneShot {
+        bpm = None;
+    }
+
+    Analysis {
+        bpm,
+        key: key(analysed, buffer.sample_rate),
+        peak,
+        rms: to_db(overall),
+        lufs: integrated_loudness(buffer),
+        sample_type,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::integrated_loudness;
+    use crate::blerp::audiofile::AudioBuffer;
+
+    fn sine(frames: usize, amplitude: f32) -> AudioBuffer {
+        #[allow(clippy::cast_precision_loss)]
+        let samples = (0..frames)
+            .flat_map(|frame| {
+                let sample = ((frame as f32 + 0.25) * 1000. * std::f32::consts::TAU / 48000.).cos() * amplitude;
+                [sample, sample]
+            })
+            .collect();
+        AudioBuffer {
+            sample_rate: 48000,
+            channels: 2,
+            samples,
+        }
+    }
+
+    #[test]
+    fn empty_files_have_no_loudness() {
+        assert_eq!(integrated_loudness(&sine(0, 0.5)), None);
+    }
+
+    #[test]
+    fn files_shorter_than_a_block_get_measured() {
+        for frames in [1, 100, 19199] {
+            assert!(integrated_loudness(&sine(frames, 0.5)).is_some(), "{frames} frames");
+        }
+    }
+
+    #[test]
+    fn silence_is_gated_out() {
+        assert_eq!(integrated_loudness(&sine(48000, 0.)), None);
+    }
+
+    #[test]
+    fn full_scale_sine_is_about_zero_lufs() {
+        // A 1 kHz sine at full scale reads -3.01 LUFS in a single channel, so twice the power with
+        // both channels playing it
+        let loudness = integrated_loudness(&sine(48000 * 3, 1.)).unwrap_or(f64::NEG_INFINITY);
+        assert!(loudness.abs() < 0.1, "{loudness}");
+    }
+}
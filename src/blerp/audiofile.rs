@@ -0,0 +1,265 @@
+use std::{fmt, fs::File, io, io::BufReader, path::Path};
+
+use rodio::{decoder::DecoderError, Decoder, Source};
+
+use crate::blerp;
+
+// A fully decoded file, samples are interleaved
+#[derive(Debug, Clone, PartialEq)]
+pub struct AudioBuffer {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub samples: Vec<f32>,
+}
+
+#[derive(Debug)]
+pub enum DecodeError {
+    Io(io::Error),
+    Format(DecoderError),
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Format(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl From<io::Error> for DecodeError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<DecoderError> for DecodeError {
+    fn from(err: DecoderError) -> Self {
+        Self::Format(err)
+    }
+}
+
+// Only what the header says, files that don't know their length are left without one
+pub fn duration(path: &Path) -> Option<f64> {
+    let decoder = Decoder::new(BufReader::new(File::open(path).ok()?)).ok()?;
+    decoder.total_duration().map(|duration| duration.as_secs_f64())
+}
+
+// Decoding goes through rodio's decoders for now
+pub fn decode(path: &Path) -> Result<AudioBuffer, DecodeError> {
+    let decoder = Decoder::new(BufReader::new(File::open(path)?))?;
+    let sample_rate = decoder.sample_rate();
+    let channels = decoder.channels();
+    Ok(AudioBuffer {
+        sample_rate,
+        channels,
+        samples: decoder.convert_samples().collect(),
+    })
+}
+
+// Zero crossings of the resampling kernel on either side of each output sample
+const SINC_ZERO_CROSSINGS: usize = 16;
+// Points per zero crossing the kernel is tabulated at, it's interpolated linearly in between
+const SINC_RESOLUTION: usize = 512;
+// Where the passband ends, as a fraction of the lower Nyquist frequency. The window needs some room
+// to roll off before it
+const SINC_CUTOFF: f64 = 0.94;
+
+// Blackman windowed sinc from 0 to the last zero crossing
+fn sinc_table() -> Vec<f64> {
+    use std::f64::consts::PI;
+    #[allow(clippy::cast_precision_loss)]
+    (0..=SINC_ZERO_CROSSINGS * SINC_RESOLUTION)
+        .map(|index| {
+            let x = index as f64 / SINC_RESOLUTION as f64;
+            let sinc = if index == 0 { 1. } else { (PI * x).sin() / (PI * x) };
+            let window = x / SINC_ZERO_CROSSINGS as f64;
+            sinc * (0.08f64.mul_add((2. * PI * window).cos(), 0.5f64.mul_add((PI * window).cos(), 0.42)))
+        })
+        .collect()
+}
+
+impl AudioBuffer {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / usize::from(self.channels.max(1))
+    }
+
+    pub fn duration_secs(&self) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let frames = self.frames() as f64;
+        frames / f64::from(self.sample_rate.max(1))
+    }
+
+    // Mono gets duplicated, anything past the first two channels gets dropped
+    pub fn to_stereo(&self) -> Self {
+        let samples = match self.channels {
+            1 => blerp::f32_samples_mono_to_stereo(&self.samples),
+            2 => self.samples.clone(),
+            channels => self
+                .samples
+                .chunks_exact(usize::from(channels))
+                .flat_map(|frame| [frame[0], frame[1]])
+                .collect(),
+        };
+        Self {
+            sample_rate: self.sample_rate,
+            channels: 2,
+            samples,
+        }
+    }
+
+    // Linear interpolation, good enough for clicks and overviews but not for anything listened to closely
+    pub fn resampled(&self, sample_rate: u32) -> Self {
+        if sample_rate == self.sample_rate || self.samples.is_empty() {
+            return Self {
+                sample_rate,
+                ..self.clone()
+            };
+        }
+        let channels = usize::from(self.channels.max(1));
+        let frames = self.frames();
+        let ratio = f64::from(self.sample_rate) / f64::from(sample_rate);
+        #[allow(
+            clippy::cast_possible_truncation,
+            clippy::cast_sign_loss,
+            clippy::cast_precision_loss
+        )]
+        let new_frames = (frames as f64 / ratio) as usize;
+        let mut samples = Vec::with_capacity(new_frames * channels);
+        for frame in 0..new_frames {
+            #[allow(clippy::cast_precision_loss)]
+            let position = frame as f64 * ratio;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let index = position as usize;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+            let fraction = (position - index as f64) as f32;
+            let next = (index + 1).min(frames - 1);
+            for channel in 0..channels {
+                let current = self.samples[index * channels + channel];
+                let next = self.samples[next * channels + channel];
+                samples.push((next - current).mul_add(fraction, current));
+            }
+        }
+        Self {
+            sample_rate,
+            channels: self.channels,
+            samples,
+        }
+    }
+    // Windowed sinc, for anything that gets written to disk, previewed or played in the arrangement.
+    // Everything above the lower of the two Nyquist frequencies gets filtered out, so downsampling
+    // doesn't alias
+    pub fn resampled_band_limited(&self, sample_rate: u32) -> Self {
+        if sample_rate == self.sample_rate || self.samples.is_empty() {
+            return Self {
+                sample_rate,
+                ..self.clone()
+            };
+        }
+        let channels = usize::from(self.channels.max(1));
+        let frames = self.frames();
+        let step = f64::from(self.sample_rate) / f64::from(sample_rate);
+        let cutoff = SINC_CUTOFF * (1. / step).min(1.);
+        let table = sinc_table();
+        // The kernel as a function of the distance in input frames, stretched when downsampling
+        #[allow(clippy::cast_precision_loss)]
+        let kernel = |distance: f64| {
+            let position = distance.abs() * cutoff * SINC_RESOLUTION as f64;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let index = position as usize;
+            if index + 1 >= table.len() {
+                return 0.;
+            }
+            #[allow(clippy::cast_precision_loss)]
+            let fraction = position - index as f64;
+            (table[index + 1] - table[index]).mul_add(fraction, table[index])
+        };
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+        let reach = (SINC_ZERO_CROSSINGS as f64 / cutoff).ceil() as usize;
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+        let new_frames = (frames as f64 / step) as usize;
+
+        let mut samples = Vec::with_capacity(new_frames * channels);
+        let mut weights = Vec::with_capacity(reach * 2);
+        for frame in 0..new_frames {
+            #[allow(clippy::cast_precision_loss)]
+            let position = frame as f64 * step;
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let center = position as usize;
+            // Frames past either end of the file count as silence
+            let first = (center + 1).saturating_sub(reach);
+            let last = (center + reach).min(frames - 1);
+            weights.clear();
+            #[allow(clippy::cast_precision_loss)]
+            weights.extend((first..=last).map(|index| kernel(index as f64 - position)));
+            // Keeps DC at exactly unity gain whatever the phase
+            let total: f64 = weights.iter().sum();
+            let scale = if total.abs() > f64::EPSILON { 1. / total } else { 0. };
+            for channel in 0..channels {
+                let sum: f64 = weights
+                    .iter()
+                    .zip(first..=last)
+                    .map(|(weight, index)| weight * f64::from(self.samples[index * channels + channel]))
+                    .sum();
+                #[allow(clippy::cast_possible_truncation)]
+                samples.push((sum * scale) as f32);
+            }
+        }
+        Self {
+            sample_rate,
+            channels: self.channels,
+            samples,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::AudioBuffer;
+
+    fn sine(frequency: f64, sample_rate: u32, frames: usize) -> AudioBuffer {
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+        let samples = (0..frames)
+            .map(|frame| (frame as f64 * frequency * std::f64::consts::TAU / f64::from(sample_rate)).sin() as f32)
+            .collect();
+        AudioBuffer {
+            sample_rate,
+            channels: 1,
+            samples,
+        }
+    }
+
+    // Away from the edges, where the kernel runs out of input
+    fn peak(buffer: &AudioBuffer) -> f32 {
+        let frames = buffer.frames();
+        buffer.samples[frames / 4..frames * 3 / 4]
+            .iter()
+            .fold(0., |peak, sample| peak.max(sample.abs()))
+    }
+
+    #[test]
+    fn band_limited_resampling_keeps_the_passband() {
+        for (from, to) in [(44100, 48000), (48000, 44100), (96000, 44100), (22050, 48000)] {
+            let resampled = sine(1000., from, 20000).resampled_band_limited(to);
+            assert_eq!(resampled.sample_rate, to);
+            let peak = peak(&resampled);
+            assert!((peak - 1.).abs() < 0.01, "{from} to {to}: {peak}");
+        }
+    }
+
+    #[test]
+    fn band_limited_downsampling_does_not_alias() {
+        // 30 kHz is fine at 96 kHz but above Nyquist at 44.1, linear interpolation folds it back down
+        let source = sine(30000., 96000, 40000);
+        let band_limited = peak(&source.resampled_band_limited(44100));
+        let linear = peak(&source.resampled(44100));
+        assert!(band_limited < 0.001, "{band_limited}");
+        assert!(linear > 0.1, "{linear}");
+    }
+
+    #[test]
+    fn band_limited_resampling_keeps_the_length() {
+        let resampled = sine(440., 44100, 44100).resampled_band_limited(48000);
+        assert_eq!(resampled.frames(), 48000);
+    }
+}
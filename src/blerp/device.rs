@@ -1,13 +1,29 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter};
+
+use crate::blerp::processing::live::{stereo_buffer, EngineHandle};
+
+pub mod cpal_host;
+#[cfg(feature = "jack")]
+pub mod jack_host;
+pub mod latency;
+
+#[allow(dead_code)]
 pub struct Device {
     pub name: String,
 }
+#[allow(dead_code)]
 pub struct DeviceEntry {
     pub id: String,
     pub device: Device,
 }
+#[allow(dead_code)]
 pub struct DeviceHandler {
     pub devices: Vec<DeviceEntry>,
 }
+#[allow(dead_code)]
 impl DeviceHandler {
     pub fn add_device(&mut self, id: String, device: Device) {
         self.devices.push(DeviceEntry { id, device });
@@ -16,3 +32,86 @@ impl DeviceHandler {
         self.devices
     }
 }
+
+#[derive(Display, EnumIter, Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Backend {
+    #[default]
+    #[strum(to_string = "cpal")]
+    Cpal,
+    #[cfg(feature = "jack")]
+    #[strum(to_string = "JACK")]
+    Jack,
+}
+
+#[derive(Debug)]
+pub enum StreamError {
+    NoDevice,
+    UnsupportedFormat(String),
+    Backend(String),
+}
+
+impl fmt::Display for StreamError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NoDevice => write!(f, "no output device available"),
+            Self::UnsupportedFormat(format) => write!(f, "unsupported sample format '{format}'"),
+            Self::Backend(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+#[allow(dead_code, reason = "the streams are only held on to so they keep running")]
+pub enum AudioStream {
+    Cpal(cpal_host::CpalStreams),
+    #[cfg(feature = "jack")]
+    Jack(jack_host::JackStream),
+}
+
+// Identifies a device setup, latency measurements only hold for the exact setup they were made with
+#[derive(Debug, Clone, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct DeviceConfig {
+    pub backend: String,
+    pub device: String,
+    pub sample_rate: u32,
+    pub buffer_size: Option<u32>,
+}
+
+// A running stream together with the handle to the engine it drives
+pub struct Output {
+    pub config: DeviceConfig,
+    pub engine: EngineHandle,
+    // Recorded material gets moved this many frames earlier to line up with what was playing
+    pub recording_offset: Option<u32>,
+    stream: AudioStream,
+}
+
+impl Backend {
+    pub fn start(self) -> Result<Output, StreamError> {
+        let (stream, engine, config) = match self {
+            Self::Cpal => cpal_host::start()
+                .map(|(stream, engine, config)| (AudioStream::Cpal(stream), engine, config))?,
+            #[cfg(feature = "jack")]
+            Self::Jack => jack_host::start()
+                .map(|(stream, engine, config)| (AudioStream::Jack(stream), engine, config))?,
+        };
+        Ok(Output {
+            recording_offset: latency::LatencyProfiles::load().round_trip(&config),
+            config,
+            engine,
+            stream,
+        })
+    }
+}
+
+impl Output {
+    // Gives every track its own output, backends with named ports (JACK) also expose them
+    pub fn set_track_outputs(&mut self, names: &[String]) {
+        match &mut self.stream {
+            AudioStream::Cpal(_) => {}
+            #[cfg(feature = "jack")]
+            AudioStream::Jack(stream) => stream.set_track_ports(names),
+        }
+        self.engine
+            .set_track_outputs(names.iter().map(|_| stereo_buffer()).collect());
+    }
+}
@@ -0,0 +1,246 @@
+use std::sync::{
+    atomic::{AtomicU32, AtomicUsize, Ordering},
+    Arc,
+};
+
+use cpal::{
+    traits::{DeviceTrait, HostTrait, StreamTrait},
+    BufferSize, FromSample, SampleFormat, SampleRate, SizedSample, Stream, StreamConfig,
+};
+
+use super::{Backend, DeviceConfig, StreamError};
+use crate::blerp::processing::live::{engine, Engine, EngineHandle, EngineStatus, MAX_BLOCK_SIZE};
+
+const INPUT_RING_FRAMES: usize = MAX_BLOCK_SIZE * 16;
+// How far behind the input the output callback reads, so the two callbacks don't have to line up
+// exactly. It never changes, so it's part of the round trip calibration measures through the engine
+const INPUT_FILL_FRAMES: usize = MAX_BLOCK_SIZE * 4;
+
+// The output and, when there's an input device, the input, both only stop when dropped
+pub struct CpalStreams {
+    _output: Stream,
+    _input: Option<Stream>,
+}
+
+// Carries stereo input from the input callback to the output callback, which drives the engine.
+// Positions only ever count up, samples are f32 bits
+struct InputRing {
+    samples: Vec<AtomicU32>,
+    written: AtomicUsize,
+    read: AtomicUsize,
+}
+
+impl InputRing {
+    fn new() -> Self {
+        Self {
+            samples: (0..INPUT_RING_FRAMES * 2).map(|_| AtomicU32::new(0)).collect(),
+            written: AtomicUsize::new(0),
+            read: AtomicUsize::new(0),
+        }
+    }
+
+    // Drops what doesn't fit, the output side fell behind
+    fn push(&self, frame: [f32; 2]) {
+        let written = self.written.load(Ordering::Relaxed);
+        if written - self.read.load(Ordering::Acquire) >= INPUT_RING_FRAMES {
+            return;
+        }
+        let index = written % INPUT_RING_FRAMES * 2;
+        self.samples[index].store(frame[0].to_bits(), Ordering::Relaxed);
+        self.samples[index + 1].store(frame[1].to_bits(), Ordering::Relaxed);
+        self.written.store(written + 1, Ordering::Release);
+    }
+
+    // Fills `output` with the frames `INPUT_FILL_FRAMES` behind the input, leaves it silent until
+    // there are that many. When the callbacks drift apart far enough to run out or pile up, reading
+    // jumps back to that distance instead of waiting for the ring to fill up again, which would
+    // leave input arriving later than calibration measured
+    fn pop(&self, output: [&mut [f32]; 2], primed: &mut bool) {
+        let written = self.written.load(Ordering::Acquire);
+        let mut read = self.read.load(Ordering::Relaxed);
+        let frames = output[0].len();
+        if !*primed || written - read < frames || written - read > INPUT_FILL_FRAMES * 2 {
+            if written < INPUT_FILL_FRAMES {
+                return;
+            }
+            *primed = true;
+            read = written - INPUT_FILL_FRAMES;
+        }
+        for (channel, output) in output.into_iter().enumerate() {
+            for (offset, sample) in output.iter_mut().enumerate() {
+                let index = (read + offset) % INPUT_RING_FRAMES * 2 + channel;
+                *sample = f32::from_bits(self.samples[index].load(Ordering::Relaxed));
+            }
+        }
+        self.read.store(read + frames, Ordering::Release);
+    }
+}
+
+pub fn start() -> Result<(CpalStreams, EngineHandle, DeviceConfig), StreamError> {
+    let host = cpal::default_host();
+    let device = host.default_output_device().ok_or(StreamError::NoDevice)?;
+    let supported_config = device
+        .default_output_config()
+        .map_err(|err| StreamError::Backend(err.to_string()))?;
+    let sample_format = supported_config.sample_format();
+    let config: StreamConfig = supported_config.into();
+    let (engine, handle) = engine(config.sample_rate.0);
+    let status = handle.status.clone();
+    let ring = Arc::new(InputRing::new());
+    let input = start_input(&host, config.sample_rate, &ring)
+        .map_err(|err| eprintln!("Failed to open the audio input, recording will be silent: {err}"))
+        .ok()
+        .flatten();
+    // Without an input the engine only ever gets silence
+    let ring = input.is_some().then_some(ring);
+    // The metronome can have outputs 3 and 4 to itself
+    status
+        .separate_click_output
+        .store(config.channels >= 4, Ordering::Relaxed);
+    let stream = match sample_format {
+        SampleFormat::F64 => build_stream::<f64>(&device, &config, engine, ring, status),
+        SampleFormat::I64 => build_stream::<i64>(&device, &config, engine, ring, status),
+        SampleFormat::U64 => build_stream::<u64>(&device, &config, engine, ring, status),
+        SampleFormat::F32 => build_stream::<f32>(&device, &config, engine, ring, status),
+        SampleFormat::I32 => build_stream::<i32>(&device, &config, engine, ring, status),
+        SampleFormat::U32 => build_stream::<u32>(&device, &config, engine, ring, status),
+        SampleFormat::I16 => build_stream::<i16>(&device, &config, engine, ring, status),
+        SampleFormat::U16 => build_stream::<u16>(&device, &config, engine, ring, status),
+        SampleFormat::I8 => build_stream::<i8>(&device, &config, engine, ring, status),
+        SampleFormat::U8 => build_stream::<u8>(&device, &config, engine, ring, status),
+        sample_format => return Err(StreamError::UnsupportedFormat(sample_format.to_string())),
+    }
+    .map_err(|err| StreamError::Backend(err.to_string()))?;
+    stream
+        .play()
+        .map_err(|err| StreamError::Backend(err.to_string()))?;
+    let streams = CpalStreams {
+        _output: stream,
+        _input: input,
+    };
+    Ok((streams, handle, device_config(&device, &config)))
+}
+
+// Opens the default input at the output's sample rate, `None` when there's no input device
+fn start_input(
+    host: &cpal::Host,
+    sample_rate: SampleRate,
+    ring: &Arc<InputRing>,
+) -> Result<Option<Stream>, StreamError> {
+    let Some(device) = host.default_input_device() else {
+        return Ok(None);
+    };
+    let supported_config = device
+        .default_input_config()
+        .ok()
+        .filter(|config| config.sample_rate() == sample_rate)
+        .or_else(|| {
+            device.supported_input_configs().ok()?.find_map(|config| {
+                (config.min_sample_rate() <= sample_rate && config.max_sample_rate() >= sample_rate)
+                    .then(|| config.with_sample_rate(sample_rate))
+            })
+        })
+        .ok_or_else(|| StreamError::UnsupportedFormat(format!("{} Hz input", sample_rate.0)))?;
+    let sample_format = supported_config.sample_format();
+    let config: StreamConfig = supported_config.into();
+    let ring = ring.clone();
+    let stream = match sample_format {
+        SampleFormat::F64 => build_input_stream::<f64>(&device, &config, ring),
+        SampleFormat::I64 => build_input_stream::<i64>(&device, &config, ring),
+        SampleFormat::U64 => build_input_stream::<u64>(&device, &config, ring),
+        SampleFormat::F32 => build_input_stream::<f32>(&device, &config, ring),
+        SampleFormat::I32 => build_input_stream::<i32>(&device, &config, ring),
+        SampleFormat::U32 => build_input_stream::<u32>(&device, &config, ring),
+        SampleFormat::I16 => build_input_stream::<i16>(&device, &config, ring),
+        SampleFormat::U16 => build_input_stream::<u16>(&device, &config, ring),
+        SampleFormat::I8 => build_input_stream::<i8>(&device, &config, ring),
+        SampleFormat::U8 => build_input_stream::<u8>(&device, &config, ring),
+        sample_format => return Err(StreamError::UnsupportedFormat(sample_format.to_string())),
+    }
+    .map_err(|err| StreamError::Backend(err.to_string()))?;
+    stream
+        .play()
+        .map_err(|err| StreamError::Backend(err.to_string()))?;
+    Ok(Some(stream))
+}
+
+fn build_input_stream<T: SizedSample>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    ring: Arc<InputRing>,
+) -> Result<Stream, cpal::BuildStreamError>
+where
+    f32: FromSample<T>,
+{
+    let channels = usize::from(config.channels);
+    device.build_input_stream(
+        config,
+        move |data: &[T], _: &cpal::InputCallbackInfo| {
+            for frame in data.chunks(channels) {
+                let left = frame.first().map_or(0., |sample| sample.to_sample::<f32>());
+                // Mono inputs go to both sides
+                let right = frame.get(1).map_or(left, |sample| sample.to_sample::<f32>());
+                ring.push([left, right]);
+            }
+        },
+        |err| eprintln!("an error occurred on the input audio stream: {err}"),
+        None,
+    )
+}
+
+fn device_config(device: &cpal::Device, config: &StreamConfig) -> DeviceConfig {
+    DeviceConfig {
+        backend: Backend::Cpal.to_string(),
+        device: device.name().unwrap_or_default(),
+        sample_rate: config.sample_rate.0,
+        buffer_size: match config.buffer_size {
+            BufferSize::Fixed(size) => Some(size),
+            BufferSize::Default => None,
+        },
+    }
+}
+
+fn build_stream<T: SizedSample + FromSample<f32>>(
+    device: &cpal::Device,
+    config: &StreamConfig,
+    mut engine: Engine,
+    input: Option<Arc<InputRing>>,
+    status: Arc<EngineStatus>,
+) -> Result<Stream, cpal::BuildStreamError> {
+    let channels = usize::from(config.channels);
+    let mut primed = false;
+    device.build_output_stream(
+        config,
+        move |data: &mut [T], _: &cpal::OutputCallbackInfo| {
+            for chunk in data.chunks_mut(MAX_BLOCK_SIZE * channels) {
+                let frames = chunk.len() / channels;
+                if let Some(input) = &input {
+                    input.pop(engine.input(frames), &mut primed);
+                }
+                engine.process(frames);
+                let [left, right] = engine.master(frames);
+                let click = engine.click(frames);
+                for (index, frame) in chunk.chunks_mut(channels).enumerate() {
+                    let (left, right) = (left[index], right[index]);
+                    if let [mono] = frame {
+                        *mono = T::from_sample((left + right) * 0.5);
+                        continue;
+                    }
+                    for (channel, sample) in frame.iter_mut().enumerate() {
+                        *sample = T::from_sample(match (channel, click) {
+                            (0, _) => left,
+                            (1, _) => right,
+                            (2 | 3, Some(click)) => click[channel - 2][index],
+                            _ => 0.,
+                        });
+                    }
+                }
+            }
+        },
+        move |err| {
+            eprintln!("an error occurred on the output audio stream: {err}");
+            status.stream_errors.fetch_add(1, Ordering::Relaxed);
+        },
+        None,
+    )
+}
@@ -0,0 +1,227 @@
+use std::sync::{
+    atomic::Ordering,
+    mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+    Arc,
+};
+
+use jack::{
+    AsyncClient, AudioIn, AudioOut, Client, ClientOptions, ClientStatus, Control, NotificationHandler,
+    Port, ProcessHandler, ProcessScope, TransportState,
+};
+
+use super::{Backend, DeviceConfig, StreamError};
+use crate::blerp::processing::live::{engine, Engine, EngineHandle, EngineStatus, MAX_BLOCK_SIZE};
+
+type StereoPorts = [Port<AudioOut>; 2];
+
+// Sets of track ports the process thread swapped out that haven't been unregistered yet, they get
+// unregistered before new ones are sent
+const RETIRED_PORT_SETS: usize = 8;
+
+pub struct JackStream {
+    client: AsyncClient<Notifications, Process>,
+    track_ports: Sender<Vec<StereoPorts>>,
+    retired_ports: Receiver<Vec<StereoPorts>>,
+}
+
+struct Notifications {
+    status: Arc<EngineStatus>,
+}
+
+struct Process {
+    engine: Engine,
+    master: StereoPorts,
+    input: [Port<AudioIn>; 2],
+    click: StereoPorts,
+    tracks: Vec<StereoPorts>,
+    track_ports: Receiver<Vec<StereoPorts>>,
+    retired_ports: SyncSender<Vec<StereoPorts>>,
+}
+
+fn backend_error(err: jack::Error) -> StreamError {
+    StreamError::Backend(err.to_string())
+}
+
+fn register_stereo(client: &Client, name: &str) -> Result<StereoPorts, jack::Error> {
+    Ok([
+        client.register_port(&format!("{name} L"), AudioOut)?,
+        client.register_port(&format!("{name} R"), AudioOut)?,
+    ])
+}
+
+pub fn start() -> Result<(JackStream, EngineHandle, DeviceConfig), StreamError> {
+    // Don't spin up a server behind the user's back, PipeWire provides one anyway
+    let (client, _) =
+        Client::new("Volt", ClientOptions::NO_START_SERVER).map_err(backend_error)?;
+    #[allow(clippy::cast_possible_truncation)]
+    let (engine, handle) = engine(client.sample_rate() as u32);
+    let config = DeviceConfig {
+        backend: Backend::Jack.to_string(),
+        device: "JACK server".to_string(),
+        sample_rate: engine.sample_rate(),
+        buffer_size: Some(client.buffer_size()),
+    };
+    let master = register_stereo(&client, "Master").map_err(backend_error)?;
+    let click = register_stereo(&client, "Click").map_err(backend_error)?;
+    let input = [
+        client.register_port("In L", AudioIn).map_err(backend_error)?,
+        client.register_port("In R", AudioIn).map_err(backend_error)?,
+    ];
+    handle.status.separate_click_output.store(true, Ordering::Relaxed);
+    let (track_sender, track_receiver) = channel();
+    let (retired_sender, retired_receiver) = sync_channel(RETIRED_PORT_SETS);
+    let client = client
+        .activate_async(
+            Notifications {
+                status: handle.status.clone(),
+            },
+            Process {
+                engine,
+                master,
+                input,
+                click,
+                tracks: Vec::new(),
+                track_ports: track_receiver,
+                retired_ports: retired_sender,
+            },
+        )
+        .map_err(backend_error)?;
+
+    // Hook the master up to the speakers, the user can reroute it later
+    let name = client.as_client().name().to_string();
+    for (port, playback) in ["Master L", "Master R"]
+        .iter()
+        .zip(["system:playback_1", "system:playback_2"])
+    {
+        let _ = client
+            .as_client()
+            .connect_ports_by_name(&format!("{name}:{port}"), playback);
+    }
+    // And the first inputs up to what gets recorded
+    for (capture, port) in ["system:capture_1", "system:capture_2"]
+        .iter()
+        .zip(["In L", "In R"])
+    {
+        let _ = client
+            .as_client()
+            .connect_ports_by_name(capture, &format!("{name}:{port}"));
+    }
+
+    Ok((
+        JackStream {
+            client,
+            track_ports: track_sender,
+            retired_ports: retired_receiver,
+        },
+        handle,
+        config,
+    ))
+}
+
+impl JackStream {
+    pub fn set_track_ports(&mut self, names: &[String]) {
+        // Ports the process thread has let go of can be unregistered now
+        for ports in self.retired_ports.try_iter().flatten() {
+            for port in ports {
+                let _ = self.client.as_client().unregister_port(port);
+            }
+        }
+        let ports = names
+            .iter()
+            .filter_map(|name| register_stereo(self.client.as_client(), name).ok())
+            .collect();
+        let _ = self.track_ports.send(ports);
+    }
+}
+
+impl NotificationHandler for Notifications {
+    fn shutdown(&mut self, _: ClientStatus, reason: &str) {
+        eprintln!("the JACK server shut down: {reason}");
+        self.status.stream_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn xrun(&mut self, _: &Client) -> Control {
+        self.status.xruns.fetch_add(1, Ordering::Relaxed);
+        Control::Continue
+    }
+}
+
+impl ProcessHandler for Process {
+    fn process(&mut self, client: &Client, process_scope: &ProcessScope) -> Control {
+        if let Ok(tracks) = self.track_ports.try_recv() {
+            let retired = std::mem::replace(&mut self.tracks, tracks);
+            // Bounded, so sending never allocates. Should it be full these stay registered until the
+            // client closes
+            let _ = self.retired_ports.try_send(retired);
+        }
+        let transport = client.transport();
+        if let Ok(state) = transport.query() {
+            self.engine.follow_transport(
+                state.state == TransportState::Rolling,
+                u64::from(state.pos.frame()),
+            );
+        }
+
+        let frames = process_scope.n_frames() as usize;
+        let [master_left, master_right] = &mut self.master;
+        let mut master = [
+            master_left.as_mut_slice(process_scope),
+            master_right.as_mut_slice(process_scope),
+        ];
+        let [click_left, click_right] = &mut self.click;
+        let mut click = [
+            click_left.as_mut_slice(process_scope),
+            click_right.as_mut_slice(process_scope),
+        ];
+        let input = [
+            self.input[0].as_slice(process_scope),
+            self.input[1].as_slice(process_scope),
+        ];
+        let mut offset = 0;
+        while offset < frames {
+            let block = (frames - offset).min(MAX_BLOCK_SIZE);
+            for (buffer, port) in self.engine.input(block).into_iter().zip(input) {
+                buffer.copy_from_slice(&port[offset..offset + block]);
+            }
+            self.engine.process(block);
+            for (port, rendered) in master.iter_mut().zip(self.engine.master(block)) {
+                port[offset..offset + block].copy_from_slice(rendered);
+            }
+            let rendered = self.engine.click(block);
+            for (channel, port) in click.iter_mut().enumerate() {
+                let port = &mut port[offset..offset + block];
+                match rendered {
+                    Some(rendered) => port.copy_from_slice(rendered[channel]),
+                    None => port.fill(0.),
+                }
+            }
+            for (index, ports) in self.tracks.iter_mut().enumerate() {
+                let rendered = self.engine.track(index, block);
+                for (channel, port) in ports.iter_mut().enumerate() {
+                    let port = &mut port.as_mut_slice(process_scope)[offset..offset + block];
+                    match rendered {
+                        Some(rendered) => port.copy_from_slice(rendered[channel]),
+                        None => port.fill(0.),
+                    }
+                }
+            }
+            offset += block;
+        }
+
+        // Whatever comes of these shows up in the next cycles' query, which the engine follows
+        let requests = self.engine.take_transport_requests();
+        if let Some(frame) = requests.locate {
+            let _ = transport.locate(u32::try_from(frame).unwrap_or(u32::MAX));
+        }
+        match requests.rolling {
+            Some(true) => {
+                let _ = transport.start();
+            }
+            Some(false) => {
+                let _ = transport.stop();
+            }
+            None => {}
+        }
+        Control::Continue
+    }
+}
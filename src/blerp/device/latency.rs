@@ -0,0 +1,229 @@
+use std::{
+    io,
+    iter::repeat,
+    sync::Arc,
+    thread::{self, JoinHandle},
+    time::{Duration, Instant},
+};
+
+use rustfft::{num_complex::Complex, FftPlanner};
+use serde::{Deserialize, Serialize};
+
+use super::{DeviceConfig, Output, StreamError};
+use crate::{
+    blerp::processing::live::{capture::Calibration, EngineStatus},
+    config,
+};
+
+const PROFILES_FILE: &str = "latency.ron";
+// Silence played before the test signal, gives the input stream time to settle
+const PRE_ROLL: Duration = Duration::from_millis(250);
+// How long to keep listening after the test signal ended
+const TAIL: Duration = Duration::from_secs(1);
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LatencyMeasurement {
+    pub config: DeviceConfig,
+    // Frames between the engine rendering a sample and the same sample arriving at the input
+    pub round_trip: u32,
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct LatencyProfiles {
+    pub measurements: Vec<LatencyMeasurement>,
+}
+
+impl LatencyProfiles {
+    pub fn load() -> Self {
+        config::load(PROFILES_FILE)
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        config::save(PROFILES_FILE, self)
+    }
+
+    pub fn round_trip(&self, config: &DeviceConfig) -> Option<u32> {
+        self.measurements
+            .iter()
+            .find(|measurement| measurement.config == *config)
+            .map(|measurement| measurement.round_trip)
+    }
+
+    pub fn insert(&mut self, measurement: LatencyMeasurement) {
+        self.measurements
+            .retain(|existing| existing.config != measurement.config);
+        self.measurements.push(measurement);
+    }
+}
+
+// Maximum length sequence of order 15 (x^15 + x^14 + 1), flat spectrum and a single sharp autocorrelation peak
+pub fn mls() -> Vec<f32> {
+    let mut state: u16 = 1;
+    (0..(1 << 15) - 1)
+        .map(|_| {
+            let bit = (state ^ (state >> 1)) & 1;
+            state = (state >> 1) | (bit << 14);
+            if state & 1 == 1 {
+                0.5
+            } else {
+                -0.5
+            }
+        })
+        .collect()
+}
+
+// Finds where `reference` shows up in `captured` by cross-correlating the two,
+// `None` if nothing stands out (muted input, no loopback...)
+pub fn find_delay(reference: &[f32], captured: &[f32]) -> Option<usize> {
+    if reference.is_empty() || captured.len() < reference.len() {
+        return None;
+    }
+    let len = (reference.len() + captured.len()).next_power_of_two();
+    let mut planner = FftPlanner::<f32>::new();
+    let forward = planner.plan_fft_forward(len);
+    let inverse = planner.plan_fft_inverse(len);
+    let spectrum = |samples: &[f32]| {
+        let mut buffer: Vec<_> = samples
+            .iter()
+            .map(|sample| Complex::new(*sample, 0.))
+            .chain(repeat(Complex::new(0., 0.)))
+            .take(len)
+            .collect();
+        forward.process(&mut buffer);
+        buffer
+    };
+    let mut correlation = spectrum(captured);
+    for (captured, reference) in correlation.iter_mut().zip(spectrum(reference)) {
+        *captured *= reference.conj();
+    }
+    inverse.process(&mut correlation);
+
+    // Only lags where the whole reference fits into the capture
+    let lags: Vec<f32> = correlation[..=captured.len() - reference.len()]
+        .iter()
+        .map(|value| value.re.abs())
+        .collect();
+    let (lag, peak) = lags
+        .iter()
+        .enumerate()
+        .max_by(|(_, a), (_, b)| a.total_cmp(b))?;
+    #[allow(clippy::cast_precision_loss)]
+    let mean = lags.iter().sum::<f32>() / lags.len() as f32;
+    (*peak > mean * 20.).then_some(lag)
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+fn frames(duration: Duration, sample_rate: u32) -> usize {
+    (duration.as_secs_f64() * f64::from(sample_rate)) as usize
+}
+
+// Plays an MLS out of the running engine's master and records it back through its input, the two
+// have to be connected (loopback cable or speakers and a microphone). Goes through whatever backend
+// and device are in use, so that's what gets measured
+pub struct CalibrationRun {
+    config: DeviceConfig,
+    engine: Arc<EngineStatus>,
+    pre_roll: usize,
+    started: Instant,
+    measuring: Option<JoinHandle<Option<usize>>>,
+}
+
+impl CalibrationRun {
+    pub fn start(output: &Output) -> Self {
+        let sample_rate = output.config.sample_rate;
+        let pre_roll = frames(PRE_ROLL, sample_rate);
+        let mut signal = vec![0.; pre_roll];
+        signal.extend(mls());
+        let length = signal.len() + frames(TAIL, sample_rate);
+        // Left over from a run that timed out
+        while output.engine.capture.calibrated().is_some() {}
+        output.engine.capture.calibrate(Box::new(Calibration {
+            signal,
+            captured: Vec::with_capacity(length),
+            length,
+        }));
+        Self {
+            config: output.config.clone(),
+            engine: output.engine.status.clone(),
+            pre_roll,
+            started: Instant::now(),
+            measuring: None,
+        }
+    }
+
+    // Called every frame, `None` while it's still running
+    pub fn poll(&mut self, output: Option<&Output>) -> Option<Result<LatencyMeasurement, StreamError>> {
+        if let Some(measuring) = self.measuring.take_if(|measuring| measuring.is_finished()) {
+            let found = measuring
+                .join()
+                .map_err(|_| StreamError::Backend("the measurement crashed".to_string()))
+                .and_then(|found| {
+                    found.ok_or_else(|| {
+                        StreamError::Backend("the test signal wasn't picked up by the input".to_string())
+                    })
+                });
+            return Some(found.map(|found| LatencyMeasurement {
+                config: self.config.clone(),
+                round_trip: u32::try_from(found).unwrap_or(u32::MAX),
+            }));
+        }
+        if self.measuring.is_some() {
+            return None;
+        }
+        let Some(output) = output.filter(|output| Arc::ptr_eq(&output.engine.status, &self.engine)) else {
+            return Some(Err(StreamError::Backend("the audio backend stopped".to_string())));
+        };
+        if let Some(calibration) = output.engine.capture.calibrated() {
+            let pre_roll = self.pre_roll;
+            // Correlating takes a moment, the signal starts after the pre-roll so its lag is the round trip
+            self.measuring = Some(thread::spawn(move || {
+                find_delay(&calibration.signal[pre_roll..], &calibration.captured)
+            }));
+            return None;
+        }
+        (self.started.elapsed() > TIMEOUT)
+            .then(|| Err(StreamError::Backend("timed out waiting for the engine".to_string())))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_util::Random;
+
+    // Uniform noise between -level and level
+    fn noise(random: &mut Random, frames: usize, level: f32) -> Vec<f32> {
+        (0..frames)
+            .map(|_| {
+                #[allow(clippy::cast_precision_loss)]
+                let unit = random.below(1 << 16) as f32 / 65536.;
+                (unit * 2. - 1.) * level
+            })
+            .collect()
+    }
+
+    #[test]
+    fn finds_the_sequence_through_a_delay_and_noise() {
+        let reference = mls();
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        for delay in [0, 1, 517, 12_000] {
+            // Quieter than the noise it's buried in, like a microphone across the room
+            let mut captured = noise(&mut random, delay + reference.len() + 3000, 0.3);
+            for (captured, sample) in captured[delay..].iter_mut().zip(&reference) {
+                *captured += sample * 0.2;
+            }
+            assert_eq!(find_delay(&reference, &captured), Some(delay));
+        }
+    }
+
+    #[test]
+    fn rejects_silence_and_unrelated_input() {
+        let reference = mls();
+        assert_eq!(find_delay(&reference, &vec![0.; reference.len() * 2]), None);
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        assert_eq!(find_delay(&reference, &noise(&mut random, reference.len() * 2, 0.5)), None);
+        // Too short to hold the whole sequence
+        assert_eq!(find_delay(&reference, &reference[1..]), None);
+    }
+}
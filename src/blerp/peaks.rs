@@ -0,0 +1,374 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::{self, File},
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+    time::UNIX_EPOCH,
+};
+
+use super::audiofile::{self, AudioBuffer};
+
+const MAGIC: &[u8; 4] = b"VPK1";
+// Frames per peak of the finest level, every level after it is this many times coarser
+const BASE_RESOLUTION: usize = 256;
+const LEVEL_FACTOR: usize = 4;
+const LEVELS: usize = 5;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Peak {
+    pub min: f32,
+    pub max: f32,
+    pub rms: f32,
+}
+
+impl Peak {
+    // Combines neighbouring peaks, `weight` being how many frames each one covers
+    fn merge(peaks: impl Iterator<Item = (Self, usize)>) -> Self {
+        let (mut min, mut max, mut power, mut frames) = (0_f32, 0_f32, 0_f64, 0);
+        for (peak, weight) in peaks {
+            min = min.min(peak.min);
+            max = max.max(peak.max);
+            #[allow(clippy::cast_precision_loss)]
+            {
+                power += f64::from(peak.rms).powi(2) * weight as f64;
+            }
+            frames += weight;
+        }
+        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+        let rms = if frames > 0 {
+            (power / frames as f64).sqrt() as f32
+        } else {
+            0.
+        };
+        Self { min, max, rms }
+    }
+}
+
+// One level of detail, `peaks[channel][index]` covers `frames_per_peak` frames
+#[derive(Debug, Clone)]
+pub struct PeakLevel {
+    pub frames_per_peak: usize,
+    pub peaks: Vec<Vec<Peak>>,
+}
+
+// Min/max/RMS overview of a whole file at a few resolutions, for drawing waveforms without
+// touching the samples
+#[derive(Debug, Clone)]
+pub struct Peaks {
+    pub sample_rate: u32,
+    pub channels: u16,
+    pub frames: u64,
+    pub levels: Vec<PeakLevel>,
+}
+
+impl Peaks {
+    pub fn compute(buffer: &AudioBuffer) -> Self {
+        let channels = usize::from(buffer.channels.max(1));
+        let finest: Vec<Vec<Peak>> = (0..channels)
+            .map(|channel| {
+                buffer
+                    .samples
+                    .chunks(BASE_RESOLUTION * channels)
+                    .map(|chunk| {
+                        let (mut min, mut max, mut power, mut frames) = (0_f32, 0_f32, 0_f64, 0);
+                        for sample in chunk.iter().skip(channel).step_by(channels) {
+                            min = min.min(*sample);
+                            max = max.max(*sample);
+                            power += f64::from(*sample).powi(2);
+                            frames += 1;
+                        }
+                        #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+                        let rms = (power / f64::from(frames.max(1))).sqrt() as f32;
+                        Peak { min, max, rms }
+                    })
+                    .collect()
+            })
+            .collect();
+
+        let mut levels = vec![PeakLevel {
+            frames_per_peak: BASE_RESOLUTION,
+            peaks: finest,
+        }];
+        for _ in 1..LEVELS {
+            let previous = &levels[levels.len() - 1];
+            let peaks = previous
+                .peaks
+                .iter()
+                .map(|channel| {
+                    channel
+                        .chunks(LEVEL_FACTOR)
+                        .map(|chunk| {
+                            Peak::merge(chunk.iter().map(|peak| (*peak, previous.frames_per_peak)))
+                        })
+                        .collect()
+                })
+                .collect();
+            levels.push(PeakLevel {
+                frames_per_peak: previous.frames_per_peak * LEVEL_FACTOR,
+                peaks,
+            });
+        }
+        Self {
+            sample_rate: buffer.sample_rate,
+            channels: buffer.channels.max(1),
+            frames: buffer.frames() as u64,
+            levels,
+        }
+    }
+
+    // The coarsest level that still has at least one peak per pixel
+    pub fn level_for(&self, frames_per_pixel: f64) -> &PeakLevel {
+        #[allow(clippy::cast_precision_loss)]
+        self.levels
+            .iter()
+            .rev()
+            .find(|level| level.frames_per_peak as f64 <= frames_per_pixel)
+            .unwrap_or(&self.levels[0])
+    }
+
+    // `count` evenly sized buckets covering `frames`, for drawing one per pixel column.
+    // `None` mixes all channels together
+    pub fn buckets(&self, channel: Option<usize>, frames: Range<u64>, count: usize) -> Vec<Peak> {
+        if count == 0 || frames.is_empty() {
+            return Vec::new();
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let frames_per_bucket = (frames.end - frames.start) as f64 / count as f64;
+        let level = self.level_for(frames_per_bucket);
+        let channels = match channel {
+            Some(channel) => channel..channel + 1,
+            None => 0..level.peaks.len(),
+        };
+        (0..count)
+            .map(|bucket| {
+                #[allow(
+                    clippy::cast_possible_truncation,
+                    clippy::cast_sign_loss,
+                    clippy::cast_precision_loss
+                )]
+                let (start, end) = (
+                    ((frames.start as f64 + bucket as f64 * frames_per_bucket) as usize)
+                        / level.frames_per_peak,
+                    ((frames.start as f64 + (bucket + 1) as f64 * frames_per_bucket).ceil()
+                        as usize)
+                        .div_ceil(level.frames_per_peak),
+                );
+                Peak::merge(channels.clone().filter_map(|channel| level.peaks.get(channel)).flat_map(
+                    |peaks| {
+                        let end = end.max(start + 1).min(peaks.len());
+                        peaks[start.min(end)..end]
+                            .iter()
+                            .map(|peak| (*peak, level.frames_per_peak))
+                    },
+                ))
+            })
+            .collect()
+    }
+}
+
+// Path, size and modification time, anything else means the cached peaks are stale
+fn cache_key(path: &Path) -> Option<String> {
+    let metadata = fs::metadata(path).ok()?;
+    let modified = metadata
+        .modified()
+        .ok()
+        .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+        .map_or(0, |modified| modified.as_nanos());
+    Some(format!("{}\0{}\0{modified}", path.display(), metadata.len()))
+}
+
+// FNV-1a, unlike the std hasher it stays the same between builds
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+fn cache_path(key: &str) -> Option<PathBuf> {
+    dirs::cache_dir().map(|dir| {
+        dir.join("volt")
+            .join("peaks")
+            .join(format!("{:016x}.peaks", fnv1a(key.as_bytes())))
+    })
+}
+
+fn read_u32(reader: &mut impl Read) -> io::Result<u32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_f32(reader: &mut impl Read) -> io::Result<f32> {
+    let mut bytes = [0; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(f32::from_le_bytes(bytes))
+}
+
+fn invalid(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn read(path: &Path, key: &str) -> io::Result<Peaks> {
+    let mut reader = BufReader::new(File::open(path)?);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != *MAGIC {
+        return Err(invalid("not a peak file"));
+    }
+    let mut stored_key = vec![0; read_u32(&mut reader)? as usize];
+    reader.read_exact(&mut stored_key)?;
+    // Two files can share a hash, the key itself has to match too
+    if stored_key != key.as_bytes() {
+        return Err(invalid("peak file belongs to another file"));
+    }
+    let sample_rate = read_u32(&mut reader)?;
+    let channels = u16::try_from(read_u32(&mut reader)?).map_err(|_| invalid("too many channels"))?;
+    let frames = read_u64(&mut reader)?;
+    let level_count = read_u32(&mut reader)?;
+    let mut levels = Vec::new();
+    for _ in 0..level_count {
+        let frames_per_peak = read_u32(&mut reader)? as usize;
+        let count = usize::try_from(read_u64(&mut reader)?).map_err(|_| invalid("too many peaks"))?;
+        let mut peaks = Vec::new();
+        for _ in 0..channels {
+            let mut channel = Vec::with_capacity(count);
+            for _ in 0..count {
+                channel.push(Peak {
+                    min: read_f32(&mut reader)?,
+                    max: read_f32(&mut reader)?,
+                    rms: read_f32(&mut reader)?,
+                });
+            }
+            peaks.push(channel);
+        }
+        levels.push(PeakLevel {
+            frames_per_peak,
+            peaks,
+        });
+    }
+    if levels.is_empty() {
+        return Err(invalid("peak file has no levels"));
+    }
+    Ok(Peaks {
+        sample_rate,
+        channels,
+        frames,
+        levels,
+    })
+}
+
+fn write(path: &Path, key: &str, peaks: &Peaks) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    let too_big = |_| invalid("peaks too big for the file format");
+    // Written next to the final file first, so a half written file never gets read
+    let temporary = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&temporary)?);
+    writer.write_all(MAGIC)?;
+    writer.write_all(&u32::try_from(key.len()).map_err(too_big)?.to_le_bytes())?;
+    writer.write_all(key.as_bytes())?;
+    writer.write_all(&peaks.sample_rate.to_le_bytes())?;
+    writer.write_all(&u32::from(peaks.channels).to_le_bytes())?;
+    writer.write_all(&peaks.frames.to_le_bytes())?;
+    writer.write_all(&u32::try_from(peaks.levels.len()).map_err(too_big)?.to_le_bytes())?;
+    for level in &peaks.levels {
+        writer.write_all(&u32::try_from(level.frames_per_peak).map_err(too_big)?.to_le_bytes())?;
+        let count = level.peaks.first().map_or(0, Vec::len);
+        writer.write_all(&(count as u64).to_le_bytes())?;
+        for channel in &level.peaks {
+            for peak in channel {
+                writer.write_all(&peak.min.to_le_bytes())?;
+                writer.write_all(&peak.max.to_le_bytes())?;
+                writer.write_all(&peak.rms.to_le_bytes())?;
+            }
+        }
+    }
+    writer.into_inner().map_err(io::IntoInnerError::into_error)?.sync_all()?;
+    fs::rename(temporary, path)
+}
+
+// Peaks from the disk cache if they're still current, otherwise computed from `buffer` and cached
+pub fn load_or_compute(path: &Path, buffer: &AudioBuffer) -> Peaks {
+    let key = cache_key(path);
+    let cached = key.as_ref().and_then(|key| Some((cache_path(key)?, key)));
+    if let Some(peaks) = cached
+        .as_ref()
+        .and_then(|(cache, key)| read(cache, key).ok())
+    {
+        return peaks;
+    }
+    let peaks = Peaks::compute(buffer);
+    if let Some((cache, key)) = cached {
+        if let Err(err) = write(&cache, key, &peaks) {
+            eprintln!("Failed to cache the waveform of {}: {err}", path.display());
+        }
+    }
+    peaks
+}
+
+fn load(path: &Path) -> Option<Peaks> {
+    let key = cache_key(path)?;
+    if let Some(peaks) = cache_path(&key).and_then(|cache| read(&cache, &key).ok()) {
+        return Some(peaks);
+    }
+    let buffer = audiofile::decode(path).ok()?;
+    Some(load_or_compute(path, &buffer))
+}
+
+// Hands out peaks for any file, generating the missing ones on a background thread
+pub struct PeakCache {
+    requests: Sender<PathBuf>,
+    results: Receiver<(PathBuf, Option<Peaks>)>,
+    peaks: HashMap<PathBuf, Arc<Peaks>>,
+    requested: HashSet<PathBuf>,
+}
+
+impl PeakCache {
+    pub fn new() -> Self {
+        let (requests, request_receiver) = channel::<PathBuf>();
+        let (result_sender, results) = channel();
+        thread::spawn(move || {
+            while let Ok(path) = request_receiver.recv() {
+                let peaks = load(&path);
+                if result_sender.send((path, peaks)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            results,
+            peaks: HashMap::new(),
+            requested: HashSet::new(),
+        }
+    }
+
+    // `None` until the peaks are ready, or forever if the file can't be decoded
+    pub fn get(&mut self, path: &Path) -> Option<Arc<Peaks>> {
+        for (path, peaks) in self.results.try_iter() {
+            if let Some(peaks) = peaks {
+                self.peaks.insert(path, Arc::new(peaks));
+            }
+        }
+        if let Some(peaks) = self.peaks.get(path) {
+            return Some(peaks.clone());
+        }
+        if self.requested.insert(path.to_path_buf()) {
+            let _ = self.requests.send(path.to_path_buf());
+        }
+        None
+    }
+}
@@ -1,5 +1,11 @@
+use std::ops::RangeInclusive;
+
+use serde::{Deserialize, Serialize};
+use strum::Display;
+
 pub mod export;
 pub mod generation;
+pub mod limiter;
 pub mod live;
 
 pub fn effect_clipper(threshold: f64, sample: f64) -> f64 {
@@ -15,3 +21,51 @@ pub fn effect_clipper(threshold: f64, sample: f64) -> f64 {
 pub fn effect_volume(volume: f64, sample: f64) -> f64 {
     sample * volume
 }
+
+// What can go in a mixer channel's inserts. Effects work one sample at a time, the ones that need
+// more than that (looking ahead) keep their state in the graph and report how late they make the
+// signal, which the graph makes up for on every other path
+#[derive(Display, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum Effect {
+    Clipper { threshold: f64 },
+    Volume { volume: f64 },
+    Limiter { ceiling: f64 },
+}
+
+// Every effect, as it gets added
+pub const EFFECTS: [Effect; 3] = [
+    Effect::Clipper { threshold: 0.8 },
+    Effect::Volume { volume: 1. },
+    Effect::Limiter { ceiling: 0.9 },
+];
+
+impl Effect {
+    // The limiter's gain has already been applied, this only catches what it missed
+    pub fn process(self, sample: f32) -> f32 {
+        let sample = f64::from(sample);
+        #[allow(clippy::cast_possible_truncation)]
+        let processed = match self {
+            Self::Clipper { threshold } => effect_clipper(threshold, sample),
+            Self::Volume { volume } => effect_volume(volume, sample),
+            Self::Limiter { ceiling } => effect_clipper(ceiling, sample),
+        } as f32;
+        processed
+    }
+
+    // Frames between a sample going in and coming out
+    pub fn latency(self, sample_rate: u32) -> usize {
+        match self {
+            Self::Clipper { .. } | Self::Volume { .. } => 0,
+            Self::Limiter { .. } => limiter::lookahead(sample_rate),
+        }
+    }
+
+    // Its one setting, along with what's sensible for it
+    pub fn parameter(&mut self) -> (&mut f64, RangeInclusive<f64>) {
+        match self {
+            Self::Clipper { threshold } => (threshold, 0.01..=1.),
+            Self::Volume { volume } => (volume, 0.0..=4.),
+            Self::Limiter { ceiling } => (ceiling, 0.01..=1.),
+        }
+    }
+}
@@ -1 +1,28 @@
+use std::f32::consts::TAU;
 
+// Quiet enough to stop at, relative to where a tone starts
+const SILENCE: f32 = 0.001;
+// Ramped into, starting a tone at full level makes it pop
+const ATTACK_SECONDS: f32 = 0.0005;
+
+// A few sine partials dying away exponentially, `decay` per second. Interleaved stereo, the same
+// on both sides
+pub fn tone(partials: &[(f32, f32)], decay: f32, sample_rate: u32) -> Vec<f32> {
+    #[allow(clippy::cast_precision_loss)]
+    let rate = sample_rate as f32;
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let frames = (-SILENCE.ln() / decay * rate).ceil() as usize;
+    (0..frames)
+        .flat_map(|frame| {
+            #[allow(clippy::cast_precision_loss)]
+            let time = frame as f32 / rate;
+            let envelope = (-decay * time).exp() * (time / ATTACK_SECONDS).min(1.);
+            let sample: f32 = partials
+                .iter()
+                .map(|(frequency, level)| (TAU * frequency * time).sin() * level)
+                .sum::<f32>()
+                * envelope;
+            [sample, sample]
+        })
+        .collect()
+}
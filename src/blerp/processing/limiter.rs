@@ -0,0 +1,131 @@
+// How far ahead the limiter looks, which is also how late everything comes out of it
+const LOOKAHEAD_SECONDS: f64 = 0.005;
+// Roughly how long it takes to get back to full level
+const RELEASE_SECONDS: f64 = 0.1;
+
+pub fn lookahead(sample_rate: u32) -> usize {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let frames = (LOOKAHEAD_SECONDS * f64::from(sample_rate)).round() as usize;
+    frames
+}
+
+// How much of the way back to full level the gain gets every frame
+pub fn release(sample_rate: u32) -> f32 {
+    #[allow(clippy::cast_possible_truncation)]
+    let release = (1. - (-1. / (RELEASE_SECONDS * f64::from(sample_rate))).exp()) as f32;
+    release
+}
+
+// The limiter's gain. Every sample goes in `lookahead` frames before it comes out, so the gain can
+// ramp down to what a peak needs by the time the peak comes out, and only recovers once nothing
+// that loud is on its way anymore
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Envelope {
+    pub gain: f32,
+    pub target: f32,
+    pub step: f32,
+    // Frames until the last peak that held the gain down is out
+    pub hold: u32,
+}
+
+impl Default for Envelope {
+    fn default() -> Self {
+        Self {
+            gain: 1.,
+            target: 1.,
+            step: 0.,
+            hold: 0,
+        }
+    }
+}
+
+impl Envelope {
+    // Takes the peak of the frame going in, returns the gain for the one coming out
+    pub fn next(&mut self, peak: f32, ceiling: f32, lookahead: u32, release: f32) -> f32 {
+        let required = if peak > ceiling { ceiling / peak } else { 1. };
+        if required < self.target {
+            #[allow(clippy::cast_precision_loss)]
+            let step = (self.gain - required) / lookahead.max(1) as f32;
+            // Already on the way down to an earlier peak, which has to get there in time as well
+            self.step = if self.gain > self.target { self.step.max(step) } else { step };
+            self.target = required;
+            self.hold = lookahead + 1;
+        } else if required < 1. {
+            // Quieter than what the gain is already at, but it still can't come back up over it
+            self.hold = lookahead + 1;
+        }
+        if self.gain > self.target {
+            let next = self.gain - self.step;
+            // Steps too small to change the gain at all would never get there
+            self.gain = if next < self.gain { next.max(self.target) } else { self.target };
+        } else if self.hold == 0 {
+            self.gain += (1. - self.gain) * release;
+            self.target = self.gain;
+        }
+        self.hold = self.hold.saturating_sub(1);
+        self.gain
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::*;
+
+    const LOOKAHEAD: u32 = 4;
+    const RELEASE: f32 = 0.1;
+
+    // Runs peaks through the envelope and a delay as long as the lookahead, like the limiter does,
+    // returns the gains along with the peaks they got applied to
+    fn limit(peaks: &[f32], ceiling: f32) -> Vec<(f32, f32)> {
+        let mut envelope = Envelope::default();
+        let mut delay: VecDeque<f32> = VecDeque::from(vec![0.; LOOKAHEAD as usize]);
+        peaks
+            .iter()
+            .chain(&[0.; 64])
+            .map(|peak| {
+                let gain = envelope.next(*peak, ceiling, LOOKAHEAD, RELEASE);
+                delay.push_back(*peak);
+                (gain, delay.pop_front().unwrap_or_default())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn quiet_signals_pass_untouched() {
+        assert!(limit(&[0.2, 0.9, 1., 0.5], 1.).iter().all(|(gain, _)| *gain == 1.));
+    }
+
+    #[test]
+    fn peaks_come_out_at_the_ceiling() {
+        let limited = limit(&[0., 2., 0., 0., 0., 0., 0.], 1.);
+        for (gain, peak) in &limited {
+            assert!(gain * peak <= 1. + 1e-6, "{peak} came out at {}", gain * peak);
+        }
+        // Ramps down over the lookahead, so the peak gets exactly as much as it needs
+        let (gain, peak) = limited[1 + LOOKAHEAD as usize];
+        assert_eq!(peak, 2.);
+        assert!((gain - 0.5).abs() < 1e-6);
+        assert!(limited[..=1 + LOOKAHEAD as usize].windows(2).all(|pair| pair[1].0 <= pair[0].0));
+    }
+
+    #[test]
+    fn louder_peaks_while_ramping_down_still_make_it() {
+        let limited = limit(&[2., 0., 4., 0., 3., 1.5, 0.], 1.);
+        for (gain, peak) in &limited {
+            assert!(gain * peak <= 1. + 1e-6, "{peak} came out at {}", gain * peak);
+        }
+    }
+
+    #[test]
+    fn gain_recovers_once_the_peak_is_out() {
+        let limited = limit(&[2.], 1.);
+        let lowest = LOOKAHEAD as usize;
+        assert_eq!(limited[lowest].1, 2.);
+        // Held until the peak is out, then it only goes back up
+        assert!(limited[lowest].0 <= limited[lowest - 1].0);
+        assert!(limited[lowest..].windows(2).all(|pair| pair[1].0 >= pair[0].0));
+        assert!(limited.last().is_some_and(|(gain, _)| *gain > 0.99));
+    }
+}
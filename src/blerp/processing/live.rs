@@ -1 +1,433 @@
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU32, AtomicU64, Ordering},
+        mpsc::{channel, sync_channel, Receiver, Sender, SyncSender},
+        Arc,
+    },
+};
 
+use capture::{Capture, CaptureHandle};
+use metronome::{MetronomeCommand, MetronomeVoice};
+use preview::{PreviewCommand, PreviewVoice};
+use scheduler::{Scheduler, SchedulerHandle};
+
+use crate::project::Snapshot;
+
+pub mod capture;
+pub mod delay;
+pub mod graph;
+pub mod metronome;
+pub mod preview;
+pub mod scheduler;
+
+// Backends split their callbacks into blocks of at most this many frames
+pub const MAX_BLOCK_SIZE: usize = 1024;
+
+// Sets of track buffers the audio thread swapped out that the UI hasn't freed yet. The UI frees them
+// before it sends new ones, so this only fills up if it sends several within one block
+const RETIRED_TRACK_SETS: usize = 8;
+
+pub type StereoBuffer = [Vec<f32>; 2];
+
+pub fn stereo_buffer() -> StereoBuffer {
+    [vec![0.; MAX_BLOCK_SIZE], vec![0.; MAX_BLOCK_SIZE]]
+}
+
+// State the audio thread reports back to the UI, only ever touched through atomics
+#[derive(Debug, Default)]
+pub struct EngineStatus {
+    pub sample_rate: AtomicU32,
+    pub xruns: AtomicU64,
+    pub stream_errors: AtomicU64,
+    // Blocks of input lost while recording because the UI didn't keep up
+    pub dropped_input: AtomicU64,
+    pub preview_playing: AtomicBool,
+    pub preview_position: AtomicU64,
+    pub transport_rolling: AtomicBool,
+    pub transport_frame: AtomicU64,
+    pub transport_recording: AtomicBool,
+    // Clicking the bars before recording starts
+    pub counting_in: AtomicBool,
+    // Set by backends that have somewhere other than the master to send the metronome to
+    pub separate_click_output: AtomicBool,
+    // Generation of the snapshot the engine is playing, older ones can be freed
+    pub snapshot_generation: AtomicU64,
+}
+
+impl EngineStatus {
+    // Whether the song is playing and where it is
+    pub fn playhead(&self) -> (bool, u64) {
+        (
+            self.transport_rolling.load(Ordering::Relaxed),
+            self.transport_frame.load(Ordering::Relaxed),
+        )
+    }
+}
+
+pub enum TransportCommand {
+    Play,
+    // Stops recording as well
+    Stop,
+    // Arms or disarms recording, arming it starts playing, after the count-in when stopped. The
+    // input gets recorded for as long as it's armed and playing
+    Record(bool),
+    // Moves the playhead to a frame
+    Locate(u64),
+}
+
+// What the engine wants an external transport to do, the backend passes it on after every cycle
+#[derive(Debug, Default, Clone, Copy)]
+pub struct TransportRequests {
+    // Start or stop
+    pub rolling: Option<bool>,
+    pub locate: Option<u64>,
+}
+
+// A transport outside the engine, JACK's. The engine starts, stops and moves along with it, and the
+// user's play, stop and locate go to it rather than straight to the engine so everything connected
+// to it follows too. Loops stay within the engine, the external transport keeps counting on
+#[derive(Debug, Default)]
+#[cfg_attr(not(feature = "jack"), allow(dead_code, reason = "only the JACK backend has a transport of its own"))]
+struct ExternalTransport {
+    rolling: bool,
+    frame: u64,
+    // Rendered since it was last heard from, to tell whether anyone moved it in the meantime
+    frames: u64,
+    requests: TransportRequests,
+}
+
+pub enum Command {
+    // Buffers for the per-track outputs, allocated on the UI thread. The old ones go back to it to be freed
+    SetTrackOutputs(Vec<StereoBuffer>),
+    Preview(PreviewCommand),
+    Metronome(MetronomeCommand),
+    Transport(TransportCommand),
+    // Sent through `EngineHandle::set_snapshot`, which hands it to the workers first
+    SetSnapshot(Arc<Snapshot>),
+}
+
+// Lives on the audio thread, the backends call `process` and copy the rendered buffers out
+pub struct Engine {
+    commands: Receiver<Command>,
+    status: Arc<EngineStatus>,
+    master: StereoBuffer,
+    // Backends copy their input in here before every block
+    input: StereoBuffer,
+    capture: Capture,
+    tracks: Vec<StereoBuffer>,
+    retired_tracks: SyncSender<Vec<StereoBuffer>>,
+    preview: PreviewVoice,
+    metronome: MetronomeVoice,
+    // The metronome renders in here, it only gets mixed into the master when it has no output of its own
+    click: StereoBuffer,
+    rolling: bool,
+    recording: bool,
+    frame: u64,
+    // Set once the backend reports a transport of its own
+    external: Option<ExternalTransport>,
+    snapshot: Option<Arc<Snapshot>>,
+    // Renders the tracks and buses across the worker threads
+    scheduler: Scheduler,
+}
+
+// Lives on the UI thread
+pub struct EngineHandle {
+    pub status: Arc<EngineStatus>,
+    pub capture: CaptureHandle,
+    commands: Sender<Command>,
+    retired_tracks: Receiver<Vec<StereoBuffer>>,
+    scheduler: SchedulerHandle,
+}
+
+pub fn engine(sample_rate: u32) -> (Engine, EngineHandle) {
+    engine_with_workers(sample_rate, scheduler::default_workers())
+}
+
+// Renders on the audio thread alone with no workers
+pub fn engine_with_workers(sample_rate: u32, workers: usize) -> (Engine, EngineHandle) {
+    let (sender, receiver) = channel();
+    let (retired_sender, retired_receiver) = sync_channel(RETIRED_TRACK_SETS);
+    let (scheduler, scheduler_handle) = scheduler::scheduler(workers);
+    let (capture, capture_handle) = capture::capture();
+    let status = Arc::new(EngineStatus::default());
+    status.sample_rate.store(sample_rate, Ordering::Relaxed);
+    (
+        Engine {
+            commands: receiver,
+            status: status.clone(),
+            master: stereo_buffer(),
+            input: stereo_buffer(),
+            capture,
+            tracks: Vec::new(),
+            retired_tracks: retired_sender,
+            preview: PreviewVoice::default(),
+            metronome: MetronomeVoice::default(),
+            click: stereo_buffer(),
+            rolling: false,
+            recording: false,
+            frame: 0,
+            external: None,
+            snapshot: None,
+            scheduler,
+        },
+        EngineHandle {
+            status,
+            capture: capture_handle,
+            commands: sender,
+            retired_tracks: retired_receiver,
+            scheduler: scheduler_handle,
+        },
+    )
+}
+
+impl Engine {
+    pub fn sample_rate(&self) -> u32 {
+        self.status.sample_rate.load(Ordering::Relaxed)
+    }
+
+    fn handle_command(&mut self, command: Command) {
+        match command {
+            Command::SetTrackOutputs(tracks) => {
+                let retired = std::mem::replace(&mut self.tracks, tracks);
+                // Bounded, so sending never allocates. Should it be full these get freed right here
+                let _ = self.retired_tracks.try_send(retired);
+            }
+            Command::Preview(command) => self.preview.handle_command(command),
+            Command::Metronome(command) => self.metronome.handle_command(command),
+            Command::Transport(TransportCommand::Play) => {
+                self.metronome.cancel_count_in();
+                self.set_rolling(true);
+            }
+            Command::Transport(TransportCommand::Stop) => {
+                self.metronome.cancel_count_in();
+                self.set_rolling(false);
+                self.recording = false;
+            }
+            Command::Transport(TransportCommand::Record(recording)) => {
+                self.recording = recording;
+                if !recording {
+                    self.metronome.cancel_count_in();
+                } else if !self.rolling && !self.metronome.counting_in() {
+                    let sample_rate = self.sample_rate();
+                    let counting_in = self
+                        .snapshot
+                        .as_ref()
+                        .is_some_and(|snapshot| self.metronome.start_count_in(&snapshot.tempo, self.frame, sample_rate));
+                    if !counting_in {
+                        self.set_rolling(true);
+                    }
+                }
+            }
+            Command::Transport(TransportCommand::Locate(frame)) => match &mut self.external {
+                Some(external) => external.requests.locate = Some(frame),
+                None => self.frame = frame,
+            },
+            Command::SetSnapshot(snapshot) => {
+                let generation = snapshot.generation;
+                if let Some(previous) = &self.snapshot {
+                    snapshot.graph.carry_over(&previous.graph);
+                }
+                // The UI holds on to the previous snapshot until the new generation shows up, so
+                // letting go of it has to happen first for it to get freed over there
+                drop(self.snapshot.replace(snapshot));
+                self.status.snapshot_generation.store(generation, Ordering::Release);
+            }
+        }
+    }
+
+    // With an external transport this only asks it to, the engine follows once it did
+    fn set_rolling(&mut self, rolling: bool) {
+        match &mut self.external {
+            Some(external) => external.requests.rolling = Some(rolling),
+            None => self.rolling = rolling,
+        }
+    }
+
+    // Called by backends with a transport of their own before every cycle, with whether it's
+    // rolling and where it is at the start of the cycle
+    #[cfg_attr(not(feature = "jack"), allow(dead_code, reason = "only the JACK backend has a transport of its own"))]
+    pub fn follow_transport(&mut self, rolling: bool, frame: u64) {
+        let previous = self.external.take();
+        let expected = previous
+            .as_ref()
+            .map(|previous| previous.frame + if previous.rolling { previous.frames } else { 0 });
+        if expected != Some(frame) {
+            self.frame = frame;
+        }
+        if previous.as_ref().map(|previous| previous.rolling) != Some(rolling) {
+            self.metronome.cancel_count_in();
+            self.rolling = rolling;
+            self.recording &= rolling;
+        }
+        self.external = Some(ExternalTransport {
+            rolling,
+            frame,
+            frames: 0,
+            requests: previous.map(|previous| previous.requests).unwrap_or_default(),
+        });
+    }
+
+    // What the external transport should do, once the cycle is rendered
+    #[cfg_attr(not(feature = "jack"), allow(dead_code, reason = "only the JACK backend has a transport of its own"))]
+    pub fn take_transport_requests(&mut self) -> TransportRequests {
+        self.external
+            .as_mut()
+            .map(|external| std::mem::take(&mut external.requests))
+            .unwrap_or_default()
+    }
+
+    // Renders `frames` (at most `MAX_BLOCK_SIZE`) frames into the engine's buffers
+    pub fn process(&mut self, frames: usize) {
+        debug_assert!(frames <= MAX_BLOCK_SIZE);
+        while let Ok(command) = self.commands.try_recv() {
+            self.handle_command(command);
+        }
+        for buffer in self.tracks.iter_mut().chain([&mut self.master, &mut self.click]) {
+            for channel in buffer {
+                channel[..frames].fill(0.);
+            }
+        }
+        self.preview.render(&mut self.master, frames, &self.status);
+
+        let mut counted = 0;
+        if self.metronome.counting_in() {
+            counted = self.metronome.count_in(&mut self.click, frames);
+            if !self.metronome.counting_in() {
+                // Rolls right away to stay in time with the count-in, the external transport catches up
+                self.rolling = true;
+                if let Some(external) = &mut self.external {
+                    external.requests.rolling = Some(true);
+                }
+            }
+        }
+        if self.rolling {
+            self.roll(counted..frames);
+        }
+        if !self.separate_click() {
+            mix(&mut self.master, &self.click, 0..frames);
+        }
+        if !(self.rolling && self.recording) {
+            self.capture.flush(true);
+        }
+        self.capture.calibrate(&mut self.master, &self.input, frames);
+        for channel in &mut self.input {
+            channel[..frames].fill(0.);
+        }
+        if let Some(external) = &mut self.external {
+            external.frames += frames as u64;
+        }
+        self.status.transport_rolling.store(self.rolling, Ordering::Relaxed);
+        self.status.transport_recording.store(self.recording, Ordering::Relaxed);
+        self.status.counting_in.store(self.metronome.counting_in(), Ordering::Relaxed);
+        self.status.transport_frame.store(self.frame, Ordering::Relaxed);
+    }
+
+    // Renders the block in pieces when the loop end falls inside it, so it jumps back right there
+    fn roll(&mut self, block: Range<usize>) {
+        let loop_range = self.snapshot.as_ref().and_then(|snapshot| snapshot.loop_range.clone());
+        let mut done = block.start;
+        while done < block.end {
+            let mut length = block.end - done;
+            let wraps = loop_range
+                .as_ref()
+                .filter(|range| self.frame < range.end && self.frame + length as u64 >= range.end);
+            if let Some(range) = wraps {
+                #[allow(clippy::cast_possible_truncation)]
+                let until_end = (range.end - self.frame) as usize;
+                length = until_end;
+            }
+            self.render_arrangement(done..done + length);
+            let latency = self.snapshot.as_ref().map_or(0, |snapshot| snapshot.graph.latency as u64);
+            if self.recording {
+                // Lined up with what was coming out of the master while it was played in
+                if !self.capture.record(&self.input, done..done + length, self.frame.saturating_sub(latency)) {
+                    self.status.dropped_input.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+            if let Some(snapshot) = self.snapshot.as_ref().filter(|snapshot| snapshot.sample_rate == self.sample_rate()) {
+                // Just as late as the master, and silent until the song's start comes out of it
+                #[allow(clippy::cast_possible_truncation)]
+                let silent = latency.saturating_sub(self.frame).min(length as u64) as usize;
+                let start = (self.frame + silent as u64).saturating_sub(latency);
+                self.metronome.render_song(&mut self.click, done + silent..done + length, start, &snapshot.tempo, snapshot.sample_rate);
+            }
+            self.frame += length as u64;
+            if let Some(range) = wraps {
+                self.frame = range.start;
+            }
+            done += length;
+        }
+    }
+
+    // Plays the clips under the playhead into their tracks, buses and the master, at `block` in the buffers
+    fn render_arrangement(&mut self, block: Range<usize>) {
+        let Some(snapshot) = &self.snapshot else {
+            return;
+        };
+        if snapshot.sample_rate != self.sample_rate() {
+            return;
+        }
+        self.scheduler.render(snapshot, self.frame, block.clone());
+        let graph = &snapshot.graph;
+        for (index, track) in snapshot.tracks.iter().enumerate() {
+            if let Some(output) = self.tracks.get_mut(index) {
+                graph.tasks[index].mix_into(output, block.clone(), track.strip.factors);
+            }
+        }
+        graph.master().mix_into(&mut self.master, block, snapshot.master.factors);
+    }
+
+    // Where backends with inputs copy them to before `process`, the engine clears it afterwards
+    pub fn input(&mut self, frames: usize) -> [&mut [f32]; 2] {
+        let [left, right] = &mut self.input;
+        [&mut left[..frames], &mut right[..frames]]
+    }
+
+    pub fn master(&self, frames: usize) -> [&[f32]; 2] {
+        [&self.master[0][..frames], &self.master[1][..frames]]
+    }
+
+    // Only while the metronome has an output of its own, otherwise it's in the master
+    pub fn click(&self, frames: usize) -> Option<[&[f32]; 2]> {
+        self.separate_click()
+            .then(|| [&self.click[0][..frames], &self.click[1][..frames]])
+    }
+
+    fn separate_click(&self) -> bool {
+        self.metronome.separate_output && self.status.separate_click_output.load(Ordering::Relaxed)
+    }
+
+    #[cfg_attr(not(feature = "jack"), allow(dead_code, reason = "only JACK has ports for every track"))]
+    pub fn track(&self, index: usize, frames: usize) -> Option<[&[f32]; 2]> {
+        self.tracks
+            .get(index)
+            .map(|[left, right]| [&left[..frames], &right[..frames]])
+    }
+}
+
+fn mix(output: &mut StereoBuffer, input: &StereoBuffer, block: Range<usize>) {
+    for (output, input) in output.iter_mut().zip(input) {
+        for (output, input) in output[block.clone()].iter_mut().zip(&input[block.clone()]) {
+            *output += input;
+        }
+    }
+}
+
+impl EngineHandle {
+    pub fn send(&self, command: Command) {
+        // The engine only goes away together with its stream, at which point nobody's listening anyway
+        let _ = self.commands.send(command);
+    }
+
+    pub fn set_track_outputs(&self, tracks: Vec<StereoBuffer>) {
+        // Buffers the audio thread let go of get freed here
+        self.retired_tracks.try_iter().for_each(drop);
+        self.send(Command::SetTrackOutputs(tracks));
+    }
+
+    pub fn set_snapshot(&self, snapshot: Arc<Snapshot>) {
+        self.scheduler.send(&snapshot);
+        self.send(Command::SetSnapshot(snapshot));
+    }
+}
@@ -0,0 +1,171 @@
+use std::{
+    ops::Range,
+    sync::mpsc::{channel, Receiver, Sender},
+};
+
+use super::StereoBuffer;
+
+// Frames in each of the buffers recorded input travels to the UI in
+pub const CAPTURE_FRAMES: usize = 16384;
+
+// Input recorded from frame `start` of the song on, interleaved stereo
+#[derive(Debug)]
+pub struct Recorded {
+    pub start: u64,
+    pub samples: Vec<f32>,
+    // Recording stopped after this one
+    pub last: bool,
+}
+
+impl Recorded {
+    pub fn end(&self) -> u64 {
+        self.start + self.samples.len() as u64 / 2
+    }
+}
+
+// A test signal played out of the master while the first input channel gets recorded, for measuring
+// how long it takes to come back in through whatever the backend is connected to
+#[derive(Debug)]
+pub struct Calibration {
+    pub signal: Vec<f32>,
+    // Allocated with room for `length` samples up front, the calibration is done once it has them
+    pub captured: Vec<f32>,
+    pub length: usize,
+}
+
+// The audio thread's end, records input into buffers the UI hands it and sends them back once they
+// are full, so it never allocates any
+pub struct Capture {
+    empty: Receiver<Vec<f32>>,
+    recorded: Sender<Recorded>,
+    current: Option<Recorded>,
+    calibrations: Receiver<Box<Calibration>>,
+    calibrated: Sender<Box<Calibration>>,
+    calibration: Option<Box<Calibration>>,
+}
+
+// The UI's end
+pub struct CaptureHandle {
+    empty: Sender<Vec<f32>>,
+    recorded: Receiver<Recorded>,
+    calibrations: Sender<Box<Calibration>>,
+    calibrated: Receiver<Box<Calibration>>,
+}
+
+pub fn capture() -> (Capture, CaptureHandle) {
+    let (empty_sender, empty) = channel();
+    let (recorded, recorded_receiver) = channel();
+    let (calibration_sender, calibrations) = channel();
+    let (calibrated, calibrated_receiver) = channel();
+    (
+        Capture {
+            empty,
+            recorded,
+            current: None,
+            calibrations,
+            calibrated,
+            calibration: None,
+        },
+        CaptureHandle {
+            empty: empty_sender,
+            recorded: recorded_receiver,
+            calibrations: calibration_sender,
+            calibrated: calibrated_receiver,
+        },
+    )
+}
+
+impl Capture {
+    // Records `block` of `input`, which came in while the song was at `frame`. Returns false when
+    // the UI didn't hand over buffers fast enough and some of it got lost
+    pub fn record(&mut self, input: &StereoBuffer, block: Range<usize>, frame: u64) -> bool {
+        let mut frame = frame;
+        let mut block = block;
+        while !block.is_empty() {
+            // Loops and locates start a new buffer, the UI makes a new take out of it
+            if self
+                .current
+                .as_ref()
+                .is_some_and(|current| current.end() != frame || current.samples.len() + 2 > current.samples.capacity())
+            {
+                self.flush(false);
+            }
+            if self.current.is_none() {
+                let Ok(mut samples) = self.empty.try_recv() else {
+                    return false;
+                };
+                samples.clear();
+                self.current = Some(Recorded {
+                    start: frame,
+                    samples,
+                    last: false,
+                });
+            }
+            let Some(current) = &mut self.current else {
+                return false;
+            };
+            let room = (current.samples.capacity() - current.samples.len()) / 2;
+            let length = room.min(block.len());
+            let frames = block.start..block.start + length;
+            for (left, right) in input[0][frames.clone()].iter().zip(&input[1][frames]) {
+                current.samples.extend([left, right]);
+            }
+            block.start += length;
+            frame += length as u64;
+        }
+        true
+    }
+
+    // Sends whatever got recorded so far to the UI, `last` when recording stopped
+    pub fn flush(&mut self, last: bool) {
+        if let Some(mut current) = self.current.take() {
+            current.last = last;
+            let _ = self.recorded.send(current);
+        }
+    }
+
+    // Plays the signal of a calibration the UI started in place of the master, and records the
+    // input along with it
+    pub fn calibrate(&mut self, master: &mut StereoBuffer, input: &StereoBuffer, frames: usize) {
+        if self.calibration.is_none() {
+            self.calibration = self.calibrations.try_recv().ok();
+        }
+        let Some(calibration) = &mut self.calibration else {
+            return;
+        };
+        for frame in 0..frames {
+            let position = calibration.captured.len();
+            if position >= calibration.length {
+                break;
+            }
+            let sample = calibration.signal.get(position).copied().unwrap_or_default();
+            master[0][frame] = sample;
+            master[1][frame] = sample;
+            calibration.captured.push(input[0][frame]);
+        }
+        if calibration.captured.len() >= calibration.length {
+            if let Some(calibration) = self.calibration.take() {
+                let _ = self.calibrated.send(calibration);
+            }
+        }
+    }
+}
+
+impl CaptureHandle {
+    // An empty buffer for the engine to record into, with room for `CAPTURE_FRAMES`
+    pub fn give(&self, buffer: Vec<f32>) {
+        let _ = self.empty.send(buffer);
+    }
+
+    pub fn recorded(&self) -> impl Iterator<Item = Recorded> + '_ {
+        self.recorded.try_iter()
+    }
+
+    pub fn calibrate(&self, calibration: Box<Calibration>) {
+        let _ = self.calibrations.send(calibration);
+    }
+
+    pub fn calibrated(&self) -> Option<Box<Calibration>> {
+        self.calibrated.try_recv().ok()
+    }
+}
@@ -0,0 +1,105 @@
+use std::sync::atomic::{AtomicU32, AtomicUsize, Ordering};
+
+// A fixed delay for a stereo signal. Lives in the graph and gets used by whichever thread renders
+// the task it belongs to, one at a time, so it's made of atomics like the task's buffer
+#[derive(Debug)]
+pub struct Delay {
+    // Samples are f32 bits, the frame that went in `len` frames ago is at `position`
+    samples: [Vec<AtomicU32>; 2],
+    position: AtomicUsize,
+}
+
+impl Delay {
+    pub fn new(frames: usize) -> Self {
+        Self {
+            samples: [0, 1].map(|_| (0..frames).map(|_| AtomicU32::new(0)).collect()),
+            position: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples[0].len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    // Where the next frame goes, for going through a block frame by frame with `swap`
+    pub fn position(&self) -> usize {
+        self.position.load(Ordering::Relaxed)
+    }
+
+    // Puts a frame in at `position` and returns the one that went in `len` frames before it, along
+    // with the position after it
+    pub fn swap(&self, position: usize, frame: [f32; 2]) -> ([f32; 2], usize) {
+        if self.is_empty() {
+            return (frame, 0);
+        }
+        let delayed = [0, 1].map(|channel| f32::from_bits(self.samples[channel][position].swap(frame[channel].to_bits(), Ordering::Relaxed)));
+        (delayed, (position + 1) % self.len())
+    }
+
+    pub fn set_position(&self, position: usize) {
+        self.position.store(position, Ordering::Relaxed);
+    }
+
+    // Takes over what's in `other`'s delay when it's as long, so a new snapshot carries on where the
+    // old one left off
+    pub fn carry_over(&self, other: &Self) {
+        if other.len() != self.len() {
+            return;
+        }
+        for (samples, other) in self.samples.iter().zip(&other.samples) {
+            for (sample, other) in samples.iter().zip(other) {
+                sample.store(other.load(Ordering::Relaxed), Ordering::Relaxed);
+            }
+        }
+        self.set_position(other.position());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Runs frames through the delay, a block at a time like the graph does
+    fn run(delay: &Delay, frames: impl IntoIterator<Item = f32>) -> Vec<f32> {
+        let mut position = delay.position();
+        let out = frames
+            .into_iter()
+            .map(|frame| {
+                let ([left, right], next) = delay.swap(position, [frame, -frame]);
+                assert_eq!(left, -right);
+                position = next;
+                left
+            })
+            .collect();
+        delay.set_position(position);
+        out
+    }
+
+    #[test]
+    fn frames_come_out_len_frames_later() {
+        let delay = Delay::new(3);
+        assert_eq!(run(&delay, [1., 2., 3., 4.]), [0., 0., 0., 1.]);
+        assert_eq!(run(&delay, [5., 6.]), [2., 3.]);
+        let empty = Delay::new(0);
+        assert!(empty.is_empty());
+        assert_eq!(run(&empty, [1., 2.]), [1., 2.]);
+    }
+
+    #[test]
+    fn carries_over_what_was_in_a_delay_as_long() {
+        let previous = Delay::new(3);
+        run(&previous, [1., 2., 3., 4.]);
+        let next = Delay::new(3);
+        next.carry_over(&previous);
+        assert_eq!(run(&next, [5., 6., 7.]), [2., 3., 4.]);
+
+        // A different length would come out at the wrong time, so it starts over
+        let longer = Delay::new(4);
+        longer.carry_over(&previous);
+        assert_eq!(run(&longer, [5., 6., 7., 8., 9.]), [0., 0., 0., 0., 5.]);
+    }
+}
@@ -0,0 +1,439 @@
+use std::{
+    mem::discriminant,
+    ops::Range,
+    sync::atomic::{AtomicU32, AtomicU64, Ordering},
+};
+
+use super::{delay::Delay, StereoBuffer, MAX_BLOCK_SIZE};
+use crate::{
+    blerp::processing::{
+        limiter::{self, Envelope},
+        Effect,
+    },
+    project::{BusSnapshot, Snapshot, StripSnapshot, TrackSnapshot},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Node {
+    Track(usize),
+    Bus(usize),
+    Master,
+}
+
+// Another task's signal mixed into this one, through its fader or one of its sends. Delayed by
+// however much less latency its path has than the task's latest input, so they all line up
+#[derive(Debug)]
+pub struct Input {
+    pub task: usize,
+    pub factors: [f32; 2],
+    pub delay: Delay,
+}
+
+// An effect along with whatever it has to remember between blocks
+#[derive(Debug)]
+pub struct Insert {
+    pub effect: Effect,
+    // As long as the effect's latency
+    lookahead: Delay,
+    // The limiter's `Envelope` as f32 bits, the hold last
+    envelope: [AtomicU32; 4],
+    release: f32,
+}
+
+#[derive(Debug)]
+pub struct Task {
+    pub node: Node,
+    // Always tasks that come before this one
+    pub inputs: Vec<Input>,
+    pub inserts: Vec<Insert>,
+    // Frames between the song's position and what comes out of the task's inserts
+    pub latency: usize,
+    // The signal after the inserts and before the fader, whoever renders the task writes it and the
+    // tasks it feeds read it once it's done. Samples are f32 bits
+    buffer: [Vec<AtomicU32>; 2],
+    // Block the buffer was last rendered for
+    rendered: AtomicU64,
+}
+
+// The tracks, buses and master as tasks in an order where everything comes after what feeds into
+// it: the tracks, the buses in the snapshot's order and the master last. Compiled along with the
+// snapshot, rendering it never allocates. None of the effects take a sidechain yet, so the only
+// paths to compensate are outputs and sends. A sidechain would be one more `Input` of the task
+// it keys, delayed the same way
+#[derive(Debug)]
+pub struct Graph {
+    pub tasks: Vec<Task>,
+    // How far behind the song's position the master comes out
+    pub latency: usize,
+}
+
+// What a task renders, the same for every task in a block
+#[derive(Debug, Clone)]
+pub struct Block {
+    // Counts up with every block rendered, so tasks know whether their inputs are done
+    pub number: u64,
+    // Frame of the song the block starts at
+    pub start: u64,
+    // Where in the buffers it goes
+    pub range: Range<usize>,
+}
+
+fn atomic_buffer() -> [Vec<AtomicU32>; 2] {
+    [0, 1].map(|_| (0..MAX_BLOCK_SIZE).map(|_| AtomicU32::new(0)).collect())
+}
+
+impl Insert {
+    fn new(effect: Effect, sample_rate: u32) -> Self {
+        let insert = Self {
+            effect,
+            lookahead: Delay::new(effect.latency(sample_rate)),
+            envelope: Default::default(),
+            release: limiter::release(sample_rate),
+        };
+        insert.store_envelope(Envelope::default());
+        insert
+    }
+
+    fn envelope(&self) -> Envelope {
+        let [gain, target, step, hold] = self.envelope.each_ref().map(|value| value.load(Ordering::Relaxed));
+        Envelope {
+            gain: f32::from_bits(gain),
+            target: f32::from_bits(target),
+            step: f32::from_bits(step),
+            hold,
+        }
+    }
+
+    fn store_envelope(&self, envelope: Envelope) {
+        let values = [envelope.gain.to_bits(), envelope.target.to_bits(), envelope.step.to_bits(), envelope.hold];
+        for (atomic, value) in self.envelope.iter().zip(values) {
+            atomic.store(value, Ordering::Relaxed);
+        }
+    }
+
+    fn process(&self, buffer: &mut StereoBuffer, range: Range<usize>) {
+        let Effect::Limiter { ceiling } = self.effect else {
+            for channel in buffer.iter_mut() {
+                for sample in &mut channel[range.clone()] {
+                    *sample = self.effect.process(*sample);
+                }
+            }
+            return;
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        let ceiling = ceiling as f32;
+        let lookahead = u32::try_from(self.lookahead.len()).unwrap_or(u32::MAX);
+        let mut envelope = self.envelope();
+        let mut position = self.lookahead.position();
+        for frame in range {
+            let input = [buffer[0][frame], buffer[1][frame]];
+            let gain = envelope.next(input[0].abs().max(input[1].abs()), ceiling, lookahead, self.release);
+            let (delayed, next) = self.lookahead.swap(position, input);
+            position = next;
+            for (channel, delayed) in buffer.iter_mut().zip(delayed) {
+                channel[frame] = self.effect.process(delayed * gain);
+            }
+        }
+        self.lookahead.set_position(position);
+        self.store_envelope(envelope);
+    }
+
+    fn carry_over(&self, previous: &Self) {
+        if discriminant(&self.effect) == discriminant(&previous.effect) {
+            self.lookahead.carry_over(&previous.lookahead);
+            self.store_envelope(previous.envelope());
+        }
+    }
+}
+
+impl Input {
+    // Adds the input task's rendered signal to `output`, through the delay
+    fn mix_into(&self, tasks: &[Task], output: &mut StereoBuffer, range: Range<usize>) {
+        let source = &tasks[self.task];
+        if self.delay.is_empty() {
+            source.mix_into(output, range, self.factors);
+            return;
+        }
+        let mut position = self.delay.position();
+        for frame in range {
+            let input = [0, 1].map(|channel| f32::from_bits(source.buffer[channel][frame].load(Ordering::Relaxed)) * self.factors[channel]);
+            let (delayed, next) = self.delay.swap(position, input);
+            position = next;
+            for (channel, delayed) in output.iter_mut().zip(delayed) {
+                channel[frame] += delayed;
+            }
+        }
+        self.delay.set_position(position);
+    }
+}
+
+impl Task {
+    pub fn is_rendered(&self, block: u64) -> bool {
+        self.rendered.load(Ordering::Acquire) == block
+    }
+
+    // Adds the rendered signal times `factors` to `output`
+    pub fn mix_into(&self, output: &mut StereoBuffer, range: Range<usize>, factors: [f32; 2]) {
+        for ((output, buffer), factor) in output.iter_mut().zip(&self.buffer).zip(factors) {
+            for (output, sample) in output[range.clone()].iter_mut().zip(&buffer[range.clone()]) {
+                *output += f32::from_bits(sample.load(Ordering::Relaxed)) * factor;
+            }
+        }
+    }
+}
+
+impl Graph {
+    pub fn compile(tracks: &[TrackSnapshot], buses: &[BusSnapshot], master: &StripSnapshot, sample_rate: u32) -> Self {
+        let bus_task = |bus: usize| tracks.len() + bus;
+        let master_task = tracks.len() + buses.len();
+        let nodes: Vec<(Node, &StripSnapshot)> = tracks
+            .iter()
+            .enumerate()
+            .map(|(index, track)| (Node::Track(index), &track.strip))
+            .chain(buses.iter().enumerate().map(|(index, bus)| (Node::Bus(index), &bus.strip)))
+            .chain([(Node::Master, master)])
+            .collect();
+
+        // Everything feeding into each task, from its output and its sends
+        let mut routes: Vec<Vec<(usize, [f32; 2])>> = nodes.iter().map(|_| Vec::new()).collect();
+        let outputs = tracks.iter().map(|track| track.output).chain(buses.iter().map(|bus| bus.output));
+        for (task, output) in outputs.enumerate() {
+            let strip = nodes[task].1;
+            routes[output.map_or(master_task, bus_task)].push((task, strip.factors));
+            for send in &strip.sends {
+                routes[bus_task(send.bus)].push((task, send.factors));
+            }
+        }
+
+        // Whatever feeds into a task comes before it, so its inputs' latencies are known by the time
+        // it gets its own. Inputs with less of it get delayed by the difference
+        let mut tasks: Vec<Task> = Vec::with_capacity(nodes.len());
+        for ((node, strip), routes) in nodes.iter().zip(routes) {
+            let latency = routes.iter().map(|(task, _)| tasks[*task].latency).max().unwrap_or(0);
+            let inputs = routes
+                .into_iter()
+                .map(|(task, factors)| Input {
+                    task,
+                    factors,
+                    delay: Delay::new(latency - tasks[task].latency),
+                })
+                .collect();
+            let inserts: Vec<Insert> = strip.inserts.iter().map(|effect| Insert::new(*effect, sample_rate)).collect();
+            tasks.push(Task {
+                node: *node,
+                inputs,
+                latency: latency + inserts.iter().map(|insert| insert.lookahead.len()).sum::<usize>(),
+                inserts,
+                buffer: atomic_buffer(),
+                rendered: AtomicU64::new(u64::MAX),
+            });
+        }
+        Self {
+            latency: tasks[master_task].latency,
+            tasks,
+        }
+    }
+
+    pub fn master(&self) -> &Task {
+        &self.tasks[self.tasks.len() - 1]
+    }
+
+    // Whether everything `index` needs is rendered, so it can start
+    pub fn is_ready(&self, index: usize, block: u64) -> bool {
+        self.tasks[index].inputs.iter().all(|input| self.tasks[input.task].is_rendered(block))
+    }
+
+    // Takes over the delays and effect states of the graph the engine played before, so compensated
+    // paths and limiters don't start over from silence every time the project changes. Goes by
+    // position, which lines up whenever the routing stayed the same, like while dragging a fader
+    pub fn carry_over(&self, previous: &Self) {
+        for (task, previous) in self.tasks.iter().zip(&previous.tasks) {
+            if task.node != previous.node {
+                continue;
+            }
+            for (input, previous) in task.inputs.iter().zip(&previous.inputs) {
+                if input.task == previous.task {
+                    input.delay.carry_over(&previous.delay);
+                }
+            }
+            for (insert, previous) in task.inserts.iter().zip(&previous.inserts) {
+                insert.carry_over(previous);
+            }
+        }
+    }
+}
+
+// Plays the clips under the playhead
+fn play_clips(track: &TrackSnapshot, output: &mut StereoBuffer, block: &Block) {
+    let start = block.start;
+    let end = start + block.range.len() as u64;
+    // Clips are sorted by where they start
+    for clip in track.clips.iter().take_while(|clip| clip.start < end) {
+        if clip.end() <= start {
+            continue;
+        }
+        let samples = &clip.buffer.samples;
+        #[allow(clippy::cast_possible_truncation)]
+        for frame in clip.start.max(start)..clip.end().min(end) {
+            let source = (frame - clip.start + clip.offset) as usize * 2;
+            if source + 1 >= samples.len() {
+                break;
+            }
+            let target = block.range.start + (frame - start) as usize;
+            output[0][target] += samples[source];
+            output[1][target] += samples[source + 1];
+        }
+    }
+}
+
+// Renders one task once its inputs are, `scratch` being the rendering thread's own buffer
+pub fn render(snapshot: &Snapshot, index: usize, block: &Block, scratch: &mut StereoBuffer) {
+    let tasks = &snapshot.graph.tasks;
+    let task = &tasks[index];
+    let range = block.range.clone();
+    for channel in scratch.iter_mut() {
+        channel[range.clone()].fill(0.);
+    }
+    if let Node::Track(track) = task.node {
+        play_clips(&snapshot.tracks[track], scratch, block);
+    }
+    for input in &task.inputs {
+        input.mix_into(tasks, scratch, range.clone());
+    }
+    for insert in &task.inserts {
+        insert.process(scratch, range.clone());
+    }
+    for (buffer, channel) in task.buffer.iter().zip(scratch.iter()) {
+        for (sample, value) in buffer[range.clone()].iter().zip(&channel[range.clone()]) {
+            sample.store(value.to_bits(), Ordering::Relaxed);
+        }
+    }
+    task.rendered.store(block.number, Ordering::Release);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::{
+        blerp::{audiofile::AudioBuffer, processing::live},
+        project::{AudioClipSnapshot, SendSnapshot, TempoMap},
+        test_util::Random,
+    };
+
+    const SAMPLE_RATE: u32 = 48000;
+
+    const IMPULSE_FRAME: u64 = 1000;
+    const IMPULSE_LEVEL: f32 = 0.1;
+    // Doesn't line up with anything, so delays straddle block boundaries
+    const IMPULSE_BLOCK_SIZE: usize = 100;
+
+    fn random_inserts(random: &mut Random) -> Vec<Effect> {
+        // Nothing gets loud enough for these to change the level, only the limiter's latency matters
+        (0..random.below(3))
+            .map(|_| match random.below(3) {
+                0 => Effect::Limiter { ceiling: 1. },
+                1 => Effect::Volume { volume: 1. },
+                _ => Effect::Clipper { threshold: 1. },
+            })
+            .collect()
+    }
+
+    // Sends to buses from `from` on
+    fn random_sends(random: &mut Random, from: usize, buses: usize) -> Vec<SendSnapshot> {
+        (0..random.below(3))
+            .filter(|_| from < buses)
+            .map(|_| SendSnapshot {
+                bus: from + random.below(buses - from),
+                factors: [0.5; 2],
+            })
+            .collect()
+    }
+
+    // Tracks that all play an impulse at the same frame, through buses and sends with limiters here and
+    // there, so they only come out at the same frame if the graph made up for the limiters' latency
+    fn impulse_session(random: &mut Random, generation: u64) -> Snapshot {
+        let bus_count = random.below(5);
+        let impulse = Arc::new(AudioBuffer {
+            sample_rate: SAMPLE_RATE,
+            channels: 2,
+            samples: vec![IMPULSE_LEVEL, IMPULSE_LEVEL, 0., 0., 0., 0.],
+        });
+        let strip = |random: &mut Random, sends| StripSnapshot {
+            inserts: random_inserts(random),
+            sends,
+            factors: [1.; 2],
+        };
+        let tracks: Vec<_> = (0..=random.below(8))
+            .map(|_| {
+                let sends = random_sends(random, 0, bus_count);
+                TrackSnapshot {
+                    clips: vec![AudioClipSnapshot {
+                        start: IMPULSE_FRAME,
+                        length: 3,
+                        offset: 0,
+                        buffer: impulse.clone(),
+                    }],
+                    output: (bus_count > 0 && random.below(3) > 0).then(|| random.below(bus_count)),
+                    strip: strip(random, sends),
+                }
+            })
+            .collect();
+        let buses: Vec<_> = (0..bus_count)
+            .map(|bus| {
+                let sends = random_sends(random, bus + 1, bus_count);
+                BusSnapshot {
+                    output: (bus + 1 < bus_count && random.below(2) == 0).then(|| bus + 1 + random.below(bus_count - bus - 1)),
+                    strip: strip(random, sends),
+                }
+            })
+            .collect();
+        let master = strip(random, Vec::new());
+        Snapshot {
+            generation,
+            sample_rate: SAMPLE_RATE,
+            graph: Graph::compile(&tracks, &buses, &master, SAMPLE_RATE),
+            tracks,
+            master,
+            tempo: TempoMap::default(),
+            loop_range: None,
+        }
+    }
+
+    // Renders random impulse sessions and checks every impulse comes out of the master at the same
+    // frame, the one the graph says it's delayed to. Halfway through, half of them switch to a new
+    // snapshot of the same session, which has to carry on with what's in the delays
+    #[test]
+    fn every_path_reaches_the_master_at_once() {
+        let mut random = Random(0x9e37_79b9_7f4a_7c15);
+        let lookahead = Effect::Limiter { ceiling: 1. }.latency(SAMPLE_RATE);
+        for round in 0..300 {
+            let seed = random.0;
+            let snapshot = Arc::new(impulse_session(&mut random, 1));
+            let latency = snapshot.graph.latency;
+            assert!(latency.is_multiple_of(lookahead.max(1)), "latency isn't made of lookaheads (round {round})");
+            let expected = IMPULSE_FRAME as usize + latency;
+            let switch = (round % 2 == 1 && latency > 0).then_some(expected - latency / 2);
+
+            let (mut engine, handle) = live::engine_with_workers(SAMPLE_RATE, 2);
+            handle.set_snapshot(snapshot);
+            handle.send(live::Command::Transport(live::TransportCommand::Play));
+            let mut master = Vec::new();
+            while master.len() < expected + IMPULSE_BLOCK_SIZE * 4 {
+                if switch.is_some_and(|switch| (master.len()..master.len() + IMPULSE_BLOCK_SIZE).contains(&switch)) {
+                    // Built from the same seed, so it's the same session
+                    handle.set_snapshot(Arc::new(impulse_session(&mut Random(seed), 2)));
+                }
+                engine.process(IMPULSE_BLOCK_SIZE);
+                master.extend_from_slice(engine.master(IMPULSE_BLOCK_SIZE)[0]);
+            }
+            let arrivals: Vec<usize> = master.iter().enumerate().filter(|(_, sample)| sample.abs() > 1e-9).map(|(frame, _)| frame).collect();
+            assert!(
+                arrivals == [expected],
+                "impulses came out at {arrivals:?} instead of only {expected} (round {round}, {:?})",
+                switch.map(|_| "switched snapshots")
+            );
+        }
+    }
+}
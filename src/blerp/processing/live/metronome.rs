@@ -0,0 +1,208 @@
+use std::{ops::Range, sync::Arc};
+
+use super::StereoBuffer;
+use crate::project::TempoMap;
+
+// What the metronome clicks with, already stereo and at the engine's sample rate
+#[derive(Debug)]
+pub struct ClickSounds {
+    // Interleaved stereo, played on the first beat of every bar
+    pub accent: Vec<f32>,
+    pub beat: Vec<f32>,
+}
+
+pub enum MetronomeCommand {
+    Sounds(Arc<ClickSounds>),
+    Enable(bool),
+    Volume(f32),
+    // Clicks through their own output instead of the master, where the backend has one
+    SeparateOutput(bool),
+    // Bars to click before recording starts from a standstill
+    CountInBars(u32),
+}
+
+// Clicks counted from when the count-in started, at the tempo and time signature the recording
+// starts at
+struct CountIn {
+    frame: u64,
+    clicks: u64,
+    // Frames between clicks
+    spacing: f64,
+    beats_per_bar: u64,
+}
+
+impl CountIn {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+    fn click_frame(&self, click: u64) -> u64 {
+        (click as f64 * self.spacing).round() as u64
+    }
+
+    fn length(&self) -> u64 {
+        self.click_frame(self.clicks)
+    }
+
+    // First click at or after `frame`, and whether it starts a bar
+    fn next_click(&self, frame: u64) -> Option<(u64, bool)> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss, clippy::cast_precision_loss)]
+        let mut click = (frame as f64 / self.spacing).floor() as u64;
+        while self.click_frame(click) < frame {
+            click += 1;
+        }
+        (click < self.clicks).then(|| (self.click_frame(click), click.is_multiple_of(self.beats_per_bar)))
+    }
+}
+
+// First beat of the time signature at or after `frame`, and whether it starts a bar. In 6/8 that's
+// every eighth note
+fn next_click(tempo: &TempoMap, frame: u64, sample_rate: u32) -> (u64, bool) {
+    let (mut bar, into_bar) = tempo.bar_at(tempo.beat_at_frame(frame, sample_rate));
+    let mut signature = tempo.signature_at_bar(bar);
+    let mut unit = 4. / f64::from(signature.denominator.max(1));
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let mut beat = (into_bar / unit).floor() as u32;
+    loop {
+        if beat >= signature.numerator {
+            bar += 1;
+            beat = 0;
+            signature = tempo.signature_at_bar(bar);
+            unit = 4. / f64::from(signature.denominator.max(1));
+        }
+        // Frames get rounded, so the beat `frame` is in can still click at or after it
+        let click = tempo.frame_at(tempo.bar_start(bar) + f64::from(beat) * unit, sample_rate);
+        if click >= frame {
+            return (click, beat == 0);
+        }
+        beat += 1;
+    }
+}
+
+// Generates the click track from the tempo map while the transport rolls, and the count-in before
+// recording
+pub struct MetronomeVoice {
+    sounds: Option<Arc<ClickSounds>>,
+    enabled: bool,
+    volume: f32,
+    pub separate_output: bool,
+    count_in_bars: u32,
+    count_in: Option<CountIn>,
+    // The click that's sounding, whether it's the accent and how far into it
+    playing: Option<(bool, usize)>,
+}
+
+impl Default for MetronomeVoice {
+    fn default() -> Self {
+        Self {
+            sounds: None,
+            enabled: false,
+            volume: 1.,
+            separate_output: false,
+            count_in_bars: 0,
+            count_in: None,
+            playing: None,
+        }
+    }
+}
+
+impl MetronomeVoice {
+    pub fn handle_command(&mut self, command: MetronomeCommand) {
+        match command {
+            // The UI holds on to the sounds until the engine lets go of them, so this never frees any
+            MetronomeCommand::Sounds(sounds) => self.sounds = Some(sounds),
+            MetronomeCommand::Enable(enabled) => self.enabled = enabled,
+            MetronomeCommand::Volume(volume) => self.volume = volume,
+            MetronomeCommand::SeparateOutput(separate) => self.separate_output = separate,
+            MetronomeCommand::CountInBars(bars) => self.count_in_bars = bars,
+        }
+    }
+
+    pub fn counting_in(&self) -> bool {
+        self.count_in.is_some()
+    }
+
+    // Returns whether there's anything to count in, if not recording can start right away
+    pub fn start_count_in(&mut self, tempo: &TempoMap, frame: u64, sample_rate: u32) -> bool {
+        if self.count_in_bars == 0 {
+            return false;
+        }
+        let beat = tempo.beat_at_frame(frame, sample_rate);
+        let signature = tempo.signature_at(beat);
+        let unit = 4. / f64::from(signature.denominator.max(1));
+        let beats_per_bar = u64::from(signature.numerator.max(1));
+        self.count_in = Some(CountIn {
+            frame: 0,
+            clicks: u64::from(self.count_in_bars) * beats_per_bar,
+            spacing: unit * 60. / tempo.tempo_at(beat) * f64::from(sample_rate),
+            beats_per_bar,
+        });
+        true
+    }
+
+    pub fn cancel_count_in(&mut self) {
+        self.count_in = None;
+    }
+
+    // Clicks the count-in into the start of the block, returns how many frames it took up. Once it
+    // returns less than `frames` the count-in is over and the transport takes the rest of the block
+    pub fn count_in(&mut self, output: &mut StereoBuffer, frames: usize) -> usize {
+        let Some(count_in) = self.count_in.take() else {
+            return 0;
+        };
+        #[allow(clippy::cast_possible_truncation)]
+        let counted = (count_in.length() - count_in.frame).min(frames as u64) as usize;
+        self.render(output, 0..counted, count_in.frame, |frame| count_in.next_click(frame));
+        if count_in.frame + (counted as u64) < count_in.length() {
+            self.count_in = Some(CountIn {
+                frame: count_in.frame + counted as u64,
+                ..count_in
+            });
+        }
+        counted
+    }
+
+    // Clicks the beats between `start` and the end of `block` on the song's timeline
+    pub fn render_song(&mut self, output: &mut StereoBuffer, block: Range<usize>, start: u64, tempo: &TempoMap, sample_rate: u32) {
+        if self.enabled {
+            self.render(output, block, start, |frame| Some(next_click(tempo, frame, sample_rate)));
+        } else {
+            self.playing = None;
+        }
+    }
+
+    // `start` is the frame at the start of `block` on whatever timeline `next_click` counts in
+    fn render(&mut self, output: &mut StereoBuffer, block: Range<usize>, start: u64, next_click: impl Fn(u64) -> Option<(u64, bool)>) {
+        let end = start + block.len() as u64;
+        let mut from = start;
+        let mut position = block.start;
+        loop {
+            let next = next_click(from).filter(|(click, _)| *click < end);
+            #[allow(clippy::cast_possible_truncation)]
+            let until = next.map_or(block.end, |(click, _)| block.start + (click - start) as usize);
+            self.play(output, position..until);
+            position = until;
+            let Some((click, accent)) = next else {
+                break;
+            };
+            self.playing = Some((accent, 0));
+            from = click + 1;
+        }
+    }
+
+    // Carries on with whatever click is sounding
+    fn play(&mut self, [left, right]: &mut StereoBuffer, range: Range<usize>) {
+        let (Some(sounds), Some((accent, position))) = (&self.sounds, &mut self.playing) else {
+            return;
+        };
+        let samples = if *accent { &sounds.accent } else { &sounds.beat };
+        let remaining = (samples.len() / 2).saturating_sub(*position);
+        let frames = range.len().min(remaining);
+        for (index, (left, right)) in left[range.clone()].iter_mut().zip(&mut right[range]).take(frames).enumerate() {
+            *left += samples[(*position + index) * 2] * self.volume;
+            *right += samples[(*position + index) * 2 + 1] * self.volume;
+        }
+        *position += frames;
+        if frames == remaining {
+            self.playing = None;
+        }
+    }
+}
+
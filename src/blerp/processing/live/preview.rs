@@ -0,0 +1,132 @@
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+use super::{EngineStatus, StereoBuffer};
+use crate::blerp::peaks::Peaks;
+
+// A decoded file ready for the preview voice, already stereo and at the engine's sample rate
+#[derive(Debug)]
+pub struct PreviewSample {
+    pub path: PathBuf,
+    // Interleaved stereo
+    pub samples: Vec<f32>,
+    pub sample_rate: u32,
+    // What the file looked like before it got converted for playback
+    pub source_sample_rate: u32,
+    pub source_channels: u16,
+    pub bits_per_sample: Option<u16>,
+    // Of the file as it is on disk, for drawing the waveform
+    pub peaks: Arc<Peaks>,
+}
+
+impl PreviewSample {
+    pub fn frames(&self) -> usize {
+        self.samples.len() / 2
+    }
+}
+
+pub enum PreviewCommand {
+    // Switches to a sample without starting it
+    Load(Arc<PreviewSample>),
+    Play(Arc<PreviewSample>),
+    Resume,
+    Stop,
+    Seek(u64),
+    SetLooping(bool),
+    SetVolume(f32),
+    // Playback speed, 1.0 plays the file as is
+    SetRate(f64),
+}
+
+// The single voice previews play through, mixed into the master by the engine
+pub struct PreviewVoice {
+    sample: Option<Arc<PreviewSample>>,
+    position: f64,
+    playing: bool,
+    looping: bool,
+    volume: f32,
+    rate: f64,
+}
+
+impl Default for PreviewVoice {
+    fn default() -> Self {
+        Self {
+            sample: None,
+            position: 0.,
+            playing: false,
+            looping: false,
+            volume: 1.,
+            rate: 1.,
+        }
+    }
+}
+
+impl PreviewVoice {
+    pub fn handle_command(&mut self, command: PreviewCommand) {
+        match command {
+            PreviewCommand::Load(sample) => {
+                // The UI keeps every sample it sent until the engine lets go of it, so replacing one
+                // never frees it here
+                self.sample = Some(sample);
+                self.position = 0.;
+                self.playing = false;
+            }
+            PreviewCommand::Play(sample) => {
+                self.sample = Some(sample);
+                self.position = 0.;
+                self.playing = true;
+            }
+            PreviewCommand::Resume => self.playing = self.sample.is_some(),
+            PreviewCommand::Stop => self.playing = false,
+            #[allow(clippy::cast_precision_loss)]
+            PreviewCommand::Seek(frame) => self.position = frame as f64,
+            PreviewCommand::SetLooping(looping) => self.looping = looping,
+            PreviewCommand::SetVolume(volume) => self.volume = volume,
+            PreviewCommand::SetRate(rate) => self.rate = rate.max(0.),
+        }
+    }
+
+    pub fn render(&mut self, [left, right]: &mut StereoBuffer, frames: usize, status: &EngineStatus) {
+        if let Some(sample) = self.sample.as_ref().filter(|_| self.playing) {
+            let samples = &sample.samples;
+            let len = sample.frames();
+            #[allow(clippy::cast_precision_loss)]
+            let end = len as f64;
+            for (left, right) in left[..frames].iter_mut().zip(&mut right[..frames]) {
+                if self.position >= end {
+                    if self.looping && len > 0 {
+                        self.position %= end;
+                    } else {
+                        self.playing = false;
+                        break;
+                    }
+                }
+                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                let index = self.position as usize;
+                #[allow(clippy::cast_possible_truncation, clippy::cast_precision_loss)]
+                let fraction = (self.position - index as f64) as f32;
+                let next = if index + 1 < len {
+                    index + 1
+                } else if self.looping {
+                    0
+                } else {
+                    index
+                };
+                let interpolate = |channel: usize| {
+                    let current = samples[index * 2 + channel];
+                    (samples[next * 2 + channel] - current).mul_add(fraction, current)
+                };
+                *left += interpolate(0) * self.volume;
+                *right += interpolate(1) * self.volume;
+                self.position += self.rate;
+            }
+        }
+        status.preview_playing.store(self.playing, Ordering::Relaxed);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        status
+            .preview_position
+            .store(self.position as u64, Ordering::Relaxed);
+    }
+}
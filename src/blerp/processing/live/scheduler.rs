@@ -0,0 +1,399 @@
+use std::{
+    ops::Range,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread::{self, Thread},
+};
+
+use super::{
+    graph::{self, Block},
+    stereo_buffer, StereoBuffer,
+};
+use crate::project::Snapshot;
+
+// More than this mostly spin waiting on each other
+const MAX_WORKERS: usize = 7;
+
+// The block being rendered goes in the top half of `cursor`, the next task to take in the bottom half
+const BLOCK_SHIFT: u32 = 32;
+const TASK_MASK: u64 = (1 << BLOCK_SHIFT) - 1;
+
+// Spins a little before giving the core up, what's being waited for is usually a few microseconds
+// away unless the thread rendering it got preempted
+const SPINS: u32 = 64;
+
+// One worker per core besides the audio thread's
+pub fn default_workers() -> usize {
+    thread::available_parallelism()
+        .map_or(1, std::num::NonZero::get)
+        .saturating_sub(1)
+        .min(MAX_WORKERS)
+}
+
+// What the audio thread and the workers share, only ever touched through atomics. A block's
+// parameters are written before `cursor` moves on to it, and can't change while it has tasks left
+#[derive(Default)]
+struct Shared {
+    cursor: AtomicU64,
+    generation: AtomicU64,
+    start: AtomicU64,
+    range_start: AtomicUsize,
+    range_end: AtomicUsize,
+    quit: AtomicBool,
+    // The audio thread's realtime scheduling, see `priority`
+    priority: AtomicU64,
+}
+
+impl Shared {
+    // Takes the next task of `snapshot`'s graph if the block still has any and is rendering that
+    // snapshot. A block can't end while it has tasks nobody took, so whoever takes one reads that
+    // block's parameters
+    fn take(&self, snapshot: &Snapshot) -> Option<(usize, Block)> {
+        loop {
+            let cursor = self.cursor.load(Ordering::Acquire);
+            #[allow(clippy::cast_possible_truncation)]
+            let task = (cursor & TASK_MASK) as usize;
+            if task >= snapshot.graph.tasks.len() || self.generation.load(Ordering::Acquire) != snapshot.generation {
+                return None;
+            }
+            if self
+                .cursor
+                .compare_exchange_weak(cursor, cursor + 1, Ordering::AcqRel, Ordering::Acquire)
+                .is_ok()
+            {
+                let block = Block {
+                    number: cursor >> BLOCK_SHIFT,
+                    start: self.start.load(Ordering::Acquire),
+                    range: self.range_start.load(Ordering::Acquire)..self.range_end.load(Ordering::Acquire),
+                };
+                return Some((task, block));
+            }
+        }
+    }
+
+    // Renders tasks until there are none left to take. Tasks are taken in order, so whatever a task
+    // waits for has already been taken by a thread that's rendering it
+    fn help(&self, snapshot: &Snapshot, scratch: &mut StereoBuffer) {
+        while let Some((task, block)) = self.take(snapshot) {
+            wait_until(|| snapshot.graph.is_ready(task, block.number));
+            graph::render(snapshot, task, &block, scratch);
+        }
+    }
+}
+
+fn wait_until(done: impl Fn() -> bool) {
+    let mut spins = 0;
+    while !done() {
+        if spins < SPINS {
+            spins += 1;
+            std::hint::spin_loop();
+        } else {
+            thread::yield_now();
+        }
+    }
+}
+
+// The calling thread's realtime policy in the top half and its priority in the bottom, zero when
+// it isn't realtime
+#[cfg(unix)]
+fn priority() -> u64 {
+    let mut policy = 0;
+    // SAFETY: sched_param is plain data, and both pointers are valid for the call
+    let found = unsafe {
+        let mut param: libc::sched_param = std::mem::zeroed();
+        (libc::pthread_getschedparam(libc::pthread_self(), &mut policy, &mut param) == 0)
+            .then_some(param.sched_priority)
+    };
+    match found {
+        Some(priority) if policy == libc::SCHED_FIFO || policy == libc::SCHED_RR => {
+            #[allow(clippy::cast_sign_loss)]
+            let packed = (u64::from(policy as u32) << 32) | u64::from(priority as u32);
+            packed
+        }
+        _ => 0,
+    }
+}
+
+#[cfg(unix)]
+fn set_priority(packed: u64) -> std::io::Result<()> {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_possible_wrap)]
+    let (policy, priority) = if packed == 0 {
+        (libc::SCHED_OTHER, 0)
+    } else {
+        ((packed >> 32) as i32, packed as u32 as i32)
+    };
+    // SAFETY: sched_param is plain data, and the pointer is valid for the call
+    let result = unsafe {
+        let mut param: libc::sched_param = std::mem::zeroed();
+        param.sched_priority = priority;
+        libc::pthread_setschedparam(libc::pthread_self(), policy, &param)
+    };
+    if result == 0 {
+        Ok(())
+    } else {
+        Err(std::io::Error::from_raw_os_error(result))
+    }
+}
+
+// Everywhere else the workers stay at normal priority
+#[cfg(not(unix))]
+const fn priority() -> u64 {
+    0
+}
+
+#[cfg(not(unix))]
+#[allow(clippy::unnecessary_wraps)]
+const fn set_priority(_packed: u64) -> std::io::Result<()> {
+    Ok(())
+}
+
+fn worker(index: usize, shared: &Shared, snapshots: &Receiver<Arc<Snapshot>>) {
+    let mut scratch = stereo_buffer();
+    let mut snapshot: Option<Arc<Snapshot>> = None;
+    let mut priority = 0;
+    loop {
+        thread::park();
+        if shared.quit.load(Ordering::Acquire) {
+            return;
+        }
+        // The audio thread waits on whatever a worker is rendering, so workers run at the same
+        // realtime priority it does. Otherwise anything else on the system can preempt them halfway
+        // through a task while the audio thread spins
+        let wanted = shared.priority.load(Ordering::Acquire);
+        if wanted != priority {
+            priority = wanted;
+            if let Err(err) = set_priority(wanted) {
+                // Every worker fails the same way
+                if index == 0 {
+                    eprintln!("Failed to give the audio workers realtime priority: {err}");
+                }
+            }
+        }
+        // Snapshots arrive before the engine switches to them, older ones aren't needed anymore
+        let previous = snapshots.try_iter().last().and_then(|latest| snapshot.replace(latest));
+        if let Some(snapshot) = &snapshot {
+            shared.help(snapshot, &mut scratch);
+        }
+        // Freed after helping rather than in the middle of a block
+        drop(previous);
+    }
+}
+
+// The audio thread's end of the worker pool, renders a snapshot's graph with the workers' help
+pub struct Scheduler {
+    shared: Arc<Shared>,
+    workers: Vec<Thread>,
+    block: u64,
+    scratch: StereoBuffer,
+    // Whether the workers were told what priority the audio thread runs at
+    shared_priority: bool,
+}
+
+// The UI's end, workers get every snapshot straight from the UI so the audio thread never has to
+// hand them over
+pub struct SchedulerHandle {
+    workers: Vec<(Sender<Arc<Snapshot>>, Thread)>,
+}
+
+pub fn scheduler(workers: usize) -> (Scheduler, SchedulerHandle) {
+    let shared = Arc::new(Shared::default());
+    let workers: Vec<_> = (0..workers)
+        .filter_map(|index| {
+            let (sender, receiver) = channel();
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name(format!("Audio worker {index}"))
+                .spawn(move || worker(index, &shared, &receiver))
+                .map_err(|err| eprintln!("Failed to start an audio worker: {err}"))
+                .ok()
+                .map(|handle| (sender, handle.thread().clone()))
+        })
+        .collect();
+    (
+        Scheduler {
+            shared,
+            workers: workers.iter().map(|(_, thread)| thread.clone()).collect(),
+            block: 0,
+            scratch: stereo_buffer(),
+            shared_priority: false,
+        },
+        SchedulerHandle { workers },
+    )
+}
+
+impl Scheduler {
+    // Renders every task of the snapshot's graph for `range` of the buffers, starting at frame
+    // `start` of the song, returns once the master is done
+    pub fn render(&mut self, snapshot: &Snapshot, start: u64, range: Range<usize>) {
+        self.block = (self.block + 1) & TASK_MASK;
+        let shared = &self.shared;
+        // The backend set the audio thread up by the time it renders anything
+        if !self.shared_priority {
+            self.shared_priority = true;
+            shared.priority.store(priority(), Ordering::Release);
+        }
+        shared.generation.store(snapshot.generation, Ordering::Release);
+        shared.start.store(start, Ordering::Release);
+        shared.range_start.store(range.start, Ordering::Release);
+        shared.range_end.store(range.end, Ordering::Release);
+        shared.cursor.store(self.block << BLOCK_SHIFT, Ordering::Release);
+        // A single track and the master don't have anything to share
+        if snapshot.graph.tasks.len() > 2 {
+            for worker in &self.workers {
+                worker.unpark();
+            }
+        }
+        shared.help(snapshot, &mut self.scratch);
+        // Everything ends up in the master, so once it's rendered every task is
+        wait_until(|| snapshot.graph.master().is_rendered(self.block));
+    }
+}
+
+impl Drop for Scheduler {
+    fn drop(&mut self) {
+        self.shared.quit.store(true, Ordering::Release);
+        for worker in &self.workers {
+            worker.unpark();
+        }
+    }
+}
+
+impl SchedulerHandle {
+    pub fn send(&self, snapshot: &Arc<Snapshot>) {
+        for (sender, thread) in &self.workers {
+            // Only fails once the worker quit, along with the engine
+            if sender.send(snapshot.clone()).is_ok() {
+                // So it lets go of the previous one even while nothing plays
+                thread.unpark();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::{
+        blerp::{
+            audiofile::AudioBuffer,
+            processing::{live, Effect},
+        },
+        project::{AudioClipSnapshot, BusSnapshot, SendSnapshot, StripSnapshot, TempoMap, TrackSnapshot},
+    };
+    use graph::Graph;
+
+    const BENCH_TRACKS: usize = 200;
+    const BENCH_GROUPS: usize = 20;
+    const BENCH_SAMPLE_RATE: u32 = 48000;
+    const BENCH_SECONDS: u32 = 10;
+    const BENCH_BLOCK_SIZE: usize = 256;
+
+    // A big mix: every track plays a clip through a clipper and sends to a reverb bus, in groups of ten
+    // that go to a mix bus along with the reverb
+    fn synthetic_session() -> Snapshot {
+        let frames = BENCH_SAMPLE_RATE * BENCH_SECONDS;
+        // Tracks share a handful of buffers, 200 different ones would take most of a gigabyte
+        let buffers: Vec<_> = (0..8_u16)
+            .map(|index| {
+                let frequency = 55. * f32::from(index + 1);
+                #[allow(clippy::cast_precision_loss)]
+                let samples = (0..frames)
+                    .flat_map(|frame| {
+                        let sample = (std::f32::consts::TAU * frequency * frame as f32 / BENCH_SAMPLE_RATE as f32).sin() * 0.2;
+                        [sample, sample]
+                    })
+                    .collect();
+                Arc::new(AudioBuffer {
+                    sample_rate: BENCH_SAMPLE_RATE,
+                    channels: 2,
+                    samples,
+                })
+            })
+            .collect();
+        let strip = |effect, factors, sends| StripSnapshot {
+            inserts: vec![effect],
+            sends,
+            factors,
+        };
+        let reverb = BENCH_GROUPS;
+        let mix_bus = BENCH_GROUPS + 1;
+        let tracks: Vec<_> = (0..BENCH_TRACKS)
+            .map(|index| TrackSnapshot {
+                clips: vec![AudioClipSnapshot {
+                    start: 0,
+                    length: frames.into(),
+                    offset: index as u64,
+                    buffer: buffers[index % buffers.len()].clone(),
+                }],
+                output: Some(index % BENCH_GROUPS),
+                strip: strip(Effect::Clipper { threshold: 0.8 }, [0.5, 0.4], vec![SendSnapshot { bus: reverb, factors: [0.1; 2] }]),
+            })
+            .collect();
+        let buses: Vec<_> = (0..BENCH_GROUPS)
+            .map(|_| BusSnapshot {
+                output: Some(mix_bus),
+                strip: strip(Effect::Volume { volume: 0.5 }, [0.8; 2], Vec::new()),
+            })
+            .chain([reverb, mix_bus].map(|bus| BusSnapshot {
+                output: (bus == reverb).then_some(mix_bus),
+                strip: strip(Effect::Clipper { threshold: 0.9 }, [0.7; 2], Vec::new()),
+            }))
+            .collect();
+        let master = strip(Effect::Clipper { threshold: 1. }, [1.; 2], Vec::new());
+        Snapshot {
+            generation: 1,
+            sample_rate: BENCH_SAMPLE_RATE,
+            graph: Graph::compile(&tracks, &buses, &master, BENCH_SAMPLE_RATE),
+            tracks,
+            master,
+            tempo: TempoMap::default(),
+            loop_range: None,
+        }
+    }
+
+    // Renders the session with `workers` worker threads, returns the master and how long it took
+    fn render_session(snapshot: &Arc<Snapshot>, workers: usize) -> (Vec<f32>, Duration) {
+        let (mut engine, handle) = live::engine_with_workers(BENCH_SAMPLE_RATE, workers);
+        handle.set_snapshot(snapshot.clone());
+        handle.send(live::Command::Transport(live::TransportCommand::Play));
+        let frames = (BENCH_SAMPLE_RATE * BENCH_SECONDS) as usize;
+        let mut master = Vec::with_capacity(frames * 2);
+        let started = Instant::now();
+        for block in (0..frames).step_by(BENCH_BLOCK_SIZE) {
+            let length = BENCH_BLOCK_SIZE.min(frames - block);
+            engine.process(length);
+            let [left, right] = engine.master(length);
+            master.extend(left.iter().zip(right).flat_map(|(left, right)| [*left, *right]));
+        }
+        (master, started.elapsed())
+    }
+
+    // Renders a synthetic 200 track session offline on the audio thread alone and with the worker
+    // pool, checks they come out the same and prints how long each took. Run with
+    // `cargo test renders_a_big_session -- --ignored --nocapture`
+    #[test]
+    #[ignore = "benchmark"]
+    fn renders_a_big_session() {
+        let snapshot = Arc::new(synthetic_session());
+        // At least a couple, so the pool gets exercised on any machine
+        let workers = default_workers().max(2);
+        let (alone, alone_time) = render_session(&snapshot, 0);
+        assert!(alone.iter().any(|sample| *sample != 0.), "the session rendered silence");
+        let (pooled, pooled_time) = render_session(&snapshot, workers);
+        // Every task sums its inputs in the same order whichever thread renders it
+        assert!(alone == pooled, "the worker pool rendered something different");
+        let realtime = |time: Duration| f64::from(BENCH_SECONDS) / time.as_secs_f64();
+        println!(
+            "{BENCH_TRACKS} tracks, {BENCH_SECONDS} s in blocks of {BENCH_BLOCK_SIZE}: {:.0} ms on one thread ({:.1}x realtime), {:.0} ms with {workers} workers ({:.1}x realtime)",
+            alone_time.as_secs_f64() * 1000.,
+            realtime(alone_time),
+            pooled_time.as_secs_f64() * 1000.,
+            realtime(pooled_time),
+        );
+    }
+}
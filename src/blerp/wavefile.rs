@@ -1,10 +1,56 @@
-use std::{fs::File, io, io::Write, path::Path};
+use std::{
+    fs::File,
+    io,
+    io::{Read, Seek, SeekFrom, Write},
+    path::Path,
+};
 
 pub enum WaveAudioFormat {
     PulseCodeModulation,
     FloatingPoint,
 }
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WaveFormat {
+    pub audio_format: u16,
+    pub channels: u16,
+    pub sample_rate: u32,
+    pub bits_per_sample: u16,
+}
+
+// Reads the fmt chunk, skipping over whatever chunks come before it
+pub fn read_wav_format(location: &Path) -> io::Result<WaveFormat> {
+    let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+    let mut file = File::open(location)?;
+    let mut riff_header = [0; 12];
+    file.read_exact(&mut riff_header)?;
+    if &riff_header[0..4] != b"RIFF" || &riff_header[8..12] != b"WAVE" {
+        return Err(invalid("not a RIFF/WAVE file"));
+    }
+    loop {
+        let mut chunk_header = [0; 8];
+        file.read_exact(&mut chunk_header)?;
+        let chunk_length = u32::from_le_bytes([
+            chunk_header[4],
+            chunk_header[5],
+            chunk_header[6],
+            chunk_header[7],
+        ]);
+        if &chunk_header[0..4] == b"fmt " {
+            let mut format = [0; 16];
+            file.read_exact(&mut format)?;
+            return Ok(WaveFormat {
+                audio_format: u16::from_le_bytes([format[0], format[1]]),
+                channels: u16::from_le_bytes([format[2], format[3]]),
+                sample_rate: u32::from_le_bytes([format[4], format[5], format[6], format[7]]),
+                bits_per_sample: u16::from_le_bytes([format[14], format[15]]),
+            });
+        }
+        // Chunks are padded to an even length
+        file.seek(SeekFrom::Current(i64::from(chunk_length) + i64::from(chunk_length % 2)))?;
+    }
+}
+
 pub fn form_wav_file_header(
     sample_rate: u32,
     channels: u16,
@@ -24,18 +70,18 @@ pub fn form_wav_file_header(
     };
 
     let mut filebuf: Vec<u8> = Vec::with_capacity((file_length + 4) as usize);
-    filebuf.write(b"RIFF")?;
-    filebuf.write(&file_length.to_le_bytes())?;
-    filebuf.write(b"WAVEfmt ")?;
-    filebuf.write(&format_data_length.to_le_bytes())?;
-    filebuf.write(&audio_format_u16.to_le_bytes())?;
-    filebuf.write(&channels.to_le_bytes())?;
-    filebuf.write(&sample_rate.to_le_bytes())?;
-    filebuf.write(&byte_rate.to_le_bytes())?;
-    filebuf.write(&block_align.to_le_bytes())?;
-    filebuf.write(&bits_per_sample.to_le_bytes())?;
-    filebuf.write(b"data")?;
-    filebuf.write(&data_length.to_le_bytes())?;
+    filebuf.write_all(b"RIFF")?;
+    filebuf.write_all(&file_length.to_le_bytes())?;
+    filebuf.write_all(b"WAVEfmt ")?;
+    filebuf.write_all(&format_data_length.to_le_bytes())?;
+    filebuf.write_all(&audio_format_u16.to_le_bytes())?;
+    filebuf.write_all(&channels.to_le_bytes())?;
+    filebuf.write_all(&sample_rate.to_le_bytes())?;
+    filebuf.write_all(&byte_rate.to_le_bytes())?;
+    filebuf.write_all(&block_align.to_le_bytes())?;
+    filebuf.write_all(&bits_per_sample.to_le_bytes())?;
+    filebuf.write_all(b"data")?;
+    filebuf.write_all(&data_length.to_le_bytes())?;
 
     Ok(filebuf)
 }
@@ -48,6 +94,7 @@ pub fn form_wav_file_data_f32(buffer: &[f32], header_buffer: Vec<u8>) -> io::Res
     Ok(filebuf)
 }
 
+#[allow(dead_code)]
 pub fn form_wav_file_data_f64(buffer: &[f64], header_buffer: Vec<u8>) -> io::Result<Vec<u8>> {
     let mut filebuf = header_buffer;
 
@@ -68,6 +115,7 @@ pub fn form_wav_file_data_f32tof64(buffer: &[f32], header_buffer: Vec<u8>) -> io
     Ok(filebuf)
 }
 
+#[allow(dead_code)]
 pub fn form_wav_file_data_f64tof32(buffer: &[f64], header_buffer: Vec<u8>) -> io::Result<Vec<u8>> {
     let mut filebuf = header_buffer;
 
@@ -92,6 +140,7 @@ pub fn form_wav_file_data_f32toi16(buffer: &[f32], header_buffer: Vec<u8>) -> io
     Ok(filebuf)
 }
 
+#[allow(dead_code)]
 pub fn form_wav_file_data_f64toi16(buffer: &[f64], header_buffer: Vec<u8>) -> io::Result<Vec<u8>> {
     let mut filebuf = header_buffer;
 
@@ -116,6 +165,7 @@ pub fn form_wav_file_data_f32toi8(buffer: &[f32], header_buffer: Vec<u8>) -> io:
     Ok(filebuf)
 }
 
+#[allow(dead_code)]
 pub fn form_wav_file_data_f64toi8(buffer: &[f64], header_buffer: Vec<u8>) -> io::Result<Vec<u8>> {
     let mut filebuf = header_buffer;
 
@@ -140,6 +190,7 @@ pub fn form_wav_file_data_f32toi32(buffer: &[f32], header_buffer: Vec<u8>) -> io
     Ok(filebuf)
 }
 
+#[allow(dead_code)]
 pub fn form_wav_file_data_f64toi32(buffer: &[f64], header_buffer: Vec<u8>) -> io::Result<Vec<u8>> {
     let mut filebuf = header_buffer;
 
@@ -164,6 +215,7 @@ pub fn form_wav_file_data_f32toi64(buffer: &[f32], header_buffer: Vec<u8>) -> io
     Ok(filebuf)
 }
 
+#[allow(dead_code)]
 pub fn form_wav_file_data_f64toi64(buffer: &[f64], header_buffer: Vec<u8>) -> io::Result<Vec<u8>> {
     let mut filebuf = header_buffer;
 
@@ -176,6 +228,7 @@ pub fn form_wav_file_data_f64toi64(buffer: &[f64], header_buffer: Vec<u8>) -> io
     Ok(filebuf)
 }
 
+#[allow(dead_code)]
 pub fn write_wav_file_f64(
     location: &Path,
     buffer: &[f64],
@@ -202,22 +255,22 @@ pub fn write_wav_file_f64(
             let _bmatch: std::result::Result<(), &str> = match bits_per_sample {
                 8 => {
                     let filebuf = form_wav_file_data_f64toi8(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 16 => {
                     let filebuf = form_wav_file_data_f64toi16(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 32 => {
                     let filebuf = form_wav_file_data_f64toi32(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 64 => {
                     let filebuf = form_wav_file_data_f64toi64(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 _ => std::result::Result::Err("Invalid bits per sample!"),
@@ -234,12 +287,12 @@ pub fn write_wav_file_f64(
             let _bmatch: std::result::Result<(), &str> = match bits_per_sample {
                 32 => {
                     let filebuf = form_wav_file_data_f64tof32(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 64 => {
                     let filebuf = form_wav_file_data_f64(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 _ => std::result::Result::Err("Invalid bits per sample!"),
@@ -275,22 +328,22 @@ pub fn write_wav_file_f32(
             let _bmatch: std::result::Result<(), &str> = match bits_per_sample {
                 8 => {
                     let filebuf = form_wav_file_data_f32toi8(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 16 => {
                     let filebuf = form_wav_file_data_f32toi16(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 32 => {
                     let filebuf = form_wav_file_data_f32toi32(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 64 => {
                     let filebuf = form_wav_file_data_f32toi64(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 _ => std::result::Result::Err("Invalid bits per sample!"),
@@ -307,12 +360,12 @@ pub fn write_wav_file_f32(
             let _bmatch: std::result::Result<(), &str> = match bits_per_sample {
                 32 => {
                     let filebuf = form_wav_file_data_f32(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 64 => {
                     let filebuf = form_wav_file_data_f32tof64(buffer, filebuf)?;
-                    file.write(&filebuf)?;
+                    file.write_all(&filebuf)?;
                     std::result::Result::Ok(())
                 }
                 _ => std::result::Result::Err("Invalid bits per sample!"),
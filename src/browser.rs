@@ -1,28 +1,121 @@
 use itertools::Itertools;
 use std::{
     cmp::Ordering,
-    collections::HashSet,
-    fs::{read_dir, File},
+    collections::{hash_map::DefaultHasher, HashMap, HashSet},
+    hash::{Hash, Hasher},
+    io,
     iter::Iterator,
-    path::PathBuf,
-    str::FromStr,
-    thread::JoinHandle,
+    path::{Path, PathBuf},
+    sync::atomic,
 };
-use strum::Display;
+use serde::{Deserialize, Serialize};
+use strum::{Display, IntoEnumIterator};
 
-// FIXME: Temporary rodio playback, might need to use cpal or make rodio proper
 use egui::{
-    include_image, pos2, vec2, Align2, Context, DroppedFile, FontFamily, FontId, Image, LayerId,
-    PointerButton, Pos2, Rect, Stroke, Ui,
+    include_image, pos2, vec2, Align, Align2, Context, DroppedFile, FontFamily, FontId, Image, LayerId,
+    PointerButton, Pos2, Rect, Sense, Stroke, Ui,
 };
 use open::that_detached;
-use rodio::{Decoder, OutputStream, Sink};
 
-use std::io::BufReader;
+use crate::{
+    blerp::device::{Backend, Output},
+    config, project,
+    visual::{
+        tree_view::{Column, RowShape, TreeView},
+        ThemeColors,
+    },
+};
+
+pub use analyser::Analyser;
+pub use drag::DragPayload;
+pub use files::FileManager;
+pub use index::Index;
+pub use library::Library;
+pub use preview::Preview;
+pub use search::Search;
+pub use selection::Selection;
+pub use sort::SortMode;
+
+use selection::KeyAction;
+
+mod analyser;
+mod drag;
+mod files;
+mod index;
+mod library;
+mod places;
+mod preview;
+mod search;
+mod selection;
+mod sort;
+
+const STATE_FILE: &str = "browser.ron";
+// Analysis columns only show up once the browser is at least this wide
+const COLUMNS_MIN_WIDTH: f32 = 480.;
+// Type, tempo, key and loudness
+const ANALYSIS_COLUMNS: [Column; 4] = [
+    Column { width: 42., align: Align::Min },
+    Column { width: 24., align: Align::Max },
+    Column { width: 42., align: Align::Min },
+    Column { width: 36., align: Align::Max },
+];
+const ROW_HEIGHT: f32 = 16.;
+const MAX_RECENT_FILES: usize = 50;
+
+// An open folder as it gets saved. Sort modes are kept on their own, so they stick around
+// after the folder gets closed
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SavedFolder {
+    pub path: PathBuf,
+    pub expanded_directories: HashSet<PathBuf>,
+}
+
+// What the browser remembers between sessions
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default)]
+pub struct BrowserState {
+    pub sort_modes: HashMap<PathBuf, SortMode>,
+    pub open_folders: Vec<SavedFolder>,
+    pub offset_y: f32,
+    pub sidebar_width: f32,
+    pub selected_category: Category,
+    // Newest first
+    pub recent_files: Vec<PathBuf>,
+}
+
+impl Default for BrowserState {
+    fn default() -> Self {
+        // The first start opens the home folder, it's where samples usually are
+        let home = dirs::home_dir().unwrap_or_else(|| PathBuf::from("/"));
+        Self {
+            sort_modes: HashMap::new(),
+            open_folders: vec![SavedFolder {
+                path: home,
+                expanded_directories: HashSet::new(),
+            }],
+            offset_y: 0.,
+            sidebar_width: 300.,
+            selected_category: Category::Files,
+            recent_files: Vec::new(),
+        }
+    }
+}
+
+impl BrowserState {
+    pub fn load() -> Self {
+        config::load(STATE_FILE)
+    }
 
-use unicode_truncate::UnicodeTruncateStr;
+    pub fn add_recent_file(&mut self, path: &Path) {
+        self.recent_files.retain(|recent| recent != path);
+        self.recent_files.insert(0, path.to_path_buf());
+        self.recent_files.truncate(MAX_RECENT_FILES);
+    }
 
-use crate::visual::ThemeColors;
+    pub fn save(&self) -> io::Result<()> {
+        config::save(STATE_FILE, self)
+    }
+}
 
 fn hovered(ctx: &Context, rect: &Rect) -> bool {
     ctx.rect_contains_pointer(
@@ -32,7 +125,46 @@ fn hovered(ctx: &Context, rect: &Rect) -> bool {
     )
 }
 
-#[derive(Display, Debug, Clone, Copy, PartialEq, Eq)]
+// What clicking a row, or pressing enter on it, does
+fn activate(
+    entry: &Entry,
+    expanded_directories: &mut HashSet<PathBuf>,
+    preview: &mut Preview,
+    state: &mut BrowserState,
+    output: Option<&Output>,
+    opened_project: &mut Option<PathBuf>,
+) {
+    if entry.kind != EntryKind::Directory {
+        state.add_recent_file(&entry.path);
+    }
+    match entry.kind {
+        EntryKind::Directory => {
+            if !expanded_directories.insert(entry.path.clone()) {
+                expanded_directories.remove(&entry.path);
+            }
+        }
+        EntryKind::Audio => {
+            if let Some(output) = output {
+                // Clicking the file that's playing stops it
+                let playing = preview.sample.as_ref().is_some_and(|sample| sample.path == entry.path)
+                    && preview.is_playing(&output.engine);
+                if playing {
+                    preview.stop(&output.engine);
+                } else {
+                    preview.select(entry.path.clone(), &output.engine);
+                }
+            }
+        }
+        EntryKind::File if project::is_project_file(&entry.path) => {
+            *opened_project = Some(entry.path.clone());
+        }
+        EntryKind::File => {
+            that_detached(entry.path.clone()).unwrap();
+        }
+    }
+}
+
+#[derive(Display, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub enum Category {
     Files,
     Devices,
@@ -45,21 +177,13 @@ pub struct Entry {
     pub indent: usize,
 }
 
-// FIXME: THIS NEEDS TO BE FIXED ASAP, the ordering is wrong
-// Because of the new browser tree layout, the ordering no longer works as expected
-// We either need to do these comparisons within each layer of the tree, or make a more complicated ordering algorithm,
-// which would generally avoid the problem of this stuff being put into the wrong layer.
-// For now, ordering is based on path.
+// Directories first, then natural name order. Only meaningful between siblings, the index
+// sorts each level of the tree on its own
 impl Ord for Entry {
     fn cmp(&self, other: &Self) -> Ordering {
-        match (self.kind, other.kind) {
-            // (EntryKind::Directory, EntryKind::Directory) => self.path.cmp(&other.path),
-            // (EntryKind::Directory, _) => std::cmp::Ordering::Less,
-            // (_, EntryKind::Directory) => std::cmp::Ordering::Greater,
-            // (EntryKind::Audio, EntryKind::File) => std::cmp::Ordering::Less,
-            // (EntryKind::File, EntryKind::Audio) => std::cmp::Ordering::Greater,
-            _ => self.path.cmp(&other.path)
-        }
+        (other.kind == EntryKind::Directory)
+            .cmp(&(self.kind == EntryKind::Directory))
+            .then_with(|| sort::name_cmp(&self.path, &other.path))
     }
 }
 
@@ -76,48 +200,141 @@ pub enum EntryKind {
     File,
 }
 
-pub struct Preview {
-    pub preview_thread: Option<JoinHandle<()>>,
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Calibration {
+    Idle,
+    Requested,
+    Running,
+    Failed(String),
 }
 
-impl Preview {
-    pub fn play_file(&mut self, file: File) {
-        // Kill the current thread if it's not sleeping
-        if let Some(thread) = self.preview_thread.take() {
-            if !thread.is_finished() {
-                thread.thread().unpark();
-            }
+pub struct OpenFolder {
+    pub path: PathBuf,
+    pub expanded_directories: HashSet<PathBuf>,
+    pub sort: SortMode,
+}
+
+impl OpenFolder {
+    pub fn new(path: PathBuf, state: &BrowserState) -> Self {
+        Self {
+            sort: state.sort_modes.get(&path).copied().unwrap_or_default(),
+            path,
+            expanded_directories: HashSet::new(),
         }
+    }
 
-        let file = BufReader::new(file);
-        self.preview_thread = Some(std::thread::spawn(move || {
-            let (_stream, stream_handle) = OutputStream::try_default().unwrap();
-            let source = Decoder::new(file).unwrap();
-            let sink = Sink::try_new(&stream_handle).unwrap();
-            // let source = SineWave::new(440.0).take_duration(Duration::from_secs_f32(0.25)).amplify(0.20);
-            sink.append(source);
-            std::thread::park();
-        }));
+    pub fn restore(saved: &SavedFolder, state: &BrowserState) -> Self {
+        Self {
+            expanded_directories: saved.expanded_directories.clone(),
+            ..Self::new(saved.path.clone(), state)
+        }
     }
 }
 
-pub struct OpenFolder {
-    pub path: PathBuf,
-    pub expanded_directories: HashSet<PathBuf>,
+// Everything the rows in the list come from
+#[derive(PartialEq)]
+struct RowsKey {
+    index: u64,
+    search: Option<u64>,
+    recent: Option<Vec<PathBuf>>,
+    // Each open folder's sort mode and expanded directories, the latter as a hash
+    folders: Vec<(PathBuf, SortMode, u64)>,
+    show_columns: bool,
 }
 
 pub struct Browser {
     pub selected_category: Category,
     pub open_folders: Vec<OpenFolder>,
+    pub index: Index,
     pub preview: Preview,
+    pub search: Search,
+    pub library: Library,
+    pub analyser: Analyser,
+    pub selection: Selection,
+    pub files: FileManager,
+    pub tree: TreeView,
+    // Every row in the list and which open folder it belongs to, rebuilt whenever `rows_key` changes
+    rows: Vec<Entry>,
+    folder_of: Vec<usize>,
+    rows_key: Option<RowsKey>,
+    pub showing_recent: bool,
+    // Where the open project lives, if it was saved somewhere
+    pub project_folder: Option<PathBuf>,
+    // The project's tempo at the playhead, synced previews play at it
+    pub project_tempo: f64,
+    // A project file that got clicked, for whoever opens projects to pick up
+    pub opened_project: Option<PathBuf>,
     pub offset_y: f32,
-    pub dragging_audio: bool,
-    pub dragging_audio_text: String,
     pub sidebar_width: f32,
     pub started_drag: bool,
+    pub selected_backend: Backend,
+    pub calibration: Calibration,
+    pub state: BrowserState,
 }
 
 impl Browser {
+    pub fn new(state: BrowserState) -> Self {
+        Self {
+            selected_category: state.selected_category,
+            open_folders: state
+                .open_folders
+                .iter()
+                .map(|saved| OpenFolder::restore(saved, &state))
+                .collect(),
+            index: Index::new(),
+            preview: Preview::new(),
+            search: Search::new(),
+            library: Library::load(),
+            analyser: Analyser::new(),
+            selection: Selection::new(),
+            files: FileManager::new(),
+            tree: TreeView::new(),
+            rows: Vec::new(),
+            folder_of: Vec::new(),
+            rows_key: None,
+            showing_recent: false,
+            project_folder: None,
+            project_tempo: 120.,
+            opened_project: None,
+            offset_y: state.offset_y,
+            sidebar_width: state.sidebar_width,
+            started_drag: false,
+            selected_backend: Backend::default(),
+            calibration: Calibration::Idle,
+            state,
+        }
+    }
+
+    pub fn save_state(&mut self) {
+        self.library.flush();
+        self.state.open_folders = self
+            .open_folders
+            .iter()
+            .map(|open_folder| SavedFolder {
+                path: open_folder.path.clone(),
+                expanded_directories: open_folder.expanded_directories.clone(),
+            })
+            .collect();
+        self.state.offset_y = self.offset_y;
+        self.state.sidebar_width = self.sidebar_width;
+        self.state.selected_category = self.selected_category;
+        if let Err(err) = self.state.save() {
+            eprintln!("Failed to save the browser state: {err}");
+        }
+    }
+
+    // Opens a folder at the top of the browser, or moves it there if it's already open
+    fn open_root(&mut self, path: PathBuf) {
+        let open_folder = match self.open_folders.iter().position(|open_folder| open_folder.path == path) {
+            Some(position) => self.open_folders.remove(position),
+            None => OpenFolder::new(path, &self.state),
+        };
+        self.open_folders.insert(0, open_folder);
+        self.offset_y = 0.;
+        self.showing_recent = false;
+        self.save_state();
+    }
+
     pub fn paint_button(
         ctx: &Context,
         ui: &Ui,
@@ -156,7 +373,14 @@ impl Browser {
     }
 
     #[allow(clippy::too_many_lines, clippy::cognitive_complexity)]
-    pub fn paint(&mut self, ctx: &Context, ui: &mut Ui, viewport: &Rect, theme: &ThemeColors) {
+    pub fn paint(
+        &mut self,
+        ctx: &Context,
+        ui: &mut Ui,
+        viewport: &Rect,
+        theme: &ThemeColors,
+        output: Option<&Output>,
+    ) {
         ui.painter().rect_filled(
             Rect {
                 min: Pos2 { x: 0., y: 50. },
@@ -181,6 +405,9 @@ impl Browser {
             ],
             Stroke::new(0.5, theme.browser_outline),
         );
+        if let Some(output) = output {
+            self.preview.update(&output.engine, &self.library, self.project_tempo);
+        }
         let (was_pressed, press_position) = ctx
             .input(|input_state| {
                 Some((
@@ -189,6 +416,8 @@ impl Browser {
                 ))
             })
             .unwrap_or_default();
+        // Clicks on menus and popups are theirs, not whatever is painted underneath
+        let was_pressed = was_pressed && !ctx.is_pointer_over_area();
         for (category, rect) in [
             (
                 Category::Files,
@@ -229,80 +458,170 @@ impl Browser {
                             .map(move |DroppedFile { path, .. }| path.clone().ok_or(()))
                             .try_collect()
                     }).unwrap_or_default();
-                for path in files {
-                    self.open_folders.push(OpenFolder {
-                        path,
-                        expanded_directories: HashSet::new(),
-                    });
+                for path in &files {
+                    self.open_folders.push(OpenFolder::new(path.clone(), &self.state));
+                }
+                let removed_root = self.files.take_removed_root();
+                if let Some(root) = &removed_root {
+                    self.open_folders.retain(|open_folder| open_folder.path != *root);
+                }
+                if !files.is_empty() || removed_root.is_some() {
+                    self.save_state();
                 }
+                self.files.update();
+                self.files.paint_dialogs(ctx, &mut self.library);
 
-                let allowed_audio_formats = [".wav", ".wave", ".mp3", ".ogg", ".flac", ".opus"];
-
-                let open_folders = self.open_folders
-                    .iter_mut()
-                    .map(|open_folder| {
-                        let mut stack = vec![(open_folder.path.clone(), 0)];
-                        let mut discovered = HashSet::new();
-                        let mut entries = Vec::new();
-                        while let Some((path, indent)) = stack.pop() {
-                            entries.push((path.clone(), indent));
-                            if discovered.insert(path.clone()) {
-                                let Ok(entries) = read_dir(&path) else {
-                                    continue;
-                                };
-                                for entry in entries {
-                                    let entry = entry.unwrap();
-                                    if open_folder.expanded_directories.contains(
-                                        &PathBuf::from_str(
-                                            &entry
-                                                .file_name()
-                                                .into_string()
-                                                .unwrap_or_else(|_| "• Invalid Name •".into()),
-                                        ).unwrap(),
-                                    ) || open_folder.expanded_directories.contains(&path)
-                                    {
-                                        stack.push((entry.path(), indent + 1));
-                                    }
-                                }
-                            }
+                self.index.update();
+                self.analyser.update(&mut self.index, &mut self.library);
+                if self.analyser.is_busy() || self.files.is_busy() {
+                    // Results come in on their own, there's no input to trigger a repaint
+                    ctx.request_repaint_after(analyser::APPLY_INTERVAL);
+                }
+                let list_top = 90. + places::HEIGHT + search::HEIGHT;
+                match places::paint(
+                    ctx,
+                    ui,
+                    Rect::from_min_size(pos2(0., 90.), vec2(self.sidebar_width, places::HEIGHT)),
+                    theme,
+                    self.project_folder.as_deref(),
+                    self.showing_recent,
+                    press_position.filter(|_| was_pressed),
+                ) {
+                    Some(places::Place::Recent) => {
+                        self.showing_recent = !self.showing_recent;
+                        self.offset_y = 0.;
+                    }
+                    Some(place) => {
+                        if let Some(folder) = place.folder(self.project_folder.as_deref()) {
+                            self.open_root(folder);
                         }
-                        (
-                            entries.into_iter()
-                                .map(|(path, indent)| Entry {
-                                    kind: if path.is_file() {
-                                        if path.extension()
-                                            .and_then(|extension| extension.to_str())
-                                            .is_some_and(|extension| allowed_audio_formats.contains(&extension))
-                                        {
-                                            EntryKind::Audio
-                                        } else {
-                                            EntryKind::File
-                                        }
-                                    } else {
-                                        EntryKind::Directory
-                                    },
-                                    path,
-                                    indent,
-                                })
-                                .sorted_unstable()
-                                .collect_vec(),
-                            &mut open_folder.expanded_directories,
-                        )
-                    })
-                    .collect_vec();
+                    }
+                    None => {}
+                }
+                self.search.paint_controls(
+                    ctx,
+                    ui,
+                    Rect::from_min_size(pos2(0., 90. + places::HEIGHT), vec2(self.sidebar_width, search::HEIGHT)),
+                    theme,
+                    &self.library,
+                    press_position.filter(|_| was_pressed),
+                );
+                let roots = self.open_folders.iter().map(|open_folder| open_folder.path.clone()).collect_vec();
+                self.search.update(&mut self.index, &self.library, &roots);
+                if self.search.is_active() {
+                    // The crawl keeps filling the index in without any input coming in
+                    ctx.request_repaint_after(search::REFRESH_INTERVAL);
+                }
+                let show_columns = self.sidebar_width >= COLUMNS_MIN_WIDTH;
+                // Building the rows means going through everything that's expanded, so it only
+                // happens when something they come from changed
+                let rows_key = RowsKey {
+                    index: self.index.generation(),
+                    search: self.search.is_active().then(|| self.search.generation()),
+                    recent: self.showing_recent.then(|| self.state.recent_files.clone()),
+                    folders: self
+                        .open_folders
+                        .iter()
+                        .map(|open_folder| {
+                            let expanded = open_folder.expanded_directories.iter().fold(0u64, |hash, path| {
+                                let mut hasher = DefaultHasher::new();
+                                path.hash(&mut hasher);
+                                // Doesn't depend on the order the set hands them out in
+                                hash.wrapping_add(hasher.finish())
+                            });
+                            (open_folder.path.clone(), open_folder.sort, expanded)
+                        })
+                        .collect(),
+                    show_columns,
+                };
+                if self.rows_key.as_ref() != Some(&rows_key) {
+                    // Search results show up flat, there's nothing to expand or sort in there
+                    let folders = if self.search.is_active() {
+                        vec![self.search.results.clone()]
+                    } else if self.showing_recent {
+                        vec![self.state
+                            .recent_files
+                            .iter()
+                            .filter(|path| path.exists())
+                            .map(|path| Entry {
+                                path: path.clone(),
+                                kind: index::entry_kind(path),
+                                indent: 0,
+                            })
+                            .collect_vec()]
+                    } else {
+                        self.open_folders
+                            .iter()
+                            .map(|open_folder| {
+                                self.index.set_sort_mode(&open_folder.path, open_folder.sort);
+                                self.index.entries(&open_folder.path, &open_folder.expanded_directories)
+                            })
+                            .collect_vec()
+                    };
+                    self.folder_of = folders
+                        .iter()
+                        .enumerate()
+                        .flat_map(|(folder, entries)| std::iter::repeat_n(folder, entries.len()))
+                        .collect_vec();
+                    self.rows = folders.into_iter().flatten().collect_vec();
+                    self.tree.columns = if show_columns { ANALYSIS_COLUMNS.to_vec() } else { Vec::new() };
+                    self.tree.set_rows(self.rows.iter().map(|row| RowShape {
+                        height: ROW_HEIGHT,
+                        indent: row.indent,
+                        has_columns: show_columns && row.kind == EntryKind::Audio,
+                    }));
+                    // Sorting by what the listing filled in can change the index again
+                    self.rows_key = Some(RowsKey {
+                        index: self.index.generation(),
+                        ..rows_key
+                    });
+                }
+                self.tree.next_frame();
+                let rows = &self.rows;
+                let folder_of = &self.folder_of;
+                let mut unused_expanded = HashSet::new();
+                let mut open_folders = if self.search.is_active() || self.showing_recent {
+                    vec![(&mut unused_expanded, None)]
+                } else {
+                    self.open_folders
+                        .iter_mut()
+                        .map(|open_folder| (&mut open_folder.expanded_directories, Some(&mut open_folder.sort)))
+                        .collect_vec()
+                };
 
-                // Calculate the maximum offset based on the number of entries and browser height
-                let max_entries = open_folders
-                    .iter()
-                    .map(|(entries, _)| entries.len())
-                    .sum::<usize>();
-                let browser_height = viewport.height() - 90.0; // Adjust for header height
-                let bottom_margin = 8.0; // Add a slight margin at the bottom
-                #[allow(clippy::cast_precision_loss)]
-                let max_offset = (max_entries as f32).mul_add(16.0, -browser_height) + bottom_margin;
+                let list_bottom = viewport.height() - preview::STRIP_HEIGHT;
+                let list_area = Rect::from_min_max(pos2(0., list_top), pos2(self.sidebar_width, list_bottom));
 
-                // Clamp the offset
-                self.offset_y = self.offset_y.clamp(-max_offset.max(0.0), 0.0);
+                let (moved, action) = if self.files.has_dialog() {
+                    (None, None)
+                } else {
+                    self.selection.handle_keys(ctx, rows)
+                };
+                match action {
+                    Some(KeyAction::Activate(index)) => {
+                        activate(
+                            &rows[index],
+                            open_folders[folder_of[index]].0,
+                            &mut self.preview,
+                            &mut self.state,
+                            output,
+                            &mut self.opened_project,
+                        );
+                    }
+                    Some(KeyAction::Expand(index)) => {
+                        open_folders[folder_of[index]].0.insert(rows[index].path.clone());
+                    }
+                    Some(KeyAction::Collapse(index)) => {
+                        open_folders[folder_of[index]].0.remove(&rows[index].path);
+                    }
+                    None => {}
+                }
+                let mut scroll = -self.offset_y;
+                // Keep the cursor on screen
+                if let Some(index) = moved {
+                    scroll = self.tree.scroll_to_row(index, scroll, list_area.height());
+                }
+                self.offset_y = -self.tree.clamp_scroll(scroll, list_area.height());
 
                 // Handle sidebar resizing
                 let resize_rect = Rect::from_min_size(
@@ -336,175 +655,310 @@ impl Browser {
                     }
                 }
 
-                let mut current_y = 90. + self.offset_y;
-                for (entries, expanded_directories) in open_folders {
-                    for entry in entries {
-                        const INDENT_SIZE: f32 = 20.0;
-                        #[allow(clippy::cast_precision_loss)]
-                        let x = INDENT_SIZE * entry.indent as f32;
-                        #[allow(clippy::cast_precision_loss)]
-                        let rect = &Rect::from_min_size(
-                            pos2(0., current_y),
-                            vec2(self.sidebar_width, 16.),
+                // Set while files from here are dragged somewhere, rows don't take clicks then
+                let dragging = DragPayload::current(ctx).is_some();
+                // The few pixels next to the resize handle belong to it
+                let clickable = list_area.with_max_x(self.sidebar_width - 10.);
+                let mut list_ui = ui.child_ui(list_area, *ui.layout(), None);
+                list_ui.set_clip_rect(list_area);
+                let painter = list_ui.painter();
+                let name_font = FontId::new(14., FontFamily::Name("IBMPlexMono".into()));
+                let control_font = FontId::new(12., FontFamily::Name("IBMPlexMono".into()));
+                let column_font = FontId::new(11., FontFamily::Name("IBMPlexMono".into()));
+                for row in self.tree.layout(list_area, -self.offset_y) {
+                    let entry = &rows[row.index];
+                    let (expanded_directories, sort) = &mut open_folders[folder_of[row.index]];
+                    let rect = row.rect;
+                    let mut label_rect = row.label_rect;
+                    if self.selection.contains(entry) {
+                        painter.rect_filled(rect, 0.0, theme.browser_selected_button_fg.gamma_multiply(0.08));
+                    }
+                    if self.selection.cursor.as_ref() == Some(&entry.path) {
+                        painter.rect_stroke(
+                            rect.shrink(0.5),
+                            0.0,
+                            Stroke::new(0.5, theme.browser_selected_button_fg.gamma_multiply(0.4)),
                         );
-                        if current_y >= 90. {
-                            egui::Frame::none().show(ui, |ui| {
-                                ui.allocate_space(ui.available_size());
-                                let mut invalid = false;
-                                let name = entry
-                                    .path
-                                    .file_name()
-                                    .map_or(entry.path.to_str(), |name| name.to_str())
-                                    .map_or_else(
-                                        || {
-                                            invalid = true;
-                                            String::from_utf8_lossy(
-                                                entry.path.file_name().unwrap().as_encoded_bytes(),
-                                            )
-                                            .to_string()
-                                        },
-                                        ToString::to_string,
-                                    );
-                                let chars_to_truncate;
-                                let text_width = ui
-                                    .painter()
-                                    .layout_no_wrap(
-                                        name.clone(),
-                                        FontId::new(14., FontFamily::Name("IBMPlexMono".into())),
-                                        theme.browser_unselected_button_fg,
-                                    )
-                                    .rect
-                                    .width();
-                                let char_width = ui
-                                    .painter()
-                                    .layout_no_wrap(
-                                        "a".to_string(),
-                                        FontId::new(14., FontFamily::Name("IBMPlexMono".into())),
-                                        theme.browser_unselected_button_fg,
-                                    )
-                                    .rect
-                                    .width();
-                                if invalid {
-                                    ui.painter().rect_filled(
-                                        Rect::from_min_size(
-                                            pos2(30., current_y),
-                                            vec2(text_width, 16.),
-                                        ),
-                                        0.0,
-                                        theme.browser_invalid_name_bg,
-                                    );
-                                }
-                                #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-                                {
-                                    chars_to_truncate = (((self.sidebar_width - x) / char_width) - 10.) as usize;
-                                }
-                                ui.painter().text(
-                                    pos2(30. + x, current_y),
-                                    Align2::LEFT_TOP,
-                                    if name.unicode_truncate(chars_to_truncate).1 == chars_to_truncate {
-                                        name.unicode_truncate(chars_to_truncate).0.to_string() + "..."
-                                    } else {
-                                        name
-                                    },
-                                    FontId::new(14., FontFamily::Name("IBMPlexMono".into())),
-                                    match (hovered(ctx, rect), invalid) {
-                                        (true, true) => {
-                                            theme.browser_unselected_hover_button_fg_invalid
-                                        }
-                                        (true, false) => theme.browser_unselected_hover_button_fg,
-                                        (false, true) => theme.browser_unselected_button_fg_invalid,
-                                        (false, false) => theme.browser_unselected_button_fg,
-                                    },
-                                )
-                            });
+                    }
 
-                            Image::new(match entry.kind {
-                                EntryKind::Directory => {
-                                    if expanded_directories.contains(&entry.path) {
-                                        include_image!("images/icons/folder_open.png")
-                                    } else {
-                                        include_image!("images/icons/folder.png")
-                                    }
-                                }
-                                EntryKind::Audio => include_image!("images/icons/audio.png"),
-                                EntryKind::File => include_image!("images/icons/file.png"),
-                            })
-                            .paint_at(ui, Rect::from_min_size(
-                                pos2(10. + x, current_y + 2.),
-                                vec2(14., 14.)
-                            ));
+                    // Open folders get their sort controls on the right of their row
+                    let sort_controls = sort.as_deref().copied().filter(|_| entry.indent == 0).map(|mode| {
+                        (
+                            Rect::from_min_size(pos2(rect.right() - 134., rect.top()), vec2(80., rect.height())),
+                            Rect::from_min_size(pos2(rect.right() - 54., rect.top()), vec2(40., rect.height())),
+                            mode,
+                        )
+                    });
+                    if let Some((key_rect, direction_rect, mode)) = sort_controls {
+                        for (control, text) in [
+                            (direction_rect, if mode.descending { "desc" } else { "asc" }),
+                            (key_rect, &mode.key.to_string()),
+                        ] {
+                            let color = if hovered(ctx, &control) {
+                                theme.browser_unselected_hover_button_fg
+                            } else {
+                                theme.browser_unselected_button_fg
+                            };
+                            let drawn = self.tree.text(painter, control, Align2::RIGHT_CENTER, text, &control_font, color);
+                            label_rect.max.x = label_rect.max.x.min(drawn.left() - 8.);
                         }
-                        if entry.kind == EntryKind::Audio {
-                            let is_dragging = ctx.input(|i| i.pointer.is_decidedly_dragging());
-                            let cursor_pos = ctx.input(|i| i.pointer.hover_pos());
-
-                            if is_dragging
-                                && cursor_pos.is_some()
-                                && rect.contains(cursor_pos.unwrap())
-                                && !self.dragging_audio
-                                && cursor_pos.unwrap().x <= self.sidebar_width - 10.
-                                && !self.started_drag
-                            {
-                                self.dragging_audio = true;
-                                self.dragging_audio_text = entry
-                                    .path
-                                    .file_name()
-                                    .unwrap()
-                                    .to_str()
-                                    .unwrap()
-                                    .to_string();
-                            }
+                    }
 
-                            if let Some(cursor_pos) = cursor_pos {
-                                if self.dragging_audio
-                                    && self.dragging_audio_text == *entry.path.file_name().unwrap().to_str().unwrap()
-                                {
-                                    ui.painter().text(
-                                        cursor_pos + vec2(5.0, 2.0),
-                                        Align2::CENTER_CENTER,
-                                        &self.dragging_audio_text,
-                                        FontId::new(14.0, FontFamily::Name("IBMPlexMono".into())),
-                                        theme.browser_selected_button_fg,
-                                    );
-                                }
-                            }
+                    // Wide enough browsers show what the analysis found
+                    let analysis = self.library
+                        .analysed(&entry.path)
+                        .filter(|_| entry.kind == EntryKind::Audio);
+                    if let Some(analysis) = analysis.filter(|_| !row.columns.is_empty()) {
+                        let cells = [
+                            analysis.sample_type.to_string(),
+                            analysis.bpm.map_or_else(|| "-".to_string(), |bpm| format!("{bpm:.0}")),
+                            analysis.key.map_or_else(|| "-".to_string(), |key| key.to_string()),
+                            analysis.lufs.map_or_else(|| "-".to_string(), |lufs| format!("{lufs:.1}")),
+                        ];
+                        for ((cell, cell_rect), column) in cells.iter().zip(&row.columns).zip(ANALYSIS_COLUMNS) {
+                            self.tree.text(
+                                painter,
+                                *cell_rect,
+                                Align2([column.align, Align::Center]),
+                                cell,
+                                &column_font,
+                                theme.browser_unselected_button_fg,
+                            );
+                        }
+                    }
+
+                    if let Some(info) = self.library.info(&entry.path) {
+                        if let Some(color) = info.color {
+                            painter.rect_filled(
+                                Rect::from_min_size(rect.min + vec2(2., 2.), vec2(3., rect.height() - 4.)),
+                                1.0,
+                                color.color(),
+                            );
+                        }
+                        let stars = "★".repeat(usize::from(info.rating));
+                        let marks = if info.favourite { format!("♥ {stars}") } else { stars };
+                        if sort_controls.is_none() && !marks.is_empty() {
+                            let drawn = self.tree.text(
+                                painter,
+                                label_rect,
+                                Align2::RIGHT_CENTER,
+                                &marks,
+                                &FontId::proportional(11.),
+                                theme.browser_selected_button_fg,
+                            );
+                            label_rect.max.x = drawn.left() - 6.;
+                        }
+                    }
 
-                            if !is_dragging {
-                                self.dragging_audio = false;
-                                self.dragging_audio_text = String::new();
+                    list_ui.interact(rect, list_ui.id().with(&entry.path), Sense::click())
+                        .context_menu(|ui| {
+                            // Right clicking a selected row works on the whole selection
+                            let paths = if self.selection.contains(entry) {
+                                self.selection.paths(rows)
+                            } else {
+                                vec![entry.path.clone()]
+                            };
+                            self.library.menu(ui, &paths);
+                            ui.separator();
+                            self.files.menu(ui, &paths, sort_controls.is_some());
+                        });
+
+                    let name = entry.path.file_name().unwrap_or(entry.path.as_os_str());
+                    let invalid = name.to_str().is_none();
+                    let galley = self.tree.galley(painter, &name.to_string_lossy(), &name_font, label_rect.width());
+                    let name_rect = Align2::LEFT_CENTER.align_size_within_rect(galley.size(), label_rect);
+                    if invalid {
+                        painter.rect_filled(name_rect, 0.0, theme.browser_invalid_name_bg);
+                    }
+                    painter.galley(
+                        name_rect.min,
+                        galley,
+                        match (hovered(ctx, &rect), invalid) {
+                            (true, true) => theme.browser_unselected_hover_button_fg_invalid,
+                            (true, false) => theme.browser_unselected_hover_button_fg,
+                            (false, true) => theme.browser_unselected_button_fg_invalid,
+                            (false, false) => theme.browser_unselected_button_fg,
+                        },
+                    );
+
+                    Image::new(match entry.kind {
+                        EntryKind::Directory => {
+                            if expanded_directories.contains(&entry.path) {
+                                include_image!("images/icons/folder_open.png")
+                            } else {
+                                include_image!("images/icons/folder.png")
                             }
                         }
-                        if press_position.is_some_and(|press_position| {
-                            rect.contains(press_position)
-                            && !self.dragging_audio
-                            // TODO: make these two comparisons part of the `rect.contains` check
-                            && press_position.x <= self.sidebar_width - 10.
-                            && press_position.y >= 90.
-                        }) && was_pressed
+                        EntryKind::Audio => include_image!("images/icons/audio.png"),
+                        EntryKind::File => include_image!("images/icons/file.png"),
+                    })
+                    .paint_at(&list_ui, row.icon_rect);
+
+                    if entry.kind == EntryKind::Audio {
+                        let (is_dragging, press_origin) =
+                            ctx.input(|i| (i.pointer.is_decidedly_dragging(), i.pointer.press_origin()));
+                        if is_dragging
+                            && press_origin.is_some_and(|origin| rect.contains(origin) && clickable.contains(origin))
+                            && !dragging
+                            && !self.started_drag
                         {
-                            match entry.kind {
-                                EntryKind::Directory => {
-                                    if !expanded_directories.insert(entry.path.clone()) {
-                                        expanded_directories.remove(&entry.path);
-                                    }
-                                }
-                                EntryKind::Audio => {
-                                    // TODO: Proper preview implementation with cpal. This is temporary (or at least make it work well with a proper preview widget)
-                                    // Also, don't spawn a new thread - instead, dedicate a thread for preview
-                                    let file = File::open(entry.path.as_path()).unwrap();
-                                    self.preview.play_file(file);
-                                }
-                                EntryKind::File => {
-                                    that_detached(entry.path.clone()).unwrap();
-                                }
+                            // Dragging a selected file takes the rest of the selected audio along
+                            let paths = if self.selection.contains(entry) {
+                                rows.iter()
+                                    .filter(|row| row.kind == EntryKind::Audio && self.selection.contains(row))
+                                    .map(|row| row.path.clone())
+                                    .collect()
+                            } else {
+                                vec![entry.path.clone()]
+                            };
+                            DragPayload { paths }.start(ctx);
+                        }
+                    }
+                    if press_position.is_some_and(|press_position| {
+                        rect.contains(press_position) && clickable.contains(press_position) && !dragging
+                    }) && was_pressed
+                    {
+                        let control = sort_controls.and_then(|(key_rect, direction_rect, sort)| {
+                            let press_position = press_position?;
+                            if key_rect.contains(press_position) {
+                                Some(SortMode { key: sort.key.next(), ..sort })
+                            } else if direction_rect.contains(press_position) {
+                                Some(SortMode { descending: !sort.descending, ..sort })
+                            } else {
+                                None
+                            }
+                        });
+                        if let Some((mode, sort)) = control.zip(sort.as_deref_mut()) {
+                            *sort = mode;
+                            self.state.sort_modes.insert(entry.path.clone(), mode);
+                            if let Err(err) = self.state.save() {
+                                eprintln!("Failed to save the browser state: {err}");
+                            }
+                        } else {
+                            let modifiers = ctx.input(|input| input.modifiers);
+                            self.selection.select(rows, row.index, modifiers);
+                            // Shift and command clicks only change the selection
+                            if !modifiers.shift && !modifiers.command {
+                                activate(
+                                    entry,
+                                    expanded_directories,
+                                    &mut self.preview,
+                                    &mut self.state,
+                                    output,
+                                    &mut self.opened_project,
+                                );
                             }
                         }
-                        current_y += 16.;
+                    }
+                }
+
+                DragPayload::paint(ctx, theme);
+
+                let strip = Rect::from_min_max(pos2(0., list_bottom), pos2(self.sidebar_width, viewport.height()));
+                self.preview.paint_strip(
+                    ctx,
+                    ui,
+                    strip,
+                    theme,
+                    output.map(|output| &output.engine),
+                    press_position.filter(|_| was_pressed && !dragging),
+                );
+                // Files dropped on the strip get previewed, handy for ones dragged out of search results
+                if DragPayload::hovering(ctx, strip).is_some() {
+                    ui.painter().rect_stroke(strip.shrink(1.), 0.0, Stroke::new(1., theme.browser_selected_button_fg));
+                }
+                if let Some((payload, output)) = DragPayload::dropped(ctx, strip).zip(output) {
+                    if let Some(path) = payload.paths.first() {
+                        self.state.add_recent_file(path);
+                        self.preview.select(path.clone(), &output.engine);
                     }
                 }
             }
             Category::Devices => {
                 // TODO: Show some devices here!
+                let mut current_y = 90.;
+                for backend in Backend::iter() {
+                    let rect = Rect::from_min_size(pos2(0., current_y), vec2(self.sidebar_width, 16.));
+                    ui.painter().text(
+                        pos2(10., current_y),
+                        Align2::LEFT_TOP,
+                        format!("Backend: {backend}"),
+                        FontId::new(14., FontFamily::Name("IBMPlexMono".into())),
+                        if self.selected_backend == backend {
+                            theme.browser_selected_button_fg
+                        } else if hovered(ctx, &rect) {
+                            theme.browser_unselected_hover_button_fg
+                        } else {
+                            theme.browser_unselected_button_fg
+                        },
+                    );
+                    if press_position.is_some_and(|press_position| rect.contains(press_position))
+                        && was_pressed
+                    {
+                        self.selected_backend = backend;
+                    }
+                    current_y += 16.;
+                }
+
+                let calibrate_rect = Rect::from_min_size(pos2(0., current_y), vec2(self.sidebar_width, 16.));
+                let can_calibrate = matches!(self.calibration, Calibration::Idle | Calibration::Failed(_));
+                ui.painter().text(
+                    pos2(10., current_y),
+                    Align2::LEFT_TOP,
+                    "Calibrate round-trip latency",
+                    FontId::new(14., FontFamily::Name("IBMPlexMono".into())),
+                    if !can_calibrate {
+                        theme.browser_selected_button_fg
+                    } else if hovered(ctx, &calibrate_rect) {
+                        theme.browser_unselected_hover_button_fg
+                    } else {
+                        theme.browser_unselected_button_fg
+                    },
+                );
+                if press_position.is_some_and(|press_position| calibrate_rect.contains(press_position))
+                    && was_pressed
+                    && can_calibrate
+                {
+                    self.calibration = Calibration::Requested;
+                }
+                current_y += 32.;
+
+                let mut status = match &self.calibration {
+                    Calibration::Idle => vec![],
+                    Calibration::Requested | Calibration::Running => {
+                        vec!["Calibrating, loop the output back into the input".to_string()]
+                    }
+                    Calibration::Failed(err) => vec![format!("Calibration failed: {err}")],
+                };
+                status.extend(output.map_or_else(
+                    || vec!["No audio output running".to_string()],
+                    |output| {
+                        let engine = &output.engine.status;
+                        let sample_rate = engine.sample_rate.load(atomic::Ordering::Relaxed);
+                        vec![
+                            format!("Sample rate: {sample_rate} Hz"),
+                            format!("Xruns: {}", engine.xruns.load(atomic::Ordering::Relaxed)),
+                            format!("Stream errors: {}", engine.stream_errors.load(atomic::Ordering::Relaxed)),
+                            output.recording_offset.map_or_else(
+                                || "Round-trip latency: not calibrated".to_string(),
+                                |frames| format!(
+                                    "Round-trip latency: {frames} frames ({:.1} ms)",
+                                    f64::from(frames) * 1000. / f64::from(sample_rate.max(1)),
+                                ),
+                            ),
+                        ]
+                    },
+                ));
+                for line in status {
+                    ui.painter().text(
+                        pos2(10., current_y),
+                        Align2::LEFT_TOP,
+                        line,
+                        FontId::new(14., FontFamily::Name("IBMPlexMono".into())),
+                        theme.browser_unselected_button_fg,
+                    );
+                    current_y += 16.;
+                }
             }
         }
     }
@@ -0,0 +1,111 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use crate::blerp::{analysis, audiofile};
+
+use super::{
+    library::{FileStamp, StoredAnalysis},
+    Index, Library,
+};
+
+// Anything bigger is a recording or a bounce rather than a sample
+const MAX_FILE_SIZE: u64 = 256 * 1024 * 1024;
+// Results get handed to the library in batches, since every batch means saving it
+pub const APPLY_INTERVAL: Duration = Duration::from_secs(3);
+
+fn analyse(path: &Path) -> Option<StoredAnalysis> {
+    let stamp = FileStamp::of(path)?;
+    let analysis = if stamp.size <= MAX_FILE_SIZE {
+        audiofile::decode(path)
+            .ok()
+            .map(|buffer| analysis::analyse(&buffer))
+    } else {
+        None
+    };
+    Some(StoredAnalysis { stamp, analysis })
+}
+
+fn worker(requests: &Receiver<PathBuf>, results: &Sender<(PathBuf, StoredAnalysis)>) {
+    let mut queue = Vec::new();
+    loop {
+        if queue.is_empty() {
+            match requests.recv() {
+                Ok(path) => queue.push(path),
+                Err(_) => return,
+            }
+        }
+        queue.extend(requests.try_iter());
+        // Newest first, whatever was just opened is what's being looked at
+        let Some(path) = queue.pop() else {
+            continue;
+        };
+        if let Some(stored) = analyse(&path) {
+            if results.send((path, stored)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+// Works out tempo, key, loudness and type for every audio file the index comes across
+pub struct Analyser {
+    requests: Sender<PathBuf>,
+    results: Receiver<(PathBuf, StoredAnalysis)>,
+    queued: HashSet<PathBuf>,
+    applied_at: Instant,
+}
+
+impl Analyser {
+    pub fn new() -> Self {
+        let (requests, request_receiver) = channel();
+        let (result_sender, results) = channel();
+        thread::spawn(move || worker(&request_receiver, &result_sender));
+        Self {
+            requests,
+            results,
+            queued: HashSet::new(),
+            applied_at: Instant::now(),
+        }
+    }
+
+    pub fn is_busy(&self) -> bool {
+        !self.queued.is_empty()
+    }
+
+    pub fn update(&mut self, index: &mut Index, library: &mut Library) {
+        let mut bpms = Vec::new();
+        for (path, stamp) in index.take_audio() {
+            if let Some(stored) = library.analysis(&path, stamp) {
+                bpms.extend(stored.analysis.and_then(|analysis| analysis.bpm).map(|bpm| (path, bpm)));
+            } else if self.queued.insert(path.clone()) {
+                let _ = self.requests.send(path);
+            }
+        }
+
+        if self.applied_at.elapsed() >= APPLY_INTERVAL {
+            let results: Vec<_> = self.results.try_iter().collect();
+            if !results.is_empty() {
+                for (path, stored) in &results {
+                    self.queued.remove(path);
+                    bpms.extend(
+                        stored
+                            .analysis
+                            .and_then(|analysis| analysis.bpm)
+                            .map(|bpm| (path.clone(), bpm)),
+                    );
+                }
+                library.add_analyses(results);
+            }
+            self.applied_at = Instant::now();
+        }
+
+        if !bpms.is_empty() {
+            index.set_bpms(bpms);
+        }
+    }
+}
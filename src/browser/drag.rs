@@ -0,0 +1,74 @@
+use std::{path::PathBuf, sync::Arc};
+
+use egui::{Align2, Context, DragAndDrop, FontFamily, FontId, Id, LayerId, Order, Rect, Vec2};
+
+use super::{index, EntryKind};
+use crate::visual::ThemeColors;
+
+// Files dragged out of the browser. It lives in egui's drag and drop state, so whatever takes
+// files can pick it up from there without knowing about the browser. Timeline tracks and the
+// preview strip are the only drop targets for now: there are no sampler or instrument slots yet,
+// and egui can't start a drag the system sees, so other applications get paths through "Copy path"
+// instead
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DragPayload {
+    pub paths: Vec<PathBuf>,
+}
+
+impl DragPayload {
+    pub fn label(&self) -> String {
+        match self.paths.as_slice() {
+            [path] => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            paths => format!("{} samples", paths.len()),
+        }
+    }
+
+    // Only the audio files, for targets that can't take anything else
+    pub fn audio_files(&self) -> impl Iterator<Item = &PathBuf> {
+        self.paths
+            .iter()
+            .filter(|path| path.is_file() && index::file_kind(path) == EntryKind::Audio)
+    }
+
+    pub fn start(self, ctx: &Context) {
+        DragAndDrop::set_payload(ctx, self);
+    }
+
+    // Whatever is being dragged right now. egui drops it by itself once the pointer is released
+    pub fn current(ctx: &Context) -> Option<Arc<Self>> {
+        DragAndDrop::payload(ctx)
+    }
+
+    // What's being held over `rect`, for highlighting drop targets
+    pub fn hovering(ctx: &Context, rect: Rect) -> Option<Arc<Self>> {
+        let over = ctx
+            .pointer_hover_pos()
+            .is_some_and(|position| rect.contains(position));
+        Self::current(ctx).filter(|_| over)
+    }
+
+    // What got let go of over `rect` this frame. It's taken, so nothing underneath gets it too
+    pub fn dropped(ctx: &Context, rect: Rect) -> Option<Arc<Self>> {
+        let released = ctx.input(|input| input.pointer.any_released());
+        if released && Self::hovering(ctx, rect).is_some() {
+            DragAndDrop::take_payload(ctx)
+        } else {
+            None
+        }
+    }
+
+    // The name of what's being dragged next to the cursor, above everything else
+    pub fn paint(ctx: &Context, theme: &ThemeColors) {
+        let (Some(payload), Some(position)) = (Self::current(ctx), ctx.pointer_hover_pos()) else {
+            return;
+        };
+        ctx.layer_painter(LayerId::new(Order::Tooltip, Id::new("browser_drag")))
+            .text(
+                position + Vec2::new(12., 0.),
+                Align2::LEFT_CENTER,
+                payload.label(),
+                FontId::new(14.0, FontFamily::Name("IBMPlexMono".into())),
+                theme.browser_selected_button_fg,
+            );
+    }
+}
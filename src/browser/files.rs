@@ -0,0 +1,445 @@
+use std::{
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+};
+
+use egui::{Align2, ComboBox, Context, Key, TextEdit, Ui, Window};
+use itertools::Itertools;
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::blerp::{
+    audiofile,
+    wavefile::{self, WaveAudioFormat},
+};
+
+use super::{index, EntryKind, Library};
+
+const SAMPLE_RATES: [u32; 4] = [44100, 48000, 88200, 96000];
+
+#[derive(Display, EnumIter, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConvertFormat {
+    #[strum(to_string = "WAV 16-bit")]
+    Wav16,
+    #[strum(to_string = "WAV 32-bit")]
+    Wav32,
+    #[strum(to_string = "WAV 32-bit float")]
+    Wav32Float,
+}
+
+impl ConvertFormat {
+    const fn bits_per_sample(self) -> u16 {
+        match self {
+            Self::Wav16 => 16,
+            Self::Wav32 | Self::Wav32Float => 32,
+        }
+    }
+
+    const fn short_name(self) -> &'static str {
+        match self {
+            Self::Wav16 => "16-bit",
+            Self::Wav32 => "32-bit",
+            Self::Wav32Float => "32-bit float",
+        }
+    }
+
+    const fn audio_format(self) -> WaveAudioFormat {
+        match self {
+            Self::Wav16 | Self::Wav32 => WaveAudioFormat::PulseCodeModulation,
+            Self::Wav32Float => WaveAudioFormat::FloatingPoint,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Conversion {
+    pub format: ConvertFormat,
+    // `None` keeps the rate the file already has
+    pub sample_rate: Option<u32>,
+}
+
+fn rate_label(sample_rate: Option<u32>) -> String {
+    sample_rate.map_or_else(|| "Keep rate".to_string(), |rate| format!("{rate} Hz"))
+}
+
+// Next free name next to `path`: "kick copy.wav", "kick copy 2.wav" and so on
+fn free_name(path: &Path, suffix: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|number| {
+            let name = if number == 1 {
+                format!("{stem} {suffix}{extension}")
+            } else {
+                format!("{stem} {suffix} {number}{extension}")
+            };
+            path.with_file_name(name)
+        })
+        .find(|candidate| !candidate.exists())
+        .unwrap()
+}
+
+#[cfg(unix)]
+fn copy_link(from: &Path, to: &Path) -> io::Result<()> {
+    std::os::unix::fs::symlink(fs::read_link(from)?, to)
+}
+
+#[cfg(windows)]
+fn copy_link(from: &Path, to: &Path) -> io::Result<()> {
+    let target = fs::read_link(from)?;
+    // Windows wants to know which kind of link it is, going by what it points to
+    if fs::metadata(from).is_ok_and(|metadata| metadata.is_dir()) {
+        std::os::windows::fs::symlink_dir(target, to)
+    } else {
+        std::os::windows::fs::symlink_file(target, to)
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+fn copy_link(_: &Path, _: &Path) -> io::Result<()> {
+    Err(io::Error::new(io::ErrorKind::Unsupported, "can't copy symlinks on this platform"))
+}
+
+// Symlinks get copied as links instead of followed, so one pointing at a folder above can't make
+// this go on forever
+fn copy_recursively(from: &Path, to: &Path) -> io::Result<()> {
+    let metadata = fs::symlink_metadata(from)?;
+    if metadata.is_symlink() {
+        return copy_link(from, to);
+    }
+    if !metadata.is_dir() {
+        return fs::copy(from, to).map(|_| ());
+    }
+    fs::create_dir(to)?;
+    for child in fs::read_dir(from)? {
+        let child = child?;
+        copy_recursively(&child.path(), &to.join(child.file_name()))?;
+    }
+    Ok(())
+}
+
+fn duplicate(path: &Path) -> io::Result<PathBuf> {
+    let copy = free_name(path, "copy");
+    copy_recursively(path, &copy)?;
+    Ok(copy)
+}
+
+fn rename(path: &Path, name: &str) -> io::Result<PathBuf> {
+    let name = name.trim();
+    if name.is_empty() || name.contains(['/', '\\']) {
+        return Err(io::Error::new(io::ErrorKind::InvalidInput, "not a valid file name"));
+    }
+    let renamed = path.with_file_name(name);
+    // fs::rename happily replaces files, which is never what renaming in the browser means
+    if renamed.exists() {
+        return Err(io::Error::new(
+            io::ErrorKind::AlreadyExists,
+            format!("{name} already exists"),
+        ));
+    }
+    fs::rename(path, &renamed)?;
+    Ok(renamed)
+}
+
+// Shows the file selected in its folder where the platform can do that, otherwise just opens the folder
+fn reveal(path: &Path) -> io::Result<()> {
+    if cfg!(target_os = "macos") {
+        std::process::Command::new("open").arg("-R").arg(path).spawn().map(|_| ())
+    } else if cfg!(target_os = "windows") {
+        let mut select = OsString::from("/select,");
+        select.push(path);
+        std::process::Command::new("explorer").arg(select).spawn().map(|_| ())
+    } else {
+        open::that_detached(path.parent().unwrap_or(path))
+    }
+}
+
+fn convert(path: &Path, conversion: Conversion) -> Result<PathBuf, String> {
+    let buffer = audiofile::decode(path).map_err(|err| err.to_string())?;
+    let buffer = match conversion.sample_rate {
+        Some(sample_rate) => buffer.resampled_band_limited(sample_rate),
+        None => buffer,
+    };
+    let frames = u32::try_from(buffer.frames()).map_err(|_| "too long for a WAV file".to_string())?;
+    let suffix = format!("{} Hz {}", buffer.sample_rate, conversion.format.short_name());
+    let target = free_name(&path.with_extension("wav"), &suffix);
+    wavefile::write_wav_file_f32(
+        &target,
+        &buffer.samples,
+        buffer.sample_rate,
+        buffer.channels,
+        conversion.format.bits_per_sample(),
+        frames,
+        conversion.format.audio_format(),
+    )
+    .map_err(|err| err.to_string())?;
+    Ok(target)
+}
+
+fn describe(paths: &[PathBuf]) -> String {
+    match paths {
+        [path] => path.file_name().unwrap_or_default().to_string_lossy().to_string(),
+        paths => format!("{} files", paths.len()),
+    }
+}
+
+enum Dialog {
+    Rename { path: PathBuf, name: String },
+    Trash(Vec<PathBuf>),
+    Convert { paths: Vec<PathBuf>, conversion: Conversion },
+    Errors(Vec<String>),
+}
+
+// Renaming, duplicating, trashing and converting files from the browser's right click menu
+pub struct FileManager {
+    dialog: Option<Dialog>,
+    removed_root: Option<PathBuf>,
+    result_sender: Sender<Result<PathBuf, String>>,
+    results: Receiver<Result<PathBuf, String>>,
+    // Duplicates and conversions still running in the background
+    working: usize,
+}
+
+impl FileManager {
+    pub fn new() -> Self {
+        let (result_sender, results) = channel();
+        Self {
+            dialog: None,
+            removed_root: None,
+            result_sender,
+            results,
+            working: 0,
+        }
+    }
+
+    pub const fn is_busy(&self) -> bool {
+        self.working > 0
+    }
+
+    // While a dialog is up the keyboard belongs to it
+    pub const fn has_dialog(&self) -> bool {
+        self.dialog.is_some()
+    }
+
+    // Open folders can't be removed while the browser is going through them, so it asks afterwards
+    pub fn take_removed_root(&mut self) -> Option<PathBuf> {
+        self.removed_root.take()
+    }
+
+    fn fail(&mut self, error: String) {
+        match &mut self.dialog {
+            Some(Dialog::Errors(errors)) => errors.push(error),
+            _ => self.dialog = Some(Dialog::Errors(vec![error])),
+        }
+    }
+
+    pub fn update(&mut self) {
+        let results: Vec<_> = self.results.try_iter().collect();
+        self.working -= results.len();
+        for result in results {
+            if let Err(err) = result {
+                self.fail(err);
+            }
+        }
+    }
+
+    // Goes under the library entries in the right click menu. `root` is set for open folders
+    pub fn menu(&mut self, ui: &mut Ui, paths: &[PathBuf], root: bool) {
+        let Some(first) = paths.first() else {
+            return;
+        };
+        // Open folders are only renamed or deleted from outside, the browser would lose track of them
+        if paths.len() == 1 && !root && ui.button("Rename…").clicked() {
+            self.dialog = Some(Dialog::Rename {
+                path: first.clone(),
+                name: first.file_name().unwrap_or_default().to_string_lossy().to_string(),
+            });
+            ui.close_menu();
+        }
+        if !root && ui.button("Duplicate").clicked() {
+            let paths = paths.to_vec();
+            self.working += paths.len();
+            let results = self.result_sender.clone();
+            // Folders can be big, so copying happens off the UI thread
+            thread::spawn(move || {
+                for path in paths {
+                    let result = duplicate(&path)
+                        .map_err(|err| format!("Couldn't duplicate {}: {err}", path.display()));
+                    if results.send(result).is_err() {
+                        return;
+                    }
+                }
+            });
+            ui.close_menu();
+        }
+        if !root && ui.button("Move to trash…").clicked() {
+            self.dialog = Some(Dialog::Trash(paths.to_vec()));
+            ui.close_menu();
+        }
+        let audio = paths
+            .iter()
+            .filter(|path| index::file_kind(path) == EntryKind::Audio)
+            .cloned()
+            .collect_vec();
+        if !audio.is_empty() && ui.button("Convert…").clicked() {
+            self.dialog = Some(Dialog::Convert {
+                paths: audio,
+                conversion: Conversion {
+                    format: ConvertFormat::Wav16,
+                    sample_rate: None,
+                },
+            });
+            ui.close_menu();
+        }
+        ui.separator();
+        if ui.button("Reveal in file manager").clicked() {
+            if let Err(err) = reveal(first) {
+                self.fail(format!("Couldn't open the file manager: {err}"));
+            }
+            ui.close_menu();
+        }
+        // Stands in for dragging files out to other applications
+        let copy = if paths.len() == 1 { "Copy path" } else { "Copy paths" };
+        if ui.button(copy).clicked() {
+            ui.ctx().copy_text(paths.iter().map(|path| path.display().to_string()).join("\n"));
+            ui.close_menu();
+        }
+        if root && ui.button("Remove from browser").clicked() {
+            self.removed_root = Some(first.clone());
+            ui.close_menu();
+        }
+    }
+
+    // Whichever dialog the menu asked for, centered over everything else
+    pub fn paint_dialogs(&mut self, ctx: &Context, library: &mut Library) {
+        let Some(dialog) = &mut self.dialog else {
+            return;
+        };
+        let title = match dialog {
+            Dialog::Rename { .. } => "Rename",
+            Dialog::Trash(_) => "Move to trash",
+            Dialog::Convert { .. } => "Convert",
+            Dialog::Errors(_) => "Something went wrong",
+        };
+        let mut close = false;
+        let mut confirmed = false;
+        Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0., 0.])
+            .show(ctx, |ui| {
+                match dialog {
+                    Dialog::Rename { name, .. } => {
+                        let response = ui.add(TextEdit::singleline(name));
+                        if !response.has_focus() && !response.lost_focus() {
+                            response.request_focus();
+                        }
+                        confirmed = response.lost_focus() && ui.input(|input| input.key_pressed(Key::Enter));
+                    }
+                    Dialog::Trash(paths) => {
+                        ui.label(format!("Move {} to the trash?", describe(paths)));
+                    }
+                    Dialog::Convert { paths, conversion } => {
+                        ui.label(format!("Convert {} to WAV", describe(paths)));
+                        ComboBox::from_label("Format")
+                            .selected_text(conversion.format.to_string())
+                            .show_ui(ui, |ui| {
+                                for format in ConvertFormat::iter() {
+                                    ui.selectable_value(&mut conversion.format, format, format.to_string());
+                                }
+                            });
+                        ComboBox::from_label("Sample rate")
+                            .selected_text(rate_label(conversion.sample_rate))
+                            .show_ui(ui, |ui| {
+                                for sample_rate in [None].into_iter().chain(SAMPLE_RATES.map(Some)) {
+                                    ui.selectable_value(&mut conversion.sample_rate, sample_rate, rate_label(sample_rate));
+                                }
+                            });
+                    }
+                    Dialog::Errors(errors) => {
+                        for error in errors.iter() {
+                            ui.label(error);
+                        }
+                    }
+                }
+                ui.horizontal(|ui| {
+                    if matches!(dialog, Dialog::Errors(_)) {
+                        close = ui.button("OK").clicked();
+                    } else {
+                        let action = match dialog {
+                            Dialog::Trash(_) => "Move to trash",
+                            Dialog::Convert { .. } => "Convert",
+                            _ => "Rename",
+                        };
+                        confirmed |= ui.button(action).clicked();
+                        close = ui.button("Cancel").clicked() || ui.input(|input| input.key_pressed(Key::Escape));
+                    }
+                });
+            });
+
+        if confirmed {
+            self.confirm(library);
+        } else if close {
+            self.dialog = None;
+        }
+    }
+
+    fn confirm(&mut self, library: &mut Library) {
+        match self.dialog.take() {
+            Some(Dialog::Rename { path, name }) => match rename(&path, &name) {
+                Ok(renamed) => library.moved(&path, &renamed),
+                Err(err) => self.fail(format!("Couldn't rename {}: {err}", path.display())),
+            },
+            Some(Dialog::Trash(paths)) => match trash::delete_all(&paths) {
+                Ok(()) => library.removed(&paths),
+                Err(err) => self.fail(format!("Couldn't move {} to the trash: {err}", describe(&paths))),
+            },
+            Some(Dialog::Convert { paths, conversion }) => {
+                self.working += paths.len();
+                let results = self.result_sender.clone();
+                // Decoding whole files takes a while, so it happens off the UI thread
+                thread::spawn(move || {
+                    for path in paths {
+                        let result = convert(&path, conversion)
+                            .map_err(|err| format!("Couldn't convert {}: {err}", path.display()));
+                        if results.send(result).is_err() {
+                            return;
+                        }
+                    }
+                });
+            }
+            Some(Dialog::Errors(_)) | None => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::copy_recursively;
+
+    #[cfg(unix)]
+    #[test]
+    fn symlinks_get_copied_as_links() {
+        let root = std::env::temp_dir().join(format!("volt-copy-test-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        let from = root.join("from");
+        fs::create_dir_all(from.join("inner")).unwrap();
+        fs::write(from.join("inner/kick.wav"), b"kick").unwrap();
+        // Following this one would copy forever
+        std::os::unix::fs::symlink(&from, from.join("inner/loop")).unwrap();
+
+        let to = root.join("to");
+        copy_recursively(&from, &to).unwrap();
+        assert_eq!(fs::read(to.join("inner/kick.wav")).unwrap(), b"kick");
+        assert!(fs::symlink_metadata(to.join("inner/loop")).unwrap().is_symlink());
+        assert_eq!(fs::read_link(to.join("inner/loop")).unwrap(), from);
+        fs::remove_dir_all(&root).unwrap();
+    }
+}
@@ -0,0 +1,502 @@
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{metadata, read_dir, symlink_metadata},
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::SystemTime,
+};
+
+use indextree::{Arena, NodeId};
+use notify::{RecursiveMode, Watcher};
+
+use crate::blerp::audiofile;
+
+use super::{
+    library::FileStamp,
+    sort::{self, SortKey, SortMode},
+    Entry, EntryKind,
+};
+
+const AUDIO_EXTENSIONS: [&str; 6] = ["wav", "wave", "mp3", "ogg", "flac", "opus"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Listing {
+    Unlisted,
+    Pending,
+    Listed,
+    // Read by a search crawl, which doesn't watch for changes
+    Crawled,
+    Failed,
+}
+
+#[derive(Debug, Clone)]
+pub struct IndexEntry {
+    pub path: PathBuf,
+    pub kind: EntryKind,
+    pub listing: Listing,
+    pub size: Option<u64>,
+    pub modified: Option<SystemTime>,
+    // Seconds, filled in after the listing since it means opening the file
+    pub duration: Option<f64>,
+    pub bpm: Option<f64>,
+}
+
+impl IndexEntry {
+    fn new(path: PathBuf, kind: EntryKind) -> Self {
+        Self {
+            path,
+            kind,
+            listing: Listing::Unlisted,
+            size: None,
+            modified: None,
+            duration: None,
+            bpm: None,
+        }
+    }
+}
+
+enum Job {
+    List(PathBuf),
+    Changed(Vec<PathBuf>),
+}
+
+enum Update {
+    Listed(PathBuf, Vec<IndexEntry>),
+    Crawled(PathBuf, Vec<IndexEntry>),
+    Durations(Vec<(PathBuf, f64)>),
+    Failed(PathBuf),
+}
+
+// Cached tree of everything under the open folders. Directories get read on a worker thread
+// the first time they're shown, and read again whenever the file watcher says they changed
+pub struct Index {
+    arena: Arena<IndexEntry>,
+    nodes: HashMap<PathBuf, NodeId>,
+    // Keyed by open folder, everything below one uses its mode
+    sort_modes: HashMap<PathBuf, SortMode>,
+    jobs: Sender<Job>,
+    update_sender: Sender<Update>,
+    updates: Receiver<Update>,
+    crawled: HashSet<PathBuf>,
+    // Bumped whenever the tree changes, so searches know when to run again
+    generation: u64,
+    // Audio files seen in listings since the last `take_audio`
+    audio: Vec<(PathBuf, FileStamp)>,
+}
+
+// Only looks at the extension, for paths already known not to be directories
+pub fn file_kind(path: &Path) -> EntryKind {
+    if path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| {
+            AUDIO_EXTENSIONS
+                .iter()
+                .any(|audio| audio.eq_ignore_ascii_case(extension))
+        })
+    {
+        EntryKind::Audio
+    } else {
+        EntryKind::File
+    }
+}
+
+pub fn entry_kind(path: &Path) -> EntryKind {
+    if path.is_dir() {
+        EntryKind::Directory
+    } else {
+        file_kind(path)
+    }
+}
+
+fn list(path: &Path) -> Option<Vec<IndexEntry>> {
+    Some(
+        read_dir(path)
+            .ok()?
+            .filter_map(Result::ok)
+            .map(|entry| {
+                let path = entry.path();
+                let kind = match entry.file_type() {
+                    Ok(file_type) if file_type.is_dir() => EntryKind::Directory,
+                    Ok(file_type) if file_type.is_file() => file_kind(&path),
+                    // Symlinks need the extra stat to find out what they point to
+                    _ => entry_kind(&path),
+                };
+                // Follows symlinks, so the size is that of the file they point to
+                let metadata = metadata(&path).ok();
+                IndexEntry {
+                    size: metadata
+                        .as_ref()
+                        .filter(|_| kind != EntryKind::Directory)
+                        .map(std::fs::Metadata::len),
+                    modified: metadata.and_then(|metadata| metadata.modified().ok()),
+                    ..IndexEntry::new(path, kind)
+                }
+            })
+            .collect(),
+    )
+}
+
+fn durations(audio: Vec<PathBuf>) -> Vec<(PathBuf, f64)> {
+    audio
+        .into_iter()
+        .filter_map(|path| audiofile::duration(&path).map(|duration| (path, duration)))
+        .collect()
+}
+
+fn audio_paths(children: &[IndexEntry]) -> Vec<PathBuf> {
+    children
+        .iter()
+        .filter(|child| child.kind == EntryKind::Audio)
+        .map(|child| child.path.clone())
+        .collect()
+}
+
+// Hidden folders and the kernel's pseudo filesystems are never worth searching through
+fn crawlable(path: &Path) -> bool {
+    const SKIPPED: [&str; 4] = ["/proc", "/sys", "/dev", "/run"];
+    !path
+        .file_name()
+        .is_some_and(|name| name.to_string_lossy().starts_with('.'))
+        && !SKIPPED.iter().any(|skipped| path == Path::new(skipped))
+        // Following symlinks could loop forever
+        && symlink_metadata(path).is_ok_and(|metadata| metadata.is_dir())
+}
+
+// Reads everything below an open folder so it can be searched. Runs on its own thread so expanding
+// folders never waits on it, and doesn't watch what it reads since that would use up the watches
+fn crawl(root: PathBuf, updates: &Sender<Update>) {
+    let mut queue = VecDeque::from([root]);
+    while let Some(path) = queue.pop_front() {
+        let Some(children) = list(&path) else {
+            continue;
+        };
+        queue.extend(
+            children
+                .iter()
+                .filter(|child| child.kind == EntryKind::Directory && crawlable(&child.path))
+                .map(|child| child.path.clone()),
+        );
+        let audio = audio_paths(&children);
+        if updates.send(Update::Crawled(path, children)).is_err() {
+            return;
+        }
+        let durations = durations(audio);
+        if !durations.is_empty() && updates.send(Update::Durations(durations)).is_err() {
+            return;
+        }
+    }
+}
+
+fn worker(jobs: &Receiver<Job>, job_sender: Sender<Job>, updates: &Sender<Update>) {
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = job_sender.send(Job::Changed(event.paths));
+        }
+    })
+    .map_err(|err| eprintln!("File watching is unavailable, the browser won't pick up changes: {err}"))
+    .ok();
+    let mut watched = HashSet::new();
+
+    while let Ok(job) = jobs.recv() {
+        // Coalesce bursts (copying a folder of samples fires an event per file)
+        let mut to_list = Vec::new();
+        for job in [job].into_iter().chain(jobs.try_iter()) {
+            match job {
+                Job::List(path) => to_list.push(path),
+                Job::Changed(paths) => {
+                    for path in paths {
+                        if watched.contains(&path) {
+                            to_list.push(path.clone());
+                        }
+                        if let Some(parent) = path.parent().filter(|parent| watched.contains(*parent)) {
+                            to_list.push(parent.to_path_buf());
+                        }
+                    }
+                }
+            }
+        }
+        to_list.sort_unstable();
+        to_list.dedup();
+
+        for path in to_list {
+            let mut audio = Vec::new();
+            let update = match list(&path) {
+                Some(children) => {
+                    audio = audio_paths(&children);
+                    if let Some(watcher) = watcher.as_mut() {
+                        if !watched.contains(&path)
+                            && watcher.watch(&path, RecursiveMode::NonRecursive).is_ok()
+                        {
+                            watched.insert(path.clone());
+                        }
+                    }
+                    Update::Listed(path, children)
+                }
+                None => {
+                    if watched.remove(&path) {
+                        if let Some(watcher) = watcher.as_mut() {
+                            let _ = watcher.unwatch(&path);
+                        }
+                    }
+                    Update::Failed(path)
+                }
+            };
+            if updates.send(update).is_err() {
+                return;
+            }
+            // Sent separately so the folder shows up before every file in it got opened
+            let durations = durations(audio);
+            if !durations.is_empty() && updates.send(Update::Durations(durations)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+impl Index {
+    pub fn new() -> Self {
+        let (jobs, job_receiver) = channel();
+        let (update_sender, updates) = channel();
+        let job_sender = jobs.clone();
+        let worker_updates = update_sender.clone();
+        thread::spawn(move || worker(&job_receiver, job_sender, &worker_updates));
+        Self {
+            arena: Arena::new(),
+            nodes: HashMap::new(),
+            sort_modes: HashMap::new(),
+            jobs,
+            update_sender,
+            updates,
+            crawled: HashSet::new(),
+            generation: 0,
+            audio: Vec::new(),
+        }
+    }
+
+    pub fn root(&mut self, path: &Path) -> NodeId {
+        if let Some(node) = self.nodes.get(path) {
+            return *node;
+        }
+        let node = self
+            .arena
+            .new_node(IndexEntry::new(path.to_path_buf(), entry_kind(path)));
+        self.nodes.insert(path.to_path_buf(), node);
+        node
+    }
+
+    pub fn get(&self, node: NodeId) -> Option<&IndexEntry> {
+        self.arena.get(node).map(indextree::Node::get)
+    }
+
+    // Asks the worker to read the directory if nobody did yet
+    pub fn request_listing(&mut self, node: NodeId) {
+        if let Some(entry) = self.arena.get_mut(node).map(indextree::Node::get_mut) {
+            if entry.kind == EntryKind::Directory
+                && matches!(entry.listing, Listing::Unlisted | Listing::Crawled)
+            {
+                entry.listing = Listing::Pending;
+                let _ = self.jobs.send(Job::List(entry.path.clone()));
+            }
+        }
+    }
+
+    fn remove_subtree(&mut self, node: NodeId) {
+        for descendant in node.descendants(&self.arena) {
+            self.nodes.remove(&self.arena[descendant].get().path);
+        }
+        node.remove_subtree(&mut self.arena);
+    }
+
+    fn sort_mode(&self, node: NodeId) -> SortMode {
+        node.ancestors(&self.arena)
+            .find_map(|ancestor| self.sort_modes.get(&self.arena[ancestor].get().path))
+            .copied()
+            .unwrap_or_default()
+    }
+
+    // Siblings get sorted among themselves, so children always stay right under their parent
+    fn sort_children(&mut self, node: NodeId) {
+        let mode = self.sort_mode(node);
+        let mut children: Vec<_> = node.children(&self.arena).collect();
+        children.sort_by(|a, b| sort::compare(self.arena[*a].get(), self.arena[*b].get(), mode));
+        for child in children {
+            child.detach(&mut self.arena);
+            node.append(child, &mut self.arena);
+        }
+    }
+
+    pub fn set_sort_mode(&mut self, root: &Path, mode: SortMode) {
+        if self.sort_modes.insert(root.to_path_buf(), mode) == Some(mode) {
+            return;
+        }
+        let root = self.root(root);
+        let directories: Vec<_> = root
+            .descendants(&self.arena)
+            .filter(|node| {
+                matches!(
+                    self.arena[*node].get().listing,
+                    Listing::Listed | Listing::Crawled
+                )
+            })
+            .collect();
+        for directory in directories {
+            self.sort_children(directory);
+        }
+    }
+
+    // Reads everything below the folder in the background, once
+    pub fn crawl(&mut self, root: &Path) {
+        if self.crawled.insert(root.to_path_buf()) {
+            let updates = self.update_sender.clone();
+            let root = root.to_path_buf();
+            thread::spawn(move || crawl(root, &updates));
+        }
+    }
+
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    // Everything known below an open folder, not including the folder itself
+    pub fn descendants(&mut self, root: &Path) -> impl Iterator<Item = &IndexEntry> {
+        let root = self.root(root);
+        root.descendants(&self.arena)
+            .skip(1)
+            .map(|node| self.arena[node].get())
+    }
+
+    fn apply_listing(&mut self, path: &Path, children: Vec<IndexEntry>, listing: Listing) {
+        let Some(node) = self.nodes.get(path).copied() else {
+            return;
+        };
+        let existing: HashMap<PathBuf, NodeId> = node
+            .children(&self.arena)
+            .map(|child| (self.arena[child].get().path.clone(), child))
+            .collect();
+        let current: HashSet<&PathBuf> = children.iter().map(|child| &child.path).collect();
+        for (path, child) in &existing {
+            if !current.contains(path) {
+                self.remove_subtree(*child);
+            }
+        }
+
+        for child_entry in children {
+            if let Some(child) = existing.get(&child_entry.path).copied() {
+                let entry = self.arena[child].get_mut();
+                if entry.kind == child_entry.kind {
+                    entry.size = child_entry.size;
+                    entry.modified = child_entry.modified;
+                } else {
+                    *entry = child_entry;
+                }
+            } else {
+                let path = child_entry.path.clone();
+                let child = self.arena.new_node(child_entry);
+                node.append(child, &mut self.arena);
+                self.nodes.insert(path, child);
+            }
+        }
+        for child in node.children(&self.arena) {
+            let child = self.arena[child].get();
+            if child.kind == EntryKind::Audio {
+                self.audio.push((
+                    child.path.clone(),
+                    FileStamp {
+                        size: child.size.unwrap_or_default(),
+                        modified: child.modified,
+                    },
+                ));
+            }
+        }
+        let entry = self.arena[node].get_mut();
+        // A crawl doesn't make a watched folder any less watched
+        if !(listing == Listing::Crawled
+            && matches!(entry.listing, Listing::Listed | Listing::Pending))
+        {
+            entry.listing = listing;
+        }
+        self.sort_children(node);
+    }
+
+    // Fills in values that show up after the listing, re-sorting the folders that sort by them
+    fn fill_in(
+        &mut self,
+        values: Vec<(PathBuf, f64)>,
+        key: SortKey,
+        field: fn(&mut IndexEntry) -> &mut Option<f64>,
+    ) {
+        let mut parents = HashSet::new();
+        for (path, value) in values {
+            if let Some(node) = self.nodes.get(&path).copied() {
+                *field(self.arena[node].get_mut()) = Some(value);
+                parents.extend(self.arena[node].parent());
+            }
+        }
+        for parent in parents {
+            if self.sort_mode(parent).key == key {
+                self.sort_children(parent);
+            }
+        }
+    }
+
+    pub fn set_bpms(&mut self, bpms: Vec<(PathBuf, f64)>) {
+        self.generation += 1;
+        self.fill_in(bpms, SortKey::Bpm, |entry| &mut entry.bpm);
+    }
+
+    pub fn take_audio(&mut self) -> Vec<(PathBuf, FileStamp)> {
+        std::mem::take(&mut self.audio)
+    }
+
+    // Applies whatever the worker finished since the last frame
+    pub fn update(&mut self) {
+        while let Ok(update) = self.updates.try_recv() {
+            self.generation += 1;
+            match update {
+                Update::Listed(path, children) => {
+                    self.apply_listing(&path, children, Listing::Listed);
+                }
+                Update::Crawled(path, children) => {
+                    self.apply_listing(&path, children, Listing::Crawled);
+                }
+                Update::Durations(durations) => {
+                    self.fill_in(durations, SortKey::Duration, |entry| &mut entry.duration);
+                }
+                Update::Failed(path) => {
+                    if let Some(node) = self.nodes.get(&path).copied() {
+                        let children: Vec<_> = node.children(&self.arena).collect();
+                        for child in children {
+                            self.remove_subtree(child);
+                        }
+                        self.arena[node].get_mut().listing = Listing::Failed;
+                    }
+                }
+            }
+        }
+    }
+
+    // Flattens an open folder into rows, descending into expanded directories only
+    pub fn entries(&mut self, root: &Path, expanded_directories: &HashSet<PathBuf>) -> Vec<Entry> {
+        let root = self.root(root);
+        let mut entries = Vec::new();
+        let mut stack = vec![(root, 0)];
+        while let Some((node, indent)) = stack.pop() {
+            let Some(entry) = self.get(node) else {
+                continue;
+            };
+            entries.push(Entry {
+                path: entry.path.clone(),
+                kind: entry.kind,
+                indent,
+            });
+            if entry.kind == EntryKind::Directory && expanded_directories.contains(&entry.path) {
+                self.request_listing(node);
+                let children: Vec<_> = node.children(&self.arena).collect();
+                stack.extend(children.into_iter().rev().map(|child| (child, indent + 1)));
+            }
+        }
+        entries
+    }
+}
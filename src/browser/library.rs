@@ -0,0 +1,482 @@
+#[cfg(unix)]
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+#[cfg(windows)]
+use std::os::windows::ffi::{OsStrExt, OsStringExt};
+use std::{
+    collections::{BTreeMap, BTreeSet, HashMap},
+    ffi::OsString,
+    fs, io,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Sender},
+    thread::{self, JoinHandle},
+    time::{Duration, Instant, SystemTime},
+};
+
+use egui::{Color32, Id, TextEdit, Ui};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::{blerp::analysis::Analysis, config};
+
+const LIBRARY_FILE: &str = "library.ron";
+// Kept apart from the rest since it's big and changes all the time while folders get analysed
+const ANALYSIS_FILE: &str = "analysis.ron";
+// New analyses get added to this instead of rewriting the whole file, and folded into it on startup
+const ANALYSIS_JOURNAL: &str = "analysis.journal";
+// Results keep coming in every few seconds while a folder gets analysed, they're written in batches
+const WRITE_DELAY: Duration = Duration::from_secs(10);
+
+type AnalysisWriter = (Sender<Vec<(PathBuf, StoredAnalysis)>>, JoinHandle<()>);
+pub const MAX_RATING: u8 = 5;
+
+#[derive(Display, EnumIter, Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorLabel {
+    Red,
+    Orange,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+}
+
+impl ColorLabel {
+    pub fn color(self) -> Color32 {
+        match self {
+            Self::Red => Color32::from_rgb(0xe0, 0x5a, 0x5a),
+            Self::Orange => Color32::from_rgb(0xe8, 0x9a, 0x4c),
+            Self::Yellow => Color32::from_rgb(0xe6, 0xd0, 0x5c),
+            Self::Green => Color32::from_rgb(0x6c, 0xc0, 0x6a),
+            Self::Blue => Color32::from_rgb(0x5a, 0x9c, 0xe0),
+            Self::Purple => Color32::from_rgb(0xa8, 0x78, 0xe0),
+        }
+    }
+}
+
+// Everything the user said about a single file
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SampleInfo {
+    pub tags: BTreeSet<String>,
+    // 0 means unrated
+    pub rating: u8,
+    pub color: Option<ColorLabel>,
+    pub favourite: bool,
+}
+
+impl SampleInfo {
+    fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+}
+
+// Tells apart versions of a file without reading it
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FileStamp {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileStamp {
+    pub fn of(path: &Path) -> Option<Self> {
+        let metadata = fs::metadata(path).ok()?;
+        Some(Self {
+            size: metadata.len(),
+            modified: metadata.modified().ok(),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct StoredAnalysis {
+    pub stamp: FileStamp,
+    // `None` when the file couldn't be decoded, so it doesn't get tried again until it changes
+    pub analysis: Option<Analysis>,
+}
+
+// Paths as they get written to disk. Valid UTF-8 is kept as a plain string, anything else as the
+// raw bytes (UTF-16 units on Windows) so no file name can make a save fail
+#[derive(PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(untagged)]
+enum StoredPath {
+    Text(String),
+    #[cfg(unix)]
+    Bytes(Vec<u8>),
+    #[cfg(windows)]
+    Wide(Vec<u16>),
+}
+
+impl StoredPath {
+    fn new(path: &Path) -> Self {
+        if let Some(text) = path.to_str() {
+            return Self::Text(text.to_string());
+        }
+        #[cfg(unix)]
+        return Self::Bytes(path.as_os_str().as_bytes().to_vec());
+        #[cfg(windows)]
+        return Self::Wide(path.as_os_str().encode_wide().collect());
+        #[cfg(not(any(unix, windows)))]
+        Self::Text(path.to_string_lossy().to_string())
+    }
+
+    fn path(self) -> PathBuf {
+        match self {
+            Self::Text(text) => PathBuf::from(text),
+            #[cfg(unix)]
+            Self::Bytes(bytes) => OsString::from_vec(bytes).into(),
+            #[cfg(windows)]
+            Self::Wide(wide) => OsString::from_wide(&wide).into(),
+        }
+    }
+}
+
+fn serialize_paths<S: Serializer, V: Serialize>(map: &HashMap<PathBuf, V>, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(map.iter().map(|(path, value)| (StoredPath::new(path), value)))
+}
+
+fn deserialize_paths<'de, D: Deserializer<'de>, V: Deserialize<'de>>(deserializer: D) -> Result<HashMap<PathBuf, V>, D::Error> {
+    Ok(HashMap::<StoredPath, V>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(path, value)| (path.path(), value))
+        .collect())
+}
+
+fn serialize_collections<S: Serializer>(
+    collections: &BTreeMap<String, BTreeSet<PathBuf>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.collect_map(collections.iter().map(|(name, members)| {
+        (name, members.iter().map(|path| StoredPath::new(path)).collect::<Vec<_>>())
+    }))
+}
+
+fn deserialize_collections<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<BTreeMap<String, BTreeSet<PathBuf>>, D::Error> {
+    Ok(BTreeMap::<String, Vec<StoredPath>>::deserialize(deserializer)?
+        .into_iter()
+        .map(|(name, members)| (name, members.into_iter().map(StoredPath::path).collect()))
+        .collect())
+}
+
+// How analysis.ron looks on disk
+#[derive(Debug, Default, Clone, Serialize, Deserialize)]
+#[serde(transparent)]
+struct Analyses(
+    #[serde(serialize_with = "serialize_paths", deserialize_with = "deserialize_paths")]
+    HashMap<PathBuf, StoredAnalysis>,
+);
+
+// Writes new analyses on a thread of their own, `compacted` being everything there is when the
+// journal needs folding into the file
+fn analysis_writer(compacted: Option<Analyses>) -> AnalysisWriter {
+    let (sender, receiver) = channel::<Vec<(PathBuf, StoredAnalysis)>>();
+    let thread = thread::spawn(move || {
+        if let Some(analyses) = compacted {
+            if let Err(err) = config::save(ANALYSIS_FILE, &analyses).and_then(|()| config::remove(ANALYSIS_JOURNAL)) {
+                eprintln!("Failed to save the sample analysis: {err}");
+            }
+        }
+        while let Ok(mut batch) = receiver.recv() {
+            let deadline = Instant::now() + WRITE_DELAY;
+            while let Ok(more) = receiver.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                batch.extend(more);
+            }
+            let entries: Vec<_> = batch
+                .iter()
+                .map(|(path, stored)| (StoredPath::new(path), stored))
+                .collect();
+            if let Err(err) = config::append(ANALYSIS_JOURNAL, &entries) {
+                eprintln!("Failed to save the sample analysis: {err}");
+            }
+        }
+    });
+    (sender, thread)
+}
+
+// Tags, ratings and the rest, kept per path in the config directory. Files nobody said anything
+// about aren't stored at all, so the file stays small even for huge sample folders
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub struct Library {
+    #[serde(serialize_with = "serialize_paths", deserialize_with = "deserialize_paths")]
+    samples: HashMap<PathBuf, SampleInfo>,
+    #[serde(serialize_with = "serialize_collections", deserialize_with = "deserialize_collections")]
+    collections: BTreeMap<String, BTreeSet<PathBuf>>,
+    #[serde(skip)]
+    analyses: Analyses,
+    #[serde(skip)]
+    writer: Option<AnalysisWriter>,
+    // Bumped on every change, so searches know when to run again
+    #[serde(skip)]
+    generation: u64,
+}
+
+impl Library {
+    pub fn load() -> Self {
+        let mut analyses: Analyses = config::load(ANALYSIS_FILE);
+        let journal: Vec<(StoredPath, StoredAnalysis)> = config::load_lines(ANALYSIS_JOURNAL);
+        let compact = !journal.is_empty();
+        analyses
+            .0
+            .extend(journal.into_iter().map(|(path, stored)| (path.path(), stored)));
+        Self {
+            writer: Some(analysis_writer(compact.then(|| analyses.clone()))),
+            analyses,
+            ..config::load(LIBRARY_FILE)
+        }
+    }
+
+    pub fn save(&self) -> io::Result<()> {
+        config::save(LIBRARY_FILE, self)
+    }
+
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn info(&self, path: &Path) -> Option<&SampleInfo> {
+        self.samples.get(path)
+    }
+
+    // Only if it's still about the file as it is now
+    pub fn analysis(&self, path: &Path, stamp: FileStamp) -> Option<&StoredAnalysis> {
+        self.analyses
+            .0
+            .get(path)
+            .filter(|stored| stored.stamp == stamp)
+    }
+
+    pub fn analysed(&self, path: &Path) -> Option<&Analysis> {
+        self.analyses
+            .0
+            .get(path)
+            .and_then(|stored| stored.analysis.as_ref())
+    }
+
+    pub fn add_analyses(&mut self, analyses: Vec<(PathBuf, StoredAnalysis)>) {
+        if let Some((writer, _)) = &self.writer {
+            let _ = writer.send(analyses.clone());
+        }
+        self.analyses.0.extend(analyses);
+        self.generation += 1;
+    }
+
+    // Waits for the analyses that haven't been written yet, for when Volt exits
+    pub fn flush(&mut self) {
+        if let Some((writer, thread)) = self.writer.take() {
+            drop(writer);
+            let _ = thread.join();
+        }
+    }
+
+    pub fn collections(&self) -> impl Iterator<Item = &String> {
+        self.collections.keys()
+    }
+
+    pub fn in_collection(&self, collection: &str, path: &Path) -> bool {
+        self.collections
+            .get(collection)
+            .is_some_and(|paths| paths.contains(path))
+    }
+
+    pub fn all_tags(&self) -> BTreeSet<&String> {
+        self.samples.values().flat_map(|info| &info.tags).collect()
+    }
+
+    fn changed(&mut self) {
+        self.generation += 1;
+        if let Err(err) = self.save() {
+            eprintln!("Failed to save the sample library: {err}");
+        }
+    }
+
+    pub fn edit(&mut self, paths: &[PathBuf], edit: impl Fn(&mut SampleInfo)) {
+        for path in paths {
+            let info = self.samples.entry(path.clone()).or_default();
+            edit(info);
+            if info.is_empty() {
+                self.samples.remove(path);
+            }
+        }
+        self.changed();
+    }
+
+    pub fn set_in_collection(&mut self, collection: &str, paths: &[PathBuf], member: bool) {
+        let members = self.collections.entry(collection.to_string()).or_default();
+        for path in paths {
+            if member {
+                members.insert(path.clone());
+            } else {
+                members.remove(path);
+            }
+        }
+        self.changed();
+    }
+
+    // Keeps tags and the rest with a file, or everything under a folder, after it got renamed
+    pub fn moved(&mut self, from: &Path, to: &Path) {
+        let moved = |path: &Path| path.strip_prefix(from).ok().map(|rest| to.join(rest));
+        self.samples = self
+            .samples
+            .drain()
+            .map(|(path, info)| (moved(&path).unwrap_or(path), info))
+            .collect();
+        for members in self.collections.values_mut() {
+            *members = std::mem::take(members)
+                .into_iter()
+                .map(|path| moved(&path).unwrap_or(path))
+                .collect();
+        }
+        self.changed();
+    }
+
+    // Forgets about files that are gone, or everything under a folder that is
+    pub fn removed(&mut self, paths: &[PathBuf]) {
+        let gone = |path: &Path| paths.iter().any(|removed| path.starts_with(removed));
+        self.samples.retain(|path, _| !gone(path));
+        for members in self.collections.values_mut() {
+            members.retain(|path| !gone(path));
+        }
+        self.changed();
+    }
+
+    // Contents of the right click menu on browser rows. With several files selected, what the
+    // menu shows is what the first one has, and whatever gets picked applies to all of them
+    pub fn menu(&mut self, ui: &mut Ui, paths: &[PathBuf]) {
+        let Some(first) = paths.first() else {
+            return;
+        };
+        let info = self.info(first).cloned().unwrap_or_default();
+        if paths.len() > 1 {
+            ui.label(format!("{} files", paths.len()));
+        }
+
+        if ui
+            .button(if info.favourite { "♥ Unfavourite" } else { "♡ Favourite" })
+            .clicked()
+        {
+            let favourite = !info.favourite;
+            self.edit(paths, |info| info.favourite = favourite);
+        }
+
+        ui.horizontal(|ui| {
+            for rating in 1..=MAX_RATING {
+                let star = if rating <= info.rating { "★" } else { "☆" };
+                if ui.small_button(star).clicked() {
+                    // Clicking the current rating again clears it
+                    let rating = if rating == info.rating { 0 } else { rating };
+                    self.edit(paths, |info| info.rating = rating);
+                }
+            }
+        });
+
+        ui.menu_button("Colour label", |ui| {
+            for label in ColorLabel::iter() {
+                let text = egui::RichText::new(format!("● {label}")).color(label.color());
+                if ui.selectable_label(info.color == Some(label), text).clicked() {
+                    self.edit(paths, |info| info.color = Some(label));
+                    ui.close_menu();
+                }
+            }
+            if ui.button("None").clicked() {
+                self.edit(paths, |info| info.color = None);
+                ui.close_menu();
+            }
+        });
+
+        ui.menu_button("Tags", |ui| {
+            let tags: Vec<String> = self.all_tags().into_iter().cloned().collect();
+            for tag in tags {
+                let mut tagged = paths
+                    .iter()
+                    .all(|path| self.info(path).is_some_and(|info| info.tags.contains(&tag)));
+                if ui.checkbox(&mut tagged, &tag).changed() {
+                    self.edit(paths, |info| {
+                        if tagged {
+                            info.tags.insert(tag.clone());
+                        } else {
+                            info.tags.remove(&tag);
+                        }
+                    });
+                }
+            }
+            if let Some(tag) = new_name_field(ui, "New tag") {
+                self.edit(paths, |info| {
+                    info.tags.insert(tag.clone());
+                });
+            }
+        });
+
+        ui.menu_button("Collections", |ui| {
+            let collections: Vec<String> = self.collections().cloned().collect();
+            for collection in collections {
+                let mut member = paths
+                    .iter()
+                    .all(|path| self.in_collection(&collection, path));
+                if ui.checkbox(&mut member, &collection).changed() {
+                    self.set_in_collection(&collection, paths, member);
+                }
+            }
+            if let Some(collection) = new_name_field(ui, "New collection") {
+                self.set_in_collection(&collection, paths, true);
+            }
+        });
+    }
+}
+
+// Text field that hands its contents over when enter gets pressed
+fn new_name_field(ui: &mut Ui, hint: &str) -> Option<String> {
+    let id = Id::new(("library_new_name", hint));
+    let mut text = ui.data_mut(|data| data.get_temp::<String>(id).unwrap_or_default());
+    let response = ui.add(TextEdit::singleline(&mut text).hint_text(hint));
+    let submitted = response.lost_focus() && ui.input(|input| input.key_pressed(egui::Key::Enter));
+    let name = text.trim().to_string();
+    if submitted && !name.is_empty() {
+        ui.data_mut(|data| data.remove::<String>(id));
+        Some(name)
+    } else {
+        ui.data_mut(|data| data.insert_temp(id, text));
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::{Library, SampleInfo};
+
+    #[cfg(unix)]
+    #[test]
+    fn paths_that_are_not_utf8_survive_a_save() {
+        use std::{ffi::OsString, os::unix::ffi::OsStringExt};
+
+        let odd = PathBuf::from(OsString::from_vec(b"/samples/kick \xff.wav".to_vec()));
+        let plain = PathBuf::from("/samples/snare.wav");
+        // Filled in directly, going through `edit` would save over the real library
+        let mut library = Library::default();
+        for path in [&odd, &plain] {
+            let info = SampleInfo {
+                favourite: true,
+                ..SampleInfo::default()
+            };
+            library.samples.insert(path.clone(), info);
+            library.collections.entry("Drums".to_string()).or_default().insert(path.clone());
+        }
+
+        let saved = ron::to_string(&library).unwrap();
+        // Paths that are valid UTF-8 stay readable
+        assert!(saved.contains("\"/samples/snare.wav\""));
+        let loaded: Library = ron::from_str(&saved).unwrap();
+        for path in [&odd, &plain] {
+            assert_eq!(loaded.info(path).map(|info| info.favourite), Some(true));
+            assert!(loaded.in_collection("Drums", path));
+        }
+    }
+
+    #[test]
+    fn libraries_saved_with_plain_paths_still_load() {
+        let loaded: Library = ron::from_str(r#"(samples: {"/a.wav": (tags: [], rating: 3, color: None, favourite: false)}, collections: {"Kit": ["/a.wav"]})"#).unwrap();
+        assert_eq!(loaded.info(&PathBuf::from("/a.wav")).map(|info| info.rating), Some(3));
+        assert!(loaded.in_collection("Kit", &PathBuf::from("/a.wav")));
+        assert_eq!(loaded.info(&PathBuf::from("/b.wav")), None::<&SampleInfo>);
+    }
+}
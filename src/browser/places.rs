@@ -0,0 +1,73 @@
+use std::path::{Path, PathBuf};
+
+use egui::{pos2, vec2, Align2, Context, FontFamily, FontId, Pos2, Rect, Ui};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use super::hovered;
+use crate::visual::ThemeColors;
+
+// Space the row of places takes above the search
+pub const HEIGHT: f32 = 20.;
+
+// Shortcuts shown above the search, folders get opened as roots
+#[derive(Display, EnumIter, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Place {
+    Home,
+    Music,
+    Project,
+    Recent,
+}
+
+impl Place {
+    // `None` for places that aren't a folder, or don't exist on this system
+    pub fn folder(self, project_folder: Option<&Path>) -> Option<PathBuf> {
+        match self {
+            Self::Home => dirs::home_dir(),
+            Self::Music => dirs::audio_dir(),
+            Self::Project => project_folder.map(Path::to_path_buf),
+            Self::Recent => None,
+        }
+    }
+}
+
+// Returns the place that got clicked. Places that don't lead anywhere aren't shown
+pub fn paint(
+    ctx: &Context,
+    ui: &Ui,
+    area: Rect,
+    theme: &ThemeColors,
+    project_folder: Option<&Path>,
+    showing_recent: bool,
+    click: Option<Pos2>,
+) -> Option<Place> {
+    let font = FontId::new(12., FontFamily::Name("IBMPlexMono".into()));
+    let mut clicked = None;
+    let mut x = area.left() + 8.;
+    for place in Place::iter().filter(|place| *place == Place::Recent || place.folder(project_folder).is_some()) {
+        let text = place.to_string();
+        let width = ui
+            .painter()
+            .layout_no_wrap(text.clone(), font.clone(), theme.browser_unselected_button_fg)
+            .rect
+            .width();
+        let rect = Rect::from_min_size(pos2(x, area.top() + 2.), vec2(width, 16.));
+        ui.painter().text(
+            rect.left_center(),
+            Align2::LEFT_CENTER,
+            text,
+            font.clone(),
+            if place == Place::Recent && showing_recent {
+                theme.browser_selected_button_fg
+            } else if hovered(ctx, &rect) {
+                theme.browser_unselected_hover_button_fg
+            } else {
+                theme.browser_unselected_button_fg
+            },
+        );
+        if click.is_some_and(|click| rect.contains(click)) {
+            clicked = Some(place);
+        }
+        x = rect.right() + 12.;
+    }
+    clicked
+}
@@ -0,0 +1,391 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use egui::{pos2, vec2, Align2, Context, FontFamily, FontId, Pos2, Rect, Stroke, Ui};
+use unicode_truncate::UnicodeTruncateStr;
+
+use super::{hovered, Library};
+use crate::{
+    blerp::{
+        audiofile, peaks,
+        processing::live::{
+            preview::{PreviewCommand, PreviewSample},
+            Command, EngineHandle,
+        },
+        wavefile,
+    },
+    visual::{switch::switch_widget, ThemeColors},
+};
+
+type Loaded = (PathBuf, Result<PreviewSample, String>);
+
+// Height of the preview strip at the bottom of the browser
+pub const STRIP_HEIGHT: f32 = 96.;
+
+// Drives the engine's preview voice, files get decoded on a dedicated thread
+pub struct Preview {
+    requests: Sender<(PathBuf, u32)>,
+    loaded: Receiver<Loaded>,
+    pending: Option<PathBuf>,
+    pub sample: Option<Arc<PreviewSample>>,
+    pub error: Option<String>,
+    pub auto_play: bool,
+    pub looping: bool,
+    pub volume: f32,
+    // Speeds files with a known tempo up or down to the project's, pitch and all, instead of playing
+    // them at their own speed
+    pub varispeed: bool,
+    rate: f64,
+    // Samples the engine might still be playing, so they never get freed on the audio thread
+    sent: Vec<Arc<PreviewSample>>,
+}
+
+fn load(path: &Path, sample_rate: u32) -> Result<PreviewSample, String> {
+    let buffer = audiofile::decode(path).map_err(|err| err.to_string())?;
+    let samples = buffer.to_stereo().resampled_band_limited(sample_rate).samples;
+    Ok(PreviewSample {
+        path: path.to_path_buf(),
+        peaks: Arc::new(peaks::load_or_compute(path, &buffer)),
+        samples,
+        sample_rate,
+        source_sample_rate: buffer.sample_rate,
+        source_channels: buffer.channels,
+        // Decoders don't tell, but wav headers do
+        bits_per_sample: wavefile::read_wav_format(path)
+            .ok()
+            .map(|format| format.bits_per_sample),
+    })
+}
+
+fn format_time(seconds: f64) -> String {
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let centiseconds = (seconds * 100.).round() as u64;
+    format!(
+        "{}:{:02}.{:02}",
+        centiseconds / 6000,
+        centiseconds / 100 % 60,
+        centiseconds % 100
+    )
+}
+
+impl Preview {
+    pub fn new() -> Self {
+        let (requests, request_receiver) = channel::<(PathBuf, u32)>();
+        let (loaded_sender, loaded) = channel();
+        thread::spawn(move || {
+            while let Ok(request) = request_receiver.recv() {
+                // Only the most recent click matters
+                let (path, sample_rate) = request_receiver.try_iter().last().unwrap_or(request);
+                let sample = load(&path, sample_rate);
+                if loaded_sender.send((path, sample)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            loaded,
+            pending: None,
+            sample: None,
+            error: None,
+            auto_play: true,
+            looping: false,
+            volume: 1.,
+            varispeed: false,
+            rate: 1.,
+            sent: Vec::new(),
+        }
+    }
+
+    // Loads the file into the strip, and starts it if auto-play is on
+    pub fn select(&mut self, path: PathBuf, engine: &EngineHandle) {
+        let sample_rate = engine.status.sample_rate.load(Ordering::Relaxed);
+        self.pending = Some(path.clone());
+        let _ = self.requests.send((path, sample_rate));
+    }
+
+    pub fn stop(&self, engine: &EngineHandle) {
+        engine.send(Command::Preview(PreviewCommand::Stop));
+    }
+
+    pub fn resume(&self, engine: &EngineHandle) {
+        engine.send(Command::Preview(PreviewCommand::Resume));
+    }
+
+    pub fn seek(&self, engine: &EngineHandle, frame: u64) {
+        engine.send(Command::Preview(PreviewCommand::Seek(frame)));
+    }
+
+    pub fn set_looping(&mut self, engine: &EngineHandle, looping: bool) {
+        self.looping = looping;
+        engine.send(Command::Preview(PreviewCommand::SetLooping(looping)));
+    }
+
+    pub fn set_volume(&mut self, engine: &EngineHandle, volume: f32) {
+        self.volume = volume;
+        engine.send(Command::Preview(PreviewCommand::SetVolume(volume)));
+    }
+
+    // Speeds the preview up or down to `project_tempo` with varispeed on, going by the file's analysed
+    // tempo
+    fn varispeed_rate(&mut self, engine: &EngineHandle, library: &Library, project_tempo: f64) {
+        let tempo = self
+            .sample
+            .as_ref()
+            .and_then(|sample| library.analysed(&sample.path))
+            .and_then(|analysis| analysis.bpm);
+        let rate = match tempo {
+            Some(tempo) if self.varispeed && tempo > 0. => project_tempo / tempo,
+            _ => 1.,
+        };
+        if rate != self.rate {
+            self.rate = rate;
+            engine.send(Command::Preview(PreviewCommand::SetRate(rate)));
+        }
+    }
+
+    pub fn is_playing(&self, engine: &EngineHandle) -> bool {
+        engine.status.preview_playing.load(Ordering::Relaxed)
+    }
+
+    pub fn position(&self, engine: &EngineHandle) -> u64 {
+        engine.status.preview_position.load(Ordering::Relaxed)
+    }
+
+    // Hands finished loads over to the engine and keeps varispeed previews at the project's tempo,
+    // call once per frame
+    pub fn update(&mut self, engine: &EngineHandle, library: &Library, project_tempo: f64) {
+        self.sent.retain(|sample| Arc::strong_count(sample) > 1);
+        for (path, sample) in self.loaded.try_iter().collect::<Vec<_>>() {
+            // A newer request is still on its way
+            if self.pending.as_ref() != Some(&path) {
+                continue;
+            }
+            self.pending = None;
+            match sample {
+                Ok(sample) => {
+                    let sample = Arc::new(sample);
+                    self.sample = Some(sample.clone());
+                    self.sent.push(sample.clone());
+                    self.error = None;
+                    engine.send(Command::Preview(PreviewCommand::SetLooping(self.looping)));
+                    engine.send(Command::Preview(PreviewCommand::SetVolume(self.volume)));
+                    self.varispeed_rate(engine, library, project_tempo);
+                    engine.send(Command::Preview(if self.auto_play {
+                        PreviewCommand::Play(sample)
+                    } else {
+                        PreviewCommand::Load(sample)
+                    }));
+                }
+                Err(err) => self.error = Some(format!("{}: {err}", path.display())),
+            }
+        }
+        // The analysis might only come in after the file started playing
+        self.varispeed_rate(engine, library, project_tempo);
+    }
+
+    #[allow(clippy::too_many_lines)]
+    pub fn paint_strip(
+        &mut self,
+        ctx: &Context,
+        ui: &mut Ui,
+        strip: Rect,
+        theme: &ThemeColors,
+        engine: Option<&EngineHandle>,
+        click: Option<Pos2>,
+    ) {
+        let font = FontId::new(12., FontFamily::Name("IBMPlexMono".into()));
+        ui.painter().rect_filled(strip, 0.0, theme.browser);
+        ui.painter().line_segment(
+            [strip.left_top(), strip.right_top()],
+            Stroke::new(0.5, theme.browser_outline),
+        );
+        let char_width = ui
+            .painter()
+            .layout_no_wrap("a".to_string(), font.clone(), theme.browser_unselected_button_fg)
+            .rect
+            .width();
+
+        let text_button = |ui: &Ui, position: Pos2, text: &str, active: bool| {
+            #[allow(clippy::cast_precision_loss)]
+            let rect = Rect::from_min_size(position, vec2(char_width * text.len() as f32, 14.));
+            ui.painter().text(
+                position,
+                Align2::LEFT_TOP,
+                text,
+                font.clone(),
+                if active {
+                    theme.browser_selected_button_fg
+                } else if hovered(ctx, &rect) {
+                    theme.browser_unselected_hover_button_fg
+                } else {
+                    theme.browser_unselected_button_fg
+                },
+            );
+            click.is_some_and(|click| rect.contains(click))
+        };
+
+        // Header: file name and the transport
+        let playing = engine.is_some_and(|engine| self.is_playing(engine));
+        let name = self.sample.as_ref().map_or_else(
+            || self.error.clone().unwrap_or_else(|| "No file selected".to_string()),
+            |sample| {
+                sample
+                    .path
+                    .file_name()
+                    .map_or_else(String::new, |name| name.to_string_lossy().to_string())
+            },
+        );
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let max_chars = ((strip.width() - 20.) / char_width.max(1.)) as usize;
+        let max_name_chars = max_chars.saturating_sub(23);
+        ui.painter().text(
+            strip.left_top() + vec2(10., 6.),
+            Align2::LEFT_TOP,
+            if name.unicode_truncate(max_name_chars).1 == max_name_chars {
+                name.unicode_truncate(max_name_chars).0.to_string() + "..."
+            } else {
+                name
+            },
+            font.clone(),
+            theme.browser_unselected_button_fg,
+        );
+        let transport_x = strip.right() - 10. - char_width * 10.;
+        if let Some(engine) = engine {
+            if text_button(ui, pos2(transport_x, strip.top() + 6.), if playing { "Stop" } else { "Play" }, false) {
+                if playing {
+                    self.stop(engine);
+                } else {
+                    self.resume(engine);
+                }
+            }
+            let looping = self.looping;
+            if text_button(ui, pos2(transport_x + char_width * 6., strip.top() + 6.), "Loop", looping) {
+                self.set_looping(engine, !looping);
+            }
+            // Picked up by the next `update`
+            if text_button(ui, pos2(transport_x - char_width * 11., strip.top() + 6.), "Varispeed", self.varispeed) {
+                self.varispeed = !self.varispeed;
+            }
+        }
+
+        // Waveform with the playhead, click anywhere on it to seek
+        let waveform = Rect::from_min_max(
+            pos2(strip.left() + 10., strip.top() + 24.),
+            pos2(strip.right() - 10., strip.bottom() - 26.),
+        );
+        ui.painter().rect_filled(waveform, 2.0, theme.navbar);
+        if let Some(sample) = &self.sample {
+            let center = waveform.center().y;
+            let half_height = waveform.height() * 0.5;
+            // One bucket per pixel column
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let columns = waveform.width().max(0.) as usize;
+            let peaks = sample.peaks.buckets(None, 0..sample.peaks.frames, columns);
+            for (index, peak) in peaks.iter().enumerate() {
+                #[allow(clippy::cast_precision_loss)]
+                let x = waveform.left() + index as f32 + 0.5;
+                ui.painter().line_segment(
+                    [
+                        pos2(x, peak.max.mul_add(-half_height, center)),
+                        pos2(x, peak.min.mul_add(-half_height, center) + 0.5),
+                    ],
+                    Stroke::new(1., theme.browser_unselected_button_fg),
+                );
+                // RMS drawn on top, so the body of the sound stands out from its peaks
+                ui.painter().line_segment(
+                    [
+                        pos2(x, peak.rms.mul_add(-half_height, center)),
+                        pos2(x, peak.rms.mul_add(half_height, center) + 0.5),
+                    ],
+                    Stroke::new(1., theme.browser_unselected_hover_button_fg),
+                );
+            }
+            if let Some(engine) = engine {
+                #[allow(clippy::cast_precision_loss)]
+                let frames = sample.frames().max(1) as f32;
+                #[allow(clippy::cast_precision_loss)]
+                let progress = (self.position(engine) as f32 / frames).min(1.);
+                let x = waveform.width().mul_add(progress, waveform.left());
+                ui.painter().line_segment(
+                    [pos2(x, waveform.top()), pos2(x, waveform.bottom())],
+                    Stroke::new(1., theme.browser_selected_button_fg),
+                );
+                if let Some(click) = click.filter(|click| waveform.contains(*click)) {
+                    let progress = (click.x - waveform.left()) / waveform.width();
+                    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                    self.seek(engine, (progress * frames) as u64);
+                    self.resume(engine);
+                }
+                if playing {
+                    ctx.request_repaint();
+                }
+            }
+        }
+
+        // Footer: time, format, volume and auto-play
+        let footer_y = strip.bottom() - 20.;
+        if let Some(sample) = &self.sample {
+            #[allow(clippy::cast_precision_loss)]
+            let rate = f64::from(sample.sample_rate.max(1));
+            #[allow(clippy::cast_precision_loss)]
+            let position = engine.map_or(0, |engine| self.position(engine)) as f64 / rate;
+            #[allow(clippy::cast_precision_loss)]
+            let duration = sample.frames() as f64 / rate;
+            let bits = sample
+                .bits_per_sample
+                .map_or_else(String::new, |bits| format!(" {bits}bit"));
+            ui.painter().text(
+                pos2(strip.left() + 10., footer_y),
+                Align2::LEFT_TOP,
+                format!(
+                    "{}/{} {}Hz{bits} {}ch",
+                    format_time(position.min(duration)),
+                    format_time(duration),
+                    sample.source_sample_rate,
+                    sample.source_channels,
+                ),
+                font.clone(),
+                theme.browser_unselected_button_fg,
+            );
+        }
+        if let Some(engine) = engine {
+            let volume_bar = Rect::from_min_size(
+                pos2(strip.right() - 150., footer_y + 5.),
+                vec2(50., 4.),
+            );
+            ui.painter().rect_filled(volume_bar, 2.0, theme.navbar);
+            ui.painter().rect_filled(
+                Rect::from_min_size(volume_bar.min, vec2(volume_bar.width() * self.volume, 4.)),
+                2.0,
+                theme.browser_unselected_hover_button_fg,
+            );
+            let grab = volume_bar.expand2(vec2(0., 6.));
+            if let Some(pointer) = ctx
+                .input(|input| input.pointer.primary_down().then(|| input.pointer.interact_pos()))
+                .flatten()
+                .filter(|pointer| grab.contains(*pointer))
+            {
+                self.set_volume(engine, ((pointer.x - volume_bar.left()) / volume_bar.width()).clamp(0., 1.));
+            }
+        }
+        ui.painter().text(
+            pos2(strip.right() - 92., footer_y),
+            Align2::LEFT_TOP,
+            "Auto",
+            font,
+            theme.browser_unselected_button_fg,
+        );
+        ui.put(
+            Rect::from_min_size(pos2(strip.right() - 50., footer_y - 2.), vec2(40., 18.)),
+            switch_widget(&mut self.auto_play),
+        );
+    }
+}
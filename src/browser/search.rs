@@ -0,0 +1,489 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+    sync::mpsc::{channel, Receiver, Sender},
+    thread,
+    time::{Duration, Instant},
+};
+
+use egui::{pos2, vec2, Align2, Context, FontFamily, FontId, Pos2, Rect, TextEdit, Ui};
+
+use super::{hovered, sort, Entry, EntryKind, Index, Library};
+use crate::{
+    blerp::analysis::{Analysis, Key, SampleType},
+    visual::ThemeColors,
+};
+
+// Space the search box and the filter row take above the list
+pub const HEIGHT: f32 = 86.;
+
+// Past this the list stops being useful anyway, better to type more
+const MAX_RESULTS: usize = 1000;
+// Results get refreshed at most this often while a crawl keeps changing the index
+pub const REFRESH_INTERVAL: Duration = Duration::from_millis(300);
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Filter {
+    pub query: String,
+    pub kind: Option<EntryKind>,
+    // Comma separated, with or without the dots
+    pub extensions: String,
+    // Seconds, typed in by hand so they're kept as text
+    pub min_duration: String,
+    pub max_duration: String,
+    // Comma separated, a file needs all of them
+    pub tags: String,
+    pub favourites: bool,
+    pub collection: Option<String>,
+    // Everything below comes from the analysis
+    pub min_bpm: String,
+    pub max_bpm: String,
+    // Like "A min", "Am" or just "A" for either mode
+    pub key: String,
+    pub sample_type: Option<SampleType>,
+}
+
+// Subsequence match on the file name. Runs of consecutive characters and matches at the start
+// of a word score higher, `None` means not every character of the query was found
+pub fn fuzzy_score(query: &str, name: &str) -> Option<u32> {
+    let name: Vec<char> = name.chars().collect();
+    let mut score = 0;
+    let mut position = 0;
+    let mut previous = None;
+    for query_char in query.chars().filter(|query_char| !query_char.is_whitespace()) {
+        let found = (position..name.len())
+            .find(|index| name[*index].to_lowercase().eq(query_char.to_lowercase()))?;
+        score += 1;
+        if previous.is_some_and(|previous| previous + 1 == found) {
+            score += 4;
+        }
+        if found == 0 || !name[found - 1].is_alphanumeric() {
+            score += 3;
+        }
+        previous = Some(found);
+        position = found + 1;
+    }
+    Some(score)
+}
+
+fn parse_seconds(text: &str) -> Option<f64> {
+    text.trim().parse().ok().filter(|seconds: &f64| seconds.is_finite())
+}
+
+fn in_range(value: Option<f64>, (min, max): (Option<f64>, Option<f64>)) -> bool {
+    if min.is_none() && max.is_none() {
+        return true;
+    }
+    // Anything without a known value can't be said to be in range
+    value.is_some_and(|value| min.is_none_or(|min| value >= min) && max.is_none_or(|max| value <= max))
+}
+
+// Lowercase without spaces, with the mode spelled the way `Key` displays it
+fn normalize_key(key: &str) -> String {
+    let key: String = key
+        .chars()
+        .filter(|char| !char.is_whitespace())
+        .collect::<String>()
+        .to_lowercase()
+        .replace("minor", "min")
+        .replace("major", "maj");
+    match key.strip_suffix('m') {
+        Some(tonic) if !key.ends_with("maj") && !key.ends_with("min") => format!("{tonic}min"),
+        _ => key,
+    }
+}
+
+// `wanted` is already normalized
+fn matches_key(wanted: &str, key: Option<Key>) -> bool {
+    if wanted.is_empty() {
+        return true;
+    }
+    key.is_some_and(|key| {
+        let key = normalize_key(&key.to_string());
+        let tonic = key.trim_end_matches("min").trim_end_matches("maj");
+        key == wanted || tonic == wanted
+    })
+}
+
+impl Filter {
+    pub fn is_active(&self) -> bool {
+        !self.query.trim().is_empty()
+            || self.kind.is_some()
+            || !self.extensions.trim().is_empty()
+            || parse_seconds(&self.min_duration).is_some()
+            || parse_seconds(&self.max_duration).is_some()
+            || self.wanted_tags().next().is_some()
+            || self.favourites
+            || self.collection.is_some()
+            || parse_seconds(&self.min_bpm).is_some()
+            || parse_seconds(&self.max_bpm).is_some()
+            || !self.key.trim().is_empty()
+            || self.sample_type.is_some()
+    }
+
+    pub fn cycle_sample_type(&mut self) {
+        self.sample_type = match self.sample_type {
+            None => Some(SampleType::Loop),
+            Some(SampleType::Loop) => Some(SampleType::OneShot),
+            Some(SampleType::OneShot) => None,
+        };
+    }
+
+    fn wanted_tags(&self) -> impl Iterator<Item = &str> {
+        self.tags
+            .split(',')
+            .map(str::trim)
+            .filter(|tag| !tag.is_empty())
+    }
+
+    pub fn cycle_collection(&mut self, library: &Library) {
+        let mut collections = library.collections();
+        self.collection = match &self.collection {
+            None => collections.next().cloned(),
+            Some(current) => collections
+                .skip_while(|collection| *collection != current)
+                .nth(1)
+                .cloned(),
+        };
+    }
+
+    pub fn cycle_kind(&mut self) {
+        self.kind = match self.kind {
+            None => Some(EntryKind::Audio),
+            Some(EntryKind::Audio) => Some(EntryKind::File),
+            Some(EntryKind::File) => Some(EntryKind::Directory),
+            Some(EntryKind::Directory) => None,
+        };
+    }
+
+    fn parse(&self) -> Query {
+        let range = |min: &str, max: &str| (parse_seconds(min), parse_seconds(max));
+        Query {
+            text: self.query.clone(),
+            kind: self.kind,
+            extensions: self
+                .extensions
+                .split(',')
+                .map(|extension| extension.trim().trim_start_matches('.').to_lowercase())
+                .filter(|extension| !extension.is_empty())
+                .collect(),
+            duration: range(&self.min_duration, &self.max_duration),
+            tags: self.wanted_tags().map(str::to_string).collect(),
+            favourites: self.favourites,
+            collection: self.collection.clone(),
+            bpm: range(&self.min_bpm, &self.max_bpm),
+            key: normalize_key(&self.key),
+            sample_type: self.sample_type,
+        }
+    }
+}
+
+// A filter with everything typed into it parsed, once per search rather than once per file
+struct Query {
+    text: String,
+    kind: Option<EntryKind>,
+    // Lowercase and without the dots
+    extensions: Vec<String>,
+    duration: (Option<f64>, Option<f64>),
+    tags: Vec<String>,
+    favourites: bool,
+    collection: Option<String>,
+    bpm: (Option<f64>, Option<f64>),
+    key: String,
+    sample_type: Option<SampleType>,
+}
+
+// What the search thread needs to know about a file, the library stays on the UI thread
+struct Candidate {
+    path: PathBuf,
+    kind: EntryKind,
+    duration: Option<f64>,
+}
+
+// A search for the thread to run, numbered so only the latest one's results get used
+struct Request {
+    number: u64,
+    query: Query,
+    candidates: Vec<Candidate>,
+}
+
+impl Query {
+    fn matches_analysis(&self, analysis: Option<&Analysis>) -> bool {
+        in_range(analysis.and_then(|analysis| analysis.bpm), self.bpm)
+            && matches_key(&self.key, analysis.and_then(|analysis| analysis.key))
+            && self.sample_type.is_none_or(|sample_type| {
+                analysis.is_some_and(|analysis| analysis.sample_type == sample_type)
+            })
+    }
+
+    // Everything that needs the library, checked on the UI thread while gathering the candidates
+    fn matches_library(&self, path: &Path, library: &Library) -> bool {
+        let info = library.info(path);
+        self.tags.iter().all(|wanted| {
+            info.is_some_and(|info| info.tags.iter().any(|tag| tag.eq_ignore_ascii_case(wanted)))
+        }) && (!self.favourites || info.is_some_and(|info| info.favourite))
+            && self
+                .collection
+                .as_ref()
+                .is_none_or(|collection| library.in_collection(collection, path))
+            && self.matches_analysis(library.analysed(path))
+    }
+
+    fn matches_extension(&self, path: &Path) -> bool {
+        if self.extensions.is_empty() {
+            return true;
+        }
+        path.extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| {
+                self.extensions.iter().any(|wanted| wanted.eq_ignore_ascii_case(extension))
+            })
+    }
+
+    fn score(&self, candidate: &Candidate) -> Option<u32> {
+        if self.kind.is_some_and(|kind| kind != candidate.kind)
+            || !self.matches_extension(&candidate.path)
+            || !in_range(candidate.duration, self.duration)
+        {
+            return None;
+        }
+        let name = candidate.path.file_name()?.to_string_lossy();
+        fuzzy_score(&self.text, &name)
+    }
+}
+
+fn search(request: Request) -> Vec<Entry> {
+    let mut seen = HashSet::new();
+    let mut scored = Vec::new();
+    for candidate in request.candidates {
+        if let Some(score) = request.query.score(&candidate) {
+            // Open folders inside other open folders would show up twice
+            if seen.insert(candidate.path.clone()) {
+                scored.push((score, candidate.path, candidate.kind));
+            }
+        }
+    }
+    scored.sort_by(|(a_score, a, _), (b_score, b, _)| {
+        b_score.cmp(a_score).then_with(|| sort::name_cmp(a, b))
+    });
+    scored
+        .into_iter()
+        .take(MAX_RESULTS)
+        .map(|(_, path, kind)| Entry {
+            path,
+            kind,
+            indent: 0,
+        })
+        .collect()
+}
+
+// Flat results across every open folder, searched in the background index. Scoring and sorting
+// happen on a thread of their own
+pub struct Search {
+    pub filter: Filter,
+    pub results: Vec<Entry>,
+    // The filter and the index and library generations the last search was started for
+    searched: Option<(Filter, u64, u64)>,
+    searched_at: Instant,
+    requests: Sender<Request>,
+    finished: Receiver<(u64, Vec<Entry>)>,
+    requested: u64,
+    // Bumped whenever the results change
+    generation: u64,
+}
+
+impl Search {
+    pub fn new() -> Self {
+        let (requests, request_receiver) = channel::<Request>();
+        let (finished_sender, finished) = channel();
+        thread::spawn(move || {
+            while let Ok(request) = request_receiver.recv() {
+                // Only the latest search matters
+                let request = request_receiver.try_iter().last().unwrap_or(request);
+                let number = request.number;
+                if finished_sender.send((number, search(request))).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            filter: Filter::default(),
+            results: Vec::new(),
+            searched: None,
+            searched_at: Instant::now(),
+            requests,
+            finished,
+            requested: 0,
+            generation: 0,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.filter.is_active()
+    }
+
+    pub const fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    pub fn update(&mut self, index: &mut Index, library: &Library, roots: &[PathBuf]) {
+        // Results of searches that got superseded in the meantime are thrown away
+        for (number, results) in self.finished.try_iter() {
+            if number == self.requested {
+                self.results = results;
+                self.generation += 1;
+            }
+        }
+        if !self.is_active() {
+            if !self.results.is_empty() {
+                self.results.clear();
+                self.generation += 1;
+            }
+            self.searched = None;
+            // Whatever is still being searched is for a filter that's gone
+            self.requested += 1;
+            return;
+        }
+        for root in roots {
+            index.crawl(root);
+        }
+        let up_to_date = self.searched.as_ref().is_some_and(|(filter, generation, library_generation)| {
+            *filter == self.filter
+                && *library_generation == library.generation()
+                && (*generation == index.generation()
+                    || self.searched_at.elapsed() < REFRESH_INTERVAL)
+        });
+        if up_to_date {
+            return;
+        }
+
+        let query = self.filter.parse();
+        let mut candidates = Vec::new();
+        for root in roots {
+            candidates.extend(
+                index
+                    .descendants(root)
+                    .filter(|entry| query.matches_library(&entry.path, library))
+                    .map(|entry| Candidate {
+                        path: entry.path.clone(),
+                        kind: entry.kind,
+                        duration: entry.duration,
+                    }),
+            );
+        }
+        self.requested += 1;
+        let _ = self.requests.send(Request {
+            number: self.requested,
+            query,
+            candidates,
+        });
+        self.searched = Some((self.filter.clone(), index.generation(), library.generation()));
+        self.searched_at = Instant::now();
+    }
+
+    pub fn paint_controls(
+        &mut self,
+        ctx: &Context,
+        ui: &mut Ui,
+        area: Rect,
+        theme: &ThemeColors,
+        library: &Library,
+        click: Option<Pos2>,
+    ) {
+        let font = FontId::new(12., FontFamily::Name("IBMPlexMono".into()));
+        ui.put(
+            Rect::from_min_size(area.min + vec2(8., 2.), vec2(area.width() - 16., 20.)),
+            TextEdit::singleline(&mut self.filter.query)
+                .hint_text("Search open folders")
+                .font(FontId::new(14., FontFamily::Name("IBMPlexMono".into()))),
+        );
+
+        let text_button = |ui: &Ui, rect: Rect, text: &str, active: bool| {
+            ui.painter().text(
+                rect.left_center(),
+                Align2::LEFT_CENTER,
+                text,
+                font.clone(),
+                if active {
+                    theme.browser_selected_button_fg
+                } else if hovered(ctx, &rect) {
+                    theme.browser_unselected_hover_button_fg
+                } else {
+                    theme.browser_unselected_button_fg
+                },
+            );
+            click.is_some_and(|click| rect.contains(click))
+        };
+
+        let row = area.top() + 26.;
+        let kind = Rect::from_min_size(pos2(area.left() + 8., row), vec2(64., 16.));
+        let kind_text = self
+            .filter
+            .kind
+            .map_or_else(|| "Any".to_string(), |kind| kind.to_string());
+        if text_button(ui, kind, &kind_text, self.filter.kind.is_some()) {
+            self.filter.cycle_kind();
+        }
+
+        let mut x = kind.right() + 4.;
+        for (text, hint, width) in [
+            (&mut self.filter.extensions, "ext", 64.),
+            (&mut self.filter.min_duration, "min s", 52.),
+            (&mut self.filter.max_duration, "max s", 52.),
+        ] {
+            ui.put(
+                Rect::from_min_size(pos2(x, row - 1.), vec2(width, 18.)),
+                TextEdit::singleline(text).hint_text(hint).font(font.clone()),
+            );
+            x += width + 4.;
+        }
+
+        // Everything that comes from the library
+        let row = row + 20.;
+        ui.put(
+            Rect::from_min_size(pos2(area.left() + 8., row - 1.), vec2(120., 18.)),
+            TextEdit::singleline(&mut self.filter.tags)
+                .hint_text("tags")
+                .font(font.clone()),
+        );
+        let favourites = Rect::from_min_size(pos2(area.left() + 132., row), vec2(40., 16.));
+        if text_button(ui, favourites, "Fav", self.filter.favourites) {
+            self.filter.favourites = !self.filter.favourites;
+        }
+        let collection = Rect::from_min_size(
+            pos2(favourites.right() + 4., row),
+            vec2((area.right() - favourites.right() - 12.).max(0.), 16.),
+        );
+        let collection_text = self
+            .filter
+            .collection
+            .clone()
+            .unwrap_or_else(|| "Any collection".to_string());
+        if text_button(ui, collection, &collection_text, self.filter.collection.is_some()) {
+            self.filter.cycle_collection(library);
+        }
+
+        // And everything from the analysis
+        let row = row + 20.;
+        let mut x = area.left() + 8.;
+        for (text, hint, width) in [
+            (&mut self.filter.min_bpm, "min bpm", 64.),
+            (&mut self.filter.max_bpm, "max bpm", 64.),
+            (&mut self.filter.key, "key", 52.),
+        ] {
+            ui.put(
+                Rect::from_min_size(pos2(x, row - 1.), vec2(width, 18.)),
+                TextEdit::singleline(text).hint_text(hint).font(font.clone()),
+            );
+            x += width + 4.;
+        }
+        let sample_type = Rect::from_min_size(pos2(x, row), vec2(60., 16.));
+        let sample_type_text = self
+            .filter
+            .sample_type
+            .map_or_else(|| "Any type".to_string(), |sample_type| sample_type.to_string());
+        if text_button(ui, sample_type, &sample_type_text, self.filter.sample_type.is_some()) {
+            self.filter.cycle_sample_type();
+        }
+    }
+}
@@ -0,0 +1,184 @@
+use std::{
+    collections::HashSet,
+    path::PathBuf,
+    time::{Duration, Instant},
+};
+
+use egui::{Context, Event, Key, Modifiers};
+
+use super::{Entry, EntryKind};
+
+// Typing more than this long after the last key starts a new type-ahead search
+const TYPE_AHEAD_TIMEOUT: Duration = Duration::from_secs(1);
+
+// What the keyboard asked for that the selection can't do by itself
+pub enum KeyAction {
+    // Same as clicking the row
+    Activate(usize),
+    Expand(usize),
+    Collapse(usize),
+}
+
+fn is_expanded(rows: &[Entry], index: usize) -> bool {
+    rows.get(index + 1)
+        .is_some_and(|next| next.indent > rows[index].indent)
+}
+
+// Selected rows by path, so it survives rows moving around when folders get expanded
+pub struct Selection {
+    pub selected: HashSet<PathBuf>,
+    // Where the keyboard is, moves with the arrow keys
+    pub cursor: Option<PathBuf>,
+    // Other end of a shift selection
+    anchor: Option<PathBuf>,
+    type_ahead: String,
+    typed_at: Instant,
+}
+
+impl Selection {
+    pub fn new() -> Self {
+        Self {
+            selected: HashSet::new(),
+            cursor: None,
+            anchor: None,
+            type_ahead: String::new(),
+            typed_at: Instant::now(),
+        }
+    }
+
+    pub fn contains(&self, entry: &Entry) -> bool {
+        self.selected.contains(&entry.path)
+    }
+
+    fn cursor_index(&self, rows: &[Entry]) -> Option<usize> {
+        let cursor = self.cursor.as_ref()?;
+        rows.iter().position(|row| row.path == *cursor)
+    }
+
+    fn select_range(&mut self, rows: &[Entry], to: usize) {
+        let from = self
+            .anchor
+            .as_ref()
+            .and_then(|anchor| rows.iter().position(|row| row.path == *anchor))
+            .unwrap_or(to);
+        self.selected = rows[from.min(to)..=from.max(to)]
+            .iter()
+            .map(|row| row.path.clone())
+            .collect();
+    }
+
+    // Moves the cursor to a row. Shift extends the selection from the anchor, command adds to it
+    pub fn select(&mut self, rows: &[Entry], index: usize, modifiers: Modifiers) {
+        let Some(row) = rows.get(index) else {
+            return;
+        };
+        if modifiers.shift {
+            self.select_range(rows, index);
+        } else if modifiers.command {
+            if !self.selected.remove(&row.path) {
+                self.selected.insert(row.path.clone());
+            }
+            self.anchor = Some(row.path.clone());
+        } else {
+            self.selected = HashSet::from([row.path.clone()]);
+            self.anchor = Some(row.path.clone());
+        }
+        self.cursor = Some(row.path.clone());
+    }
+
+    // Everything that's selected in the order it's shown
+    pub fn paths(&self, rows: &[Entry]) -> Vec<PathBuf> {
+        rows.iter()
+            .filter(|row| self.contains(row))
+            .map(|row| row.path.clone())
+            .collect()
+    }
+
+    fn type_ahead(&mut self, rows: &[Entry], text: &str) -> Option<usize> {
+        if self.typed_at.elapsed() > TYPE_AHEAD_TIMEOUT {
+            self.type_ahead.clear();
+        }
+        self.typed_at = Instant::now();
+        self.type_ahead.push_str(&text.to_lowercase());
+        // Starting at the cursor, so typing the same letter again keeps matching the same row
+        let start = self.cursor_index(rows).unwrap_or(0);
+        (start..rows.len()).chain(0..start).find(|index| {
+            rows[*index]
+                .path
+                .file_name()
+                .is_some_and(|name| name.to_string_lossy().to_lowercase().starts_with(&self.type_ahead))
+        })
+    }
+
+    // Arrow keys, enter and type-ahead. Returns the cursor row if it moved, so it can be scrolled to
+    pub fn handle_keys(&mut self, ctx: &Context, rows: &[Entry]) -> (Option<usize>, Option<KeyAction>) {
+        // Text fields and menus get the keyboard first
+        if rows.is_empty() || ctx.memory(|memory| memory.focused().is_some() || memory.any_popup_open()) {
+            return (None, None);
+        }
+        let (events, modifiers) = ctx.input(|input| (input.events.clone(), input.modifiers));
+        let mut moved = None;
+        let mut action = None;
+        // Only shift counts for arrow keys, command is used for shortcuts
+        let arrow_modifiers = Modifiers {
+            command: false,
+            ..modifiers
+        };
+        for event in events {
+            let cursor = self.cursor_index(rows);
+            let target = match event {
+                Event::Key {
+                    key, pressed: true, ..
+                } => match key {
+                    Key::ArrowUp => Some(cursor.map_or(0, |cursor| cursor.saturating_sub(1))),
+                    Key::ArrowDown => Some(cursor.map_or(0, |cursor| (cursor + 1).min(rows.len() - 1))),
+                    Key::Home => Some(0),
+                    Key::End => Some(rows.len() - 1),
+                    Key::Enter => {
+                        action = cursor.map(KeyAction::Activate);
+                        None
+                    }
+                    Key::ArrowRight => cursor
+                        .filter(|cursor| rows[*cursor].kind == EntryKind::Directory)
+                        .and_then(|cursor| {
+                            // Already expanded, go into it instead
+                            if is_expanded(rows, cursor) {
+                                Some(cursor + 1)
+                            } else {
+                                action = Some(KeyAction::Expand(cursor));
+                                None
+                            }
+                        }),
+                    Key::ArrowLeft => cursor.and_then(|cursor| {
+                        if is_expanded(rows, cursor) {
+                            action = Some(KeyAction::Collapse(cursor));
+                            None
+                        } else {
+                            // Up to the parent
+                            (0..cursor)
+                                .rev()
+                                .find(|index| rows[*index].indent < rows[cursor].indent)
+                        }
+                    }),
+                    Key::Escape => {
+                        self.selected.clear();
+                        self.anchor = None;
+                        None
+                    }
+                    _ => None,
+                }
+                .map(|index| (index, arrow_modifiers)),
+                // Spaces are left for the transport
+                Event::Text(text) if !modifiers.command && !text.trim().is_empty() => self
+                    .type_ahead(rows, &text)
+                    .map(|index| (index, Modifiers::NONE)),
+                _ => None,
+            };
+            if let Some((index, modifiers)) = target {
+                self.select(rows, index, modifiers);
+                moved = Some(index);
+            }
+        }
+        (moved, action)
+    }
+}
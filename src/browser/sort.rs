@@ -0,0 +1,188 @@
+use std::{
+    cmp::Ordering,
+    ffi::OsStr,
+    iter::Peekable,
+    path::Path,
+    str::Chars,
+};
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use super::{index::IndexEntry, EntryKind};
+
+#[derive(Display, EnumIter, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SortKey {
+    #[default]
+    Name,
+    Type,
+    Size,
+    Modified,
+    Duration,
+    #[strum(to_string = "BPM")]
+    Bpm,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SortMode {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn next(self) -> Self {
+        Self::iter()
+            .cycle()
+            .skip_while(|key| *key != self)
+            .nth(1)
+            .unwrap_or_default()
+    }
+}
+
+fn file_name(path: &Path) -> &OsStr {
+    path.file_name().unwrap_or(path.as_os_str())
+}
+
+// Leading zeros don't count, so the length of what's left decides first
+fn take_number(chars: &mut Peekable<Chars>) -> String {
+    let mut digits = String::new();
+    while let Some(digit) = chars.next_if(char::is_ascii_digit) {
+        digits.push(digit);
+    }
+    digits.trim_start_matches('0').to_string()
+}
+
+// Compares names without caring about case or leading zeros
+fn loose_cmp(a: &str, b: &str) -> Ordering {
+    let mut a = a.chars().peekable();
+    let mut b = b.chars().peekable();
+    loop {
+        match (a.peek().copied(), b.peek().copied()) {
+            (None, None) => return Ordering::Equal,
+            (None, Some(_)) => return Ordering::Less,
+            (Some(_), None) => return Ordering::Greater,
+            (Some(x), Some(y)) if x.is_ascii_digit() && y.is_ascii_digit() => {
+                let (x, y) = (take_number(&mut a), take_number(&mut b));
+                let ordering = x.len().cmp(&y.len()).then_with(|| x.cmp(&y));
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+            }
+            (Some(x), Some(y)) => {
+                let ordering = x.to_lowercase().cmp(y.to_lowercase());
+                if ordering != Ordering::Equal {
+                    return ordering;
+                }
+                a.next();
+                b.next();
+            }
+        }
+    }
+}
+
+// "kick 2.wav" comes before "kick 10.wav". Case and leading zeros only break ties between names
+// that are the same without them, so only equal names compare equal
+pub fn natural_cmp(a: &str, b: &str) -> Ordering {
+    loose_cmp(a, b).then_with(|| a.cmp(b))
+}
+
+pub fn name_cmp(a: &Path, b: &Path) -> Ordering {
+    natural_cmp(&file_name(a).to_string_lossy(), &file_name(b).to_string_lossy())
+}
+
+fn directed(ordering: Ordering, descending: bool) -> Ordering {
+    if descending {
+        ordering.reverse()
+    } else {
+        ordering
+    }
+}
+
+// Unknown values go last no matter the direction
+fn known_cmp<T: PartialOrd>(a: Option<T>, b: Option<T>, descending: bool) -> Ordering {
+    match (a, b) {
+        (Some(a), Some(b)) => directed(a.partial_cmp(&b).unwrap_or(Ordering::Equal), descending),
+        (Some(_), None) => Ordering::Less,
+        (None, Some(_)) => Ordering::Greater,
+        (None, None) => Ordering::Equal,
+    }
+}
+
+// Siblings only, directories always stay on top whichever way the rest is sorted.
+// Ties fall back to the name in ascending order
+pub fn compare(a: &IndexEntry, b: &IndexEntry, mode: SortMode) -> Ordering {
+    let directories = (b.kind == EntryKind::Directory).cmp(&(a.kind == EntryKind::Directory));
+    let extension = |path: &Path| {
+        path.extension()
+            .map(|extension| extension.to_string_lossy().to_lowercase())
+    };
+    directories.then_with(|| {
+        match mode.key {
+            SortKey::Name => directed(name_cmp(&a.path, &b.path), mode.descending),
+            SortKey::Type => directed(
+                a.kind
+                    .cmp(&b.kind)
+                    .then_with(|| extension(&a.path).cmp(&extension(&b.path))),
+                mode.descending,
+            ),
+            SortKey::Size => known_cmp(a.size, b.size, mode.descending),
+            SortKey::Modified => known_cmp(a.modified, b.modified, mode.descending),
+            SortKey::Duration => known_cmp(a.duration, b.duration, mode.descending),
+            SortKey::Bpm => known_cmp(a.bpm, b.bpm, mode.descending),
+        }
+        .then_with(|| name_cmp(&a.path, &b.path))
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+
+    use super::natural_cmp;
+
+    #[test]
+    fn numbers_compare_by_value() {
+        assert_eq!(natural_cmp("kick 2.wav", "kick 10.wav"), Ordering::Less);
+        assert_eq!(natural_cmp("kick 10.wav", "kick 9.wav"), Ordering::Greater);
+        assert_eq!(natural_cmp("a1b2", "a1b10"), Ordering::Less);
+    }
+
+    #[test]
+    fn case_only_breaks_ties() {
+        // The "b" decides before the case of the "a" gets a say
+        assert_eq!(natural_cmp("a b", "A c"), Ordering::Less);
+        assert_eq!(natural_cmp("A b", "a c"), Ordering::Less);
+        assert_eq!(natural_cmp("a c", "A b"), Ordering::Greater);
+        assert_eq!(natural_cmp("Kick", "kick"), Ordering::Less);
+        assert_eq!(natural_cmp("kick", "Kick"), Ordering::Greater);
+    }
+
+    #[test]
+    fn leading_zeros_only_break_ties() {
+        assert_eq!(natural_cmp("a01", "a1"), Ordering::Less);
+        assert_eq!(natural_cmp("a1", "a01"), Ordering::Greater);
+        assert_eq!(natural_cmp("a01 b", "a1 c"), Ordering::Less);
+        assert_eq!(natural_cmp("a1 b", "a01 c"), Ordering::Less);
+    }
+
+    #[test]
+    fn only_equal_names_compare_equal() {
+        let names = ["a", "A", "a1", "a01", "A01", "a001", "a2", "a10", "b", "B1", "", "0", "00"];
+        for a in names {
+            for b in names {
+                assert_eq!(natural_cmp(a, b) == Ordering::Equal, a == b, "{a:?} and {b:?}");
+                assert_eq!(natural_cmp(a, b), natural_cmp(b, a).reverse(), "{a:?} and {b:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn sorting_is_transitive() {
+        let mut names = vec!["a10", "A2", "a01", "a1", "B", "a", "A", "a2", "b01", "b1"];
+        names.sort_by(|a, b| natural_cmp(a, b));
+        assert_eq!(names, ["A", "a", "a01", "a1", "A2", "a2", "a10", "B", "b01", "b1"]);
+        for window in names.windows(3) {
+            assert_eq!(natural_cmp(window[0], window[2]), Ordering::Less);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+use std::{
+    fs,
+    io::{self, Write},
+    path::PathBuf,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+// Everything Volt remembers between sessions lives in here, one RON file per concern
+pub fn config_dir() -> Option<PathBuf> {
+    dirs::config_dir().map(|dir| dir.join("volt"))
+}
+
+// Missing or unreadable files fall back to the default, a broken config should never stop Volt from starting
+pub fn load<T: DeserializeOwned + Default>(name: &str) -> T {
+    let Some(path) = config_dir().map(|dir| dir.join(name)) else {
+        return T::default();
+    };
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return T::default();
+    };
+    ron::from_str(&contents).unwrap_or_else(|err| {
+        eprintln!("Ignoring invalid config file {}: {err}", path.display());
+        T::default()
+    })
+}
+
+pub fn save<T: Serialize>(name: &str, value: &T) -> io::Result<()> {
+    let dir = config_dir().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no config directory on this platform")
+    })?;
+    fs::create_dir_all(&dir)?;
+    let contents = ron::ser::to_string_pretty(value, ron::ser::PrettyConfig::default())
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+    // Write to a temporary file first so a crash mid-write doesn't eat the old config
+    let temporary = dir.join(format!("{name}.tmp"));
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, dir.join(name))
+}
+
+// For files too big to rewrite on every change: values get added to the end, one per line
+pub fn append<T: Serialize>(name: &str, values: &[T]) -> io::Result<()> {
+    let dir = config_dir().ok_or_else(|| {
+        io::Error::new(io::ErrorKind::NotFound, "no config directory on this platform")
+    })?;
+    fs::create_dir_all(&dir)?;
+    let mut contents = String::new();
+    for value in values {
+        contents += &ron::to_string(value).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        contents.push('\n');
+    }
+    fs::File::options()
+        .create(true)
+        .append(true)
+        .open(dir.join(name))?
+        .write_all(contents.as_bytes())
+}
+
+// Everything `append` wrote, lines that don't parse (cut off by a crash) are skipped
+pub fn load_lines<T: DeserializeOwned>(name: &str) -> Vec<T> {
+    let Some(contents) = config_dir().and_then(|dir| fs::read_to_string(dir.join(name)).ok()) else {
+        return Vec::new();
+    };
+    contents.lines().filter_map(|line| ron::from_str(line).ok()).collect()
+}
+
+pub fn remove(name: &str) -> io::Result<()> {
+    match config_dir().map(|dir| fs::remove_file(dir.join(name))) {
+        Some(Err(err)) if err.kind() != io::ErrorKind::NotFound => Err(err),
+        _ => Ok(()),
+    }
+}
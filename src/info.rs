@@ -185,4 +185,6 @@ pub fn panic_handler(panic_info: &PanicHookInfo<'_>) {
     } else {
         println!("Panic occurred, message unknown.");
     }
+
+    crate::project::emergency_save();
 }
@@ -1,22 +1,33 @@
 use eframe::{egui, run_native, App, CreationContext, NativeOptions};
-use egui::{
-    Align2, CentralPanel, Color32, Context, FontData, FontDefinitions, FontFamily, FontId, Pos2,
-    Rect,
-};
+use egui::{pos2, vec2, CentralPanel, Context, FontData, FontDefinitions, FontFamily, Pos2, Rect};
 use egui_extras::install_image_loaders;
-use std::{
-    collections::HashSet,
-    path::PathBuf,
-    str::FromStr,
-};
+use std::time::Duration;
 mod blerp;
+#[allow(dead_code)]
 mod test;
+#[cfg(test)]
+mod test_util;
 // TODO: Move everything into components (visual)
 mod browser;
+mod config;
 mod info;
+mod metronome;
+mod mixer;
+mod project;
+mod timeline;
+mod transport;
 mod visual;
 
-use browser::{Browser, Category, OpenFolder};
+use blerp::device::{
+    latency::{CalibrationRun, LatencyProfiles},
+    Backend, Output,
+};
+use browser::{Browser, BrowserState, Calibration};
+use metronome::Metronome;
+use mixer::Mixer;
+use project::{Autosave, History, Playback, Project, Recorder, Session};
+use timeline::Timeline;
+use transport::Transport;
 use visual::ThemeColors;
 
 fn main() -> eframe::Result {
@@ -45,7 +56,27 @@ fn main() -> eframe::Result {
 
 struct VoltApp {
     pub browser: Browser,
+    pub timeline: Timeline,
+    pub transport: Transport,
+    pub metronome: Metronome,
+    pub mixer: Mixer,
+    pub project: Project,
+    pub history: History,
+    pub playback: Playback,
+    pub recorder: Recorder,
+    pub session: Session,
+    pub autosave: Autosave,
     pub themes: ThemeColors,
+    pub backend: Backend,
+    pub output: Option<Output>,
+    pub calibration: Option<CalibrationRun>,
+}
+
+fn start_output(backend: Backend) -> Option<Output> {
+    backend
+        .start()
+        .map_err(|err| eprintln!("Failed to start the {backend} audio backend: {err}"))
+        .ok()
 }
 
 impl VoltApp {
@@ -63,23 +94,23 @@ impl VoltApp {
             vec!["IBMPlexMono".to_owned()],
         );
         cc.egui_ctx.set_fonts(fonts);
+        let project = Project::new();
         Self {
-            browser: Browser {
-                selected_category: Category::Files,
-                open_folders: vec![OpenFolder {
-                    path: PathBuf::from_str("/").unwrap(),
-                    expanded_directories: HashSet::new(),
-                }],
-                preview: browser::Preview {
-                    preview_thread: Some(std::thread::spawn(|| {})),
-                },
-                offset_y: 0.,
-                dragging_audio: false,
-                dragging_audio_text: String::new(),
-                sidebar_width: 300.,
-                started_drag: false,
-            },
+            browser: Browser::new(BrowserState::load()),
+            timeline: Timeline::new(),
+            transport: Transport::new(),
+            metronome: Metronome::new(),
+            mixer: Mixer::new(),
+            session: Session::new(&project),
+            autosave: Autosave::new(),
+            project,
+            history: History::new(),
+            playback: Playback::new(),
+            recorder: Recorder::new(),
             themes: ThemeColors::default(),
+            backend: Backend::default(),
+            output: start_output(Backend::default()),
+            calibration: None,
         }
     }
 }
@@ -96,20 +127,120 @@ impl App for VoltApp {
                         Rect::from_min_size(Pos2::ZERO, size)
                     });
 
-                visual::background::paint_background(ui, &viewport, &self.themes);
+                visual::background::paint_background(ui, &viewport);
 
                 visual::navbar::paint_navbar(ui, &viewport, &self.themes);
+                ui.allocate_ui_at_rect(Rect::from_min_size(pos2(150., 14.), vec2(60., 22.)), |ui| {
+                    ui.menu_button("File", |ui| self.session.menu(ui, &mut self.project));
+                });
+                ui.allocate_ui_at_rect(Rect::from_min_size(pos2(200., 14.), vec2(60., 22.)), |ui| {
+                    ui.menu_button("Edit", |ui| self.history.menu(ui, &mut self.project));
+                });
+                self.transport.paint(
+                    ctx,
+                    ui,
+                    Rect::from_min_size(pos2(300., 10.), vec2(560., 30.)),
+                    &self.themes,
+                    &mut self.project,
+                    self.output.as_ref(),
+                );
+                self.metronome.paint(
+                    ui,
+                    Rect::from_min_size(pos2(870., 10.), vec2(90., 30.)),
+                    self.output.as_ref(),
+                );
+
+                self.mixer
+                    .paint_toggle(ui, Rect::from_min_size(pos2(970., 10.), vec2(60., 30.)));
 
-                self.browser.paint(ctx, ui, &viewport, &self.themes);
+                // The mixer takes the timeline's place while it's open
+                let main_area = Rect::from_min_max(
+                    pos2(self.browser.sidebar_width, 50.),
+                    pos2(viewport.width(), viewport.height()),
+                );
+                if self.mixer.open {
+                    self.mixer
+                        .paint(ctx, ui, main_area, &self.themes, &mut self.project);
+                } else {
+                    self.timeline.paint(
+                        ctx,
+                        ui,
+                        main_area,
+                        &self.themes,
+                        &mut self.project,
+                        self.output.as_ref(),
+                    );
+                }
+
+                self.browser
+                    .paint(ctx, ui, &viewport, &self.themes, self.output.as_ref());
             });
+
+        self.history.update(ctx, &mut self.project);
+        if let Some(path) = self.browser.opened_project.take() {
+            self.session.open(path, &mut self.project);
+        }
+        self.session.update(ctx, &mut self.project);
+        if self.session.take_replaced() {
+            self.history.clear();
+        }
+        self.autosave.update(&self.project, &self.session);
+        self.browser.project_folder = self.session.folder();
+        let playhead = self.output.as_ref().map_or(0., |output| {
+            let status = &output.engine.status;
+            let sample_rate = status.sample_rate.load(std::sync::atomic::Ordering::Relaxed);
+            self.project.tempo.beat_at_frame(status.playhead().1, sample_rate)
+        });
+        self.browser.project_tempo = self.project.tempo.tempo_at(playhead);
+        self.playback.update(&self.project, self.output.as_mut());
+        self.metronome.update(self.output.as_ref());
+        self.recorder.update(&mut self.project, &self.session, self.output.as_ref());
+
+        // Switch backends when a different one got picked in the browser
+        if self.browser.selected_backend != self.backend {
+            self.backend = self.browser.selected_backend;
+            // Drop the old stream first, some backends can't have two streams open on the same device
+            self.output = None;
+            self.output = start_output(self.backend);
+        }
+
+        if self.browser.calibration == Calibration::Requested {
+            self.browser.calibration = match &self.output {
+                Some(output) => {
+                    self.calibration = Some(CalibrationRun::start(output));
+                    Calibration::Running
+                }
+                None => Calibration::Failed("no audio backend is running".to_string()),
+            };
+        }
+        if let Some(calibration) = &mut self.calibration {
+            match calibration.poll(self.output.as_ref()) {
+                Some(Ok(measurement)) => {
+                    if let Some(output) = &mut self.output {
+                        output.recording_offset = Some(measurement.round_trip);
+                    }
+                    let mut profiles = LatencyProfiles::load();
+                    profiles.insert(measurement);
+                    if let Err(err) = profiles.save() {
+                        eprintln!("Failed to save the latency measurement: {err}");
+                    }
+                    self.browser.calibration = Calibration::Idle;
+                    self.calibration = None;
+                }
+                Some(Err(err)) => {
+                    self.browser.calibration = Calibration::Failed(err.to_string());
+                    self.calibration = None;
+                }
+                None => ctx.request_repaint_after(Duration::from_millis(100)),
+            }
+        }
     }
     fn on_exit(&mut self, _gl: Option<&eframe::glow::Context>) {
         // Log the exit
         println!("Volt is exiting!");
 
-        // Perform any final saves or cleanup
-        // For example, you might want to save user preferences or state
-        // self.save_state();
+        self.browser.save_state();
+        self.autosave.flush();
 
         // Close any open connections or files
         // self.close_connections();
@@ -0,0 +1,221 @@
+use std::{
+    path::PathBuf,
+    sync::{atomic::Ordering, Arc},
+};
+
+use egui::{Checkbox, Rect, Slider, TextEdit, Ui};
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::{
+    blerp::{
+        audiofile,
+        device::Output,
+        processing::{
+            generation,
+            live::{
+                metronome::{ClickSounds, MetronomeCommand},
+                Command, EngineStatus,
+            },
+        },
+    },
+    config,
+};
+
+const SETTINGS_FILE: &str = "metronome.ron";
+const COUNT_IN_BARS: [u32; 4] = [0, 1, 2, 4];
+
+#[derive(Display, EnumIter, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ClickSound {
+    #[default]
+    Beep,
+    Woodblock,
+    #[strum(to_string = "Your own files")]
+    Files,
+}
+
+impl ClickSound {
+    // Files that are missing or don't decode click with the beep instead
+    fn render(self, accent: bool, file: Option<&PathBuf>, sample_rate: u32) -> Vec<f32> {
+        let decoded = file.filter(|_| self == Self::Files).and_then(|path| {
+            audiofile::decode(path)
+                .map(|buffer| buffer.to_stereo().resampled(sample_rate).samples)
+                .map_err(|err| eprintln!("Failed to load the click sound {}: {err}", path.display()))
+                .ok()
+        });
+        decoded.unwrap_or_else(|| match (self, accent) {
+            (Self::Woodblock, true) => generation::tone(&[(1100., 0.5), (3050., 0.2)], 90., sample_rate),
+            (Self::Woodblock, false) => generation::tone(&[(800., 0.5), (2210., 0.2)], 90., sample_rate),
+            (_, true) => generation::tone(&[(1760., 0.6)], 60., sample_rate),
+            (_, false) => generation::tone(&[(880., 0.5)], 60., sample_rate),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MetronomeSettings {
+    pub enabled: bool,
+    pub volume: f32,
+    pub sound: ClickSound,
+    pub accent_file: Option<PathBuf>,
+    pub beat_file: Option<PathBuf>,
+    pub separate_output: bool,
+    pub count_in_bars: u32,
+}
+
+impl Default for MetronomeSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            volume: 0.7,
+            sound: ClickSound::default(),
+            accent_file: None,
+            beat_file: None,
+            separate_output: false,
+            count_in_bars: 1,
+        }
+    }
+}
+
+// The click track's toggle and settings in the navbar, keeps the engine's metronome in line with them
+pub struct Metronome {
+    settings: MetronomeSettings,
+    // What the engine was last told, so only changes get sent
+    sent: Option<MetronomeSettings>,
+    engine: Option<Arc<EngineStatus>>,
+    // Sounds the engine might still be playing, only dropped once it let go of them
+    sounds: Vec<Arc<ClickSounds>>,
+    // Paths being typed, applied once the field loses focus
+    accent_file: String,
+    beat_file: String,
+}
+
+fn path_text(path: Option<&PathBuf>) -> String {
+    path.map(|path| path.display().to_string()).unwrap_or_default()
+}
+
+impl Metronome {
+    pub fn new() -> Self {
+        let settings: MetronomeSettings = config::load(SETTINGS_FILE);
+        Self {
+            accent_file: path_text(settings.accent_file.as_ref()),
+            beat_file: path_text(settings.beat_file.as_ref()),
+            settings,
+            sent: None,
+            engine: None,
+            sounds: Vec::new(),
+        }
+    }
+
+    fn save(&self) {
+        if let Err(err) = config::save(SETTINGS_FILE, &self.settings) {
+            eprintln!("Failed to save the metronome settings: {err}");
+        }
+    }
+
+    pub fn paint(&mut self, ui: &mut Ui, area: Rect, output: Option<&Output>) {
+        ui.allocate_ui_at_rect(area, |ui| {
+            ui.horizontal_centered(|ui| {
+                if ui
+                    .selectable_label(self.settings.enabled, "Click")
+                    .on_hover_text("Metronome")
+                    .clicked()
+                {
+                    self.settings.enabled = !self.settings.enabled;
+                    self.save();
+                }
+                ui.menu_button("⏷", |ui| self.menu(ui, output))
+                    .response
+                    .on_hover_text("Metronome settings");
+            });
+        });
+    }
+
+    fn menu(&mut self, ui: &mut Ui, output: Option<&Output>) {
+        let before = self.settings.clone();
+        ui.add(Slider::new(&mut self.settings.volume, 0.0..=1.).text("Volume"));
+
+        ui.separator();
+        for sound in ClickSound::iter() {
+            ui.radio_value(&mut self.settings.sound, sound, sound.to_string());
+        }
+        if self.settings.sound == ClickSound::Files {
+            for (label, text, path) in [
+                ("Accent", &mut self.accent_file, &mut self.settings.accent_file),
+                ("Beat", &mut self.beat_file, &mut self.settings.beat_file),
+            ] {
+                ui.horizontal(|ui| {
+                    ui.label(label);
+                    if ui.add(TextEdit::singleline(text).hint_text("Path to a WAV file")).lost_focus() {
+                        *path = (!text.trim().is_empty()).then(|| PathBuf::from(text.trim()));
+                    }
+                });
+            }
+        }
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            ui.label("Count-in");
+            for bars in COUNT_IN_BARS {
+                let text = match bars {
+                    0 => "Off".to_string(),
+                    1 => "1 bar".to_string(),
+                    _ => format!("{bars} bars"),
+                };
+                ui.selectable_value(&mut self.settings.count_in_bars, bars, text);
+            }
+        });
+
+        let available = output.is_some_and(|output| output.engine.status.separate_click_output.load(Ordering::Relaxed));
+        ui.add_enabled(available, Checkbox::new(&mut self.settings.separate_output, "Own output"))
+            .on_hover_text("The JACK port \"Click\", or outputs 3 and 4 of the audio device")
+            .on_disabled_hover_text("Needs JACK or an audio device with more than two outputs");
+
+        if self.settings != before {
+            self.save();
+        }
+    }
+
+    // Called every frame, sends whatever changed to the engine
+    pub fn update(&mut self, output: Option<&Output>) {
+        let Some(output) = output else {
+            self.engine = None;
+            return;
+        };
+        let status = &output.engine.status;
+        if !self.engine.as_ref().is_some_and(|engine| Arc::ptr_eq(engine, status)) {
+            self.engine = Some(status.clone());
+            self.sent = None;
+        }
+        self.sounds.retain(|sounds| Arc::strong_count(sounds) > 1);
+
+        let settings = &self.settings;
+        let sent = self.sent.as_ref();
+        let send = |command| output.engine.send(Command::Metronome(command));
+        let sounds_changed = sent.map(|sent| (sent.sound, &sent.accent_file, &sent.beat_file))
+            != Some((settings.sound, &settings.accent_file, &settings.beat_file));
+        if sounds_changed {
+            let sample_rate = status.sample_rate.load(Ordering::Relaxed);
+            let sounds = Arc::new(ClickSounds {
+                accent: settings.sound.render(true, settings.accent_file.as_ref(), sample_rate),
+                beat: settings.sound.render(false, settings.beat_file.as_ref(), sample_rate),
+            });
+            self.sounds.push(sounds.clone());
+            send(MetronomeCommand::Sounds(sounds));
+        }
+        if sent.map(|sent| sent.enabled) != Some(settings.enabled) {
+            send(MetronomeCommand::Enable(settings.enabled));
+        }
+        if sent.map(|sent| sent.volume) != Some(settings.volume) {
+            send(MetronomeCommand::Volume(settings.volume));
+        }
+        if sent.map(|sent| sent.separate_output) != Some(settings.separate_output) {
+            send(MetronomeCommand::SeparateOutput(settings.separate_output));
+        }
+        if sent.map(|sent| sent.count_in_bars) != Some(settings.count_in_bars) {
+            send(MetronomeCommand::CountInBars(settings.count_in_bars));
+        }
+        self.sent = Some(self.settings.clone());
+    }
+}
@@ -0,0 +1,358 @@
+use egui::{
+    Align, ComboBox, Context, DragValue, Frame, Label, Layout, Rect, Response, RichText, ScrollArea, Sense, Slider,
+    Ui,
+};
+use strum::IntoEnumIterator;
+
+use crate::{
+    blerp::processing::EFFECTS,
+    project::{Channel, Insert, Mix, PanLaw, Project, Send, MAX_GAIN, MIN_GAIN},
+    transport::DEFAULT_SAMPLE_RATE,
+    visual::ThemeColors,
+};
+
+const STRIP_WIDTH: f32 = 110.;
+const FADER_HEIGHT: f32 = 160.;
+
+// "-12.0 dB", "-inf" at the bottom of the fader
+fn decibels(gain: f64) -> String {
+    if gain <= MIN_GAIN {
+        "-inf".to_string()
+    } else {
+        format!("{gain:.1} dB")
+    }
+}
+
+// "L50", "C", "R100"
+fn pan_text(pan: f64) -> String {
+    let percent = (pan * 100.).round();
+    if percent < 0. {
+        format!("L{}", -percent)
+    } else if percent > 0. {
+        format!("R{percent}")
+    } else {
+        "C".to_string()
+    }
+}
+
+// Every track, bus and the master side by side, shown instead of the timeline while it's open
+pub struct Mixer {
+    pub open: bool,
+    // Dragging a fader or a knob is a single step to undo
+    dragging: bool,
+}
+
+impl Mixer {
+    pub fn new() -> Self {
+        Self {
+            open: false,
+            dragging: false,
+        }
+    }
+
+    pub fn paint_toggle(&mut self, ui: &mut Ui, area: Rect) {
+        ui.allocate_ui_at_rect(area, |ui| {
+            ui.horizontal_centered(|ui| {
+                if ui.selectable_label(self.open, "Mixer").clicked() {
+                    self.open = !self.open;
+                }
+            });
+        });
+    }
+
+    pub fn paint(&mut self, ctx: &Context, ui: &mut Ui, area: Rect, theme: &ThemeColors, project: &mut Project) {
+        ui.painter_at(area).rect_filled(area, 0., theme.timeline_lane);
+        ui.allocate_ui_at_rect(area.shrink(8.), |ui| {
+            ScrollArea::both().auto_shrink(false).show(ui, |ui| {
+                ui.horizontal_top(|ui| {
+                    for channel in project.channels() {
+                        self.paint_strip(ui, theme, project, channel);
+                    }
+                    if ui.button("Add bus").clicked() {
+                        project.add_bus(None);
+                    }
+                });
+            });
+        });
+        if self.dragging && ctx.dragged_id().is_none() {
+            self.dragging = false;
+            project.end();
+        }
+    }
+
+    // Widgets that get dragged group everything until they're let go of
+    fn drag(&mut self, project: &mut Project, response: &Response, label: &str) {
+        if response.drag_started() && !self.dragging {
+            self.dragging = true;
+            project.begin(label);
+        }
+    }
+
+    fn paint_strip(&mut self, ui: &mut Ui, theme: &ThemeColors, project: &mut Project, channel: Channel) {
+        let Some(mut mix) = project.mix(channel).cloned() else {
+            return;
+        };
+        let mut label = "Change mix";
+        let name = match channel {
+            Channel::Track(id) => project.tracks.iter().find(|track| track.id == id).map(|track| track.name.clone()),
+            Channel::Bus(id) => project.buses.iter().find(|bus| bus.id == id).map(|bus| bus.name.clone()),
+            Channel::Master => Some("Master".to_string()),
+        }
+        .unwrap_or_default();
+
+        Frame::none()
+            .fill(theme.browser)
+            .rounding(4.)
+            .inner_margin(6.)
+            .show(ui, |ui| {
+                ui.set_width(STRIP_WIDTH);
+                ui.with_layout(Layout::top_down(Align::Center), |ui| {
+                    let response = ui.add(
+                        Label::new(RichText::new(&name).color(theme.browser_unselected_hover_button_fg))
+                            .truncate()
+                            .sense(Sense::click()),
+                    );
+                    if let Channel::Bus(id) = channel {
+                        response.context_menu(|ui| {
+                            if ui.button("Delete bus").clicked() {
+                                project.remove_bus(id);
+                                ui.close_menu();
+                            }
+                        });
+                    }
+                    if channel == Channel::Master {
+                        let mut pan_law = project.pan_law;
+                        ComboBox::from_id_source("pan_law")
+                            .width(STRIP_WIDTH - 8.)
+                            .selected_text(pan_law.to_string())
+                            .show_ui(ui, |ui| {
+                                for law in PanLaw::iter() {
+                                    ui.selectable_value(&mut pan_law, law, law.to_string());
+                                }
+                            })
+                            .response
+                            .on_hover_text("Pan law, how much quieter a sound panned to the middle gets");
+                        project.set_pan_law(pan_law);
+                    }
+
+                    ui.separator();
+                    self.paint_inserts(ui, project, channel, &mut mix, &mut label);
+                    if channel != Channel::Master {
+                        ui.separator();
+                        self.paint_sends(ui, project, channel, &mut mix, &mut label);
+                    }
+
+                    ui.separator();
+                    ui.horizontal(|ui| {
+                        let mut toggle = |ui: &mut Ui, value: &mut bool, text: &str, hint: &str, edit: &'static str| {
+                            if ui.selectable_label(*value, text).on_hover_text(hint).clicked() {
+                                *value = !*value;
+                                label = edit;
+                            }
+                        };
+                        toggle(ui, &mut mix.invert_phase, "Ø", "Invert phase", "Invert phase");
+                        toggle(ui, &mut mix.mute, "M", "Mute", "Mute");
+                        if channel != Channel::Master {
+                            toggle(ui, &mut mix.solo, "S", "Solo", "Solo");
+                            toggle(ui, &mut mix.solo_safe, "Safe", "Solo safe, keeps playing while others are soloed", "Solo safe");
+                        }
+                    });
+
+                    let response = ui
+                        .add(
+                            DragValue::new(&mut mix.pan)
+                                .range(-1.0..=1.)
+                                .speed(0.01)
+                                .custom_formatter(|pan, _| pan_text(pan))
+                                .custom_parser(|text| {
+                                    let text = text.trim().to_uppercase();
+                                    let percent = |number: &str| number.parse::<f64>().ok().map(|number| number / 100.);
+                                    match text.chars().next() {
+                                        Some('L') => percent(&text[1..]).map(|pan| -pan),
+                                        Some('R') => percent(&text[1..]),
+                                        Some('C') => Some(0.),
+                                        _ => percent(&text),
+                                    }
+                                }),
+                        )
+                        .on_hover_text("Pan, double click to center");
+                    self.drag(project, &response, "Change pan");
+                    if response.double_clicked() {
+                        mix.pan = 0.;
+                    }
+                    if response.changed() || response.double_clicked() {
+                        label = "Change pan";
+                    }
+
+                    // A vertical slider is as long as a horizontal one is wide
+                    ui.spacing_mut().slider_width = FADER_HEIGHT;
+                    let response = ui
+                        .add(
+                            Slider::new(&mut mix.gain, MIN_GAIN..=MAX_GAIN)
+                                .vertical()
+                                .show_value(false)
+                                .trailing_fill(true),
+                        )
+                        .on_hover_text("Volume, double click for 0 dB");
+                    self.drag(project, &response, "Change volume");
+                    if response.double_clicked() {
+                        mix.gain = 0.;
+                    }
+                    if response.changed() || response.double_clicked() {
+                        label = "Change volume";
+                    }
+                    ui.label(RichText::new(decibels(mix.gain)).small());
+
+                    self.paint_output(ui, project, channel);
+                });
+            });
+
+        project.set_mix(label, channel, mix);
+    }
+
+    fn paint_inserts(&mut self, ui: &mut Ui, project: &mut Project, channel: Channel, mix: &mut Mix, label: &mut &str) {
+        let mut removed = None;
+        for (index, insert) in mix.inserts.iter_mut().enumerate() {
+            ui.horizontal(|ui| {
+                let hint = if insert.effect.latency(DEFAULT_SAMPLE_RATE) > 0 {
+                    "Click to bypass. It looks ahead, everything else gets delayed to stay in line with it"
+                } else {
+                    "Click to bypass"
+                };
+                if ui
+                    .selectable_label(!insert.bypass, insert.effect.to_string())
+                    .on_hover_text(hint)
+                    .clicked()
+                {
+                    insert.bypass = !insert.bypass;
+                    *label = "Bypass effect";
+                }
+                let (value, range) = insert.effect.parameter();
+                let response = ui.add(DragValue::new(value).range(range).speed(0.01).max_decimals(2));
+                self.drag(project, &response, "Change effect");
+                if response.changed() {
+                    *label = "Change effect";
+                }
+                if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            mix.inserts.remove(index);
+            *label = "Remove effect";
+        }
+        ui.menu_button("Add effect", |ui| {
+            for effect in EFFECTS {
+                if ui.button(effect.to_string()).clicked() {
+                    mix.inserts.push(Insert { effect, bypass: false });
+                    *label = "Add effect";
+                    ui.close_menu();
+                }
+            }
+        })
+        .response
+        .on_hover_text(format!("Effects on {}, top to bottom", channel_name(project, channel)));
+    }
+
+    fn paint_sends(&mut self, ui: &mut Ui, project: &mut Project, channel: Channel, mix: &mut Mix, label: &mut &str) {
+        let mut removed = None;
+        for (index, send) in mix.sends.iter_mut().enumerate() {
+            let bus = project.buses.iter().find(|bus| bus.id == send.bus).map_or("", |bus| bus.name.as_str());
+            ui.horizontal(|ui| {
+                ui.add(Label::new(RichText::new(bus).small()).truncate());
+            });
+            ui.horizontal(|ui| {
+                let response = ui.add(
+                    DragValue::new(&mut send.level)
+                        .range(MIN_GAIN..=MAX_GAIN)
+                        .speed(0.2)
+                        .custom_formatter(|level, _| decibels(level)),
+                );
+                self.drag(project, &response, "Change send");
+                if response.changed() {
+                    *label = "Change send";
+                }
+                let text = if send.pre_fader { "Pre" } else { "Post" };
+                if ui
+                    .selectable_label(send.pre_fader, text)
+                    .on_hover_text("Taken before or after the fader")
+                    .clicked()
+                {
+                    send.pre_fader = !send.pre_fader;
+                    *label = "Change send";
+                }
+                if ui.small_button("✖").on_hover_text("Remove").clicked() {
+                    removed = Some(index);
+                }
+            });
+        }
+        if let Some(index) = removed {
+            mix.sends.remove(index);
+            *label = "Remove send";
+        }
+        let targets: Vec<_> = project
+            .buses
+            .iter()
+            .filter(|bus| channel != Channel::Bus(bus.id) && !mix.sends.iter().any(|send| send.bus == bus.id))
+            .map(|bus| (bus.id, bus.name.clone()))
+            .collect();
+        ui.add_enabled_ui(!targets.is_empty(), |ui| {
+            ui.menu_button("Add send", |ui| {
+                for (bus, name) in targets {
+                    if ui.button(name).clicked() {
+                        mix.sends.push(Send {
+                            bus,
+                            level: 0.,
+                            pre_fader: false,
+                        });
+                        *label = "Add send";
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+    }
+
+    fn paint_output(&mut self, ui: &mut Ui, project: &mut Project, channel: Channel) {
+        let current = match channel {
+            Channel::Track(id) => project.tracks.iter().find(|track| track.id == id).map(|track| track.output),
+            Channel::Bus(id) => project.buses.iter().find(|bus| bus.id == id).map(|bus| bus.output),
+            Channel::Master => None,
+        };
+        let Some(current) = current else {
+            return;
+        };
+        let mut output = current;
+        let name = |output: Option<_>| {
+            output
+                .and_then(|output| project.buses.iter().find(|bus| bus.id == output))
+                .map_or("Master".to_string(), |bus| bus.name.clone())
+        };
+        ComboBox::from_id_source(("mixer_output", format!("{channel:?}")))
+            .width(STRIP_WIDTH - 8.)
+            .selected_text(format!("→ {}", name(output)))
+            .show_ui(ui, |ui| {
+                ui.selectable_value(&mut output, None, "Master");
+                for bus in project.buses.iter().filter(|bus| channel != Channel::Bus(bus.id)) {
+                    ui.selectable_value(&mut output, Some(bus.id), &bus.name);
+                }
+            });
+        if output != current {
+            match channel {
+                Channel::Track(id) => project.set_track_output(id, output),
+                Channel::Bus(id) => project.set_bus_output(id, output),
+                Channel::Master => {}
+            }
+        }
+    }
+}
+
+fn channel_name(project: &Project, channel: Channel) -> String {
+    match channel {
+        Channel::Track(id) => project.tracks.iter().find(|track| track.id == id).map(|track| track.name.clone()),
+        Channel::Bus(id) => project.buses.iter().find(|bus| bus.id == id).map(|bus| bus.name.clone()),
+        Channel::Master => Some("the master".to_string()),
+    }
+    .unwrap_or_default()
+}
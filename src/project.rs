@@ -0,0 +1,433 @@
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use serde::{Deserialize, Serialize};
+
+pub use clip::{Clip, ClipSource, MIN_CLIP_LENGTH};
+pub use edit::Edit;
+pub use file::is_project_file;
+pub use history::History;
+pub use mixer::{Channel, Insert, Mix, PanLaw, Send, MAX_GAIN, MIN_GAIN};
+pub use playback::Playback;
+pub use recording::Recorder;
+pub use recovery::{emergency_save, Autosave};
+pub use session::Session;
+pub use snapshot::{BusSnapshot, Snapshot, StripSnapshot, TrackSnapshot};
+// Only the graph's tests build snapshots by hand
+#[cfg(test)]
+pub use snapshot::{AudioClipSnapshot, SendSnapshot};
+pub use tempo::{TempoMap, TimeSignature, MAX_TEMPO, MIN_TEMPO};
+pub use track::{Bus, Track};
+
+mod clip;
+mod edit;
+mod file;
+mod history;
+mod mixer;
+mod playback;
+mod pool;
+mod recording;
+mod recovery;
+mod session;
+mod snapshot;
+mod tempo;
+mod track;
+
+// Ids stay the same for as long as the thing they point to exists, unlike its position in a list
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct TrackId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct ClipId(u64);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct BusId(u64);
+
+// Part of the song that plays over and over while looping is on, in beats
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct LoopRegion {
+    pub start: f64,
+    pub end: f64,
+    pub enabled: bool,
+}
+
+impl Default for LoopRegion {
+    fn default() -> Self {
+        Self {
+            start: 0.,
+            end: 16.,
+            enabled: false,
+        }
+    }
+}
+
+// Edits made since the history last looked, as the edits that undo them
+#[derive(Debug, Clone, Default)]
+struct Journal {
+    label: Option<String>,
+    edits: Vec<Edit>,
+    // How many `begin`s are still waiting for their `end`
+    depth: usize,
+}
+
+// Tells versions of a project apart without comparing them. Every edit and every project that gets
+// made or loaded gets a number nothing else had
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Revision(u64);
+
+static REVISIONS: AtomicU64 = AtomicU64::new(0);
+
+impl Revision {
+    fn next() -> Self {
+        Self(REVISIONS.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
+impl Default for Revision {
+    fn default() -> Self {
+        Self::next()
+    }
+}
+
+// Everything that makes up a song. Only the UI thread touches this, the audio thread plays from
+// snapshots of it instead
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Project {
+    pub tempo: TempoMap,
+    #[serde(default)]
+    pub loop_region: LoopRegion,
+    pub tracks: Vec<Track>,
+    pub buses: Vec<Bus>,
+    #[serde(default)]
+    pub master: Mix,
+    #[serde(default)]
+    pub pan_law: PanLaw,
+    // Ids are never handed out twice, not even after what they pointed to is gone
+    next_id: u64,
+    #[serde(skip)]
+    journal: Journal,
+    #[serde(skip)]
+    revision: Revision,
+}
+
+// The journal and revision are bookkeeping, two projects with different histories are still the same project
+impl PartialEq for Project {
+    fn eq(&self, other: &Self) -> bool {
+        self.tempo == other.tempo
+            && self.loop_region == other.loop_region
+            && self.tracks == other.tracks
+            && self.buses == other.buses
+            && self.master == other.master
+            && self.pan_law == other.pan_law
+            && self.next_id == other.next_id
+    }
+}
+
+impl Project {
+    // What a new song starts out with
+    pub fn new() -> Self {
+        let mut project = Self::default();
+        for _ in 0..4 {
+            project.add_track(None);
+        }
+        // Nothing to undo in a project that was just made
+        project.journal = Journal::default();
+        project
+    }
+
+    pub const fn revision(&self) -> Revision {
+        self.revision
+    }
+
+    // Every change to the project goes through here so it can be undone
+    fn record(&mut self, label: &str, edit: Edit) {
+        let undo = edit.apply(self);
+        let journal = &mut self.journal;
+        journal.label.get_or_insert_with(|| label.to_string());
+        if !journal.edits.last().is_some_and(|earlier| undo.merges_into(earlier)) {
+            journal.edits.push(undo);
+        }
+    }
+
+    // Everything up to the matching `end` becomes a single step in the history, even across frames
+    pub fn begin(&mut self, label: &str) {
+        if self.journal.depth == 0 {
+            self.journal.label = Some(label.to_string());
+        }
+        self.journal.depth += 1;
+    }
+
+    pub fn end(&mut self) {
+        self.journal.depth = self.journal.depth.saturating_sub(1);
+    }
+
+    pub fn is_grouping(&self) -> bool {
+        self.journal.depth > 0
+    }
+
+    // What changed since the last call, unless a group is still open
+    pub fn take_journal(&mut self) -> Option<(String, Vec<Edit>)> {
+        if self.is_grouping() {
+            return None;
+        }
+        let Journal { label, edits, .. } = std::mem::take(&mut self.journal);
+        (!edits.is_empty()).then(|| (label.unwrap_or_default(), edits))
+    }
+
+    // Files edited by hand could hand out ids that are already taken
+    fn repair_ids(&mut self) {
+        let highest = self
+            .tracks
+            .iter()
+            .flat_map(|track| [track.id.0].into_iter().chain(track.clips.iter().map(|clip| clip.id.0)))
+            .chain(self.buses.iter().map(|bus| bus.id.0))
+            .max()
+            .unwrap_or_default();
+        self.next_id = self.next_id.max(highest);
+    }
+
+    fn next_id(&mut self, label: &str) -> u64 {
+        self.record(label, Edit::SetNextId(self.next_id + 1));
+        self.next_id
+    }
+
+    // Changes to the tempo map are made on a copy of it, which then replaces the original
+    pub fn set_tempo_map(&mut self, label: &str, tempo: TempoMap) {
+        if tempo != self.tempo {
+            self.record(label, Edit::SetTempo(tempo));
+        }
+    }
+
+    pub fn set_loop_region(&mut self, label: &str, region: LoopRegion) {
+        if region != self.loop_region {
+            self.record(label, Edit::SetLoop(region));
+        }
+    }
+
+    pub fn add_track(&mut self, name: Option<String>) -> TrackId {
+        let label = "Add track";
+        let id = TrackId(self.next_id(label));
+        let name = name.unwrap_or_else(|| format!("Track {}", self.tracks.len() + 1));
+        let track = Track {
+            id,
+            name,
+            clips: Vec::new(),
+            output: None,
+            mix: Mix::default(),
+        };
+        self.record(label, Edit::InsertTrack {
+            index: self.tracks.len(),
+            track,
+        });
+        id
+    }
+
+    pub fn remove_track(&mut self, id: TrackId) {
+        if let Some(index) = self.track_index(id) {
+            self.record("Delete track", Edit::RemoveTrack { index });
+        }
+    }
+
+    pub fn track_index(&self, id: TrackId) -> Option<usize> {
+        self.tracks.iter().position(|track| track.id == id)
+    }
+
+    pub fn add_bus(&mut self, name: Option<String>) -> BusId {
+        let label = "Add bus";
+        let id = BusId(self.next_id(label));
+        let name = name.unwrap_or_else(|| format!("Bus {}", self.buses.len() + 1));
+        let bus = Bus {
+            id,
+            name,
+            output: None,
+            mix: Mix::default(),
+        };
+        self.record(label, Edit::InsertBus {
+            index: self.buses.len(),
+            bus,
+        });
+        id
+    }
+
+    pub fn set_track_output(&mut self, track: TrackId, output: Option<BusId>) {
+        let output = output.filter(|bus| self.buses.iter().any(|existing| existing.id == *bus));
+        if let Some(index) = self.track_index(track).filter(|index| self.tracks[*index].output != output) {
+            self.record("Change track output", Edit::SetTrackOutput { track: index, output });
+        }
+    }
+
+    pub fn bus_index(&self, id: BusId) -> Option<usize> {
+        self.buses.iter().position(|bus| bus.id == id)
+    }
+
+    // Whatever was routed or sending to the bus goes to the master instead
+    pub fn remove_bus(&mut self, id: BusId) {
+        let Some(index) = self.bus_index(id) else {
+            return;
+        };
+        let label = "Delete bus";
+        self.begin(label);
+        for track in 0..self.tracks.len() {
+            if self.tracks[track].output == Some(id) {
+                self.record(label, Edit::SetTrackOutput { track, output: None });
+            }
+        }
+        for bus in 0..self.buses.len() {
+            if self.buses[bus].output == Some(id) {
+                self.record(label, Edit::SetBusOutput { bus, output: None });
+            }
+        }
+        for channel in self.channels() {
+            if let Some(mut mix) = self.mix(channel).filter(|mix| mix.sends.iter().any(|send| send.bus == id)).cloned() {
+                mix.sends.retain(|send| send.bus != id);
+                self.set_mix(label, channel, mix);
+            }
+        }
+        self.record(label, Edit::RemoveBus { index });
+        self.end();
+    }
+
+    // A bus can't output to itself, anything else that loops back gets cut off when playing
+    pub fn set_bus_output(&mut self, bus: BusId, output: Option<BusId>) {
+        let output = output.filter(|output| *output != bus && self.bus_index(*output).is_some());
+        if let Some(index) = self.bus_index(bus).filter(|index| self.buses[*index].output != output) {
+            self.record("Change bus output", Edit::SetBusOutput { bus: index, output });
+        }
+    }
+
+    // Every strip in the mixer, master last
+    pub fn channels(&self) -> Vec<Channel> {
+        self.tracks
+            .iter()
+            .map(|track| Channel::Track(track.id))
+            .chain(self.buses.iter().map(|bus| Channel::Bus(bus.id)))
+            .chain([Channel::Master])
+            .collect()
+    }
+
+    pub fn mix(&self, channel: Channel) -> Option<&Mix> {
+        match channel {
+            Channel::Track(id) => self.track_index(id).map(|index| &self.tracks[index].mix),
+            Channel::Bus(id) => self.bus_index(id).map(|index| &self.buses[index].mix),
+            Channel::Master => Some(&self.master),
+        }
+    }
+
+    // Like the tempo map, mixes get changed on a copy
+    pub fn set_mix(&mut self, label: &str, channel: Channel, mix: Mix) {
+        if self.mix(channel).is_none_or(|current| *current == mix) {
+            return;
+        }
+        let edit = match channel {
+            Channel::Track(id) => self.track_index(id).map(|track| Edit::SetTrackMix { track, mix }),
+            Channel::Bus(id) => self.bus_index(id).map(|bus| Edit::SetBusMix { bus, mix }),
+            Channel::Master => Some(Edit::SetMasterMix(mix)),
+        };
+        if let Some(edit) = edit {
+            self.record(label, edit);
+        }
+    }
+
+    pub fn set_pan_law(&mut self, pan_law: PanLaw) {
+        if pan_law != self.pan_law {
+            self.record("Change pan law", Edit::SetPanLaw(pan_law));
+        }
+    }
+
+    pub fn audio_paths_mut(&mut self) -> impl Iterator<Item = &mut PathBuf> {
+        self.tracks
+            .iter_mut()
+            .flat_map(|track| &mut track.clips)
+            .filter_map(|clip| match &mut clip.source {
+                ClipSource::Audio { path, .. } => Some(path),
+                ClipSource::Midi { .. } => None,
+            })
+    }
+
+    // Track index and index within the track
+    pub fn find_clip(&self, id: ClipId) -> Option<(usize, usize)> {
+        self.tracks.iter().enumerate().find_map(|(track, Track { clips, .. })| {
+            Some((track, clips.iter().position(|clip| clip.id == id)?))
+        })
+    }
+
+    pub fn clip(&self, id: ClipId) -> Option<&Clip> {
+        let (track, index) = self.find_clip(id)?;
+        Some(&self.tracks[track].clips[index])
+    }
+
+    // Replaces the clip with the same id
+    pub fn set_clip(&mut self, label: &str, clip: Clip) {
+        if let Some((track, index)) = self.find_clip(clip.id).filter(|&(track, index)| self.tracks[track].clips[index] != clip) {
+            self.record(label, Edit::ReplaceClip { track, index, clip });
+        }
+    }
+
+    fn push_clip(&mut self, label: &str, track: usize, clip: Clip) {
+        let index = self.tracks[track].clips.len();
+        self.record(label, Edit::InsertClip { track, index, clip });
+    }
+
+    pub fn add_clip(&mut self, track: TrackId, start: f64, length: f64, source: ClipSource) -> Option<ClipId> {
+        let label = "Add clip";
+        let index = self.track_index(track)?;
+        let id = ClipId(self.next_id(label));
+        let clip = Clip {
+            id,
+            start: start.max(0.),
+            length: length.max(MIN_CLIP_LENGTH),
+            source,
+        };
+        self.push_clip(label, index, clip);
+        Some(id)
+    }
+
+    // Copies a clip to `start` on the same track
+    pub fn duplicate_clip(&mut self, id: ClipId, start: f64) -> Option<ClipId> {
+        let label = "Duplicate clip";
+        let (track, index) = self.find_clip(id)?;
+        let copy = Clip {
+            id: ClipId(self.next_id(label)),
+            start: start.max(0.),
+            ..self.tracks[track].clips[index].clone()
+        };
+        let id = copy.id;
+        self.push_clip(label, track, copy);
+        Some(id)
+    }
+
+    pub fn remove_clip(&mut self, id: ClipId) {
+        if let Some((track, index)) = self.find_clip(id) {
+            self.record("Delete clip", Edit::RemoveClip { track, index });
+        }
+    }
+
+    pub fn move_clip(&mut self, id: ClipId, to: TrackId) {
+        let label = "Move clip";
+        let Some(to) = self.track_index(to) else {
+            return;
+        };
+        if let Some((from, index)) = self.find_clip(id).filter(|(from, _)| *from != to) {
+            let clip = self.tracks[from].clips[index].clone();
+            self.record(label, Edit::RemoveClip { track: from, index });
+            self.push_clip(label, to, clip);
+        }
+    }
+
+    // Returns the new clip after the cut
+    pub fn split_clip(&mut self, id: ClipId, at: f64) -> Option<ClipId> {
+        let label = "Split clip";
+        let (track, index) = self.find_clip(id)?;
+        let mut before = self.tracks[track].clips[index].clone();
+        let after = before.split(at, &self.tempo, ClipId(self.next_id + 1))?;
+        let after_id = ClipId(self.next_id(label));
+        self.record(label, Edit::ReplaceClip { track, index, clip: before });
+        self.push_clip(label, track, after);
+        Some(after_id)
+    }
+}
@@ -0,0 +1,99 @@
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+
+use super::{ClipId, TempoMap};
+
+// Clips can't get shorter than this many beats
+pub const MIN_CLIP_LENGTH: f64 = 1. / 16.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Note {
+    // In beats, from the start of the clip
+    pub start: f64,
+    pub length: f64,
+    pub pitch: u8,
+    pub velocity: u8,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ClipSource {
+    Audio {
+        path: PathBuf,
+        // Seconds into the file where the clip starts, so trimming the start doesn't move the audio
+        offset: f64,
+    },
+    Midi {
+        notes: Vec<Note>,
+    },
+}
+
+// Positions and lengths are in beats
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Clip {
+    pub id: ClipId,
+    pub start: f64,
+    pub length: f64,
+    pub source: ClipSource,
+}
+
+impl Clip {
+    pub fn end(&self) -> f64 {
+        self.start + self.length
+    }
+
+    pub fn name(&self) -> String {
+        match &self.source {
+            ClipSource::Audio { path, .. } => path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .to_string(),
+            ClipSource::Midi { .. } => "MIDI".to_string(),
+        }
+    }
+
+    // Moves the start of the clip without moving what's in it. Audio can't start before the file does
+    pub fn trim_start(&mut self, start: f64, tempo: &TempoMap) {
+        let mut start = start.min(self.end() - MIN_CLIP_LENGTH);
+        if let ClipSource::Audio { offset, .. } = &self.source {
+            start = start.max(tempo.beat_at(tempo.seconds_at(self.start) - offset));
+        }
+        let start = start.max(0.);
+        let moved = start - self.start;
+        match &mut self.source {
+            ClipSource::Audio { offset, .. } => {
+                *offset = (*offset + tempo.seconds_at(start) - tempo.seconds_at(self.start)).max(0.);
+            }
+            ClipSource::Midi { notes } => {
+                for note in notes {
+                    note.start -= moved;
+                }
+            }
+        }
+        self.start = start;
+        self.length -= moved;
+    }
+
+    pub fn trim_end(&mut self, end: f64) {
+        self.length = (end - self.start).max(MIN_CLIP_LENGTH);
+    }
+
+    // Cuts the clip in two at `at`, returning the part after it with `id`
+    pub fn split(&mut self, at: f64, tempo: &TempoMap, id: ClipId) -> Option<Self> {
+        if at <= self.start + MIN_CLIP_LENGTH / 2. || at >= self.end() - MIN_CLIP_LENGTH / 2. {
+            return None;
+        }
+        let mut after = Self { id, ..self.clone() };
+        after.trim_start(at, tempo);
+        if let ClipSource::Midi { notes } = &mut after.source {
+            notes.retain(|note| note.start >= 0.);
+        }
+        self.trim_end(at);
+        if let ClipSource::Midi { notes } = &mut self.source {
+            let length = self.length;
+            notes.retain(|note| note.start < length);
+        }
+        Some(after)
+    }
+}
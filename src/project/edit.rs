@@ -0,0 +1,182 @@
+use std::mem::{size_of, size_of_val};
+
+use super::{clip::Note, Bus, BusId, Clip, ClipSource, LoopRegion, Mix, PanLaw, Project, Revision, TempoMap, Track};
+
+// The smallest changes to a project, everything that edits one is made of these. Applying an edit
+// returns the edit that undoes it. Positions are indices, which is fine because edits only ever get
+// undone in the reverse order they were made in
+#[derive(Debug, Clone, PartialEq)]
+pub enum Edit {
+    SetNextId(u64),
+    SetTempo(TempoMap),
+    SetLoop(LoopRegion),
+    SetPanLaw(PanLaw),
+    SetMasterMix(Mix),
+    InsertTrack { index: usize, track: Track },
+    RemoveTrack { index: usize },
+    SetTrackOutput { track: usize, output: Option<BusId> },
+    SetTrackMix { track: usize, mix: Mix },
+    InsertBus { index: usize, bus: Bus },
+    RemoveBus { index: usize },
+    SetBusOutput { bus: usize, output: Option<BusId> },
+    SetBusMix { bus: usize, mix: Mix },
+    InsertClip { track: usize, index: usize, clip: Clip },
+    RemoveClip { track: usize, index: usize },
+    ReplaceClip { track: usize, index: usize, clip: Clip },
+}
+
+fn clip_size(clip: &Clip) -> usize {
+    size_of::<Clip>()
+        + match &clip.source {
+            ClipSource::Audio { path, .. } => path.as_os_str().len(),
+            ClipSource::Midi { notes } => notes.capacity() * size_of::<Note>(),
+        }
+}
+
+fn mix_size(mix: &Mix) -> usize {
+    size_of_val(mix.inserts.as_slice()) + size_of_val(mix.sends.as_slice())
+}
+
+impl Edit {
+    pub fn apply(self, project: &mut Project) -> Self {
+        project.revision = Revision::next();
+        match self {
+            Self::SetNextId(next_id) => Self::SetNextId(std::mem::replace(&mut project.next_id, next_id)),
+            Self::SetTempo(tempo) => Self::SetTempo(std::mem::replace(&mut project.tempo, tempo)),
+            Self::SetLoop(region) => Self::SetLoop(std::mem::replace(&mut project.loop_region, region)),
+            Self::SetPanLaw(pan_law) => Self::SetPanLaw(std::mem::replace(&mut project.pan_law, pan_law)),
+            Self::SetMasterMix(mix) => Self::SetMasterMix(std::mem::replace(&mut project.master, mix)),
+            Self::InsertTrack { index, track } => {
+                project.tracks.insert(index, track);
+                Self::RemoveTrack { index }
+            }
+            Self::RemoveTrack { index } => Self::InsertTrack {
+                index,
+                track: project.tracks.remove(index),
+            },
+            Self::SetTrackOutput { track, output } => Self::SetTrackOutput {
+                track,
+                output: std::mem::replace(&mut project.tracks[track].output, output),
+            },
+            Self::SetTrackMix { track, mix } => Self::SetTrackMix {
+                track,
+                mix: std::mem::replace(&mut project.tracks[track].mix, mix),
+            },
+            Self::InsertBus { index, bus } => {
+                project.buses.insert(index, bus);
+                Self::RemoveBus { index }
+            }
+            Self::RemoveBus { index } => Self::InsertBus {
+                index,
+                bus: project.buses.remove(index),
+            },
+            Self::SetBusOutput { bus, output } => Self::SetBusOutput {
+                bus,
+                output: std::mem::replace(&mut project.buses[bus].output, output),
+            },
+            Self::SetBusMix { bus, mix } => Self::SetBusMix {
+                bus,
+                mix: std::mem::replace(&mut project.buses[bus].mix, mix),
+            },
+            Self::InsertClip { track, index, clip } => {
+                project.tracks[track].clips.insert(index, clip);
+                Self::RemoveClip { track, index }
+            }
+            Self::RemoveClip { track, index } => Self::InsertClip {
+                track,
+                index,
+                clip: project.tracks[track].clips.remove(index),
+            },
+            Self::ReplaceClip { track, index, clip } => Self::ReplaceClip {
+                track,
+                index,
+                clip: std::mem::replace(&mut project.tracks[track].clips[index], clip),
+            },
+        }
+    }
+
+    // Whether undoing `self` is already covered by undoing `earlier`, the edit made right before it.
+    // Dragging something around changes the same thing every frame, only the first change matters
+    pub fn merges_into(&self, earlier: &Self) -> bool {
+        match (self, earlier) {
+            (Self::SetNextId(_), Self::SetNextId(_))
+            | (Self::SetTempo(_), Self::SetTempo(_))
+            | (Self::SetLoop(_), Self::SetLoop(_))
+            | (Self::SetPanLaw(_), Self::SetPanLaw(_))
+            | (Self::SetMasterMix(_), Self::SetMasterMix(_)) => true,
+            (Self::ReplaceClip { track, index, .. }, Self::ReplaceClip { track: earlier_track, index: earlier_index, .. }) => {
+                track == earlier_track && index == earlier_index
+            }
+            (Self::SetTrackOutput { track, .. }, Self::SetTrackOutput { track: earlier, .. })
+            | (Self::SetTrackMix { track, .. }, Self::SetTrackMix { track: earlier, .. }) => track == earlier,
+            (Self::SetBusOutput { bus, .. }, Self::SetBusOutput { bus: earlier, .. })
+            | (Self::SetBusMix { bus, .. }, Self::SetBusMix { bus: earlier, .. }) => bus == earlier,
+            _ => false,
+        }
+    }
+
+    // Roughly how much memory keeping this around takes
+    pub fn size(&self) -> usize {
+        size_of::<Self>()
+            + match self {
+                Self::InsertTrack { track, .. } => {
+                    track.name.len() + track.clips.iter().map(clip_size).sum::<usize>() + mix_size(&track.mix)
+                }
+                Self::InsertBus { bus, .. } => bus.name.len() + mix_size(&bus.mix),
+                Self::SetTrackMix { mix, .. } | Self::SetBusMix { mix, .. } | Self::SetMasterMix(mix) => mix_size(mix),
+                Self::SetTempo(tempo) => {
+                    size_of_val(tempo.tempos()) + size_of_val(tempo.signatures())
+                }
+                Self::InsertClip { clip, .. } | Self::ReplaceClip { clip, .. } => clip_size(clip),
+                _ => 0,
+            }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn replace(track: usize, index: usize) -> Edit {
+        Edit::ReplaceClip {
+            track,
+            index,
+            clip: Clip {
+                id: crate::project::ClipId(0),
+                start: 0.,
+                length: 1.,
+                source: ClipSource::Midi { notes: Vec::new() },
+            },
+        }
+    }
+
+    #[test]
+    fn only_changes_to_the_same_thing_merge() {
+        assert!(Edit::SetLoop(LoopRegion::default()).merges_into(&Edit::SetLoop(LoopRegion::default())));
+        assert!(Edit::SetPanLaw(PanLaw::default()).merges_into(&Edit::SetPanLaw(PanLaw::default())));
+        assert!(!Edit::SetPanLaw(PanLaw::default()).merges_into(&Edit::SetLoop(LoopRegion::default())));
+
+        assert!(replace(1, 2).merges_into(&replace(1, 2)));
+        assert!(!replace(1, 2).merges_into(&replace(1, 3)));
+        assert!(!replace(1, 2).merges_into(&replace(0, 2)));
+
+        let output = |track| Edit::SetTrackOutput { track, output: None };
+        assert!(output(3).merges_into(&output(3)));
+        assert!(!output(3).merges_into(&output(4)));
+        let bus_output = |bus| Edit::SetBusOutput { bus, output: None };
+        assert!(bus_output(0).merges_into(&bus_output(0)));
+        assert!(!bus_output(0).merges_into(&output(0)));
+    }
+
+    #[test]
+    fn structural_edits_never_merge() {
+        let edits = [
+            Edit::RemoveTrack { index: 0 },
+            Edit::RemoveBus { index: 0 },
+            Edit::RemoveClip { track: 0, index: 0 },
+        ];
+        for edit in &edits {
+            assert!(!edit.merges_into(edit), "{edit:?} merged");
+        }
+    }
+}
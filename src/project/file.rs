@@ -0,0 +1,343 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, fs, io,
+    path::{Path, PathBuf},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{Bus, LoopRegion, Project, TempoMap, TimeSignature, Track};
+use crate::config;
+
+pub const EXTENSION: &str = "volt";
+// Where audio that belongs to a project goes, next to the project file
+pub const AUDIO_FOLDER: &str = "audio";
+// Bumped whenever the format changes, together with a way to read the previous version in `parse`
+pub const VERSION: u32 = 2;
+
+#[derive(Debug)]
+pub enum ProjectError {
+    Io(io::Error),
+    Parse(ron::error::SpannedError),
+    Write(ron::Error),
+    // Saved by a newer Volt than this one
+    TooNew(u32),
+    Unsupported(u32),
+}
+
+impl fmt::Display for ProjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Io(err) => write!(f, "{err}"),
+            Self::Parse(err) => write!(f, "the file is damaged ({err})"),
+            Self::Write(err) => write!(f, "{err}"),
+            Self::TooNew(version) => write!(f, "it was saved by a newer version of Volt (format {version})"),
+            Self::Unsupported(version) => write!(f, "format {version} isn't one Volt knows about"),
+        }
+    }
+}
+
+impl From<io::Error> for ProjectError {
+    fn from(err: io::Error) -> Self {
+        Self::Io(err)
+    }
+}
+
+impl From<ron::error::SpannedError> for ProjectError {
+    fn from(err: ron::error::SpannedError) -> Self {
+        Self::Parse(err)
+    }
+}
+
+impl From<ron::Error> for ProjectError {
+    fn from(err: ron::Error) -> Self {
+        Self::Write(err)
+    }
+}
+
+// Just enough to tell which version a file is before reading the rest of it
+#[derive(Deserialize)]
+struct Header {
+    version: u32,
+}
+
+#[derive(Serialize)]
+struct ProjectFileRef<'a> {
+    version: u32,
+    project: &'a Project,
+}
+
+#[derive(Deserialize)]
+struct ProjectFile {
+    project: Project,
+}
+
+// Version 1 had a single tempo and time signature for the whole song
+#[derive(Deserialize)]
+struct ProjectV1 {
+    tempo: f64,
+    time_signature: TimeSignature,
+    tracks: Vec<Track>,
+    buses: Vec<Bus>,
+    next_id: u64,
+}
+
+#[derive(Deserialize)]
+struct ProjectFileV1 {
+    project: ProjectV1,
+}
+
+impl From<ProjectV1> for Project {
+    fn from(project: ProjectV1) -> Self {
+        Self {
+            tempo: TempoMap::new(project.tempo, project.time_signature),
+            tracks: project.tracks,
+            buses: project.buses,
+            next_id: project.next_id,
+            ..Self::default()
+        }
+    }
+}
+
+// A loaded project along with everything that was wrong with it
+pub struct Loaded {
+    pub project: Project,
+    pub problems: Vec<String>,
+}
+
+// "Song/Song.volt", with the audio folder next to it
+pub fn new_project_path(location: &Path, name: &str) -> PathBuf {
+    location.join(name).join(format!("{name}.{EXTENSION}"))
+}
+
+pub fn is_project_file(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension.eq_ignore_ascii_case(EXTENSION))
+}
+
+// Anything older gets read the way it was written and brought up to date
+fn parse(contents: &str) -> Result<Project, ProjectError> {
+    let Header { version } = ron::from_str(contents)?;
+    match version {
+        VERSION => Ok(ron::from_str::<ProjectFile>(contents)?.project),
+        1 => Ok(ron::from_str::<ProjectFileV1>(contents)?.project.into()),
+        version if version > VERSION => Err(ProjectError::TooNew(version)),
+        version => Err(ProjectError::Unsupported(version)),
+    }
+}
+
+pub fn save(project: &Project, path: &Path) -> Result<(), ProjectError> {
+    let folder = path.parent().unwrap_or(Path::new("."));
+    fs::create_dir_all(folder.join(AUDIO_FOLDER))?;
+
+    // Audio inside the project folder is stored relative to it, so the folder can be moved around
+    let mut project = project.clone();
+    for audio in project.audio_paths_mut() {
+        if let Ok(relative) = audio.strip_prefix(folder) {
+            *audio = relative.to_path_buf();
+        }
+    }
+    let contents = ron::ser::to_string_pretty(
+        &ProjectFileRef {
+            version: VERSION,
+            project: &project,
+        },
+        ron::ser::PrettyConfig::default(),
+    )?;
+    // Write to a temporary file first so a crash mid-write doesn't eat the old project
+    let temporary = path.with_extension(format!("{EXTENSION}.tmp"));
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, path)?;
+    Ok(())
+}
+
+pub fn load(path: &Path) -> Result<Loaded, ProjectError> {
+    let mut project = parse(&fs::read_to_string(path)?)?;
+    let folder = path.parent().unwrap_or(Path::new("."));
+    for audio in project.audio_paths_mut() {
+        if audio.is_relative() {
+            *audio = folder.join(&*audio);
+        }
+    }
+    let problems = validate(&mut project);
+    Ok(Loaded { project, problems })
+}
+
+// Copies audio from outside the project folder into its audio folder, so the folder has everything
+// the project needs. Returns what couldn't be copied
+pub fn collect_audio(project: &mut Project, path: &Path) -> Vec<String> {
+    let audio_folder = path.parent().unwrap_or(Path::new(".")).join(AUDIO_FOLDER);
+    if let Err(err) = fs::create_dir_all(&audio_folder) {
+        return vec![format!("Couldn't create {}: {err}", audio_folder.display())];
+    }
+    let project_folder = path.parent().unwrap_or(Path::new("."));
+    let mut copied: HashMap<PathBuf, PathBuf> = HashMap::new();
+    let mut problems = Vec::new();
+    for audio in project.audio_paths_mut() {
+        if audio.starts_with(project_folder) {
+            continue;
+        }
+        if let Some(copy) = copied.get(audio.as_path()) {
+            *audio = copy.clone();
+            continue;
+        }
+        let copy = free_path(&audio_folder, audio);
+        match fs::copy(&*audio, &copy) {
+            Ok(_) => {
+                copied.insert(audio.clone(), copy.clone());
+                *audio = copy;
+            }
+            Err(err) => problems.push(format!("Couldn't copy {}: {err}", audio.display())),
+        }
+    }
+    problems
+}
+
+// Same name as `file` inside `folder`, numbered when that's taken
+fn free_path(folder: &Path, file: &Path) -> PathBuf {
+    let stem = file.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = file
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..)
+        .map(|number| {
+            if number == 1 {
+                folder.join(format!("{stem}{extension}"))
+            } else {
+                folder.join(format!("{stem} {number}{extension}"))
+            }
+        })
+        .find(|path| !path.exists())
+        .unwrap_or_else(|| folder.join(file.file_name().unwrap_or_default()))
+}
+
+// Fixes up whatever can be fixed and reports the rest, a broken project should still open
+fn validate(project: &mut Project) -> Vec<String> {
+    let mut problems = Vec::new();
+
+    let mut missing: Vec<PathBuf> = project
+        .audio_paths_mut()
+        .filter(|path| !path.is_file())
+        .map(|path| path.clone())
+        .collect();
+    missing.sort();
+    missing.dedup();
+    problems.extend(missing.into_iter().map(|path| format!("Missing audio file {}", path.display())));
+
+    let buses: HashSet<_> = project.buses.iter().map(|bus| bus.id).collect();
+    for track in &mut project.tracks {
+        if track.output.is_some_and(|output| !buses.contains(&output)) {
+            problems.push(format!("{} was routed to a bus that doesn't exist, it goes to the master now", track.name));
+            track.output = None;
+        }
+    }
+    for bus in &mut project.buses {
+        if bus.output.is_some_and(|output| !buses.contains(&output)) {
+            problems.push(format!("{} was routed to a bus that doesn't exist, it goes to the master now", bus.name));
+            bus.output = None;
+        }
+    }
+    let bus_ids: Vec<_> = project.buses.iter().map(|bus| bus.id).collect();
+    let mut mixes_repaired = project.master.repair(&[]);
+    for mix in project.tracks.iter_mut().map(|track| &mut track.mix).chain(project.buses.iter_mut().map(|bus| &mut bus.mix)) {
+        mixes_repaired |= mix.repair(&bus_ids);
+    }
+    if mixes_repaired {
+        problems.push("Some mixer settings were damaged and got reset".to_string());
+    }
+    if project.tempo.repair() {
+        problems.push("The tempo map was damaged, parts of it might be gone".to_string());
+    }
+    let region = &mut project.loop_region;
+    if !(region.start.is_finite() && region.end.is_finite() && region.start >= 0. && region.end > region.start) {
+        project.loop_region = LoopRegion::default();
+    }
+    project.repair_ids();
+    problems
+}
+
+// The projects opened most recently, newest first
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct RecentProjects {
+    pub paths: Vec<PathBuf>,
+}
+
+impl RecentProjects {
+    const FILE: &'static str = "projects.ron";
+    const MAX: usize = 10;
+
+    pub fn load() -> Self {
+        config::load(Self::FILE)
+    }
+
+    pub fn add(&mut self, path: &Path) {
+        self.paths.retain(|recent| recent != path);
+        self.paths.insert(0, path.to_path_buf());
+        self.paths.truncate(Self::MAX);
+        if let Err(err) = config::save(Self::FILE, self) {
+            eprintln!("Failed to save the recent projects: {err}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::*;
+    use crate::project::ClipSource;
+
+    #[test]
+    fn version_1_gets_a_tempo_map() {
+        let tracks = Project::new().tracks;
+        let contents = format!(
+            "(version: 1, project: (tempo: 95.0, time_signature: (numerator: 3, denominator: 4), tracks: {}, buses: [], next_id: 40))",
+            ron::to_string(&tracks).unwrap()
+        );
+        let project = parse(&contents).unwrap();
+        assert_eq!(project.tempo, TempoMap::new(95., TimeSignature { numerator: 3, denominator: 4 }));
+        assert_eq!(project.tracks, tracks);
+        assert_eq!(project.next_id, 40);
+        assert_eq!(project.loop_region, LoopRegion::default());
+    }
+
+    #[test]
+    fn unknown_versions_are_refused() {
+        assert!(matches!(parse("(version: 99, project: ())"), Err(ProjectError::TooNew(99))));
+        assert!(matches!(parse("(version: 0, project: ())"), Err(ProjectError::Unsupported(0))));
+    }
+
+    #[test]
+    fn saved_projects_open_the_same_with_audio_next_to_them() {
+        let folder = std::env::temp_dir().join(format!("volt-file-test-{}", process::id()));
+        let song = new_project_path(&folder, "Song");
+        let inside = folder.join("Song").join(AUDIO_FOLDER).join("kick.wav");
+        let outside = folder.join("Samples").join("snare.wav");
+        for audio in [&inside, &outside] {
+            fs::create_dir_all(audio.parent().unwrap()).unwrap();
+            fs::write(audio, b"").unwrap();
+        }
+        let mut project = Project::new();
+        let track = project.tracks[0].id;
+        for (start, path) in [(0., &inside), (4., &outside)] {
+            project.add_clip(track, start, 4., ClipSource::Audio { path: path.clone(), offset: 0. });
+        }
+
+        // Only audio inside the project's own folder gets stored relative to it
+        save(&project, &song).unwrap();
+        let contents = fs::read_to_string(&song).unwrap();
+        assert!(contents.contains("\"audio/kick.wav\""), "{contents}");
+        assert!(contents.contains(&format!("{:?}", outside.display().to_string())), "{contents}");
+        let loaded = load(&song).unwrap();
+        assert!(loaded.problems.is_empty(), "{:?}", loaded.problems);
+        assert_eq!(loaded.project, project);
+
+        // Moving the project's folder takes that audio along
+        fs::rename(folder.join("Song"), folder.join("Moved")).unwrap();
+        let mut loaded = load(&folder.join("Moved").join(format!("Song.{EXTENSION}"))).unwrap().project;
+        let paths: Vec<PathBuf> = loaded.audio_paths_mut().map(|path| path.clone()).collect();
+        assert_eq!(paths, [folder.join("Moved").join(AUDIO_FOLDER).join("kick.wav"), outside]);
+        fs::remove_dir_all(folder).unwrap();
+    }
+}
@@ -0,0 +1,411 @@
+use egui::{Button, Context, Key, KeyboardShortcut, Modifiers, RichText, ScrollArea, Ui, Window};
+
+use super::{Edit, Project};
+
+// How much the history may hold on to before the oldest steps get forgotten
+const BUDGET: usize = 64 * 1024 * 1024;
+
+// One step in the history. Undo steps hold their edits in the order they were made and get applied
+// backwards, redo steps hold them in the order they get applied
+struct Transaction {
+    label: String,
+    edits: Vec<Edit>,
+    size: usize,
+}
+
+impl Transaction {
+    fn new(label: String, edits: Vec<Edit>) -> Self {
+        let size = edits.iter().map(Edit::size).sum();
+        Self { label, edits, size }
+    }
+}
+
+pub struct History {
+    undo: Vec<Transaction>,
+    redo: Vec<Transaction>,
+    size: usize,
+    panel: bool,
+}
+
+impl History {
+    pub fn new() -> Self {
+        Self {
+            undo: Vec::new(),
+            redo: Vec::new(),
+            size: 0,
+            panel: false,
+        }
+    }
+
+    // For when the project gets replaced by another one
+    pub fn clear(&mut self) {
+        self.undo.clear();
+        self.redo.clear();
+        self.size = 0;
+    }
+
+    fn push(&mut self, transaction: Transaction) {
+        self.size -= self.redo.drain(..).map(|redo| redo.size).sum::<usize>();
+        self.size += transaction.size;
+        self.undo.push(transaction);
+        while self.size > BUDGET && self.undo.len() > 1 {
+            self.size -= self.undo.remove(0).size;
+        }
+    }
+
+    // Picks up whatever the project recorded since the last frame
+    pub fn collect(&mut self, project: &mut Project) {
+        if let Some((label, edits)) = project.take_journal() {
+            self.push(Transaction::new(label, edits));
+        }
+    }
+
+    // Whether there was anything to undo
+    pub fn undo(&mut self, project: &mut Project) -> bool {
+        self.collect(project);
+        // Undoing halfway through a drag would pull the project out from under it
+        if project.is_grouping() {
+            return false;
+        }
+        let Some(Transaction { label, edits, size }) = self.undo.pop() else {
+            return false;
+        };
+        let mut redo: Vec<Edit> = edits.into_iter().rev().map(|edit| edit.apply(project)).collect();
+        redo.reverse();
+        let redo = Transaction::new(label, redo);
+        self.size = self.size - size + redo.size;
+        self.redo.push(redo);
+        true
+    }
+
+    pub fn redo(&mut self, project: &mut Project) -> bool {
+        self.collect(project);
+        if project.is_grouping() {
+            return false;
+        }
+        let Some(Transaction { label, edits, size }) = self.redo.pop() else {
+            return false;
+        };
+        let undo = edits.into_iter().map(|edit| edit.apply(project)).collect();
+        let undo = Transaction::new(label, undo);
+        self.size = self.size - size + undo.size;
+        self.undo.push(undo);
+        true
+    }
+
+    // Undoes or redoes until `steps` steps are left to undo
+    fn jump(&mut self, steps: usize, project: &mut Project) {
+        while self.undo.len() > steps && self.undo(project) {}
+        while self.undo.len() < steps && self.redo(project) {}
+    }
+
+    // Shortcuts and the history panel
+    pub fn update(&mut self, ctx: &Context, project: &mut Project) {
+        self.collect(project);
+
+        if !ctx.memory(|memory| memory.focused().is_some()) {
+            let shortcut = |modifiers, key| {
+                ctx.input_mut(|input| input.consume_shortcut(&KeyboardShortcut::new(modifiers, key)))
+            };
+            // Shift + Z has to come first, plain Z would take it otherwise
+            if shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::Z) || shortcut(Modifiers::COMMAND, Key::Y) {
+                self.redo(project);
+            } else if shortcut(Modifiers::COMMAND, Key::Z) {
+                self.undo(project);
+            }
+        }
+
+        self.paint_panel(ctx, project);
+    }
+
+    pub fn menu(&mut self, ui: &mut Ui, project: &mut Project) {
+        let undo = self.undo.last().map(|transaction| format!("Undo {}", transaction.label));
+        if ui
+            .add_enabled(undo.is_some(), Button::new(undo.unwrap_or_else(|| "Undo".to_string())))
+            .clicked()
+        {
+            self.undo(project);
+            ui.close_menu();
+        }
+        let redo = self.redo.last().map(|transaction| format!("Redo {}", transaction.label));
+        if ui
+            .add_enabled(redo.is_some(), Button::new(redo.unwrap_or_else(|| "Redo".to_string())))
+            .clicked()
+        {
+            self.redo(project);
+            ui.close_menu();
+        }
+        ui.separator();
+        if ui.checkbox(&mut self.panel, "History").clicked() {
+            ui.close_menu();
+        }
+    }
+
+    // Every step, clicking one goes back or forward to right after it
+    fn paint_panel(&mut self, ctx: &Context, project: &mut Project) {
+        let mut open = self.panel;
+        let mut jump = None;
+        Window::new("History")
+            .open(&mut open)
+            .default_width(220.)
+            .show(ctx, |ui| {
+                ScrollArea::vertical().show(ui, |ui| {
+                    if ui.selectable_label(self.undo.is_empty(), "Start").clicked() {
+                        jump = Some(0);
+                    }
+                    for (index, transaction) in self.undo.iter().enumerate() {
+                        let current = index + 1 == self.undo.len();
+                        if ui.selectable_label(current, &transaction.label).clicked() {
+                            jump = Some(index + 1);
+                        }
+                    }
+                    // Steps that got undone are next on the redo stack from the top down
+                    for (index, transaction) in self.redo.iter().rev().enumerate() {
+                        let label = RichText::new(&transaction.label).weak();
+                        if ui.selectable_label(false, label).clicked() {
+                            jump = Some(self.undo.len() + index + 1);
+                        }
+                    }
+                });
+                ui.separator();
+                #[allow(clippy::cast_precision_loss)]
+                let megabytes = self.size as f64 / 1024. / 1024.;
+                ui.label(RichText::new(format!("{megabytes:.1} MB used")).weak());
+            });
+        self.panel = open;
+        if let Some(steps) = jump {
+            self.jump(steps, project);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use strum::IntoEnumIterator;
+
+    use super::*;
+    use crate::{
+        blerp::processing::EFFECTS,
+        project::{Clip, ClipSource, Insert, LoopRegion, PanLaw, Send},
+        test_util::Random,
+        transport::COMMON_SIGNATURES,
+    };
+
+    fn random_clip(project: &Project, random: &mut Random) -> Option<Clip> {
+        let clips: Vec<&Clip> = project.tracks.iter().flat_map(|track| &track.clips).collect();
+        (!clips.is_empty()).then(|| clips[random.below(clips.len())].clone())
+    }
+
+    fn random_edit(project: &mut Project, random: &mut Random) {
+        let track = (!project.tracks.is_empty()).then(|| project.tracks[random.below(project.tracks.len())].id);
+        match random.below(15) {
+            0 => {
+                project.add_track(None);
+            }
+            1 => {
+                if let Some(track) = track {
+                    project.remove_track(track);
+                }
+            }
+            2 => {
+                project.add_bus(None);
+            }
+            3 => {
+                let bus = (random.below(3) > 0 && !project.buses.is_empty())
+                    .then(|| project.buses[random.below(project.buses.len())].id);
+                if let Some(track) = track {
+                    project.set_track_output(track, bus);
+                }
+            }
+            4 | 5 => {
+                let source = if random.below(2) == 0 {
+                    ClipSource::Midi { notes: Vec::new() }
+                } else {
+                    ClipSource::Audio {
+                        path: format!("audio/{}.wav", random.below(8)).into(),
+                        offset: random.beat(),
+                    }
+                };
+                if let Some(track) = track {
+                    project.add_clip(track, random.beat(), random.beat() + 1., source);
+                }
+            }
+            6 => {
+                if let Some(clip) = random_clip(project, random) {
+                    project.remove_clip(clip.id);
+                }
+            }
+            7 => {
+                if let Some(clip) = random_clip(project, random) {
+                    project.duplicate_clip(clip.id, random.beat());
+                }
+            }
+            8 => {
+                if let Some(clip) = random_clip(project, random) {
+                    project.split_clip(clip.id, clip.start + random.beat());
+                }
+            }
+            9 => {
+                let mut tempo = project.tempo.clone();
+                tempo.set_tempo(random.beat(), 60. + random.beat() * 10.);
+                tempo.set_ramp(random.below(tempo.tempos().len()), random.below(2) == 0);
+                tempo.set_signature(u32::try_from(random.below(8)).unwrap_or_default(), COMMON_SIGNATURES[random.below(COMMON_SIGNATURES.len())]);
+                // Beats to seconds and back has to land where it started, ramps included
+                let beat = random.beat();
+                assert!((tempo.beat_at(tempo.seconds_at(beat)) - beat).abs() < 1e-6, "{tempo:?} loses beat {beat}");
+                project.set_tempo_map("Change tempo", tempo);
+            }
+            10 => {
+                let start = random.beat();
+                project.set_loop_region("Set loop", LoopRegion {
+                    start,
+                    end: start + 1. + random.beat(),
+                    enabled: random.below(2) == 0,
+                });
+            }
+            11 | 12 => {
+                let channels = project.channels();
+                let channel = channels[random.below(channels.len())];
+                let Some(mut mix) = project.mix(channel).cloned() else {
+                    return;
+                };
+                match random.below(6) {
+                    0 => mix.gain = random.beat() * -4.,
+                    1 => mix.pan = random.beat() / 8. - 1.,
+                    2 => mix.mute = !mix.mute,
+                    3 => mix.solo = !mix.solo,
+                    4 => mix.inserts.push(Insert {
+                        effect: EFFECTS[random.below(EFFECTS.len())],
+                        bypass: random.below(2) == 0,
+                    }),
+                    _ => {
+                        if !project.buses.is_empty() {
+                            mix.sends.push(Send {
+                                bus: project.buses[random.below(project.buses.len())].id,
+                                level: random.beat() * -2.,
+                                pre_fader: random.below(2) == 0,
+                            });
+                        }
+                    }
+                }
+                project.set_mix("Change mix", channel, mix);
+            }
+            13 => {
+                let bus = (!project.buses.is_empty()).then(|| project.buses[random.below(project.buses.len())].id);
+                let output = (!project.buses.is_empty()).then(|| project.buses[random.below(project.buses.len())].id);
+                match (random.below(3), bus) {
+                    (0, Some(bus)) => project.remove_bus(bus),
+                    (1, Some(bus)) => project.set_bus_output(bus, output),
+                    _ => project.set_pan_law(PanLaw::iter().nth(random.below(4)).unwrap_or_default()),
+                }
+                // Deleting a bus doesn't leave anything routed or sending to it
+                for channel in project.channels() {
+                    let sends = project.mix(channel).map(|mix| mix.sends.clone()).unwrap_or_default();
+                    assert!(sends.iter().all(|send| project.bus_index(send.bus).is_some()), "{channel:?} sends to a deleted bus");
+                }
+            }
+            _ => {
+                // A drag, spread over a few frames like it would be in the timeline
+                let (Some(clip), Some(track)) = (random_clip(project, random), track) else {
+                    return;
+                };
+                project.begin("Move clip");
+                for _ in 0..random.below(5) {
+                    project.move_clip(clip.id, track);
+                    let moved = Clip {
+                        start: random.beat(),
+                        ..clip.clone()
+                    };
+                    project.set_clip("Move clip", moved);
+                }
+                project.end();
+            }
+        }
+    }
+
+    // Makes random edits, then checks that undoing them goes back through exactly the same states and
+    // redoing them comes back to the end
+    #[test]
+    fn undo_and_redo_go_back_through_every_state() {
+        let mut random = Random(0x2545_f491_4f6c_dd1d);
+        for round in 0..500 {
+            let mut project = Project::new();
+            let mut history = History::new();
+            let mut states = vec![project.clone()];
+            for _ in 0..random.below(60) {
+                random_edit(&mut project, &mut random);
+                history.collect(&mut project);
+                if history.undo.len() == states.len() {
+                    states.push(project.clone());
+                }
+                assert_eq!(history.undo.len() + 1, states.len(), "an edit made more than one step (round {round})");
+            }
+            for state in states.iter().rev().skip(1) {
+                assert!(history.undo(&mut project));
+                assert_eq!(project, *state, "undo didn't restore the project (round {round})");
+            }
+            assert!(!history.undo(&mut project));
+            for state in states.iter().skip(1) {
+                assert!(history.redo(&mut project));
+                assert_eq!(project, *state, "redo didn't restore the project (round {round})");
+            }
+        }
+    }
+
+    fn transaction(size: usize) -> Transaction {
+        Transaction {
+            label: String::new(),
+            edits: Vec::new(),
+            size,
+        }
+    }
+
+    #[test]
+    fn oldest_steps_get_forgotten_past_the_budget() {
+        let mut history = History::new();
+        for _ in 0..3 {
+            history.push(transaction(BUDGET / 3));
+        }
+        assert_eq!(history.undo.len(), 3);
+        history.push(transaction(BUDGET / 3));
+        assert_eq!(history.undo.len(), 3);
+        assert_eq!(history.size, BUDGET / 3 * 3);
+        // The newest step stays however big it is
+        history.push(transaction(BUDGET * 2));
+        assert_eq!(history.undo.len(), 1);
+        assert_eq!(history.size, BUDGET * 2);
+    }
+
+    #[test]
+    fn new_steps_forget_what_could_be_redone() {
+        let mut history = History::new();
+        history.push(transaction(10));
+        history.redo.push(transaction(20));
+        history.size += 20;
+        history.push(transaction(30));
+        assert!(history.redo.is_empty());
+        assert_eq!(history.size, 40);
+    }
+
+    #[test]
+    fn dragging_a_clip_makes_one_step() {
+        let mut project = Project::new();
+        let mut history = History::new();
+        let track = project.add_track(None);
+        let clip = project.add_clip(track, 0., 1., ClipSource::Midi { notes: Vec::new() });
+        history.collect(&mut project);
+        let clip = project.tracks.iter().flat_map(|track| &track.clips).find(|found| Some(found.id) == clip).cloned().unwrap();
+        let before = project.clone();
+
+        project.begin("Move clip");
+        for start in 1..10 {
+            project.set_clip("Move clip", Clip { start: f64::from(start), ..clip.clone() });
+        }
+        project.end();
+        history.collect(&mut project);
+        assert_eq!(history.undo.len(), 2);
+        assert_eq!(history.undo.last().map(|transaction| transaction.edits.len()), Some(1));
+        assert!(history.undo(&mut project));
+        assert_eq!(project, before);
+    }
+}
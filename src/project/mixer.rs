@@ -0,0 +1,130 @@
+use std::f64::consts::FRAC_PI_2;
+
+use serde::{Deserialize, Serialize};
+use strum::{Display, EnumIter};
+
+use super::{BusId, TrackId};
+use crate::blerp::processing::Effect;
+
+// Faders go down to silence at the bottom
+pub const MIN_GAIN: f64 = -60.;
+pub const MAX_GAIN: f64 = 6.;
+
+// Decibels to a factor, the bottom of the fader being silence
+pub fn gain_factor(decibels: f64) -> f64 {
+    if decibels <= MIN_GAIN {
+        0.
+    } else {
+        10_f64.powf(decibels / 20.)
+    }
+}
+
+// How loud each side gets as a mono signal is panned, named after how much quieter it is in the middle
+#[derive(Display, EnumIter, Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PanLaw {
+    // What projects from before the mixer sound like
+    #[default]
+    #[strum(to_string = "0 dB, balance")]
+    Balance,
+    #[strum(to_string = "-3 dB, constant power")]
+    ConstantPower,
+    #[strum(to_string = "-4.5 dB")]
+    Compromise,
+    #[strum(to_string = "-6 dB, linear")]
+    Linear,
+}
+
+impl PanLaw {
+    // Left and right factors for `pan` going from -1 (left) to 1 (right)
+    pub fn factors(self, pan: f64) -> [f64; 2] {
+        let right = (pan.clamp(-1., 1.) + 1.) / 2.;
+        let left = 1. - right;
+        match self {
+            Self::Balance => [(left * 2.).min(1.), (right * 2.).min(1.)],
+            Self::ConstantPower => [(left * FRAC_PI_2).sin(), (right * FRAC_PI_2).sin()],
+            Self::Compromise => [
+                (left * (left * FRAC_PI_2).sin()).sqrt(),
+                (right * (right * FRAC_PI_2).sin()).sqrt(),
+            ],
+            Self::Linear => [left, right],
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Insert {
+    pub effect: Effect,
+    #[serde(default)]
+    pub bypass: bool,
+}
+
+// Some of a channel's signal going to a bus on top of wherever its output goes
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Send {
+    pub bus: BusId,
+    // Decibels
+    pub level: f64,
+    // Taken before the fader and pan, so it stays the same when the fader moves
+    #[serde(default)]
+    pub pre_fader: bool,
+}
+
+// A strip in the mixer, what every track and bus and the master have. The signal goes through the
+// inserts, then the sends that come before the fader, then the fader and pan
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Mix {
+    // Decibels
+    pub gain: f64,
+    // From -1 (left) to 1 (right)
+    pub pan: f64,
+    pub mute: bool,
+    pub solo: bool,
+    // Keeps playing when something else is soloed, for buses like reverb returns
+    pub solo_safe: bool,
+    pub invert_phase: bool,
+    pub inserts: Vec<Insert>,
+    pub sends: Vec<Send>,
+}
+
+impl Default for Mix {
+    fn default() -> Self {
+        Self {
+            gain: 0.,
+            pan: 0.,
+            mute: false,
+            solo: false,
+            solo_safe: false,
+            invert_phase: false,
+            inserts: Vec::new(),
+            sends: Vec::new(),
+        }
+    }
+}
+
+impl Mix {
+    // Puts whatever a hand edited file did to it right, returns whether it had to
+    pub fn repair(&mut self, buses: &[BusId]) -> bool {
+        let original = self.clone();
+        let finite_or = |value: f64, default: f64| if value.is_finite() { value } else { default };
+        self.gain = finite_or(self.gain, 0.).clamp(MIN_GAIN, MAX_GAIN);
+        self.pan = finite_or(self.pan, 0.).clamp(-1., 1.);
+        for insert in &mut self.inserts {
+            let (value, range) = insert.effect.parameter();
+            *value = finite_or(*value, *range.end()).clamp(*range.start(), *range.end());
+        }
+        self.sends.retain(|send| buses.contains(&send.bus));
+        for send in &mut self.sends {
+            send.level = finite_or(send.level, 0.).clamp(MIN_GAIN, MAX_GAIN);
+        }
+        *self != original
+    }
+}
+
+// Anything with a strip in the mixer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Track(TrackId),
+    Bus(BusId),
+    Master,
+}
@@ -0,0 +1,71 @@
+use std::sync::{atomic::Ordering, Arc};
+
+use super::{pool::AudioPool, snapshot::Snapshot, Project};
+use crate::blerp::{
+    device::Output,
+    processing::live::EngineStatus,
+};
+
+// Keeps the engine playing the latest version of the project
+pub struct Playback {
+    pool: AudioPool,
+    // What the last snapshot got built from
+    built: Option<Project>,
+    // The engine it got sent to, streams get swapped out when the backend changes
+    engine: Option<Arc<EngineStatus>>,
+    generation: u64,
+    // Sent snapshots the engine might still be playing, so they never get freed on the audio thread
+    sent: Vec<Arc<Snapshot>>,
+    track_names: Vec<String>,
+}
+
+impl Playback {
+    pub fn new() -> Self {
+        Self {
+            pool: AudioPool::new(),
+            built: None,
+            engine: None,
+            generation: 0,
+            sent: Vec::new(),
+            track_names: Vec::new(),
+        }
+    }
+
+    // Called every frame, only does anything when the project or the engine changed
+    pub fn update(&mut self, project: &Project, output: Option<&mut Output>) {
+        let Some(output) = output else {
+            self.engine = None;
+            return;
+        };
+        let status = output.engine.status.clone();
+        let in_use = status.snapshot_generation.load(Ordering::Acquire);
+        self.sent.retain(|snapshot| snapshot.generation >= in_use);
+
+        let loaded = self.pool.update();
+        let same_engine = self
+            .engine
+            .as_ref()
+            .is_some_and(|engine| Arc::ptr_eq(engine, &status));
+        // Comparing is cheap next to building and sending a snapshot
+        if same_engine && !loaded && self.built.as_ref() == Some(project) {
+            return;
+        }
+        if !same_engine {
+            self.engine = Some(status.clone());
+            self.track_names.clear();
+        }
+
+        let track_names: Vec<String> = project.tracks.iter().map(|track| track.name.clone()).collect();
+        if track_names != self.track_names {
+            output.set_track_outputs(&track_names);
+            self.track_names = track_names;
+        }
+
+        self.generation += 1;
+        let sample_rate = status.sample_rate.load(Ordering::Relaxed);
+        let snapshot = Arc::new(Snapshot::build(project, sample_rate, self.generation, &mut self.pool));
+        self.sent.push(snapshot.clone());
+        output.engine.set_snapshot(snapshot);
+        self.built = Some(project.clone());
+    }
+}
@@ -0,0 +1,76 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+    sync::{
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use crate::blerp::audiofile::{self, AudioBuffer};
+
+type Key = (PathBuf, u32);
+
+// Decoded audio for clips, already stereo and at the engine's sample rate. Files get decoded on a
+// background thread, so a clip only starts playing once its audio is ready
+pub struct AudioPool {
+    requests: Sender<Key>,
+    results: Receiver<(Key, Option<AudioBuffer>)>,
+    buffers: HashMap<Key, Arc<AudioBuffer>>,
+    requested: HashSet<Key>,
+}
+
+impl AudioPool {
+    pub fn new() -> Self {
+        let (requests, request_receiver) = channel::<Key>();
+        let (result_sender, results) = channel();
+        thread::spawn(move || {
+            while let Ok((path, sample_rate)) = request_receiver.recv() {
+                let buffer = audiofile::decode(&path)
+                    .map(|buffer| buffer.to_stereo().resampled_band_limited(sample_rate))
+                    .map_err(|err| eprintln!("Failed to load {}: {err}", path.display()))
+                    .ok();
+                if result_sender.send(((path, sample_rate), buffer)).is_err() {
+                    break;
+                }
+            }
+        });
+        Self {
+            requests,
+            results,
+            buffers: HashMap::new(),
+            requested: HashSet::new(),
+        }
+    }
+
+    // Picks up finished files, returns whether there were any
+    pub fn update(&mut self) -> bool {
+        let mut loaded = false;
+        for (key, buffer) in self.results.try_iter() {
+            if let Some(buffer) = buffer {
+                self.buffers.insert(key, Arc::new(buffer));
+                loaded = true;
+            }
+        }
+        loaded
+    }
+
+    // `None` until the file is decoded, or forever if it can't be
+    pub fn get(&mut self, path: &Path, sample_rate: u32) -> Option<Arc<AudioBuffer>> {
+        let key = (path.to_path_buf(), sample_rate);
+        if let Some(buffer) = self.buffers.get(&key) {
+            return Some(buffer.clone());
+        }
+        if self.requested.insert(key.clone()) {
+            let _ = self.requests.send(key);
+        }
+        None
+    }
+
+    // Drops whatever no clip uses anymore
+    pub fn retain(&mut self, used: &HashSet<(PathBuf, u32)>) {
+        self.buffers.retain(|key, _| used.contains(key));
+        self.requested.retain(|key| used.contains(key));
+    }
+}
@@ -0,0 +1,182 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{channel, Receiver, Sender},
+        Arc,
+    },
+    thread,
+};
+
+use super::{ClipSource, Project, Session, TrackId};
+use crate::blerp::{
+    device::Output,
+    processing::live::{
+        capture::{Recorded, CAPTURE_FRAMES},
+        EngineStatus,
+    },
+    wavefile::{self, WaveAudioFormat},
+};
+
+// Buffers the engine has to record into at any time, a little under six seconds at 48 kHz
+const BUFFERS_IN_FLIGHT: usize = 16;
+const TRACK_NAME: &str = "Recordings";
+
+// Input recorded in one go, without the song jumping anywhere
+struct Take {
+    start: u64,
+    samples: Vec<f32>,
+}
+
+// A take that made it to disk, waiting to be put in the project
+struct Written {
+    path: PathBuf,
+    start: u64,
+    frames: u64,
+    sample_rate: u32,
+    // How much earlier it goes than it was recorded at, the round trip through the audio interface
+    offset: u32,
+}
+
+// Turns the input the engine records into files and clips on a track of their own
+pub struct Recorder {
+    engine: Option<Arc<EngineStatus>>,
+    take: Option<Take>,
+    dropped: u64,
+    written: Sender<Result<Written, String>>,
+    finished: Receiver<Result<Written, String>>,
+    track: Option<TrackId>,
+}
+
+fn recordings_folder(session: &Session) -> PathBuf {
+    session
+        .folder()
+        .unwrap_or_else(|| dirs::document_dir().or_else(dirs::home_dir).unwrap_or_default().join("Volt"))
+        .join("Recordings")
+}
+
+fn write_take(folder: &Path, samples: &[f32], sample_rate: u32) -> Result<PathBuf, String> {
+    std::fs::create_dir_all(folder).map_err(|err| err.to_string())?;
+    let path = (1..)
+        .map(|number| folder.join(format!("Take {number}.wav")))
+        .find(|path| !path.exists())
+        .unwrap_or_default();
+    let frames = u32::try_from(samples.len() / 2).map_err(|_| "too long for a WAV file".to_string())?;
+    wavefile::write_wav_file_f32(&path, samples, sample_rate, 2, 32, frames, WaveAudioFormat::FloatingPoint)
+        .map_err(|err| err.to_string())?;
+    Ok(path)
+}
+
+impl Recorder {
+    pub fn new() -> Self {
+        let (written, finished) = channel();
+        Self {
+            engine: None,
+            take: None,
+            dropped: 0,
+            written,
+            finished,
+            track: None,
+        }
+    }
+
+    // Called every frame, collects what the engine recorded and adds the finished takes to the project
+    pub fn update(&mut self, project: &mut Project, session: &Session, output: Option<&Output>) {
+        if let Some(output) = output {
+            self.collect(output, session);
+        } else {
+            self.engine = None;
+        }
+        for written in self.finished.try_iter().collect::<Vec<_>>() {
+            match written {
+                Ok(written) => self.place(project, written),
+                Err(err) => eprintln!("Failed to save a recording: {err}"),
+            }
+        }
+    }
+
+    fn collect(&mut self, output: &Output, session: &Session) {
+        let status = &output.engine.status;
+        let capture = &output.engine.capture;
+        if !self.engine.as_ref().is_some_and(|engine| Arc::ptr_eq(engine, status)) {
+            // A new engine starts out without any buffers, and whatever the old one was recording is gone
+            self.engine = Some(status.clone());
+            self.take = None;
+            self.dropped = status.dropped_input.load(Ordering::Relaxed);
+            for _ in 0..BUFFERS_IN_FLIGHT {
+                capture.give(Vec::with_capacity(CAPTURE_FRAMES * 2));
+            }
+        }
+        let dropped = status.dropped_input.load(Ordering::Relaxed);
+        if dropped != self.dropped {
+            eprintln!("Failed to keep up with recording, some of the input got lost");
+            self.dropped = dropped;
+        }
+
+        let sample_rate = status.sample_rate.load(Ordering::Relaxed);
+        let offset = output.recording_offset.unwrap_or(0);
+        for recorded in capture.recorded().collect::<Vec<_>>() {
+            let Recorded { start, mut samples, last } = recorded;
+            // Loops and locates show up as a gap, what came before it is a take of its own
+            if self.take.as_ref().is_some_and(|take| take.start + take.samples.len() as u64 / 2 != start) {
+                self.finish(session, sample_rate, offset);
+            }
+            self.take
+                .get_or_insert_with(|| Take { start, samples: Vec::new() })
+                .samples
+                .extend_from_slice(&samples);
+            samples.clear();
+            capture.give(samples);
+            if last {
+                self.finish(session, sample_rate, offset);
+            }
+        }
+    }
+
+    // Writes the take out on a thread of its own, it gets placed once that's done
+    fn finish(&mut self, session: &Session, sample_rate: u32, offset: u32) {
+        let Some(take) = self.take.take() else {
+            return;
+        };
+        let folder = recordings_folder(session);
+        let written = self.written.clone();
+        thread::spawn(move || {
+            let result = write_take(&folder, &take.samples, sample_rate).map(|path| Written {
+                path,
+                start: take.start,
+                frames: take.samples.len() as u64 / 2,
+                sample_rate,
+                offset,
+            });
+            let _ = written.send(result);
+        });
+    }
+
+    fn place(&mut self, project: &mut Project, written: Written) {
+        let Written { path, start, frames, sample_rate, offset } = written;
+        // Whatever came in before the song started gets trimmed off the front
+        let skipped = u64::from(offset).saturating_sub(start).min(frames);
+        let start = (start + skipped).saturating_sub(u64::from(offset));
+        let end = start + frames - skipped;
+        if end == start {
+            return;
+        }
+        let tempo = &project.tempo;
+        let beat = tempo.beat_at_frame(start, sample_rate);
+        let length = tempo.beat_at_frame(end, sample_rate) - beat;
+        #[allow(clippy::cast_precision_loss)]
+        let source = ClipSource::Audio {
+            path,
+            offset: skipped as f64 / f64::from(sample_rate),
+        };
+
+        project.begin("Record");
+        let track = match self.track.filter(|track| project.track_index(*track).is_some()) {
+            Some(track) => track,
+            None => project.add_track(Some(TRACK_NAME.to_string())),
+        };
+        self.track = Some(track);
+        project.add_clip(track, beat, length, source);
+        project.end();
+    }
+}
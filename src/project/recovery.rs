@@ -0,0 +1,311 @@
+use std::{
+    cmp::Reverse,
+    fs,
+    path::{Path, PathBuf},
+    process,
+    sync::{Mutex, MutexGuard, TryLockError},
+    thread,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use serde::{Deserialize, Serialize};
+
+use super::{
+    file::{self, Loaded, ProjectError, EXTENSION, VERSION},
+    Project, Revision, Session,
+};
+use crate::config;
+
+const INTERVAL: Duration = Duration::from_secs(30);
+
+// The latest unsaved version of the project, shared between the UI, the autosave thread and the
+// panic hook
+struct Pending {
+    file: PathBuf,
+    original: Option<PathBuf>,
+    project: Project,
+    written: bool,
+}
+
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+
+// A normal project file that also remembers where the project was saved, if it ever was
+#[derive(Serialize)]
+struct RecoveryFileRef<'a> {
+    version: u32,
+    original: Option<&'a Path>,
+    project: &'a Project,
+}
+
+#[derive(Deserialize)]
+struct RecoveryHeader {
+    #[serde(default)]
+    original: Option<PathBuf>,
+}
+
+fn recovery_dir() -> Option<PathBuf> {
+    config::config_dir().map(|dir| dir.join("recovery"))
+}
+
+fn write(file: &Path, original: Option<&Path>, project: &Project) -> Result<(), ProjectError> {
+    let contents = ron::to_string(&RecoveryFileRef {
+        version: VERSION,
+        original,
+        project,
+    })?;
+    if let Some(folder) = file.parent() {
+        fs::create_dir_all(folder)?;
+    }
+    let temporary = file.with_extension(format!("{EXTENSION}.tmp"));
+    fs::write(&temporary, contents)?;
+    fs::rename(temporary, file)?;
+    Ok(())
+}
+
+// Writes the latest version if it changed since the last write
+fn write_pending(mut pending: MutexGuard<Option<Pending>>) -> Option<Result<(), ProjectError>> {
+    let Pending {
+        file,
+        original,
+        project,
+        written,
+    } = pending.as_mut().filter(|pending| !pending.written)?;
+    // Serialising can take a moment, the UI shouldn't have to wait for it
+    let (file, original, project) = (file.clone(), original.clone(), project.clone());
+    *written = true;
+    drop(pending);
+    Some(write(&file, original.as_deref(), &project))
+}
+
+// Last chance to keep unsaved work when Volt crashes, called from the panic hook
+pub fn emergency_save() {
+    // The autosave thread only holds the lock for a moment, a panic while holding it poisons it
+    let mut attempts = 0;
+    let pending = loop {
+        match PENDING.try_lock() {
+            Ok(pending) => break pending,
+            Err(TryLockError::Poisoned(poisoned)) => break poisoned.into_inner(),
+            Err(TryLockError::WouldBlock) if attempts < 50 => {
+                attempts += 1;
+                thread::sleep(Duration::from_millis(10));
+            }
+            Err(TryLockError::WouldBlock) => return,
+        }
+    };
+    let Some(Pending {
+        file,
+        original,
+        project,
+        ..
+    }) = pending.as_ref()
+    else {
+        return;
+    };
+    match write(file, original.as_deref(), project) {
+        Ok(()) => println!("Your unsaved work was saved to {}, Volt will offer it next time it starts", file.display()),
+        Err(err) => println!("Failed to save your unsaved work: {err}"),
+    }
+}
+
+// Keeps a copy of unsaved changes in the recovery folder, written every so often in the background
+pub struct Autosave {
+    // One file per running Volt
+    file: Option<PathBuf>,
+    // What the project, the saved one and where it's saved were when it was last looked at
+    checked: Option<(Revision, Revision, Option<PathBuf>)>,
+}
+
+impl Autosave {
+    pub fn new() -> Self {
+        let started = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let file = recovery_dir().map(|dir| dir.join(format!("{started}-{}.{EXTENSION}", process::id())));
+        thread::spawn(|| loop {
+            thread::sleep(INTERVAL);
+            if let Some(Err(err)) = write_pending(PENDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner())) {
+                eprintln!("Failed to autosave the project: {err}");
+            }
+        });
+        Self { file, checked: None }
+    }
+
+    fn pending() -> MutexGuard<'static, Option<Pending>> {
+        PENDING.lock().unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+
+    // Called every frame, hands the project over when it changed
+    pub fn update(&mut self, project: &Project, session: &Session) {
+        let Some(file) = &self.file else {
+            return;
+        };
+        let original = session.path().map(Path::to_path_buf);
+        // Comparing and copying the whole project adds up, so that only happens after an edit or a save
+        let checked = (project.revision(), session.saved_revision(), original.clone());
+        if self.checked.as_ref() == Some(&checked) {
+            return;
+        }
+        self.checked = Some(checked);
+        let mut pending = Self::pending();
+        if !session.is_modified(project) {
+            // Saved, nothing left to recover
+            if pending.take().is_some() && file.exists() {
+                if let Err(err) = fs::remove_file(file) {
+                    eprintln!("Failed to remove the autosave: {err}");
+                }
+            }
+            return;
+        }
+        *pending = Some(Pending {
+            file: file.clone(),
+            original,
+            project: project.clone(),
+            written: false,
+        });
+    }
+
+    // Quitting doesn't ask about unsaved changes yet, so they stay recoverable
+    pub fn flush(&self) {
+        if let Some(Err(err)) = write_pending(Self::pending()) {
+            eprintln!("Failed to autosave the project: {err}");
+        }
+    }
+}
+
+// Unsaved work left behind by a Volt that crashed or quit
+pub struct Recoverable {
+    path: PathBuf,
+    pub original: Option<PathBuf>,
+    saved_at: Option<SystemTime>,
+}
+
+// Whether the Volt that wrote an autosave is still running, going by the process id in its name
+fn is_in_use(path: &Path) -> bool {
+    let Some(pid) = path
+        .file_stem()
+        .and_then(|stem| stem.to_str())
+        .and_then(|stem| stem.rsplit_once('-'))
+        .and_then(|(_, pid)| pid.parse::<u32>().ok())
+    else {
+        return false;
+    };
+    if pid == process::id() {
+        return true;
+    }
+    is_running(pid)
+}
+
+#[cfg(unix)]
+fn is_running(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    // Signal 0 only checks the process is there, not being allowed to signal it still means it is
+    // SAFETY: kill with signal 0 doesn't do anything to the process
+    let found = unsafe { libc::kill(pid, 0) } == 0;
+    found || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// Without a way to tell, everything gets offered like before
+#[cfg(not(unix))]
+fn is_running(_pid: u32) -> bool {
+    false
+}
+
+// Newest first, leaving out the ones another running Volt is still writing
+pub fn recoverable() -> Vec<Recoverable> {
+    let Some(Ok(entries)) = recovery_dir().map(fs::read_dir) else {
+        return Vec::new();
+    };
+    let mut recoverable: Vec<Recoverable> = entries
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| file::is_project_file(path) && !is_in_use(path))
+        .map(|path| {
+            let original = fs::read_to_string(&path)
+                .ok()
+                .and_then(|contents| ron::from_str::<RecoveryHeader>(&contents).ok())
+                .and_then(|header| header.original);
+            let saved_at = fs::metadata(&path).and_then(|metadata| metadata.modified()).ok();
+            Recoverable {
+                path,
+                original,
+                saved_at,
+            }
+        })
+        .collect();
+    recoverable.sort_by_key(|recoverable| Reverse(recoverable.saved_at));
+    recoverable
+}
+
+impl Recoverable {
+    pub fn name(&self) -> String {
+        self.original.as_ref().map_or_else(
+            || "Untitled".to_string(),
+            |path| path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        )
+    }
+
+    pub fn load(&self) -> Result<Loaded, ProjectError> {
+        file::load(&self.path)
+    }
+
+    pub fn discard(&self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            eprintln!("Failed to remove {}: {err}", self.path.display());
+        }
+    }
+
+    // "2024-05-04 13:37 UTC, 5 minutes ago"
+    pub fn when(&self) -> String {
+        let Some(saved_at) = self.saved_at else {
+            return "Unknown time".to_string();
+        };
+        let seconds = saved_at.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+        let (year, month, day) = civil_date(seconds / 86400);
+        let date = format!("{year}-{month:02}-{day:02} {:02}:{:02} UTC", seconds / 3600 % 24, seconds / 60 % 60);
+        let ago = SystemTime::now().duration_since(saved_at).unwrap_or_default().as_secs();
+        let ago = match ago {
+            0..60 => "just now".to_string(),
+            60..3600 => format!("{} minutes ago", ago / 60),
+            3600..86400 => format!("{} hours ago", ago / 3600),
+            _ => format!("{} days ago", ago / 86400),
+        };
+        format!("{date}, {ago}")
+    }
+}
+
+// Year, month and day from days since 1970, Howard Hinnant's algorithm
+fn civil_date(days: u64) -> (u64, u64, u64) {
+    let days = days + 719_468;
+    let era = days / 146_097;
+    let day_of_era = days - era * 146_097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 { shifted_month + 3 } else { shifted_month - 9 };
+    let year = year_of_era + era * 400 + u64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn autosaves_of_running_volts_are_in_use() {
+        let own = PathBuf::from(format!("1700000000-{}.{EXTENSION}", process::id()));
+        assert!(is_in_use(&own));
+        assert!(!is_in_use(Path::new("untitled.volt")));
+        assert!(!is_in_use(Path::new("1700000000-.volt")));
+    }
+
+    #[test]
+    fn edits_make_a_new_revision() {
+        let mut project = Project::new();
+        let revision = project.revision();
+        assert_eq!(project.clone().revision(), revision);
+        project.add_track(None);
+        assert_ne!(project.revision(), revision);
+        assert_ne!(Project::new().revision(), Project::new().revision());
+    }
+}
@@ -0,0 +1,393 @@
+use std::path::{Path, PathBuf};
+
+use egui::{Align2, Context, Key, KeyboardShortcut, Modifiers, TextEdit, Ui, ViewportCommand, Window};
+
+use super::{
+    file::{self, RecentProjects},
+    recovery::{self, Recoverable},
+    Project, Revision,
+};
+
+// Something that would throw away unsaved changes, waiting for a yes
+enum Pending {
+    New,
+    Open(PathBuf),
+}
+
+enum Dialog {
+    SaveAs { name: String, location: String },
+    Open { path: String },
+    Discard(Pending),
+    Problems { title: String, problems: Vec<String> },
+    // Unsaved work from a previous run, offered on startup
+    Recover(Vec<Recoverable>),
+}
+
+// Where the project is saved and everything to do with saving and opening it
+pub struct Session {
+    path: Option<PathBuf>,
+    // The project as it was last saved or opened, to tell whether anything changed since
+    saved: Project,
+    dialog: Option<Dialog>,
+    recent: RecentProjects,
+    title: String,
+    // Set whenever the project got swapped for another one, the history doesn't apply to it
+    replaced: bool,
+}
+
+fn default_location() -> PathBuf {
+    dirs::document_dir()
+        .or_else(dirs::home_dir)
+        .unwrap_or_default()
+        .join("Volt")
+}
+
+impl Session {
+    pub fn new(project: &Project) -> Self {
+        Self {
+            path: None,
+            saved: project.clone(),
+            dialog: Some(recovery::recoverable())
+                .filter(|recoverable| !recoverable.is_empty())
+                .map(Dialog::Recover),
+            recent: RecentProjects::load(),
+            title: String::new(),
+            replaced: false,
+        }
+    }
+
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    pub fn folder(&self) -> Option<PathBuf> {
+        self.path.as_ref().and_then(|path| path.parent()).map(Path::to_path_buf)
+    }
+
+    // Changes whenever the project gets saved or opened
+    pub const fn saved_revision(&self) -> Revision {
+        self.saved.revision()
+    }
+
+    pub fn is_modified(&self, project: &Project) -> bool {
+        *project != self.saved
+    }
+
+    pub fn name(&self) -> String {
+        self.path.as_ref().map_or_else(
+            || "Untitled".to_string(),
+            |path| path.file_stem().unwrap_or_default().to_string_lossy().to_string(),
+        )
+    }
+
+    pub fn take_replaced(&mut self) -> bool {
+        std::mem::take(&mut self.replaced)
+    }
+
+    fn problems(&mut self, title: &str, problems: Vec<String>) {
+        self.dialog = Some(Dialog::Problems {
+            title: title.to_string(),
+            problems,
+        });
+    }
+
+    pub fn new_project(&mut self, project: &mut Project) {
+        if self.is_modified(project) {
+            self.dialog = Some(Dialog::Discard(Pending::New));
+        } else {
+            self.reset(project);
+        }
+    }
+
+    fn reset(&mut self, project: &mut Project) {
+        *project = Project::new();
+        self.saved = project.clone();
+        self.path = None;
+        self.replaced = true;
+    }
+
+    pub fn open(&mut self, path: PathBuf, project: &mut Project) {
+        if self.is_modified(project) {
+            self.dialog = Some(Dialog::Discard(Pending::Open(path)));
+        } else {
+            self.load(path, project);
+        }
+    }
+
+    fn load(&mut self, path: PathBuf, project: &mut Project) {
+        match file::load(&path) {
+            Ok(loaded) => {
+                *project = loaded.project;
+                self.saved = project.clone();
+                self.recent.add(&path);
+                self.path = Some(path);
+                self.replaced = true;
+                if !loaded.problems.is_empty() {
+                    self.problems("Opened with problems", loaded.problems);
+                }
+            }
+            Err(err) => self.problems("Couldn't open the project", vec![format!("Couldn't open {}: {err}", path.display())]),
+        }
+    }
+
+    // Replaces the project without asking, only offered before anything got edited
+    fn recover(&mut self, recoverable: &Recoverable, project: &mut Project) {
+        match recoverable.load() {
+            Ok(loaded) => {
+                *project = loaded.project;
+                // Compared against what's saved at the original place, so it shows up as unsaved
+                self.saved = recoverable
+                    .original
+                    .as_deref()
+                    .and_then(|path| file::load(path).ok())
+                    .map_or_else(Project::default, |loaded| loaded.project);
+                self.path.clone_from(&recoverable.original);
+                self.replaced = true;
+                recoverable.discard();
+                if !loaded.problems.is_empty() {
+                    self.problems("Recovered with problems", loaded.problems);
+                }
+            }
+            Err(err) => self.problems("Couldn't recover the project", vec![format!("Couldn't recover {}: {err}", recoverable.name())]),
+        }
+    }
+
+    // Asks where to put it if it was never saved
+    pub fn save(&mut self, project: &Project) {
+        match self.path.clone() {
+            Some(path) => self.save_to(path, project),
+            None => self.save_as(),
+        }
+    }
+
+    pub fn save_as(&mut self) {
+        self.dialog = Some(Dialog::SaveAs {
+            name: self.name(),
+            location: default_location().to_string_lossy().to_string(),
+        });
+    }
+
+    fn save_to(&mut self, path: PathBuf, project: &Project) {
+        match file::save(project, &path) {
+            Ok(()) => {
+                self.saved = project.clone();
+                self.recent.add(&path);
+                self.path = Some(path);
+            }
+            Err(err) => self.problems("Couldn't save the project", vec![format!("Couldn't save {}: {err}", path.display())]),
+        }
+    }
+
+    fn collect_audio(&mut self, project: &mut Project) {
+        let Some(path) = self.path.clone() else {
+            self.save_as();
+            return;
+        };
+        let problems = file::collect_audio(project, &path);
+        self.save_to(path, project);
+        if !problems.is_empty() {
+            self.problems("Some audio couldn't be collected", problems);
+        }
+    }
+
+    // Shortcuts, dialogs and the window title
+    pub fn update(&mut self, ctx: &Context, project: &mut Project) {
+        let shortcut = |modifiers, key| {
+            ctx.input_mut(|input| input.consume_shortcut(&KeyboardShortcut::new(modifiers, key)))
+        };
+        // Shift + S has to come first, plain S would take it otherwise
+        if shortcut(Modifiers::COMMAND | Modifiers::SHIFT, Key::S) {
+            self.save_as();
+        } else if shortcut(Modifiers::COMMAND, Key::S) {
+            self.save(project);
+        } else if shortcut(Modifiers::COMMAND, Key::O) {
+            self.dialog = Some(Dialog::Open { path: String::new() });
+        } else if shortcut(Modifiers::COMMAND, Key::N) {
+            self.new_project(project);
+        }
+
+        let modified = if self.is_modified(project) { " •" } else { "" };
+        let title = format!("{}{modified} - Volt", self.name());
+        if title != self.title {
+            ctx.send_viewport_cmd(ViewportCommand::Title(title.clone()));
+            self.title = title;
+        }
+
+        self.paint_dialog(ctx, project);
+    }
+
+    pub fn menu(&mut self, ui: &mut Ui, project: &mut Project) {
+        if ui.button("New").clicked() {
+            self.new_project(project);
+            ui.close_menu();
+        }
+        if ui.button("Open…").clicked() {
+            self.dialog = Some(Dialog::Open { path: String::new() });
+            ui.close_menu();
+        }
+        ui.add_enabled_ui(!self.recent.paths.is_empty(), |ui| {
+            ui.menu_button("Open recent", |ui| {
+                for path in self.recent.paths.clone() {
+                    if ui.button(path.to_string_lossy()).clicked() {
+                        self.open(path, project);
+                        ui.close_menu();
+                    }
+                }
+            });
+        });
+        ui.separator();
+        if ui.button("Save").clicked() {
+            self.save(project);
+            ui.close_menu();
+        }
+        if ui.button("Save as…").clicked() {
+            self.save_as();
+            ui.close_menu();
+        }
+        if ui.button("Collect audio into project folder").clicked() {
+            self.collect_audio(project);
+            ui.close_menu();
+        }
+    }
+
+    fn paint_dialog(&mut self, ctx: &Context, project: &mut Project) {
+        let Some(dialog) = &mut self.dialog else {
+            return;
+        };
+        let title = match dialog {
+            Dialog::SaveAs { .. } => "Save project",
+            Dialog::Open { .. } => "Open project",
+            Dialog::Discard(_) => "Unsaved changes",
+            Dialog::Problems { title, .. } => title.as_str(),
+            Dialog::Recover(_) => "Recover unsaved work",
+        }
+        .to_string();
+        let mut close = false;
+        let mut confirmed = false;
+        // Recover or delete one of the recoverable projects
+        let mut chosen = None;
+        Window::new(title)
+            .collapsible(false)
+            .resizable(false)
+            .anchor(Align2::CENTER_CENTER, [0., 0.])
+            .show(ctx, |ui| {
+                match dialog {
+                    Dialog::SaveAs { name, location } => {
+                        ui.horizontal(|ui| {
+                            ui.label("Name");
+                            ui.add(TextEdit::singleline(name));
+                        });
+                        ui.horizontal(|ui| {
+                            ui.label("Location");
+                            ui.add(TextEdit::singleline(location));
+                        });
+                        ui.label(format!(
+                            "Saves to {}",
+                            file::new_project_path(Path::new(location), name).display()
+                        ));
+                    }
+                    Dialog::Open { path } => {
+                        ui.label(format!("Path to a .{} file", file::EXTENSION));
+                        let response = ui.add(TextEdit::singleline(path));
+                        if !response.has_focus() && !response.lost_focus() {
+                            response.request_focus();
+                        }
+                        confirmed = response.lost_focus() && ui.input(|input| input.key_pressed(Key::Enter));
+                    }
+                    Dialog::Discard(_) => {
+                        ui.label("The current project has unsaved changes, throw them away?");
+                    }
+                    Dialog::Problems { problems, .. } => {
+                        for problem in problems.iter() {
+                            ui.label(problem);
+                        }
+                    }
+                    Dialog::Recover(recoverable) => {
+                        ui.label("Volt didn't get to save these the last time it ran");
+                        for (index, recoverable) in recoverable.iter().enumerate() {
+                            ui.separator();
+                            ui.horizontal(|ui| {
+                                ui.vertical(|ui| {
+                                    ui.strong(recoverable.name());
+                                    if let Some(original) = &recoverable.original {
+                                        ui.weak(original.to_string_lossy());
+                                    }
+                                    ui.weak(recoverable.when());
+                                });
+                                if ui.button("Recover").clicked() {
+                                    chosen = Some((index, true));
+                                }
+                                if ui.button("Delete").clicked() {
+                                    chosen = Some((index, false));
+                                }
+                            });
+                        }
+                        ui.separator();
+                    }
+                }
+                ui.horizontal(|ui| {
+                    if matches!(dialog, Dialog::Problems { .. }) {
+                        close = ui.button("OK").clicked();
+                    } else {
+                        let action = match dialog {
+                            Dialog::SaveAs { .. } => "Save",
+                            Dialog::Open { .. } => "Open",
+                            Dialog::Recover(_) => "Delete all",
+                            _ => "Discard changes",
+                        };
+                        confirmed |= ui.button(action).clicked();
+                        let cancel = if matches!(dialog, Dialog::Recover(_)) { "Later" } else { "Cancel" };
+                        close = ui.button(cancel).clicked() || ui.input(|input| input.key_pressed(Key::Escape));
+                    }
+                });
+            });
+
+        if let Some((index, recover)) = chosen {
+            let Some(Dialog::Recover(recoverables)) = &mut self.dialog else {
+                return;
+            };
+            let recoverable = recoverables.remove(index);
+            // Recovering one replaces the project, the others can wait for next time
+            if recoverables.is_empty() || recover {
+                self.dialog = None;
+            }
+            if recover {
+                self.recover(&recoverable, project);
+            } else {
+                recoverable.discard();
+            }
+        } else if confirmed {
+            self.confirm(project);
+        } else if close {
+            self.dialog = None;
+        }
+    }
+
+    fn confirm(&mut self, project: &mut Project) {
+        match self.dialog.take() {
+            Some(Dialog::SaveAs { name, location }) => {
+                let name = name.trim();
+                if name.is_empty() || name.contains(['/', '\\']) {
+                    self.problems("Couldn't save the project", vec![format!("\"{name}\" can't be used as a name")]);
+                    return;
+                }
+                self.save_to(file::new_project_path(Path::new(&location), name), project);
+            }
+            Some(Dialog::Open { path }) => {
+                let path = PathBuf::from(path.trim());
+                // A project folder works as well as the file in it
+                let path = if path.is_dir() {
+                    let name = path.file_name().unwrap_or_default().to_string_lossy().to_string();
+                    file::new_project_path(path.parent().unwrap_or(Path::new(".")), &name)
+                } else {
+                    path
+                };
+                self.open(path, project);
+            }
+            Some(Dialog::Discard(Pending::New)) => self.reset(project),
+            Some(Dialog::Discard(Pending::Open(path))) => self.load(path, project),
+            Some(Dialog::Recover(recoverable)) => recoverable.iter().for_each(Recoverable::discard),
+            Some(Dialog::Problems { .. }) | None => {}
+        }
+    }
+}
@@ -0,0 +1,266 @@
+use std::{collections::HashSet, ops::Range, path::PathBuf, sync::Arc};
+
+use super::{
+    mixer::{gain_factor, Mix, PanLaw},
+    pool::AudioPool,
+    BusId, ClipSource, Project, TempoMap,
+};
+use crate::blerp::{
+    audiofile::AudioBuffer,
+    processing::{live::graph::Graph, Effect},
+};
+
+// What the audio thread plays from. Built on the UI thread whenever the project changes and never
+// modified afterwards, everything is already in frames and routing is already resolved to indices
+#[derive(Debug)]
+pub struct Snapshot {
+    // Lets the UI know which snapshots the engine is done with
+    pub generation: u64,
+    pub sample_rate: u32,
+    // In the same order as the tracks in the project, so they line up with the per-track outputs
+    pub tracks: Vec<TrackSnapshot>,
+    pub master: StripSnapshot,
+    // The tracks, buses and master as tasks for the worker threads, the buses only live in there
+    pub graph: Graph,
+    // For the metronome, which has to know where the beats fall
+    pub tempo: TempoMap,
+    // Frames the transport jumps back from the end to the start of, `None` when not looping
+    pub loop_range: Option<Range<u64>>,
+}
+
+#[derive(Debug)]
+pub struct TrackSnapshot {
+    pub clips: Vec<AudioClipSnapshot>,
+    // Index of the bus in `bus_order`, `None` being the master
+    pub output: Option<usize>,
+    pub strip: StripSnapshot,
+}
+
+// Only there for compiling the graph, ordered so a bus only ever outputs or sends to a bus after it
+#[derive(Debug)]
+pub struct BusSnapshot {
+    pub output: Option<usize>,
+    pub strip: StripSnapshot,
+}
+
+// A mixer strip with everything worked out, solo, mute, phase, the fader and the pan law are down
+// to a factor for each side
+#[derive(Debug)]
+pub struct StripSnapshot {
+    // Without the bypassed ones
+    pub inserts: Vec<Effect>,
+    // Taken after the inserts, the factors of post-fader sends include the fader
+    pub sends: Vec<SendSnapshot>,
+    pub factors: [f32; 2],
+}
+
+#[derive(Debug)]
+pub struct SendSnapshot {
+    pub bus: usize,
+    pub factors: [f32; 2],
+}
+
+impl StripSnapshot {
+    // `bus_index` gives where a send goes, `None` for buses it can't send to
+    fn build(mix: &Mix, pan_law: PanLaw, silenced: bool, bus_index: impl Fn(BusId) -> Option<usize>) -> Self {
+        let input = if silenced || mix.mute {
+            0.
+        } else if mix.invert_phase {
+            -1.
+        } else {
+            1.
+        };
+        let fader = gain_factor(mix.gain) * input;
+        #[allow(clippy::cast_possible_truncation)]
+        let factors = pan_law.factors(mix.pan).map(|pan| (pan * fader) as f32);
+        let sends = mix
+            .sends
+            .iter()
+            .filter_map(|send| {
+                #[allow(clippy::cast_possible_truncation)]
+                let level = gain_factor(send.level) as f32;
+                #[allow(clippy::cast_possible_truncation)]
+                let factors = if send.pre_fader {
+                    [level * input as f32; 2]
+                } else {
+                    factors.map(|factor| factor * level)
+                };
+                Some(SendSnapshot {
+                    bus: bus_index(send.bus)?,
+                    factors,
+                })
+            })
+            .collect();
+        Self {
+            inserts: mix.inserts.iter().filter(|insert| !insert.bypass).map(|insert| insert.effect).collect(),
+            sends,
+            factors,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct AudioClipSnapshot {
+    pub start: u64,
+    pub length: u64,
+    // Frame of the buffer the clip starts at
+    pub offset: u64,
+    // Stereo, at the snapshot's sample rate
+    pub buffer: Arc<AudioBuffer>,
+}
+
+impl AudioClipSnapshot {
+    pub fn end(&self) -> u64 {
+        self.start + self.length
+    }
+}
+
+// Buses a track or bus feeds, through its output and its sends
+fn targets(output: Option<BusId>, mix: &Mix) -> impl Iterator<Item = BusId> + '_ {
+    output.into_iter().chain(mix.sends.iter().map(|send| send.bus))
+}
+
+// Buses in an order where every bus comes before the ones it outputs and sends to. Whatever would
+// end up feeding itself goes to the master instead, or gets dropped if it's a send
+fn bus_order(project: &Project) -> Vec<BusId> {
+    let mut ordered: Vec<BusId> = Vec::with_capacity(project.buses.len());
+    let mut remaining: Vec<(BusId, Vec<BusId>)> = project
+        .buses
+        .iter()
+        .map(|bus| (bus.id, targets(bus.output, &bus.mix).collect()))
+        .collect();
+    while !remaining.is_empty() {
+        // Anything nothing else still feeds into can go next
+        let ready = remaining
+            .iter()
+            .position(|(id, _)| !remaining.iter().any(|(_, targets)| targets.contains(id)));
+        match ready {
+            Some(index) => ordered.push(remaining.remove(index).0),
+            // Only cycles are left, whatever closes one goes to the master when building
+            None => ordered.push(remaining.remove(0).0),
+        }
+    }
+    ordered
+}
+
+// Which tracks and buses solo leaves playing: the soloed ones, whatever feeds into them, whatever
+// they feed into and the solo safe ones. Everything plays when nothing is soloed
+fn audible(project: &Project) -> (Vec<bool>, Vec<bool>) {
+    let soloing = project.tracks.iter().map(|track| &track.mix).chain(project.buses.iter().map(|bus| &bus.mix)).any(|mix| mix.solo);
+    if !soloing {
+        return (vec![true; project.tracks.len()], vec![true; project.buses.len()]);
+    }
+    // Buses that end up in a soloed bus, and buses a soloed track or bus ends up in
+    let mut feeding: HashSet<BusId> = project.buses.iter().filter(|bus| bus.mix.solo).map(|bus| bus.id).collect();
+    let mut fed: HashSet<BusId> = project
+        .tracks
+        .iter()
+        .filter(|track| track.mix.solo)
+        .flat_map(|track| targets(track.output, &track.mix))
+        .chain(project.buses.iter().filter(|bus| bus.mix.solo).flat_map(|bus| targets(bus.output, &bus.mix)))
+        .collect();
+    loop {
+        let mut changed = false;
+        for bus in &project.buses {
+            if !feeding.contains(&bus.id) && targets(bus.output, &bus.mix).any(|target| feeding.contains(&target)) {
+                changed |= feeding.insert(bus.id);
+            }
+            if fed.contains(&bus.id) {
+                for target in targets(bus.output, &bus.mix) {
+                    changed |= fed.insert(target);
+                }
+            }
+        }
+        if !changed {
+            break;
+        }
+    }
+    let tracks = project
+        .tracks
+        .iter()
+        .map(|track| {
+            track.mix.solo || track.mix.solo_safe || targets(track.output, &track.mix).any(|target| feeding.contains(&target))
+        })
+        .collect();
+    let buses = project
+        .buses
+        .iter()
+        .map(|bus| bus.mix.solo || bus.mix.solo_safe || feeding.contains(&bus.id) || fed.contains(&bus.id))
+        .collect();
+    (tracks, buses)
+}
+
+impl Snapshot {
+    pub fn build(project: &Project, sample_rate: u32, generation: u64, pool: &mut AudioPool) -> Self {
+        let order = bus_order(project);
+        let bus_index = |bus: BusId| order.iter().position(|id| *id == bus);
+        let (audible_tracks, audible_buses) = audible(project);
+        let buses: Vec<BusSnapshot> = order
+            .iter()
+            .enumerate()
+            .filter_map(|(index, id)| {
+                let project_index = project.bus_index(*id)?;
+                let bus = &project.buses[project_index];
+                let after = |bus: BusId| bus_index(bus).filter(|target| *target > index);
+                Some(BusSnapshot {
+                    output: bus.output.and_then(after),
+                    strip: StripSnapshot::build(&bus.mix, project.pan_law, !audible_buses[project_index], after),
+                })
+            })
+            .collect();
+
+        let mut used: HashSet<(PathBuf, u32)> = HashSet::new();
+        let tracks: Vec<TrackSnapshot> = project
+            .tracks
+            .iter()
+            .zip(audible_tracks)
+            .map(|(track, audible)| {
+                let mut clips: Vec<AudioClipSnapshot> = track
+                    .clips
+                    .iter()
+                    .filter_map(|clip| {
+                        // Nothing plays MIDI yet
+                        let ClipSource::Audio { path, offset } = &clip.source else {
+                            return None;
+                        };
+                        used.insert((path.clone(), sample_rate));
+                        let buffer = pool.get(path, sample_rate)?;
+                        let start = project.tempo.frame_at(clip.start, sample_rate);
+                        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+                        let offset = (offset * f64::from(sample_rate)).round() as u64;
+                        Some(AudioClipSnapshot {
+                            start,
+                            length: project.tempo.frame_at(clip.end(), sample_rate) - start,
+                            offset,
+                            buffer,
+                        })
+                    })
+                    .collect();
+                clips.sort_by_key(|clip| clip.start);
+                TrackSnapshot {
+                    clips,
+                    output: track.output.and_then(bus_index),
+                    strip: StripSnapshot::build(&track.mix, project.pan_law, !audible, bus_index),
+                }
+            })
+            .collect();
+        pool.retain(&used);
+
+        let region = project.loop_region;
+        let loop_range = region.enabled.then(|| {
+            project.tempo.frame_at(region.start, sample_rate)..project.tempo.frame_at(region.end, sample_rate)
+        });
+
+        // Nothing comes after the master
+        let master = StripSnapshot::build(&project.master, project.pan_law, false, |_| None);
+        Self {
+            generation,
+            sample_rate,
+            graph: Graph::compile(&tracks, &buses, &master, sample_rate),
+            tracks,
+            master,
+            tempo: project.tempo.clone(),
+            loop_range: loop_range.filter(|range| !range.is_empty()),
+        }
+    }
+}
@@ -0,0 +1,336 @@
+use serde::{Deserialize, Serialize};
+
+pub const MIN_TEMPO: f64 = 10.;
+pub const MAX_TEMPO: f64 = 999.;
+// Ramps flatter than this are treated as constant, the closed forms divide by the slope
+const FLAT: f64 = 1e-9;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeSignature {
+    pub numerator: u32,
+    pub denominator: u32,
+}
+
+impl Default for TimeSignature {
+    fn default() -> Self {
+        Self {
+            numerator: 4,
+            denominator: 4,
+        }
+    }
+}
+
+impl TimeSignature {
+    // Beats are quarter notes everywhere, so 6/8 has 3 of them to a bar
+    pub fn beats_per_bar(self) -> f64 {
+        f64::from(self.numerator) * 4. / f64::from(self.denominator.max(1))
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct TempoPoint {
+    pub beat: f64,
+    // Quarter notes per minute
+    pub bpm: f64,
+    // Glides to the tempo of the next point instead of jumping to it there
+    #[serde(default)]
+    pub ramp: bool,
+}
+
+// Time signatures change at the start of a bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SignatureChange {
+    pub bar: u32,
+    pub signature: TimeSignature,
+}
+
+// Tempo and time signature over the course of a song, and the conversions between beats, seconds
+// and frames that follow from them. There's always a tempo at beat 0 and a time signature at bar 0,
+// both lists are sorted
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TempoMap {
+    tempos: Vec<TempoPoint>,
+    signatures: Vec<SignatureChange>,
+}
+
+impl Default for TempoMap {
+    fn default() -> Self {
+        Self::new(120., TimeSignature::default())
+    }
+}
+
+impl TempoMap {
+    pub fn new(bpm: f64, signature: TimeSignature) -> Self {
+        Self {
+            tempos: vec![TempoPoint {
+                beat: 0.,
+                bpm: bpm.clamp(MIN_TEMPO, MAX_TEMPO),
+                ramp: false,
+            }],
+            signatures: vec![SignatureChange { bar: 0, signature }],
+        }
+    }
+
+    pub fn tempos(&self) -> &[TempoPoint] {
+        &self.tempos
+    }
+
+    pub fn signatures(&self) -> &[SignatureChange] {
+        &self.signatures
+    }
+
+    // Puts whatever a hand edited file did to the map right, returns whether it had to
+    pub fn repair(&mut self) -> bool {
+        let original = self.clone();
+        self.tempos.retain(|point| point.beat.is_finite() && point.bpm.is_finite() && point.beat >= 0.);
+        for point in &mut self.tempos {
+            point.bpm = point.bpm.clamp(MIN_TEMPO, MAX_TEMPO);
+        }
+        self.tempos.sort_by(|a, b| a.beat.total_cmp(&b.beat));
+        self.tempos.dedup_by(|later, earlier| later.beat == earlier.beat);
+        match self.tempos.first_mut() {
+            Some(first) => first.beat = 0.,
+            None => self.tempos = Self::default().tempos,
+        }
+
+        self.signatures.retain(|change| {
+            change.signature.numerator > 0 && change.signature.denominator.is_power_of_two()
+        });
+        self.signatures.sort_by_key(|change| change.bar);
+        self.signatures.dedup_by_key(|change| change.bar);
+        match self.signatures.first_mut() {
+            Some(first) => first.bar = 0,
+            None => self.signatures = Self::default().signatures,
+        }
+        *self != original
+    }
+
+    // Index of the tempo point in effect at `beat`
+    pub fn tempo_index(&self, beat: f64) -> usize {
+        self.tempos.partition_point(|point| point.beat <= beat).saturating_sub(1)
+    }
+
+    // How fast a ramp starting at point `index` changes, in bpm per beat
+    fn slope(&self, index: usize) -> f64 {
+        let point = self.tempos[index];
+        match self.tempos.get(index + 1) {
+            Some(next) if point.ramp && next.beat > point.beat => (next.bpm - point.bpm) / (next.beat - point.beat),
+            _ => 0.,
+        }
+    }
+
+    // Seconds from point `index` to `beat`, which has to be before the next point. The tempo along
+    // a ramp is linear in beats, which makes time the integral of 60 / tempo
+    fn segment_seconds(&self, index: usize, beat: f64) -> f64 {
+        let point = self.tempos[index];
+        let beats = beat - point.beat;
+        let slope = self.slope(index);
+        if slope.abs() < FLAT {
+            beats * 60. / point.bpm
+        } else {
+            60. / slope * ((point.bpm + slope * beats) / point.bpm).ln()
+        }
+    }
+
+    // The inverse of `segment_seconds`
+    fn segment_beats(&self, index: usize, seconds: f64) -> f64 {
+        let point = self.tempos[index];
+        let slope = self.slope(index);
+        if slope.abs() < FLAT {
+            seconds * point.bpm / 60.
+        } else {
+            point.bpm * ((slope * seconds / 60.).exp() - 1.) / slope
+        }
+    }
+
+    pub fn tempo_at(&self, beat: f64) -> f64 {
+        let index = self.tempo_index(beat.max(0.));
+        self.tempos[index].bpm + self.slope(index) * (beat.max(0.) - self.tempos[index].beat)
+    }
+
+    pub fn seconds_at(&self, beat: f64) -> f64 {
+        let beat = beat.max(0.);
+        let index = self.tempo_index(beat);
+        let before: f64 = (0..index)
+            .map(|segment| self.segment_seconds(segment, self.tempos[segment + 1].beat))
+            .sum();
+        before + self.segment_seconds(index, beat)
+    }
+
+    pub fn beat_at(&self, seconds: f64) -> f64 {
+        let mut seconds = seconds.max(0.);
+        for index in 0..self.tempos.len() {
+            if let Some(next) = self.tempos.get(index + 1) {
+                let length = self.segment_seconds(index, next.beat);
+                if seconds >= length {
+                    seconds -= length;
+                    continue;
+                }
+            }
+            return self.tempos[index].beat + self.segment_beats(index, seconds);
+        }
+        0.
+    }
+
+    pub fn frame_at(&self, beat: f64, sample_rate: u32) -> u64 {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let frame = (self.seconds_at(beat) * f64::from(sample_rate)).round() as u64;
+        frame
+    }
+
+    pub fn beat_at_frame(&self, frame: u64, sample_rate: u32) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let seconds = frame as f64 / f64::from(sample_rate.max(1));
+        self.beat_at(seconds)
+    }
+
+    // Changes the tempo from `beat` on, adding a point there if there isn't one
+    pub fn set_tempo(&mut self, beat: f64, bpm: f64) {
+        let bpm = bpm.clamp(MIN_TEMPO, MAX_TEMPO);
+        let index = self.tempo_index(beat.max(0.));
+        if (self.tempos[index].beat - beat).abs() < FLAT {
+            self.tempos[index].bpm = bpm;
+        } else {
+            self.tempos.insert(index + 1, TempoPoint { beat, bpm, ramp: false });
+        }
+    }
+
+    pub fn set_point_tempo(&mut self, index: usize, bpm: f64) {
+        if let Some(point) = self.tempos.get_mut(index) {
+            point.bpm = bpm.clamp(MIN_TEMPO, MAX_TEMPO);
+        }
+    }
+
+    pub fn set_ramp(&mut self, index: usize, ramp: bool) {
+        if let Some(point) = self.tempos.get_mut(index) {
+            point.ramp = ramp;
+        }
+    }
+
+    // The first tempo stays, something has to say how fast the song starts
+    pub fn remove_tempo(&mut self, index: usize) {
+        if index > 0 && index < self.tempos.len() {
+            self.tempos.remove(index);
+        }
+    }
+
+    // Index of the time signature in effect at `bar`
+    pub fn signature_index(&self, bar: u32) -> usize {
+        self.signatures.partition_point(|change| change.bar <= bar).saturating_sub(1)
+    }
+
+    pub fn signature_at_bar(&self, bar: u32) -> TimeSignature {
+        self.signatures[self.signature_index(bar)].signature
+    }
+
+    pub fn signature_at(&self, beat: f64) -> TimeSignature {
+        self.signature_at_bar(self.bar_at(beat).0)
+    }
+
+    pub fn set_signature(&mut self, bar: u32, signature: TimeSignature) {
+        let index = self.signature_index(bar);
+        if self.signatures[index].bar == bar {
+            self.signatures[index].signature = signature;
+        } else {
+            self.signatures.insert(index + 1, SignatureChange { bar, signature });
+        }
+    }
+
+    pub fn remove_signature(&mut self, index: usize) {
+        if index > 0 && index < self.signatures.len() {
+            self.signatures.remove(index);
+        }
+    }
+
+    // Beat bar `bar` starts on, bars counting from 0
+    pub fn bar_start(&self, bar: u32) -> f64 {
+        let mut beat = 0.;
+        for (index, change) in self.signatures.iter().enumerate() {
+            if change.bar >= bar {
+                break;
+            }
+            let until = self.signatures.get(index + 1).map_or(bar, |next| next.bar.min(bar));
+            beat += f64::from(until - change.bar) * change.signature.beats_per_bar();
+        }
+        beat
+    }
+
+    // Bar `beat` is in and how many beats into it
+    pub fn bar_at(&self, beat: f64) -> (u32, f64) {
+        let beat = beat.max(0.);
+        let mut start = 0.;
+        for (index, change) in self.signatures.iter().enumerate() {
+            let beats_per_bar = change.signature.beats_per_bar();
+            if let Some(next) = self.signatures.get(index + 1) {
+                let length = f64::from(next.bar - change.bar) * beats_per_bar;
+                if beat >= start + length {
+                    start += length;
+                    continue;
+                }
+            }
+            // Landing right on a bar line shouldn't end up at the end of the bar before
+            let bars = ((beat - start) / beats_per_bar + FLAT).floor();
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let bar = change.bar + bars as u32;
+            return (bar, (beat - start - bars * beats_per_bar).max(0.));
+        }
+        (0, beat)
+    }
+
+    // Every bar from the one `beat` is in on, with where it starts and its time signature
+    pub fn bars(&self, beat: f64) -> impl Iterator<Item = (u32, f64, TimeSignature)> + '_ {
+        (self.bar_at(beat).0..).map(|bar| (bar, self.bar_start(bar), self.signature_at_bar(bar)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: f64, b: f64) -> bool {
+        (a - b).abs() < 1e-9
+    }
+
+    #[test]
+    fn flat_tempo_is_a_straight_line() {
+        let tempo = TempoMap::new(90., TimeSignature::default());
+        for beat in [0., 1., 4.5, 1000.] {
+            assert!(close(tempo.seconds_at(beat), beat * 60. / 90.));
+            assert!(close(tempo.beat_at(beat * 60. / 90.), beat));
+        }
+        assert_eq!(tempo.frame_at(4., 48000), 128_000);
+        assert!(close(tempo.beat_at_frame(128_000, 48000), 4.));
+    }
+
+    #[test]
+    fn beats_and_seconds_round_trip_across_ramps() {
+        let mut tempo = TempoMap::new(120., TimeSignature::default());
+        // 120 gliding up to 180 over 8 beats, 180 until beat 16, then 90
+        tempo.set_tempo(8., 180.);
+        tempo.set_ramp(0, true);
+        tempo.set_tempo(16., 90.);
+        assert!(close(tempo.tempo_at(4.), 150.));
+        assert!(close(tempo.tempo_at(12.), 180.));
+        // The integral of 60 / tempo over the ramp
+        let ramp = 60. / 7.5 * (180_f64 / 120.).ln();
+        assert!(close(tempo.seconds_at(8.), ramp));
+        assert!(close(tempo.seconds_at(16.), ramp + 8. * 60. / 180.));
+        assert!(close(tempo.seconds_at(20.), ramp + 8. * 60. / 180. + 4. * 60. / 90.));
+        for beat in [0., 0.5, 4., 7.99, 8., 12., 16., 20.] {
+            assert!(close(tempo.beat_at(tempo.seconds_at(beat)), beat), "beat {beat}");
+        }
+    }
+
+    #[test]
+    fn bars_follow_signature_changes() {
+        let mut tempo = TempoMap::default();
+        tempo.set_signature(2, TimeSignature { numerator: 6, denominator: 8 });
+        tempo.set_signature(4, TimeSignature { numerator: 7, denominator: 4 });
+        let starts: Vec<f64> = (0..6).map(|bar| tempo.bar_start(bar)).collect();
+        assert_eq!(starts, [0., 4., 8., 11., 14., 21.]);
+        assert_eq!(tempo.bar_at(8.), (2, 0.));
+        assert_eq!(tempo.bar_at(12.5), (3, 1.5));
+        assert_eq!(tempo.bar_at(22.), (5, 1.));
+        assert_eq!(tempo.signature_at(13.), TimeSignature { numerator: 6, denominator: 8 });
+    }
+}
@@ -0,0 +1,24 @@
+use serde::{Deserialize, Serialize};
+
+use super::{clip::Clip, BusId, Mix, TrackId};
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Track {
+    pub id: TrackId,
+    pub name: String,
+    pub clips: Vec<Clip>,
+    // `None` goes straight to the master
+    pub output: Option<BusId>,
+    #[serde(default)]
+    pub mix: Mix,
+}
+
+// Sums whatever gets routed into it, tracks or other buses
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Bus {
+    pub id: BusId,
+    pub name: String,
+    pub output: Option<BusId>,
+    #[serde(default)]
+    pub mix: Mix,
+}
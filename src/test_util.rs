@@ -0,0 +1,19 @@
+// Small xorshift generator, so the random checks don't need a dependency
+pub struct Random(pub u64);
+
+impl Random {
+    pub fn below(&mut self, count: usize) -> usize {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        #[allow(clippy::cast_possible_truncation)]
+        let value = (self.0 % count.max(1) as u64) as usize;
+        value
+    }
+
+    pub fn beat(&mut self) -> f64 {
+        #[allow(clippy::cast_precision_loss)]
+        let beat = self.below(64) as f64 / 4.;
+        beat
+    }
+}
@@ -0,0 +1,914 @@
+use std::{
+    path::{Path, PathBuf},
+    sync::{
+        atomic::Ordering,
+        mpsc::{channel, Receiver, Sender},
+    },
+    thread,
+};
+
+use egui::{
+    pos2, vec2, Align2, Button, Checkbox, Context, CursorIcon, DragValue, Key, Modifiers,
+    Painter, PointerButton, Pos2, Rect, Sense, Stroke, Ui,
+};
+
+use crate::{
+    blerp::{
+        audiofile,
+        device::Output,
+        peaks::PeakCache,
+        processing::live::{Command, TransportCommand},
+    },
+    browser::DragPayload,
+    project::{Clip, ClipId, ClipSource, LoopRegion, Project, TempoMap, MAX_TEMPO, MIN_CLIP_LENGTH, MIN_TEMPO},
+    transport::{COMMON_SIGNATURES, DEFAULT_SAMPLE_RATE},
+    visual::{font, ThemeColors},
+};
+
+use grid::{Snap, View};
+
+mod grid;
+
+const HEADER_WIDTH: f32 = 150.;
+const RULER_HEIGHT: f32 = 24.;
+// How close to the edge of a clip the pointer has to be to resize it
+const EDGE_WIDTH: f32 = 6.;
+const CLIP_NAME_HEIGHT: f32 = 14.;
+// Bar numbers in the ruler are at least this far apart
+const MIN_LABEL_SPACING: f64 = 48.;
+// Grid lines closer together than this aren't drawn
+const MIN_LINE_SPACING: f64 = 8.;
+// Length of dropped files that don't say how long they are, in beats
+const DEFAULT_CLIP_LENGTH: f64 = 4.;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Handle {
+    Body,
+    Start,
+    End,
+}
+
+// A clip being dragged around or resized
+struct Gesture {
+    handle: Handle,
+    clip: ClipId,
+    // The clip as it was when it got grabbed
+    original: Clip,
+    // Beat under the pointer when it got grabbed
+    grab: f64,
+    // Leaves a copy behind once the clip moves
+    duplicate: bool,
+}
+
+// Files let go of over the lanes, with how long each one is in seconds if that could be found out
+struct Drop {
+    start: f64,
+    track: usize,
+    files: Vec<(PathBuf, Option<f64>)>,
+}
+
+// Shows and edits the arrangement of a project, keeps nothing of the project itself
+pub struct Timeline {
+    snap: Snap,
+    view: View,
+    selected: Option<ClipId>,
+    gesture: Option<Gesture>,
+    // Beat a shift + drag along the ruler started from, it sets the loop
+    loop_drag: Option<f64>,
+    // Beat the ruler's menu got opened at
+    ruler_menu: Option<f64>,
+    peaks: PeakCache,
+    // Dropped files get measured on a thread, they turn into clips once that's done
+    measured: Sender<Drop>,
+    dropped: Receiver<Drop>,
+}
+
+impl Timeline {
+    pub fn new() -> Self {
+        let (measured, dropped) = channel();
+        Self {
+            snap: Snap::default(),
+            view: View::default(),
+            selected: None,
+            gesture: None,
+            loop_drag: None,
+            ruler_menu: None,
+            peaks: PeakCache::new(),
+            measured,
+            dropped,
+        }
+    }
+
+    // Snapping is skipped while alt is held
+    fn snapped(&self, project: &Project, beat: f64, modifiers: Modifiers) -> f64 {
+        if modifiers.alt {
+            beat
+        } else {
+            self.snap.round(beat, &project.tempo)
+        }
+        .max(0.)
+    }
+
+    fn track_top(&self, track: usize, lanes: Rect) -> f32 {
+        #[allow(clippy::cast_precision_loss)]
+        let offset = track as f32 * self.view.track_height;
+        lanes.top() + offset - self.view.scroll_y
+    }
+
+    // Can be past the last track
+    fn track_at(&self, y: f32, lanes: Rect) -> usize {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let track = ((y - lanes.top() + self.view.scroll_y) / self.view.track_height).max(0.) as usize;
+        track
+    }
+
+    fn clip_rect(&self, track: usize, clip: &Clip, lanes: Rect) -> Rect {
+        let top = self.track_top(track, lanes);
+        Rect::from_min_max(
+            pos2(self.view.x(clip.start, lanes.left()), top + 1.),
+            pos2(self.view.x(clip.end(), lanes.left()), top + self.view.track_height - 1.),
+        )
+    }
+
+    // Topmost clip under `position` and which part of it
+    fn clip_at(&self, project: &Project, position: Pos2, lanes: Rect) -> Option<(ClipId, Handle)> {
+        let track = self.track_at(position.y, lanes);
+        let clips = &project.tracks.get(track)?.clips;
+        clips.iter().rev().find_map(|clip| {
+            let rect = self.clip_rect(track, clip, lanes);
+            if !rect.contains(position) {
+                return None;
+            }
+            // Narrow clips are all body, otherwise they couldn't be moved
+            let edge = EDGE_WIDTH.min(rect.width() / 3.);
+            let handle = if position.x < rect.left() + edge {
+                Handle::Start
+            } else if position.x > rect.right() - edge {
+                Handle::End
+            } else {
+                Handle::Body
+            };
+            Some((clip.id, handle))
+        })
+    }
+
+    fn send(output: Option<&Output>, command: TransportCommand) {
+        if let Some(output) = output {
+            output.engine.send(Command::Transport(command));
+        }
+    }
+
+    pub fn paint(
+        &mut self,
+        ctx: &Context,
+        ui: &mut Ui,
+        area: Rect,
+        theme: &ThemeColors,
+        project: &mut Project,
+        output: Option<&Output>,
+    ) {
+        let header = Rect::from_min_max(area.min, pos2(area.left() + HEADER_WIDTH, area.bottom()));
+        let corner = Rect::from_min_max(area.min, pos2(header.right(), area.top() + RULER_HEIGHT));
+        let ruler = Rect::from_min_max(pos2(header.right(), area.top()), pos2(area.right(), corner.bottom()));
+        let lanes = Rect::from_min_max(pos2(header.right(), ruler.bottom()), area.max);
+
+        let (rolling, frame, sample_rate) = output.map_or((false, 0, DEFAULT_SAMPLE_RATE), |output| {
+            let (rolling, frame) = output.engine.status.playhead();
+            let sample_rate = output.engine.status.sample_rate.load(Ordering::Relaxed);
+            (rolling, frame, sample_rate.max(1))
+        });
+        let playhead = project.tempo.beat_at_frame(frame, sample_rate);
+
+        self.navigate(ctx, area, lanes, project);
+        // Keep the playhead on screen while playing
+        if rolling && self.view.x(playhead, lanes.left()) > lanes.right() {
+            self.view.scroll_x = playhead;
+        }
+        self.handle_keys(ctx, project, playhead);
+        self.handle_drop(ctx, lanes, project);
+        self.add_dropped(project);
+        self.handle_pointer(ctx, ruler, lanes, project, output, sample_rate);
+
+        self.paint_lanes(ui, lanes, theme, project);
+        self.paint_clips(ui, lanes, theme, project);
+        self.paint_drop_ghost(ctx, ui, lanes, theme, project);
+        self.paint_ruler(ui, ruler, theme, project);
+        self.ruler_menu(ui, ruler, project);
+        self.paint_header(ctx, ui, header, corner, theme, project);
+
+        let x = self.view.x(playhead, lanes.left());
+        if x >= ruler.left() {
+            ui.painter().line_segment(
+                [pos2(x, ruler.top()), pos2(x, lanes.bottom())],
+                Stroke::new(1., theme.timeline_playhead),
+            );
+        }
+        if rolling {
+            ctx.request_repaint();
+        }
+    }
+
+    // Scrolling, ctrl + scroll zooms in time and alt + scroll changes the track height
+    fn navigate(&mut self, ctx: &Context, area: Rect, lanes: Rect, project: &Project) {
+        let Some(pointer) = ctx.pointer_hover_pos().filter(|pointer| area.contains(*pointer)) else {
+            return;
+        };
+        if ctx.is_pointer_over_area() {
+            return;
+        }
+        let (scroll, zoom, modifiers) = ctx.input(|input| (input.smooth_scroll_delta, input.zoom_delta(), input.modifiers));
+        if zoom != 1. {
+            self.view.zoom_x(f64::from(zoom), pointer.x.max(lanes.left()), lanes.left());
+        } else if modifiers.alt && scroll.y != 0. {
+            self.view.zoom_y((scroll.y / 200.).exp());
+        } else {
+            self.view.scroll_x = (self.view.scroll_x - f64::from(scroll.x) / self.view.pixels_per_beat).max(0.);
+            self.view.scroll_y -= scroll.y;
+        }
+        #[allow(clippy::cast_precision_loss)]
+        let content_height = (project.tracks.len() + 1) as f32 * self.view.track_height;
+        self.view.scroll_y = self.view.scroll_y.clamp(0., (content_height - lanes.height()).max(0.));
+    }
+
+    fn handle_keys(&mut self, ctx: &Context, project: &mut Project, playhead: f64) {
+        // Text fields and menus get the keyboard first
+        if ctx.memory(|memory| memory.focused().is_some() || memory.any_popup_open()) {
+            return;
+        }
+        let (delete, duplicate, split) = ctx.input_mut(|input| {
+            (
+                input.consume_key(Modifiers::NONE, Key::Delete) || input.consume_key(Modifiers::NONE, Key::Backspace),
+                input.consume_key(Modifiers::COMMAND, Key::D),
+                input.consume_key(Modifiers::COMMAND, Key::E),
+            )
+        });
+        let Some(id) = self.selected else {
+            return;
+        };
+        if delete {
+            project.remove_clip(id);
+            self.selected = None;
+            if self.gesture.take().is_some() {
+                project.end();
+            }
+        } else if duplicate {
+            if let Some(end) = project.clip(id).map(Clip::end) {
+                self.selected = project.duplicate_clip(id, end);
+            }
+        } else if split {
+            project.split_clip(id, playhead);
+        }
+    }
+
+    // Files dragged in from the browser become audio clips, one track down for every file
+    fn handle_drop(&mut self, ctx: &Context, lanes: Rect, project: &mut Project) {
+        let Some(position) = ctx.pointer_latest_pos() else {
+            return;
+        };
+        let Some(payload) = DragPayload::dropped(ctx, lanes) else {
+            return;
+        };
+        let modifiers = ctx.input(|input| input.modifiers);
+        let start = self.snapped(project, self.view.beat(position.x, lanes.left()), modifiers);
+        let track = self.track_at(position.y, lanes);
+        let paths = payload.audio_files().cloned().collect::<Vec<_>>();
+        let measured = self.measured.clone();
+        let ctx = ctx.clone();
+        // Reading the headers of lots of files, or files on a slow drive, would hold the interface up
+        thread::spawn(move || {
+            let files = paths
+                .into_iter()
+                .map(|path| {
+                    let duration = audiofile::duration(&path);
+                    (path, duration)
+                })
+                .collect();
+            if measured.send(Drop { start, track, files }).is_ok() {
+                ctx.request_repaint();
+            }
+        });
+    }
+
+    fn add_dropped(&mut self, project: &mut Project) {
+        for Drop { start, track: first_track, files } in self.dropped.try_iter().collect::<Vec<_>>() {
+            if files.is_empty() {
+                continue;
+            }
+            project.begin("Add audio");
+            for (track, (path, duration)) in (first_track..).zip(files) {
+                let name = path.file_stem().unwrap_or_default().to_string_lossy().to_string();
+                while project.tracks.len() <= track {
+                    project.add_track(Some(name.clone()));
+                }
+                let tempo = &project.tempo;
+                let length = duration
+                    .map_or(DEFAULT_CLIP_LENGTH, |seconds| tempo.beat_at(tempo.seconds_at(start) + seconds) - start);
+                let source = ClipSource::Audio { path, offset: 0. };
+                self.selected = project.add_clip(project.tracks[track].id, start, length, source);
+            }
+            project.end();
+        }
+    }
+
+    fn handle_pointer(
+        &mut self,
+        ctx: &Context,
+        ruler: Rect,
+        lanes: Rect,
+        project: &mut Project,
+        output: Option<&Output>,
+        sample_rate: u32,
+    ) {
+        let (pressed, down, double_clicked, origin, position, modifiers) = ctx.input(|input| {
+            (
+                input.pointer.primary_pressed(),
+                input.pointer.primary_down(),
+                input.pointer.button_double_clicked(PointerButton::Primary),
+                input.pointer.press_origin(),
+                input.pointer.latest_pos(),
+                input.modifiers,
+            )
+        });
+        let (Some(position), false) = (position, ctx.is_pointer_over_area()) else {
+            return;
+        };
+        // Things dragged in from elsewhere aren't for the clips
+        if DragPayload::current(ctx).is_some() {
+            return;
+        }
+
+        // Clicking or dragging along the ruler moves the playhead, shift + dragging sets the loop
+        let ruler_beat = self.snapped(project, self.view.beat(position.x, ruler.left()), modifiers);
+        if pressed && modifiers.shift && ruler.contains(position) {
+            self.loop_drag = Some(ruler_beat);
+            project.begin("Set loop");
+        }
+        if let Some(anchor) = self.loop_drag {
+            if down {
+                let region = LoopRegion {
+                    start: anchor.min(ruler_beat),
+                    end: anchor.max(ruler_beat),
+                    enabled: true,
+                };
+                if region.end > region.start {
+                    project.set_loop_region("Set loop", region);
+                }
+            } else {
+                self.loop_drag = None;
+                project.end();
+            }
+        } else if down && origin.is_some_and(|origin| ruler.contains(origin)) {
+            Self::send(output, TransportCommand::Locate(project.tempo.frame_at(ruler_beat, sample_rate)));
+        }
+
+        if pressed && lanes.contains(position) {
+            if let Some((id, handle)) = self.clip_at(project, position, lanes) {
+                self.selected = Some(id);
+                if let Some(clip) = project.clip(id) {
+                    let duplicate = handle == Handle::Body && modifiers.command;
+                    self.gesture = Some(Gesture {
+                        handle,
+                        clip: id,
+                        original: clip.clone(),
+                        grab: self.view.beat(position.x, lanes.left()),
+                        duplicate,
+                    });
+                    // The whole drag is one step to undo
+                    project.begin(match handle {
+                        Handle::Body if duplicate => "Duplicate clip",
+                        Handle::Body => "Move clip",
+                        Handle::Start | Handle::End => "Trim clip",
+                    });
+                }
+            } else {
+                self.selected = None;
+            }
+        }
+        if double_clicked && lanes.contains(position) && self.clip_at(project, position, lanes).is_none() {
+            if let Some(track) = project.tracks.get(self.track_at(position.y, lanes)).map(|track| track.id) {
+                let start = self.snap.floor(self.view.beat(position.x, lanes.left()), &project.tempo);
+                let beats_per_bar = project.tempo.signature_at(start).beats_per_bar();
+                let source = ClipSource::Midi { notes: Vec::new() };
+                self.selected = project.add_clip(track, start, beats_per_bar, source);
+            }
+        }
+
+        if !down {
+            if self.gesture.take().is_some() {
+                project.end();
+            }
+            let hovered = lanes
+                .contains(position)
+                .then(|| self.clip_at(project, position, lanes))
+                .flatten();
+            if hovered.is_some_and(|(_, handle)| handle != Handle::Body) {
+                ctx.set_cursor_icon(CursorIcon::ResizeHorizontal);
+            }
+            return;
+        }
+        self.drag(ctx, position, lanes, project, modifiers);
+    }
+
+    fn drag(&mut self, ctx: &Context, position: Pos2, lanes: Rect, project: &mut Project, modifiers: Modifiers) {
+        let Some(gesture) = &self.gesture else {
+            return;
+        };
+        let moved = self.view.beat(position.x, lanes.left()) - gesture.grab;
+        let (handle, id, original) = (gesture.handle, gesture.clip, gesture.original.clone());
+        match handle {
+            Handle::Body => {
+                ctx.set_cursor_icon(CursorIcon::Grabbing);
+                let start = self.snapped(project, original.start + moved, modifiers);
+                let Some((track, _)) = project.find_clip(id) else {
+                    return;
+                };
+                let target = self.track_at(position.y, lanes).min(project.tracks.len() - 1);
+                if let Some(gesture) = self.gesture.as_mut().filter(|gesture| gesture.duplicate) {
+                    if start != original.start || target != track {
+                        gesture.duplicate = false;
+                        // The copy stays behind and the grabbed clip carries on moving
+                        project.duplicate_clip(id, original.start);
+                    }
+                }
+                project.move_clip(id, project.tracks[target].id);
+                if let Some(clip) = project.clip(id) {
+                    project.set_clip("Move clip", Clip { start, ..clip.clone() });
+                }
+            }
+            Handle::Start => {
+                ctx.set_cursor_icon(CursorIcon::ResizeHorizontal);
+                let start = self.snapped(project, original.start + moved, modifiers);
+                let mut clip = original;
+                clip.trim_start(start, &project.tempo);
+                project.set_clip("Trim clip", clip);
+            }
+            Handle::End => {
+                ctx.set_cursor_icon(CursorIcon::ResizeHorizontal);
+                let end = self.snapped(project, original.end() + moved, modifiers);
+                let mut clip = original;
+                clip.trim_end(end);
+                project.set_clip("Trim clip", clip);
+            }
+        }
+    }
+
+    // Alternating track backgrounds with bar, beat and snap lines on top
+    fn paint_lanes(&self, ui: &Ui, lanes: Rect, theme: &ThemeColors, project: &Project) {
+        let painter = ui.painter_at(lanes);
+        for track in 0..project.tracks.len() {
+            let top = self.track_top(track, lanes);
+            let rect = Rect::from_min_size(pos2(lanes.left(), top), vec2(lanes.width(), self.view.track_height));
+            let color = if track % 2 == 0 {
+                theme.timeline_lane
+            } else {
+                theme.timeline_lane_alt
+            };
+            painter.rect_filled(rect, 0., color);
+        }
+
+        // Lines inside bars at the snap size, or every beat, as long as they aren't too crowded
+        let step = match self.snap.step() {
+            Some(step) if step * self.view.pixels_per_beat >= MIN_LINE_SPACING => Some(step),
+            _ if self.view.pixels_per_beat >= MIN_LINE_SPACING => Some(1.),
+            _ => None,
+        };
+        let end = self.view.beat(lanes.right(), lanes.left());
+        let line = |beat: f64, color| {
+            let x = self.view.x(beat, lanes.left());
+            painter.line_segment([pos2(x, lanes.top()), pos2(x, lanes.bottom())], Stroke::new(1., color));
+        };
+        for (_, bar_start, signature) in project.tempo.bars(self.view.scroll_x) {
+            if bar_start > end {
+                break;
+            }
+            line(bar_start, theme.timeline_bar_line);
+            let bar_end = bar_start + signature.beats_per_bar();
+            let Some(step) = step else {
+                continue;
+            };
+            let mut beat = bar_start + step;
+            while beat < bar_end - step / 2. {
+                line(beat, theme.timeline_grid);
+                beat += step;
+            }
+        }
+    }
+
+    fn paint_clips(&mut self, ui: &Ui, lanes: Rect, theme: &ThemeColors, project: &Project) {
+        let painter = ui.painter_at(lanes);
+        for (track, clip) in project
+            .tracks
+            .iter()
+            .enumerate()
+            .flat_map(|(index, track)| track.clips.iter().map(move |clip| (index, clip)))
+        {
+            let rect = self.clip_rect(track, clip, lanes);
+            if !rect.intersects(lanes) {
+                continue;
+            }
+            let color = match clip.source {
+                ClipSource::Audio { .. } => theme.timeline_audio_clip,
+                ClipSource::Midi { .. } => theme.timeline_midi_clip,
+            };
+            painter.rect_filled(rect, 2., color);
+            let body = Rect::from_min_max(pos2(rect.left(), rect.top() + CLIP_NAME_HEIGHT), rect.max);
+            match &clip.source {
+                ClipSource::Audio { path, offset } => {
+                    let waveform = Waveform {
+                        visible: body.intersect(lanes),
+                        path,
+                        offset: *offset,
+                        start: clip.start,
+                        left: lanes.left(),
+                        tempo: &project.tempo,
+                    };
+                    self.paint_waveform(&painter, &waveform, theme);
+                }
+                ClipSource::Midi { notes } => {
+                    let (low, high) = notes
+                        .iter()
+                        .fold((u8::MAX, u8::MIN), |(low, high), note| (low.min(note.pitch), high.max(note.pitch)));
+                    let rows = f32::from(high.saturating_sub(low)) + 1.;
+                    for note in notes {
+                        let top = body.bottom() - (f32::from(note.pitch - low) + 1.) * body.height() / rows;
+                        let note_rect = Rect::from_min_max(
+                            pos2(self.view.x(clip.start + note.start, lanes.left()), top),
+                            pos2(
+                                self.view.x(clip.start + note.start + note.length, lanes.left()),
+                                top + (body.height() / rows).max(1.),
+                            ),
+                        );
+                        painter.rect_filled(note_rect.intersect(rect), 0., theme.timeline_clip_fg);
+                    }
+                }
+            }
+            painter.with_clip_rect(rect.intersect(lanes)).text(
+                pos2(rect.left() + 4., rect.top() + CLIP_NAME_HEIGHT / 2.),
+                Align2::LEFT_CENTER,
+                clip.name(),
+                font(11.),
+                theme.timeline_clip_fg,
+            );
+            if self.selected == Some(clip.id) {
+                painter.rect_stroke(rect, 2., Stroke::new(1., theme.browser_selected_button_fg));
+            }
+        }
+    }
+
+    // Only the part of the clip that's on screen, one bucket per pixel column
+    fn paint_waveform(&mut self, painter: &Painter, waveform: &Waveform, theme: &ThemeColors) {
+        let visible = waveform.visible;
+        if visible.width() < 1. || visible.height() < 2. {
+            return;
+        }
+        let Some(peaks) = self.peaks.get(waveform.path) else {
+            return;
+        };
+        let sample_rate = f64::from(peaks.sample_rate);
+        let (tempo, clip_start) = (waveform.tempo, waveform.tempo.seconds_at(waveform.start));
+        let frame_at = |x: f32| {
+            let seconds = tempo.seconds_at(self.view.beat(x, waveform.left)) - clip_start;
+            (waveform.offset + seconds) * sample_rate
+        };
+        let (first, last) = (frame_at(visible.left()), frame_at(visible.right()));
+        #[allow(clippy::cast_precision_loss)]
+        let file_end = peaks.frames as f64;
+        if first >= file_end || last <= first {
+            return;
+        }
+        let last_in_file = last.min(file_end);
+        // Past the end of the file there's nothing to draw
+        let width = f64::from(visible.width()) * (last_in_file - first) / (last - first);
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let (columns, frames) = (width as usize, first.max(0.) as u64..last_in_file as u64);
+        let center = visible.center().y;
+        let half_height = visible.height() * 0.5;
+        for (index, peak) in peaks.buckets(None, frames, columns).iter().enumerate() {
+            #[allow(clippy::cast_precision_loss)]
+            let x = visible.left() + index as f32 + 0.5;
+            painter.line_segment(
+                [
+                    pos2(x, peak.max.mul_add(-half_height, center)),
+                    pos2(x, peak.min.mul_add(-half_height, center) + 0.5),
+                ],
+                Stroke::new(1., theme.timeline_clip_fg),
+            );
+        }
+    }
+
+    fn paint_drop_ghost(&self, ctx: &Context, ui: &Ui, lanes: Rect, theme: &ThemeColors, project: &Project) {
+        let (Some(payload), Some(position)) = (DragPayload::hovering(ctx, lanes), ctx.pointer_hover_pos()) else {
+            return;
+        };
+        let modifiers = ctx.input(|input| input.modifiers);
+        let start = self.snapped(project, self.view.beat(position.x, lanes.left()), modifiers);
+        let painter = ui.painter_at(lanes);
+        let first_track = self.track_at(position.y, lanes);
+        for (track, _) in (first_track..).zip(payload.audio_files()) {
+            let top = self.track_top(track, lanes);
+            let x = self.view.x(start, lanes.left());
+            #[allow(clippy::cast_possible_truncation)]
+            let width = (DEFAULT_CLIP_LENGTH * self.view.pixels_per_beat) as f32;
+            let rect = Rect::from_min_size(pos2(x, top + 1.), vec2(width, self.view.track_height - 2.));
+            painter.rect_stroke(rect, 2., Stroke::new(1., theme.browser_selected_button_fg));
+        }
+    }
+
+    // Bar numbers with the loop behind them, time signature changes next to their bar and tempo
+    // changes along the bottom
+    fn paint_ruler(&self, ui: &Ui, ruler: Rect, theme: &ThemeColors, project: &Project) {
+        let painter = ui.painter_at(ruler);
+        painter.rect_filled(ruler, 0., theme.navbar);
+        let tempo = &project.tempo;
+
+        let region = project.loop_region;
+        let loop_rect = Rect::from_x_y_ranges(
+            self.view.x(region.start, ruler.left())..=self.view.x(region.end, ruler.left()),
+            ruler.y_range(),
+        );
+        let loop_color = if region.enabled {
+            theme.timeline_loop
+        } else {
+            theme.timeline_loop_off
+        };
+        painter.rect_filled(loop_rect, 0., loop_color);
+
+        // Every bar, or every 2nd, 4th, 8th... when zoomed out
+        let bar_width = tempo.signature_at(self.view.scroll_x).beats_per_bar() * self.view.pixels_per_beat;
+        let mut every = 1;
+        while f64::from(every) * bar_width < MIN_LABEL_SPACING {
+            every *= 2;
+        }
+        let end = self.view.beat(ruler.right(), ruler.left());
+        for (bar, beat, signature) in tempo.bars(self.view.scroll_x) {
+            if beat > end {
+                break;
+            }
+            let change = tempo.signatures().iter().any(|change| change.bar == bar);
+            if bar % every != 0 && !change {
+                continue;
+            }
+            let x = self.view.x(beat, ruler.left());
+            painter.line_segment([pos2(x, ruler.center().y), pos2(x, ruler.bottom())], Stroke::new(1., theme.bg_text));
+            let label = painter.text(
+                pos2(x + 3., ruler.top() + 2.),
+                Align2::LEFT_TOP,
+                format!("{}", bar + 1),
+                font(11.),
+                theme.bg_text,
+            );
+            if change {
+                painter.text(
+                    pos2(label.right() + 4., ruler.top() + 2.),
+                    Align2::LEFT_TOP,
+                    format!("{}/{}", signature.numerator, signature.denominator),
+                    font(11.),
+                    theme.timeline_marker,
+                );
+            }
+        }
+
+        for (index, point) in tempo.tempos().iter().enumerate() {
+            let x = self.view.x(point.beat, ruler.left());
+            let next = tempo.tempos().get(index + 1);
+            if x > ruler.right() || next.is_some_and(|next| self.view.x(next.beat, ruler.left()) < ruler.left()) {
+                continue;
+            }
+            let ramp = if point.ramp && next.is_some() { " ↗" } else { "" };
+            painter.text(
+                pos2(x.max(ruler.left()) + 3., ruler.bottom() - 1.),
+                Align2::LEFT_BOTTOM,
+                format!("{:.1}{ramp}", point.bpm),
+                font(10.),
+                theme.timeline_marker,
+            );
+        }
+        painter.line_segment(
+            [ruler.left_bottom(), ruler.right_bottom()],
+            Stroke::new(0.5, theme.navbar_outline),
+        );
+    }
+
+    // Right clicking the ruler edits tempo and time signature changes and the loop
+    fn ruler_menu(&mut self, ui: &Ui, ruler: Rect, project: &mut Project) {
+        let response = ui.interact(ruler, ui.id().with("ruler"), Sense::click());
+        if response.secondary_clicked() {
+            self.ruler_menu = response
+                .interact_pointer_pos()
+                .map(|position| self.view.beat(position.x, ruler.left()));
+        }
+        let Some(beat) = self.ruler_menu else {
+            return;
+        };
+        let near = |at: f64| (self.view.x(at, ruler.left()) - self.view.x(beat, ruler.left())).abs() <= EDGE_WIDTH;
+        let snapped = self.snapped(project, beat, Modifiers::NONE);
+        let bar = project.tempo.bar_at(self.snap.round(beat, &project.tempo)).0;
+        response.context_menu(|ui| {
+            let mut tempo = project.tempo.clone();
+            let point = tempo.tempos().iter().position(|point| near(point.beat));
+            let change = tempo.signatures().iter().position(|change| near(tempo.bar_start(change.bar)));
+            let mut label = None;
+            let mut stopped = false;
+
+            if let Some(index) = point {
+                let mut bpm = tempo.tempos()[index].bpm;
+                let response = ui.add(
+                    DragValue::new(&mut bpm)
+                        .range(MIN_TEMPO..=MAX_TEMPO)
+                        .speed(0.1)
+                        .max_decimals(2)
+                        .suffix(" bpm"),
+                );
+                if response.drag_started() {
+                    project.begin("Change tempo");
+                }
+                if response.changed() {
+                    tempo.set_point_tempo(index, bpm);
+                    label = Some("Change tempo");
+                }
+                stopped = response.drag_stopped();
+                let mut ramp = tempo.tempos()[index].ramp;
+                let last = index + 1 == tempo.tempos().len();
+                if ui.add_enabled(!last, Checkbox::new(&mut ramp, "Ramp to the next tempo")).changed() {
+                    tempo.set_ramp(index, ramp);
+                    label = Some("Change tempo");
+                }
+                if ui.add_enabled(index > 0, Button::new("Remove tempo change")).clicked() {
+                    tempo.remove_tempo(index);
+                    label = Some("Remove tempo change");
+                    ui.close_menu();
+                }
+            } else if ui.button("Add tempo change here").clicked() {
+                tempo.set_tempo(snapped, tempo.tempo_at(snapped));
+                label = Some("Add tempo change");
+                ui.close_menu();
+            }
+            ui.separator();
+
+            let change_bar = change.map_or(bar, |index| tempo.signatures()[index].bar);
+            let current = tempo.signature_at_bar(change_bar);
+            ui.menu_button(format!("Time signature from bar {}", change_bar + 1), |ui| {
+                for signature in COMMON_SIGNATURES {
+                    let text = format!("{}/{}", signature.numerator, signature.denominator);
+                    if ui.selectable_label(signature == current, text).clicked() {
+                        tempo.set_signature(change_bar, signature);
+                        label = Some("Change time signature");
+                        ui.close_menu();
+                    }
+                }
+            });
+            if let Some(index) = change.filter(|index| *index > 0) {
+                if ui.button("Remove time signature change").clicked() {
+                    tempo.remove_signature(index);
+                    label = Some("Remove time signature change");
+                    ui.close_menu();
+                }
+            }
+            ui.separator();
+
+            let mut region = project.loop_region;
+            if ui.button("Start loop here").clicked() {
+                region.start = snapped.min(region.end - MIN_CLIP_LENGTH).max(0.);
+            }
+            if ui.button("End loop here").clicked() {
+                region.end = snapped.max(region.start + MIN_CLIP_LENGTH);
+            }
+            ui.checkbox(&mut region.enabled, "Loop");
+            project.set_loop_region("Change loop", region);
+
+            if let Some(label) = label {
+                project.set_tempo_map(label, tempo);
+            }
+            if stopped {
+                project.end();
+            }
+        });
+    }
+
+    // Track names, the snap setting in the corner and a row for adding tracks
+    fn paint_header(
+        &mut self,
+        ctx: &Context,
+        ui: &Ui,
+        header: Rect,
+        corner: Rect,
+        theme: &ThemeColors,
+        project: &mut Project,
+    ) {
+        let (was_pressed, press_position) = ctx
+            .input(|input_state| {
+                Some((
+                    input_state.pointer.button_released(PointerButton::Primary),
+                    Some(input_state.pointer.press_origin()?),
+                ))
+            })
+            .unwrap_or_default();
+        let was_pressed = was_pressed && !ctx.is_pointer_over_area();
+        let clicked = |rect: Rect| was_pressed && press_position.is_some_and(|position| rect.contains(position));
+
+        let lanes = Rect::from_min_max(pos2(header.left(), corner.bottom()), header.max);
+        let painter = ui.painter_at(lanes);
+        painter.rect_filled(lanes, 0., theme.browser);
+        for index in 0..project.tracks.len() {
+            let top = self.track_top(index, lanes);
+            let rect = Rect::from_min_size(pos2(header.left(), top), vec2(header.width(), self.view.track_height));
+            let track = &project.tracks[index];
+            let output = track
+                .output
+                .and_then(|output| project.buses.iter().find(|bus| bus.id == output))
+                .map_or("Master", |bus| bus.name.as_str());
+            painter.text(
+                pos2(header.left() + 10., rect.center().y - 7.),
+                Align2::LEFT_CENTER,
+                &track.name,
+                font(12.),
+                theme.browser_unselected_hover_button_fg,
+            );
+            painter.text(
+                pos2(header.left() + 10., rect.center().y + 7.),
+                Align2::LEFT_CENTER,
+                format!("→ {output}"),
+                font(10.),
+                theme.browser_unselected_button_fg,
+            );
+            painter.line_segment([rect.left_bottom(), rect.right_bottom()], Stroke::new(0.5, theme.browser_outline));
+
+            let id = track.id;
+            ui.interact(rect.intersect(lanes), ui.id().with(("track_header", id)), Sense::click())
+                .context_menu(|ui| {
+                    ui.menu_button("Output", |ui| {
+                        let current = project.tracks.iter().find(|track| track.id == id).and_then(|track| track.output);
+                        if ui.radio(current.is_none(), "Master").clicked() {
+                            project.set_track_output(id, None);
+                            ui.close_menu();
+                        }
+                        let buses: Vec<_> = project.buses.iter().map(|bus| (bus.id, bus.name.clone())).collect();
+                        for (bus, name) in buses {
+                            if ui.radio(current == Some(bus), name).clicked() {
+                                project.set_track_output(id, Some(bus));
+                                ui.close_menu();
+                            }
+                        }
+                        ui.separator();
+                        if ui.button("New bus").clicked() {
+                            let bus = project.add_bus(None);
+                            project.set_track_output(id, Some(bus));
+                            ui.close_menu();
+                        }
+                    });
+                    ui.separator();
+                    if ui.button("Delete track").clicked() {
+                        project.remove_track(id);
+                        ui.close_menu();
+                    }
+                });
+        }
+        let add = Rect::from_min_size(
+            pos2(header.left(), self.track_top(project.tracks.len(), lanes)),
+            vec2(header.width(), self.view.track_height.min(24.)),
+        );
+        let hovering = ctx.pointer_hover_pos().is_some_and(|position| add.intersect(lanes).contains(position));
+        painter.text(
+            pos2(header.left() + 10., add.center().y),
+            Align2::LEFT_CENTER,
+            "+ Add track",
+            font(12.),
+            if hovering {
+                theme.browser_unselected_hover_button_fg
+            } else {
+                theme.browser_unselected_button_fg
+            },
+        );
+        if clicked(add.intersect(lanes)) {
+            project.add_track(None);
+        }
+        painter.line_segment(
+            [lanes.right_top(), lanes.right_bottom()],
+            Stroke::new(0.5, theme.browser_outline),
+        );
+
+        ui.painter().rect_filled(corner, 0., theme.navbar);
+        ui.painter().text(
+            pos2(corner.left() + 10., corner.center().y),
+            Align2::LEFT_CENTER,
+            format!("Snap: {}", self.snap),
+            font(12.),
+            theme.browser_unselected_hover_button_fg,
+        );
+        if clicked(corner) {
+            self.snap = self.snap.next();
+        }
+    }
+}
+
+// Where an audio clip's waveform goes and which part of the file it shows
+struct Waveform<'a> {
+    visible: Rect,
+    path: &'a Path,
+    offset: f64,
+    // Beat the clip starts at
+    start: f64,
+    // Left edge of the lanes
+    left: f32,
+    tempo: &'a TempoMap,
+}
@@ -0,0 +1,114 @@
+use strum::{Display, EnumIter, IntoEnumIterator};
+
+use crate::project::TempoMap;
+
+// What positions get rounded to while editing
+#[derive(Display, EnumIter, Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Snap {
+    Off,
+    Bar,
+    #[default]
+    Beat,
+    #[strum(to_string = "1/2 beat")]
+    HalfBeat,
+    #[strum(to_string = "1/4 beat")]
+    QuarterBeat,
+}
+
+impl Snap {
+    pub fn next(self) -> Self {
+        Self::iter().cycle().skip_while(|snap| *snap != self).nth(1).unwrap_or_default()
+    }
+
+    // Size of the grid in beats, `None` when not snapping or snapping to bars, which aren't all
+    // the same length
+    pub fn step(self) -> Option<f64> {
+        match self {
+            Self::Off | Self::Bar => None,
+            Self::Beat => Some(1.),
+            Self::HalfBeat => Some(0.5),
+            Self::QuarterBeat => Some(0.25),
+        }
+    }
+
+    // Counted from the start of the bar, so the grid lines up after bars of odd lengths
+    pub fn round(self, beat: f64, tempo: &TempoMap) -> f64 {
+        let (bar, into_bar) = tempo.bar_at(beat);
+        let bar_start = tempo.bar_start(bar);
+        match self {
+            Self::Off => beat,
+            Self::Bar => {
+                let next = tempo.bar_start(bar + 1);
+                if beat - bar_start < next - beat {
+                    bar_start
+                } else {
+                    next
+                }
+            }
+            _ => self.step().map_or(beat, |step| bar_start + (into_bar / step).round() * step),
+        }
+    }
+
+    pub fn floor(self, beat: f64, tempo: &TempoMap) -> f64 {
+        let (bar, into_bar) = tempo.bar_at(beat);
+        let bar_start = tempo.bar_start(bar);
+        match self {
+            Self::Off => beat,
+            Self::Bar => bar_start,
+            _ => self.step().map_or(beat, |step| bar_start + (into_bar / step).floor() * step),
+        }
+    }
+}
+
+// How much of the arrangement is on screen
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct View {
+    // Beat at the left edge of the lanes
+    pub scroll_x: f64,
+    // Pixels scrolled down past the first track
+    pub scroll_y: f32,
+    pub pixels_per_beat: f64,
+    pub track_height: f32,
+}
+
+impl Default for View {
+    fn default() -> Self {
+        Self {
+            scroll_x: 0.,
+            scroll_y: 0.,
+            pixels_per_beat: 24.,
+            track_height: 48.,
+        }
+    }
+}
+
+impl View {
+    pub const MIN_PIXELS_PER_BEAT: f64 = 2.;
+    pub const MAX_PIXELS_PER_BEAT: f64 = 400.;
+    pub const MIN_TRACK_HEIGHT: f32 = 24.;
+    pub const MAX_TRACK_HEIGHT: f32 = 160.;
+
+    // `left` is where the lanes start on screen
+    pub fn x(&self, beat: f64, left: f32) -> f32 {
+        #[allow(clippy::cast_possible_truncation)]
+        let offset = ((beat - self.scroll_x) * self.pixels_per_beat) as f32;
+        left + offset
+    }
+
+    pub fn beat(&self, x: f32, left: f32) -> f64 {
+        f64::from(x - left) / self.pixels_per_beat + self.scroll_x
+    }
+
+    // Keeps the beat under `x` where it is
+    pub fn zoom_x(&mut self, factor: f64, x: f32, left: f32) {
+        let anchor = self.beat(x, left);
+        self.pixels_per_beat = (self.pixels_per_beat * factor)
+            .clamp(Self::MIN_PIXELS_PER_BEAT, Self::MAX_PIXELS_PER_BEAT);
+        self.scroll_x = (anchor - f64::from(x - left) / self.pixels_per_beat).max(0.);
+    }
+
+    pub fn zoom_y(&mut self, factor: f32) {
+        self.track_height =
+            (self.track_height * factor).clamp(Self::MIN_TRACK_HEIGHT, Self::MAX_TRACK_HEIGHT);
+    }
+}
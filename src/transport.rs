@@ -0,0 +1,202 @@
+use std::sync::atomic::Ordering;
+
+use egui::{Context, DragValue, Key, Modifiers, Rect, RichText, Ui};
+
+use crate::{
+    blerp::{
+        device::Output,
+        processing::live::{Command, TransportCommand},
+    },
+    project::{Project, TimeSignature, MAX_TEMPO, MIN_TEMPO},
+    visual::{font, ThemeColors},
+};
+
+// Offered wherever a time signature gets picked
+pub const COMMON_SIGNATURES: [TimeSignature; 8] = [
+    TimeSignature { numerator: 2, denominator: 4 },
+    TimeSignature { numerator: 3, denominator: 4 },
+    TimeSignature { numerator: 4, denominator: 4 },
+    TimeSignature { numerator: 5, denominator: 4 },
+    TimeSignature { numerator: 6, denominator: 8 },
+    TimeSignature { numerator: 7, denominator: 8 },
+    TimeSignature { numerator: 9, denominator: 8 },
+    TimeSignature { numerator: 12, denominator: 8 },
+];
+// Used when nothing is playing, so there's still something to convert positions with
+pub const DEFAULT_SAMPLE_RATE: u32 = 48000;
+
+fn send(output: Option<&Output>, command: TransportCommand) {
+    if let Some(output) = output {
+        output.engine.send(Command::Transport(command));
+    }
+}
+
+// "bar.beat.sixteenth", beats counted the way the time signature counts them
+fn musical_position(project: &Project, beat: f64) -> String {
+    let (bar, into_bar) = project.tempo.bar_at(beat);
+    let signature = project.tempo.signature_at_bar(bar);
+    let unit = 4. / f64::from(signature.denominator.max(1));
+    let beats = (into_bar / unit).floor();
+    let sixteenths = ((into_bar - beats * unit) / unit * 4.).floor();
+    format!("{}.{}.{}", bar + 1, beats + 1., sixteenths + 1.)
+}
+
+// "m:ss.mmm"
+fn clock_position(frame: u64, sample_rate: u32) -> String {
+    let milliseconds = frame * 1000 / u64::from(sample_rate.max(1));
+    format!(
+        "{}:{:02}.{:03}",
+        milliseconds / 60_000,
+        milliseconds / 1000 % 60,
+        milliseconds % 1000
+    )
+}
+
+// Play, stop, record and loop, where the playhead is and the tempo and time signature there
+pub struct Transport {
+    // Dragging the tempo is a single step to undo
+    dragging_tempo: bool,
+}
+
+impl Transport {
+    pub fn new() -> Self {
+        Self { dragging_tempo: false }
+    }
+
+    pub fn paint(
+        &mut self,
+        ctx: &Context,
+        ui: &mut Ui,
+        area: Rect,
+        theme: &ThemeColors,
+        project: &mut Project,
+        output: Option<&Output>,
+    ) {
+        let (rolling, recording, counting_in, frame, sample_rate) =
+            output.map_or((false, false, false, 0, DEFAULT_SAMPLE_RATE), |output| {
+                let status = &output.engine.status;
+                let (rolling, frame) = status.playhead();
+                let recording = status.transport_recording.load(Ordering::Relaxed);
+                let counting_in = status.counting_in.load(Ordering::Relaxed);
+                // Stopping during the count-in cancels it
+                (rolling || counting_in, recording, counting_in, frame, status.sample_rate.load(Ordering::Relaxed).max(1))
+            });
+        self.handle_keys(ctx, project, output, rolling, recording);
+        let playhead = project.tempo.beat_at_frame(frame, sample_rate);
+
+        ui.allocate_ui_at_rect(area, |ui| {
+            ui.horizontal_centered(|ui| {
+                if ui.button("⏮").on_hover_text("Back to the start").clicked() {
+                    send(output, TransportCommand::Locate(0));
+                }
+                let (play, hint) = if rolling { ("⏹", "Stop") } else { ("▶", "Play") };
+                if ui.button(play).on_hover_text(hint).clicked() {
+                    send(output, if rolling { TransportCommand::Stop } else { TransportCommand::Play });
+                }
+                let record_color = if recording { theme.transport_record } else { theme.bg_text };
+                if ui
+                    .button(RichText::new("⏺").color(record_color))
+                    .on_hover_text("Record")
+                    .clicked()
+                {
+                    send(output, TransportCommand::Record(!recording));
+                }
+                let mut region = project.loop_region;
+                if ui
+                    .selectable_label(region.enabled, "🔁")
+                    .on_hover_text("Loop, shift + drag along the ruler to set it")
+                    .clicked()
+                {
+                    region.enabled = !region.enabled;
+                    project.set_loop_region("Toggle loop", region);
+                }
+
+                ui.add_space(8.);
+                let position = if counting_in { "Count-in".to_string() } else { musical_position(project, playhead) };
+                ui.label(RichText::new(position).font(font(14.)).color(theme.bg_text));
+                ui.label(RichText::new(clock_position(frame, sample_rate)).font(font(12.)).weak());
+                ui.add_space(8.);
+
+                self.paint_tempo(ui, project, playhead);
+                self.paint_signature(ui, project, playhead);
+            });
+        });
+    }
+
+    fn handle_keys(&mut self, ctx: &Context, project: &mut Project, output: Option<&Output>, rolling: bool, recording: bool) {
+        // Text fields and menus get the keyboard first
+        if ctx.memory(|memory| memory.focused().is_some() || memory.any_popup_open()) {
+            return;
+        }
+        let (space, home, looping, record) = ctx.input_mut(|input| {
+            (
+                input.consume_key(Modifiers::NONE, Key::Space),
+                input.consume_key(Modifiers::NONE, Key::Home),
+                input.consume_key(Modifiers::COMMAND, Key::L),
+                input.consume_key(Modifiers::COMMAND, Key::R),
+            )
+        });
+        if space {
+            send(output, if rolling { TransportCommand::Stop } else { TransportCommand::Play });
+        }
+        if home {
+            send(output, TransportCommand::Locate(0));
+        }
+        if looping {
+            let mut region = project.loop_region;
+            region.enabled = !region.enabled;
+            project.set_loop_region("Toggle loop", region);
+        }
+        if record {
+            send(output, TransportCommand::Record(!recording));
+        }
+    }
+
+    // The tempo at the playhead, changing it changes the tempo change it belongs to
+    fn paint_tempo(&mut self, ui: &mut Ui, project: &mut Project, playhead: f64) {
+        let index = project.tempo.tempo_index(playhead);
+        let mut bpm = project.tempo.tempos()[index].bpm;
+        let response = ui
+            .add(
+                DragValue::new(&mut bpm)
+                    .range(MIN_TEMPO..=MAX_TEMPO)
+                    .speed(0.1)
+                    .max_decimals(2)
+                    .suffix(" bpm"),
+            )
+            .on_hover_text("Tempo, right click the ruler for tempo changes");
+        if response.drag_started() {
+            self.dragging_tempo = true;
+            project.begin("Change tempo");
+        }
+        if response.changed() {
+            let mut tempo = project.tempo.clone();
+            tempo.set_point_tempo(index, bpm);
+            project.set_tempo_map("Change tempo", tempo);
+        }
+        if self.dragging_tempo && !response.dragged() {
+            self.dragging_tempo = false;
+            project.end();
+        }
+    }
+
+    fn paint_signature(&mut self, ui: &mut Ui, project: &mut Project, playhead: f64) {
+        let bar = project.tempo.bar_at(playhead).0;
+        let index = project.tempo.signature_index(bar);
+        let change = project.tempo.signatures()[index];
+        let text = format!("{}/{}", change.signature.numerator, change.signature.denominator);
+        ui.menu_button(text, |ui| {
+            for signature in COMMON_SIGNATURES {
+                let text = format!("{}/{}", signature.numerator, signature.denominator);
+                if ui.selectable_label(signature == change.signature, text).clicked() {
+                    let mut tempo = project.tempo.clone();
+                    tempo.set_signature(change.bar, signature);
+                    project.set_tempo_map("Change time signature", tempo);
+                    ui.close_menu();
+                }
+            }
+        })
+        .response
+        .on_hover_text("Time signature, right click the ruler for time signature changes");
+    }
+}
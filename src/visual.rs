@@ -1,9 +1,15 @@
-use egui::Color32;
+use egui::{Color32, FontFamily, FontId};
 
 // Expose components
 pub mod navbar;
 pub mod switch;
 pub mod background;
+pub mod tree_view;
+
+// The monospaced font most of the interface is set in
+pub fn font(size: f32) -> FontId {
+    FontId::new(size, FontFamily::Name("IBMPlexMono".into()))
+}
 
 // Theming
 #[derive(Debug, Clone, PartialEq, Eq)]
@@ -19,6 +25,18 @@ pub struct ThemeColors {
     pub browser_unselected_hover_button_fg_invalid: Color32,
     pub browser_unselected_button_fg_invalid: Color32,
     pub bg_text: Color32,
+    pub timeline_lane: Color32,
+    pub timeline_lane_alt: Color32,
+    pub timeline_grid: Color32,
+    pub timeline_bar_line: Color32,
+    pub timeline_audio_clip: Color32,
+    pub timeline_midi_clip: Color32,
+    pub timeline_clip_fg: Color32,
+    pub timeline_playhead: Color32,
+    pub timeline_loop: Color32,
+    pub timeline_loop_off: Color32,
+    pub timeline_marker: Color32,
+    pub transport_record: Color32,
 }
 
 impl Default for ThemeColors {
@@ -36,6 +54,18 @@ impl Default for ThemeColors {
             browser_unselected_hover_button_fg_invalid: Color32::from_hex("#f591b5")
                 .unwrap_or_default(),
             bg_text: Color32::from_hex("#646987").unwrap_or_default(),
+            timeline_lane: Color32::from_hex("#1e222f").unwrap_or_default(),
+            timeline_lane_alt: Color32::from_hex("#212534").unwrap_or_default(),
+            timeline_grid: Color32::from_hex("#2a2f41").unwrap_or_default(),
+            timeline_bar_line: Color32::from_hex("#363c53").unwrap_or_default(),
+            timeline_audio_clip: Color32::from_hex("#3d5a80").unwrap_or_default(),
+            timeline_midi_clip: Color32::from_hex("#5a4d80").unwrap_or_default(),
+            timeline_clip_fg: Color32::from_hex("#c9d3ef").unwrap_or_default(),
+            timeline_playhead: Color32::from_hex("#ffcf7b").unwrap_or_default(),
+            timeline_loop: Color32::from_hex("#ffcf7b40").unwrap_or_default(),
+            timeline_loop_off: Color32::from_hex("#ffffff14").unwrap_or_default(),
+            timeline_marker: Color32::from_hex("#8fb8de").unwrap_or_default(),
+            transport_record: Color32::from_hex("#e5484d").unwrap_or_default(),
         }
     }
 }
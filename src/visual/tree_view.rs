@@ -0,0 +1,304 @@
+use std::{
+    collections::{hash_map::DefaultHasher, HashMap},
+    hash::{Hash, Hasher},
+    ops::Range,
+    sync::Arc,
+};
+
+use egui::{
+    pos2,
+    text::{LayoutJob, TextFormat, TextWrapping},
+    vec2, Align, Align2, Color32, FontId, Galley, Painter, Rect,
+};
+
+// Layouts nobody asked for in this many frames get dropped
+const GALLEY_LIFETIME: u64 = 300;
+// Scrolling stops a little past the last row
+const BOTTOM_MARGIN: f32 = 8.;
+const COLUMN_SPACING: f32 = 6.;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Column {
+    pub width: f32,
+    pub align: Align,
+}
+
+// What the tree needs to know about a row to place it
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RowShape {
+    pub height: f32,
+    pub indent: usize,
+    // Rows without any cells let their label run over the columns
+    pub has_columns: bool,
+}
+
+// Where everything in a row that's on screen goes
+#[derive(Debug, Clone, PartialEq)]
+pub struct VisibleRow {
+    pub index: usize,
+    pub rect: Rect,
+    pub icon_rect: Rect,
+    pub label_rect: Rect,
+    pub columns: Vec<Rect>,
+}
+
+// A list of rows with indentation, an icon, a label and optional columns on the right. Only rows
+// that are on screen get laid out, and text layouts are kept between frames. The tree doesn't
+// paint anything by itself apart from text, so rows can look however the caller wants
+pub struct TreeView {
+    pub indent_width: f32,
+    pub icon_size: f32,
+    pub padding: f32,
+    pub columns: Vec<Column>,
+    shapes: Vec<RowShape>,
+    // Where every row starts, plus where the last one ends
+    tops: Vec<f32>,
+    galleys: HashMap<u64, (Arc<Galley>, u64)>,
+    frame: u64,
+}
+
+impl TreeView {
+    pub fn new() -> Self {
+        Self {
+            indent_width: 20.,
+            icon_size: 14.,
+            padding: 10.,
+            columns: Vec::new(),
+            shapes: Vec::new(),
+            tops: vec![0.],
+            galleys: HashMap::new(),
+            frame: 0,
+        }
+    }
+
+    // Called with every row whenever they change
+    pub fn set_rows(&mut self, shapes: impl IntoIterator<Item = RowShape>) {
+        self.shapes.clear();
+        self.shapes.extend(shapes);
+        self.tops.clear();
+        self.tops.push(0.);
+        let mut top = 0.;
+        for shape in &self.shapes {
+            top += shape.height;
+            self.tops.push(top);
+        }
+    }
+
+    // Called once per frame before laying anything out, so layouts nobody uses anymore get dropped
+    pub fn next_frame(&mut self) {
+        self.frame += 1;
+        let frame = self.frame;
+        self.galleys
+            .retain(|_, (_, used)| frame - *used <= GALLEY_LIFETIME);
+    }
+
+    pub fn content_height(&self) -> f32 {
+        self.tops.last().copied().unwrap_or_default()
+    }
+
+    // Top and bottom of a row, relative to the top of the list
+    pub fn row_span(&self, index: usize) -> Option<Range<f32>> {
+        Some(*self.tops.get(index)?..*self.tops.get(index + 1)?)
+    }
+
+    // Rows that show up at least partly when scrolled down by `scroll` in a view this high
+    pub fn visible_rows(&self, scroll: f32, height: f32) -> Range<usize> {
+        let start = self.tops[1..].partition_point(|bottom| *bottom <= scroll);
+        let end = self.tops[..self.shapes.len()].partition_point(|top| *top < scroll + height);
+        start..end.max(start)
+    }
+
+    pub fn clamp_scroll(&self, scroll: f32, height: f32) -> f32 {
+        scroll.clamp(0., (self.content_height() + BOTTOM_MARGIN - height).max(0.))
+    }
+
+    // Smallest change in scroll that gets the whole row on screen
+    pub fn scroll_to_row(&self, index: usize, scroll: f32, height: f32) -> f32 {
+        let Some(span) = self.row_span(index) else {
+            return scroll;
+        };
+        if span.start < scroll {
+            span.start
+        } else if span.end > scroll + height {
+            span.end - height
+        } else {
+            scroll
+        }
+    }
+
+    // Lays out the rows in `area` that are on screen, top to bottom
+    pub fn layout(&self, area: Rect, scroll: f32) -> Vec<VisibleRow> {
+        let mut columns = Vec::with_capacity(self.columns.len());
+        let mut right = area.right() - self.padding;
+        for column in self.columns.iter().rev() {
+            columns.push((right - column.width, right));
+            right -= column.width + COLUMN_SPACING;
+        }
+        columns.reverse();
+        let columns_left = columns.first().map_or(area.right(), |(left, _)| *left - COLUMN_SPACING);
+
+        self.visible_rows(scroll, area.height())
+            .map(|index| {
+                let shape = self.shapes[index];
+                let top = area.top() + self.tops[index] - scroll;
+                let rect = Rect::from_min_size(pos2(area.left(), top), vec2(area.width(), shape.height));
+                #[allow(clippy::cast_precision_loss)]
+                let indent = self.indent_width * shape.indent as f32;
+                let icon_rect = Rect::from_min_size(
+                    pos2(area.left() + self.padding + indent, rect.center().y - self.icon_size / 2.),
+                    vec2(self.icon_size, self.icon_size),
+                );
+                let label_right = if shape.has_columns {
+                    columns_left
+                } else {
+                    area.right() - self.padding
+                };
+                let label_rect = Rect::from_min_max(
+                    pos2(icon_rect.right() + self.padding / 2., top),
+                    pos2(label_right, rect.bottom()),
+                );
+                VisibleRow {
+                    index,
+                    rect,
+                    icon_rect,
+                    label_rect,
+                    columns: columns
+                        .iter()
+                        .map(|(left, right)| Rect::from_min_max(pos2(*left, top), pos2(*right, rect.bottom())))
+                        .collect(),
+                }
+            })
+            .collect()
+    }
+
+    // Single line of text cut short with an ellipsis when it doesn't fit
+    pub fn galley(&mut self, painter: &Painter, text: &str, font: &FontId, max_width: f32) -> Arc<Galley> {
+        let mut hasher = DefaultHasher::new();
+        text.hash(&mut hasher);
+        font.hash(&mut hasher);
+        // Widths only matter to the pixel
+        #[allow(clippy::cast_possible_truncation)]
+        (max_width.round() as i32).hash(&mut hasher);
+        let key = hasher.finish();
+
+        let frame = self.frame;
+        if let Some((galley, used)) = self.galleys.get_mut(&key) {
+            *used = frame;
+            return galley.clone();
+        }
+        let mut job = LayoutJob::single_section(
+            text.to_string(),
+            // Coloured when it gets painted, so one layout works for every colour
+            TextFormat::simple(font.clone(), Color32::PLACEHOLDER),
+        );
+        job.wrap = TextWrapping::truncate_at_width(max_width.max(0.));
+        job.break_on_newline = false;
+        let galley = painter.layout_job(job);
+        self.galleys.insert(key, (galley.clone(), frame));
+        galley
+    }
+
+    // Returns where the text ended up
+    pub fn text(
+        &mut self,
+        painter: &Painter,
+        rect: Rect,
+        align: Align2,
+        text: &str,
+        font: &FontId,
+        color: Color32,
+    ) -> Rect {
+        let galley = self.galley(painter, text, font, rect.width());
+        let text_rect = align.align_size_within_rect(galley.size(), rect);
+        painter.galley(text_rect.min, galley, color);
+        text_rect
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use egui::{Context, LayerId, RawInput};
+
+    fn tree(heights: &[f32]) -> TreeView {
+        let mut tree = TreeView::new();
+        tree.set_rows(heights.iter().map(|height| RowShape {
+            height: *height,
+            indent: 0,
+            has_columns: false,
+        }));
+        tree
+    }
+
+    #[test]
+    fn only_rows_on_screen_are_visible() {
+        let tree = tree(&[10.; 10]);
+        assert_eq!(tree.content_height(), 100.);
+        assert_eq!(tree.visible_rows(0., 25.), 0..3);
+        assert_eq!(tree.visible_rows(10., 20.), 1..3);
+        assert_eq!(tree.visible_rows(95., 50.), 9..10);
+        assert_eq!(tree.visible_rows(200., 50.), 10..10);
+    }
+
+    #[test]
+    fn scrolling_to_a_row_moves_as_little_as_possible() {
+        let tree = tree(&[10., 20., 10., 10.]);
+        assert_eq!(tree.row_span(1), Some(10.0..30.));
+        assert_eq!(tree.scroll_to_row(0, 15., 20.), 0.);
+        assert_eq!(tree.scroll_to_row(1, 10., 20.), 10.);
+        assert_eq!(tree.scroll_to_row(3, 0., 20.), 30.);
+        assert_eq!(tree.scroll_to_row(4, 5., 20.), 5.);
+        assert_eq!(tree.clamp_scroll(100., 20.), 50. + BOTTOM_MARGIN - 20.);
+        assert_eq!(tree.clamp_scroll(10., 100.), 0.);
+    }
+
+    #[test]
+    fn labels_leave_room_for_columns() {
+        let mut tree = tree(&[]);
+        tree.columns = vec![Column { width: 40., align: Align::Max }; 2];
+        tree.set_rows([
+            RowShape { height: 20., indent: 0, has_columns: true },
+            RowShape { height: 20., indent: 2, has_columns: false },
+        ]);
+        let area = Rect::from_min_size(pos2(0., 100.), vec2(200., 100.));
+        let rows = tree.layout(area, 0.);
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].columns[1].right(), 200. - tree.padding);
+        assert_eq!(rows[0].columns[0].right(), rows[0].columns[1].left() - COLUMN_SPACING);
+        assert_eq!(rows[0].label_rect.right(), rows[0].columns[0].left() - COLUMN_SPACING);
+        assert_eq!(rows[1].label_rect.right(), 200. - tree.padding);
+        assert_eq!(rows[1].icon_rect.left(), rows[0].icon_rect.left() + tree.indent_width * 2.);
+        assert_eq!(rows[1].rect.top(), 120.);
+    }
+
+    #[test]
+    fn layouts_are_reused_until_nobody_asks_for_them() {
+        let ctx = Context::default();
+        let mut tree = tree(&[]);
+        let font = FontId::proportional(12.);
+        let _ = ctx.run(RawInput::default(), |ctx| {
+            let painter = ctx.layer_painter(LayerId::background());
+            tree.next_frame();
+            let first = tree.galley(&painter, "Kick.wav", &font, 100.);
+            let again = tree.galley(&painter, "Kick.wav", &font, 100.2);
+            assert!(Arc::ptr_eq(&first, &again));
+            let narrower = tree.galley(&painter, "Kick.wav", &font, 10.);
+            assert!(!Arc::ptr_eq(&first, &narrower));
+            assert!(narrower.size().x <= first.size().x);
+
+            assert_eq!(tree.galleys.len(), 2);
+
+            for _ in 0..GALLEY_LIFETIME {
+                tree.next_frame();
+            }
+            assert!(Arc::ptr_eq(&first, &tree.galley(&painter, "Kick.wav", &font, 100.)));
+            // Only the one that was asked for again is still around
+            tree.next_frame();
+            assert_eq!(tree.galleys.len(), 1);
+            for _ in 0..GALLEY_LIFETIME {
+                tree.next_frame();
+            }
+            assert!(tree.galleys.is_empty());
+        });
+    }
+}